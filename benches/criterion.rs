@@ -2,6 +2,9 @@ mod initialisations;
 mod insert;
 mod remove;
 mod get;
+mod noise;
+mod replay;
+mod meshing;
 use criterion::{ criterion_group, criterion_main };
 
 #[allow(unused)]
@@ -9,7 +12,10 @@ use crate::{
     initialisations::measure_structs_initialization,
     get::{ measure_structs_get, measure_structs_get_random, measure_structs_get_reference },
     insert::{ measure_structs_insert, measure_structs_insert_fill, measure_structs_insert_fill_padded, measure_structs_insert_random },
-    remove::{ measure_structs_remove, measure_structs_remove_bulk, measure_structs_remove_random }
+    remove::{ measure_structs_remove, measure_structs_remove_bulk, measure_structs_remove_random },
+    noise::measure_noise_chunk,
+    replay::{ measure_structs_replay_generator, measure_structs_replay_reads },
+    meshing::measure_meshing,
 };
 
 criterion_group!(
@@ -25,6 +31,10 @@ criterion_group!(
     measure_structs_get_reference,
     // measure_structs_get,
     // measure_structs_get_random,
+    // measure_noise_chunk,
+    // measure_structs_replay_generator,
+    // measure_structs_replay_reads,
+    measure_meshing,
 );
 
 criterion_main!( benches );
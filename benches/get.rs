@@ -99,7 +99,7 @@ pub fn measure_structs_get_reference( c:&mut Criterion ) {
 
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
@@ -123,7 +123,7 @@ pub fn measure_structs_get( c:&mut Criterion ) {
 
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
@@ -191,7 +191,7 @@ pub fn measure_structs_get_random( c:&mut Criterion ) {
     let mut group = c.benchmark_group( "Random get from the structs" );
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
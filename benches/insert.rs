@@ -13,7 +13,7 @@ pub fn measure_structs_insert( c:&mut Criterion ) {
     let mut group = c.benchmark_group( "Insertion into the structs" );
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
@@ -75,7 +75,7 @@ pub fn measure_structs_insert_fill( c:&mut Criterion ) {
 
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
@@ -109,7 +109,7 @@ pub fn measure_structs_insert_fill_padded( c:&mut Criterion ) {
 
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
@@ -153,7 +153,7 @@ pub fn measure_structs_insert_random( c:&mut Criterion ) {
     let mut group = c.benchmark_group( "Random insertion into the structs" );
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
@@ -0,0 +1,68 @@
+use std::{ hint::black_box, sync::RwLock };
+use criterion::{ BatchSize, Criterion };
+
+use praca_magisterska::{
+    chunks_generators::{ biomes::GeneratorOfBiomes, test_11_height_map::GeneratorOfTest11HeightMap },
+    structure_tests::octree::Octree,
+    world::{
+        world::CHUNK_SIZE,
+        world_chunk::WorldChunk,
+        world_generator::{ generate_chunk_stages, WorldGenerative },
+        world_holder::{ Voxel, VoxelDataset }
+    }
+};
+
+/// Top corner of the chunk, above the terrain of both generators
+const FLOOD_START:(u32, u32, u32) = (0, CHUNK_SIZE as u32 - 1, 0);
+
+fn get_generated_chunks() -> [(&'static str, Octree<Voxel>); 2] {
+    let generate = |generator:&dyn WorldGenerative| generate_chunk_stages( generator, &mut VoxelDataset::new(), (0, 0, 0), CHUNK_SIZE as u8 );
+
+    [
+        ("height map", generate( &GeneratorOfTest11HeightMap::new( 50 ) )),
+        ("biomes", generate( &GeneratorOfBiomes::new_continental( 50 ) )),
+    ]
+}
+
+fn create_world_chunk( data:&Octree<Voxel> ) -> WorldChunk {
+    let mut world_chunk = WorldChunk::new();
+    world_chunk.set_data( Box::new( data.clone() ) );
+    world_chunk
+}
+
+/// Bitmask mesher of `WorldChunk::remesh` against the octree flood, on single chunks without neighbours.
+/// Remesh also calculates the light of the chunk, bitmasks are built before it, in `set_data`.
+/// The flood doesn't give sides at the chunk borders, so it gives less sides than the bitmask mesher
+pub fn measure_meshing( c:&mut Criterion ) {
+    let neighbours = (0..26).map( |_| RwLock::new( WorldChunk::new_disabled() ) ).collect::<Vec<_>>();
+    let get_neighbour_guards = || neighbours.iter().map( |neighbour| neighbour.read().unwrap() ).collect::<Vec<_>>();
+
+    for (name, chunk) in &get_generated_chunks() {
+        let mut world_chunk = create_world_chunk( chunk );
+        world_chunk.remesh( (0, 0, 0), get_neighbour_guards() );
+
+        let bitmask_sides = world_chunk.renderables.len() + world_chunk.transparent_renderables.len();
+        let flood_faces = chunk.get_visible_faces_with_flood( FLOOD_START ).len();
+        let flood_sides = chunk.get_visible_with_flood( FLOOD_START ).len();
+        let mut group = c.benchmark_group( format!(
+            "Meshing of the {name} chunk (bitmask {bitmask_sides} sides, flood {flood_faces} faces = {flood_sides} sides)"
+        ) );
+
+        group.bench_function( format!( "Bitmask mesher (size {CHUNK_SIZE})" ), |b| b.iter_batched(
+            || (create_world_chunk( chunk ), get_neighbour_guards()),
+            |(mut world_chunk, guards)| {
+                world_chunk.remesh( (0, 0, 0), guards );
+                black_box( world_chunk )
+            },
+            BatchSize::LargeInput,
+        ) );
+
+        group.bench_function( format!( "Octree flood faces (size {CHUNK_SIZE})" ), |b| b.iter(
+            || black_box( chunk.get_visible_faces_with_flood( FLOOD_START ) )
+        ) );
+
+        group.bench_function( format!( "Octree flood sides (size {CHUNK_SIZE})" ), |b| b.iter(
+            || black_box( chunk.get_visible_with_flood( FLOOD_START ) )
+        ) );
+    }
+}
@@ -0,0 +1,27 @@
+use std::hint::black_box;
+use criterion::Criterion;
+
+use praca_magisterska::noise::simplex_noise::SimplexNoise;
+
+const SIZE:usize = 64;
+const FREQUENCY:f64 = 0.025;
+
+pub fn measure_noise_chunk( c:&mut Criterion ) {
+    let mut group = c.benchmark_group( "Noise of a whole chunk" );
+    let noise = SimplexNoise::new( 0 );
+
+    group.bench_function( format!( "Scalar noise3d (size {SIZE})" ), |b| b.iter( || {
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                for z in 0..SIZE {
+                    black_box( noise.noise3d( x as f64 * FREQUENCY, y as f64 * FREQUENCY, z as f64 * FREQUENCY ) );
+                }
+            }
+        }
+    } ) );
+
+    group.bench_function( format!( "Batched noise3d_grid (size {SIZE})" ), |b| b.iter( || {
+        black_box( noise.noise3d_grid( (0.0, 0.0, 0.0), (SIZE, SIZE, SIZE), FREQUENCY ) );
+    } ) );
+
+}
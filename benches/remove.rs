@@ -15,7 +15,7 @@ pub fn measure_structs_remove( c:&mut Criterion ) {
 
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
@@ -94,7 +94,7 @@ pub fn measure_structs_remove_bulk( c:&mut Criterion ) {
 
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
@@ -154,7 +154,7 @@ pub fn measure_structs_remove_random( c:&mut Criterion ) {
     let mut group = c.benchmark_group( "Random deletion from the structs" );
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
@@ -0,0 +1,112 @@
+use std::{ hint::black_box, path::Path };
+use criterion::{ measurement::WallTime, BatchSize, BenchmarkGroup, Criterion };
+
+use praca_magisterska::{
+    chunks_generators::{ biomes::GeneratorOfBiomes, test_11_height_map::GeneratorOfTest11HeightMap },
+    structure_tests::{
+        access_trace::{ AccessTrace, ReadPattern },
+        octree::Octree, voxel_hasher::VoxelHashMap, voxel_list::VoxelList, voxel_map::VoxelMap, write_trace::WriteTrace
+    },
+    world::world_holder::{ Voxel, WorldHolding }
+};
+
+const CHUNK_SIZE:u8 = 16;
+const CHUNKS:(u32, u32, u32) = (2, 2, 2);
+
+/// Traces are recorded on the first run and kept in `target/write_traces`, so every run replays the same writes
+fn get_trace( name:&str, record:impl FnOnce() -> WriteTrace ) -> WriteTrace {
+    let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( format!( "target/write_traces/{name}.trace" ) );
+    WriteTrace::load_or_record( &path, record ).unwrap()
+}
+
+fn get_generator_traces() -> [(&'static str, WriteTrace); 2] {
+    [
+        ("height map", get_trace( "height_map", || WriteTrace::record( &GeneratorOfTest11HeightMap::new( 50 ), (0, -1, 0), CHUNKS, CHUNK_SIZE ) )),
+        ("biomes", get_trace( "biomes", || WriteTrace::record( &GeneratorOfBiomes::new_continental( 50 ), (0, -1, 0), CHUNKS, CHUNK_SIZE ) )),
+    ]
+}
+
+#[allow(dead_code)]
+pub fn measure_structs_replay_generator( c:&mut Criterion ) {
+    for (name, trace) in &get_generator_traces() {
+        let size = trace.get_size();
+        let mut group = c.benchmark_group( format!( "Replay of the {name} generator writes ({} writes)", trace.get_writes().len() ) );
+
+        group.bench_function( format!( "VoxelMap (size {size:?})" ), |b| b.iter_batched(
+            || VoxelMap::<Voxel>::from_max_sizes( size.0, size.1, size.2 ),
+            |mut wh| {
+                trace.replay( &mut wh );
+                black_box( wh )
+            },
+            BatchSize::LargeInput,
+        ) );
+
+        group.bench_function( format!( "Octree (size {size:?})" ), |b| b.iter_batched(
+            || Octree::<Voxel>::from_max_size( size.0.max( size.1 ).max( size.2 ) ),
+            |mut wh| {
+                trace.replay( &mut wh );
+                black_box( wh )
+            },
+            BatchSize::LargeInput,
+        ) );
+
+        group.bench_function( "VoxelList", |b| b.iter_batched(
+            VoxelList::<Voxel>::new,
+            |mut wh| {
+                trace.replay( &mut wh );
+                black_box( wh )
+            },
+            BatchSize::LargeInput,
+        ) );
+
+        group.bench_function( "VoxelHashMap", |b| b.iter_batched(
+            VoxelHashMap::<Voxel>::new,
+            |mut wh| {
+                trace.replay( &mut wh );
+                black_box( wh )
+            },
+            BatchSize::LargeInput,
+        ) );
+    }
+}
+
+fn bench_reads<T:WorldHolding>( group:&mut BenchmarkGroup<WallTime>, name:&str, read_trace:&AccessTrace, create:impl Fn() -> T ) {
+    group.bench_function( name, |b| b.iter_batched(
+        || {
+            let mut wh = create();
+            read_trace.prepare( &mut wh );
+            wh
+        },
+        |mut wh| {
+            black_box( read_trace.replay( &mut wh ) );
+            wh
+        },
+        BatchSize::LargeInput,
+    ) );
+}
+
+/// Reads of the engine recorded on the first chunk of the generated terrain, replayed on every structure.
+/// Like the write traces, they are kept in `target/access_traces`
+#[allow(dead_code)]
+pub fn measure_structs_replay_reads( c:&mut Criterion ) {
+    for (name, write_trace) in &get_generator_traces() {
+        for pattern in ReadPattern::ALL {
+            let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( format!( "target/access_traces/{}_{}.trace", name.replace( ' ', "_" ), pattern.get_name() ) );
+            let read_trace = AccessTrace::load_or_record( &path, || {
+                let size = write_trace.get_size();
+                let mut octree = Octree::<Voxel>::from_max_size( size.0.max( size.1 ).max( size.2 ) );
+
+                write_trace.replay( &mut octree );
+                AccessTrace::record( &octree, size, &[ pattern ] )
+            } ).unwrap();
+
+            let size = read_trace.get_size();
+            let mut group = c.benchmark_group( format!( "Replay of the {} reads on the {name} terrain ({} calls)", pattern.get_name(), read_trace.get_calls().len() ) );
+
+            bench_reads( &mut group, &format!( "VoxelMap (size {size:?})" ), &read_trace, || VoxelMap::<Voxel>::from_max_sizes( size.0, size.1, size.2 ) );
+            bench_reads( &mut group, &format!( "Octree (size {size:?})" ), &read_trace, || Octree::<Voxel>::from_max_size( size.0.max( size.1 ).max( size.2 ) ) );
+            bench_reads( &mut group, "VoxelList", &read_trace, VoxelList::<Voxel>::new );
+            bench_reads( &mut group, "VoxelHashMap", &read_trace, VoxelHashMap::<Voxel>::new );
+        }
+    }
+}
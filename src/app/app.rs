@@ -12,8 +12,8 @@ use winit::{
 };
 use crate::{
     app::{
-        camera::{ Camera, FrustumVertex },
-        control_manager::ControlManager,
+        camera::{ Camera, FrustumVertex, RaycastHit },
+        control_manager::{ ControlManager, VoxelAction },
         settings::AppSettings,
         window_manager::WindowManager,
     }, flags::{FLAG_PROFILING_SHOW_FPS, SIMULATED_TEST_WORLD_ID}, rendering::{
@@ -33,6 +33,7 @@ pub struct App {
     pub camera: Camera,
     pub renderer: Renderer,
     pub world_renderer: WorldRenderer,
+    pub transparent_world_renderer: WorldRenderer,
     pub world: World,
     pub camera_chunk_loader: ChunkLoaderhandle,
     pub settings: AppSettings,
@@ -44,7 +45,9 @@ pub struct App {
     pub frame_count: u64,
 
     frustum_model: Model<FrustumVertex>,
-    world_border_model: Option<Model<SimpleVertex>>
+    world_border_model: Option<Model<SimpleVertex>>,
+    selection_model: Model<SimpleVertex>,
+    selected_voxel: Option<RaycastHit>,
 }
 
 impl App {
@@ -53,7 +56,7 @@ impl App {
         let half_chunk_size = chunk_size / 2.0;
 
         let window_manager = WindowManager::new()?;
-        let control_manager = match SIMULATED_TEST_WORLD_ID {
+        let mut control_manager = match SIMULATED_TEST_WORLD_ID {
             1..=9 => ControlManager::new( point3( -24.0, 70.0, -165.0 ), point3( 64.0, 60.0, 64.0 ) ),
             10..=11 => ControlManager::new( point3( half_chunk_size, 45.0, half_chunk_size ), point3( 0.0, 30.0, 0.0 ) ),
             12 => ControlManager::new( point3( half_chunk_size, 45.0, -half_chunk_size ), point3( 100.0, 40.0, -100.0 ) ),
@@ -65,8 +68,11 @@ impl App {
         let renderer = Renderer::create( &window_manager.window )?;
         let world_renderer = WorldRenderer::new( &renderer );
         let settings = AppSettings::new();
+        let transparent_world_renderer = WorldRenderer::new( &renderer ).with_opacity( settings.transparent_voxels_opacity );
         let ( world, camera_chunk_loader ) = generate_world_as_world( control_manager.position );
 
+        control_manager.palette_size = world.get_palette().len();
+
         let model = unsafe {
             let mut model = Model::<FrustumVertex>::new( &renderer, VOXEL_VERTICES.map( |v| v.into() ).to_vec(), VOXEL_EDGES_INDICES.to_vec() ).unwrap();
 
@@ -109,12 +115,25 @@ impl App {
             None
         };
 
+        let selection_model = unsafe {
+            let mut selection_model = Model::<SimpleVertex>::new( &renderer, VOXEL_CORNERS.to_vec(), VOXEL_EDGES_INDICES.to_vec() ).unwrap();
+
+            selection_model.update_instance_buffer( &renderer, vec![
+                ModelInstance {
+                    instance_transform: Matrix4::identity()
+                }
+            ] ).unwrap();
+
+            selection_model
+        };
+
         Ok( App {
             window_manager,
             control_manager,
             camera,
             renderer,
             world_renderer,
+            transparent_world_renderer,
             world,
             camera_chunk_loader,
             settings,
@@ -127,6 +146,8 @@ impl App {
 
             frustum_model: model,
             world_border_model: border_model,
+            selection_model,
+            selected_voxel: None,
         } )
     }
 
@@ -160,12 +181,52 @@ impl App {
         self.control_manager.update( &self.settings, time_delta.as_secs_f32() );
         self.camera.update_view( self.control_manager.position, self.control_manager.rotation, self.control_manager.freezed );
 
-        self.world_renderer.update_instances_buffer(
-            &self.renderer,
-            if self.control_manager.freezed { self.world.debug_meshes.clone() } else { self.world.get_renderables( &self.camera ) }
-        );
+        let renderables = if self.control_manager.freezed { self.world.debug_meshes.clone() } else { self.world.get_renderables( &self.camera ) };
+
+        self.world_renderer.update_instances_buffer( &self.renderer, renderables.opaque );
+        self.transparent_world_renderer.update_instances_buffer( &self.renderer, renderables.transparent );
 
         unsafe { self.frustum_model.update_vertex_buffer::<FrustumVertex>( &self.renderer, self.camera.get_frustum_corners().into() ).unwrap() };
+
+        self.update_voxel_selection();
+    }
+
+    fn update_voxel_selection( &mut self ) {
+        let world = &self.world;
+        self.selected_voxel = self.camera.raycast( self.settings.interaction_distance, |position| {
+            world.get_voxel( position ).is_some_and( |voxel| voxel.get_material().is_solid )
+        } );
+
+        if let Some( action ) = self.control_manager.voxel_action.take() {
+            if let Some( hit ) = self.selected_voxel {
+                match action {
+                    VoxelAction::Remove => {
+                        self.world.set_voxel( hit.voxel, None );
+                    }
+
+                    VoxelAction::Place if !self.camera.overlaps_voxel( hit.adjacent ) => {
+                        let voxel = self.world.get_palette().get( self.control_manager.palette_index ).cloned();
+                        self.world.set_voxel( hit.adjacent, voxel );
+                    }
+
+                    VoxelAction::Place => {}
+                }
+            }
+        }
+
+        if let Some( hit ) = self.selected_voxel {
+            let scale = 1.02;
+            let vertices = VOXEL_CORNERS.map( |v| SimpleVertex {
+                pos: vec3(
+                    v.pos.x * scale + hit.voxel.0 as f32,
+                    v.pos.y * scale + hit.voxel.1 as f32,
+                    v.pos.z * scale + hit.voxel.2 as f32,
+                ),
+                color: vec3( 1.0, 1.0, 1.0 ),
+            } );
+
+            unsafe { self.selection_model.update_vertex_buffer::<SimpleVertex>( &self.renderer, vertices.to_vec() ).unwrap() };
+        }
     }
 
     pub fn run_loop( &mut self ) {
@@ -193,6 +254,12 @@ impl App {
                             models.push( model );
                         }
 
+                        if self.selected_voxel.is_some() {
+                            models.push( &self.selection_model );
+                        }
+
+                        models.push( &self.transparent_world_renderer );
+
                         let _ = self.renderer.render( &mut self.window_manager, &self.camera, models );
                     },
 
@@ -201,6 +268,8 @@ impl App {
                     WindowEvent::KeyboardInput { event, .. } => {
                         match event.physical_key {
                             PhysicalKey::Code( KeyCode::Escape ) => App::destroy( elwt, self ),
+                            PhysicalKey::Code( KeyCode::KeyZ ) if self.control_manager.is_shortcut( &event ) => { self.world.undo(); },
+                            PhysicalKey::Code( KeyCode::KeyY ) if self.control_manager.is_shortcut( &event ) => { self.world.redo(); },
                             PhysicalKey::Code( KeyCode::KeyR ) => {
                                 self.frame_times.clear();
                                 self.control_manager.handle_keyboard_event( &self.settings, event );
@@ -209,6 +278,10 @@ impl App {
                         }
                     }
 
+                    WindowEvent::ModifiersChanged( modifiers ) => self.control_manager.modifiers = modifiers.state(),
+                    WindowEvent::MouseInput { state, button, .. } => self.control_manager.handle_mouse_input( state, button ),
+                    WindowEvent::MouseWheel { delta, .. } => self.control_manager.handle_mouse_wheel( delta ),
+
                     _ => self.window_manager.handle_window_event( event ),
                 }
 
@@ -225,7 +298,9 @@ impl App {
         unsafe {
             app.renderer.device_wait_idle();
             app.world_renderer.model.destroy( &app.renderer.device );
+            app.transparent_world_renderer.model.destroy( &app.renderer.device );
             app.frustum_model.destroy( &app.renderer.device );
+            app.selection_model.destroy( &app.renderer.device );
             if let Some( model ) = app.world_border_model.take() {
                 model.destroy( &app.renderer.device );
             }
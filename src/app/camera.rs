@@ -1,6 +1,6 @@
 use cgmath::{ vec3, vec4, Deg, InnerSpace, Matrix, SquareMatrix, Zero };
 
-use crate::{ rendering::vertex::SimpleVertex, world::world::Position };
+use crate::{ rendering::vertex::SimpleVertex, world::world::{ Position, VoxelPosition } };
 
 type Vec2 = cgmath::Vector2<f32>;
 type Vec3 = cgmath::Vector3<f32>;
@@ -17,8 +17,16 @@ static CORRECTION:Mat4 = Mat4::new(
     0.0,  0.0, 0.5, 1.0,
 );
 
+#[derive(Clone, Copy, Debug)]
+pub struct RaycastHit {
+    pub voxel: VoxelPosition,
+    pub adjacent: VoxelPosition,
+}
+
 #[allow(dead_code)]
 pub struct Camera {
+    pub position: Point,
+    pub direction: Vec3,
     pub view_matrix: Mat4,
     pub proj_matrix: Mat4,
     pub frustum: Frustum,
@@ -47,6 +55,8 @@ impl Camera {
         );
 
         Self {
+            position,
+            direction: Self::get_direction( rotation ),
             view_matrix,
             proj_matrix,
             frustum: Frustum::from_view_proj( view_matrix, proj_matrix ),
@@ -62,6 +72,8 @@ impl Camera {
     }
 
     pub fn update_view( &mut self, position:Point, rotation:Vec2, freezed:bool ) {
+        self.position = position;
+        self.direction = Self::get_direction( rotation );
         self.view_matrix = Self::get_view_mat( position, rotation );
 
         if freezed {
@@ -80,17 +92,71 @@ impl Camera {
     }
 
     fn get_view_mat( position:Point, rotation:Vec2 ) -> Mat4 {
-        Mat4::look_at_rh(
-            position,
-            position + vec3(
-                rotation.y.cos() * rotation.x.cos(),
-                rotation.x.sin(),
-                rotation.y.sin() * rotation.x.cos(),
-            ),
-            Vec3::unit_y(),
+        Mat4::look_at_rh( position, position + Self::get_direction( rotation ), Vec3::unit_y() )
+    }
+
+    fn get_direction( rotation:Vec2 ) -> Vec3 {
+        vec3(
+            rotation.y.cos() * rotation.x.cos(),
+            rotation.x.sin(),
+            rotation.y.sin() * rotation.x.cos(),
         )
     }
 
+    /// The voxel would clip the near plane, so it is inside the camera
+    pub fn overlaps_voxel( &self, voxel:VoxelPosition ) -> bool {
+        let reach = 0.5 + self.near;
+
+        (self.position.x - voxel.0 as f32).abs() < reach
+            && (self.position.y - voxel.1 as f32).abs() < reach
+            && (self.position.z - voxel.2 as f32).abs() < reach
+    }
+
+    // Voxels are centered on integer coordinates, so the grid is shifted by a half of voxel
+    pub fn raycast( &self, max_distance:f32, is_solid:impl Fn(VoxelPosition) -> bool ) -> Option<RaycastHit> {
+        let origin = self.position + vec3( 0.5, 0.5, 0.5 );
+        let direction = self.direction.normalize();
+        let mut voxel = (origin.x.floor() as i64, origin.y.floor() as i64, origin.z.floor() as i64);
+
+        let axis_setup = |origin:f32, cell:i64, direction:f32| -> (i64, f32, f32) {
+            if direction > 0.0 {
+                (1, (cell as f32 + 1.0 - origin) / direction, 1.0 / direction)
+            } else if direction < 0.0 {
+                (-1, (origin - cell as f32) / -direction, -1.0 / direction)
+            } else {
+                (0, f32::INFINITY, f32::INFINITY)
+            }
+        };
+
+        let (step_x, mut next_x, delta_x) = axis_setup( origin.x, voxel.0, direction.x );
+        let (step_y, mut next_y, delta_y) = axis_setup( origin.y, voxel.1, direction.y );
+        let (step_z, mut next_z, delta_z) = axis_setup( origin.z, voxel.2, direction.z );
+
+        loop {
+            let adjacent = voxel;
+            let distance = next_x.min( next_y ).min( next_z );
+
+            if distance > max_distance {
+                return None
+            }
+
+            if next_x <= next_y && next_x <= next_z {
+                voxel.0 += step_x;
+                next_x += delta_x;
+            } else if next_y <= next_z {
+                voxel.1 += step_y;
+                next_y += delta_y;
+            } else {
+                voxel.2 += step_z;
+                next_z += delta_z;
+            }
+
+            if is_solid( voxel ) {
+                return Some( RaycastHit { voxel, adjacent } )
+            }
+        }
+    }
+
     pub fn get_frustum_corners( &self ) -> [FrustumVertex; 8] {
         // let tan_half_fov = (self.fov.0 * 0.5).tan();
         // let aspect = self.width as f32 / self.height as f32;
@@ -237,3 +303,45 @@ impl Plane {
         self.normal.dot( pos ) + self.d
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{ FRAC_PI_2, PI };
+
+    use cgmath::{ point3, vec2 };
+
+    use crate::world::world::World;
+
+    use super::*;
+
+    #[test]
+    fn test_raycast_hits_voxels_at_negative_coordinates() {
+        let camera = Camera::new( point3( -0.2, 0.0, -64.0 ), vec2( 0.0, PI ), 800, 600 );
+        let hit = camera.raycast( 8.0, |position| position == (-3, 0, -64) ).unwrap();
+
+        assert_eq!( hit.voxel, (-3, 0, -64) );
+        assert_eq!( hit.adjacent, (-2, 0, -64) );
+        assert!( camera.raycast( 2.0, |position| position == (-3, 0, -64) ).is_none() );
+    }
+
+    #[test]
+    fn test_raycast_crosses_chunk_borders() {
+        let camera = Camera::new( point3( 10.0, 0.3, -1.0 ), vec2( -FRAC_PI_2, 0.0 ), 800, 600 );
+        let hit = camera.raycast( 100.0, |position| position.1 == -65 ).unwrap();
+
+        assert_eq!( hit.voxel, (10, -65, -1) );
+        assert_eq!( hit.adjacent, (10, -64, -1) );
+        assert_eq!( World::split_voxel_position( hit.voxel ), ((0, -2, -1), (10, 63, 63)) );
+        assert_eq!( World::split_voxel_position( hit.adjacent ), ((0, -1, -1), (10, 0, 63)) );
+    }
+
+    #[test]
+    fn test_voxels_inside_camera_are_detected() {
+        let camera = Camera::new( point3( 0.55, 0.0, 0.0 ), vec2( 0.0, 0.0 ), 800, 600 );
+
+        assert!( camera.overlaps_voxel( (0, 0, 0) ) );
+        assert!( camera.overlaps_voxel( (1, 0, 0) ) );
+        assert!( !camera.overlaps_voxel( (2, 0, 0) ) );
+        assert!( !camera.overlaps_voxel( (1, 1, 0) ) );
+    }
+}
@@ -2,10 +2,19 @@
 use std::time::{Duration, Instant};
 
 use cgmath::{ point2, vec2, InnerSpace, Point2, Point3, Vector3 };
-use winit::{event::{ElementState, DeviceEvent::{ self, MouseMotion }, KeyEvent}, keyboard::{KeyCode, PhysicalKey}};
+use winit::{event::{ElementState, DeviceEvent::{ self, MouseMotion }, KeyEvent, MouseButton, MouseScrollDelta}, keyboard::{KeyCode, ModifiersState, PhysicalKey}};
 
 use crate::app::settings::AppSettings;
 
+/// Left button released after moving the mouse less than that rotates the camera only, it doesn't remove the voxel
+const CLICK_MAX_DRAG:f32 = 4.0;
+
+#[derive(Clone, Copy, Debug)]
+pub enum VoxelAction {
+    Remove,
+    Place,
+}
+
 #[derive(Clone, Debug)]
 pub struct ControlManager {
     pub position: Point3<f32>,
@@ -20,7 +29,14 @@ pub struct ControlManager {
     pub mouse_position: Point2<f32>,
     pub mouse_last_used_position: Point2<f32>,
     pub lmb_pressed: bool,
+    /// Mouse movement since the left button was pressed
+    pub lmb_drag: f32,
     pub freezed: bool,
+    pub voxel_action: Option<VoxelAction>,
+    pub palette_index: usize,
+    pub palette_size: usize,
+    /// Modifier keys held down, undo and redo need Ctrl
+    pub modifiers: ModifiersState,
 }
 
 impl ControlManager {
@@ -38,7 +54,12 @@ impl ControlManager {
             mouse_position: point2( 0.0, 0.0 ),
             mouse_last_used_position: point2( 0.0, 0.0 ),
             lmb_pressed: false,
+            lmb_drag: 0.0,
             freezed: false,
+            voxel_action: None,
+            palette_index: 0,
+            palette_size: 0,
+            modifiers: ModifiersState::empty(),
         };
 
         instance.update_position( position, target );
@@ -57,6 +78,7 @@ impl ControlManager {
 
     pub fn update_rotation( &mut self, settings:&AppSettings, delta_time:f32 ) {
         if self.lmb_pressed {
+            self.lmb_drag += (self.mouse_position - self.mouse_last_used_position).magnitude();
             self.rotation.x += (self.mouse_position.y - self.mouse_last_used_position.y) * -settings.rotation_sensitivity * delta_time;
             self.rotation.y += (self.mouse_position.x - self.mouse_last_used_position.x) *  settings.rotation_sensitivity * delta_time;
         }
@@ -84,6 +106,11 @@ impl ControlManager {
         self.position.y += (self.velocity_up - self.velocity_down) * settings.movement_speed * delta_time;
     }
 
+    /// Key pressed with Ctrl, repeats of a held key are ignored
+    pub fn is_shortcut( &self, event:&KeyEvent ) -> bool {
+        event.state.is_pressed() && !event.repeat && self.modifiers.control_key()
+    }
+
     pub fn handle_keyboard_event( &mut self, settings:&AppSettings, event:KeyEvent ) {
         let pressed = event.state == ElementState::Pressed;
 
@@ -96,6 +123,24 @@ impl ControlManager {
             PhysicalKey::Code( KeyCode::ArrowDown   ) | PhysicalKey::Code( KeyCode::KeyS ) => self.velocity_backward = if pressed { settings.movement_speed } else { 0.0 },
             PhysicalKey::Code( KeyCode::ControlLeft ) => self.sprint_init = if pressed { Some( Instant::now() ) } else { None },
 
+            PhysicalKey::Code( code @ (KeyCode::Digit1 | KeyCode::Digit2 | KeyCode::Digit3 | KeyCode::Digit4 | KeyCode::Digit5 | KeyCode::Digit6 | KeyCode::Digit7 | KeyCode::Digit8 | KeyCode::Digit9) ) => {
+                let index = match code {
+                    KeyCode::Digit1 => 0,
+                    KeyCode::Digit2 => 1,
+                    KeyCode::Digit3 => 2,
+                    KeyCode::Digit4 => 3,
+                    KeyCode::Digit5 => 4,
+                    KeyCode::Digit6 => 5,
+                    KeyCode::Digit7 => 6,
+                    KeyCode::Digit8 => 7,
+                    _ => 8,
+                };
+
+                if pressed && index < self.palette_size {
+                    self.palette_index = index;
+                }
+            }
+
             PhysicalKey::Code( KeyCode::KeyF ) => {
                 if pressed {
                     self.freezed = !self.freezed;
@@ -107,10 +152,52 @@ impl ControlManager {
         }
     }
 
+    pub fn handle_mouse_input( &mut self, state:ElementState, button:MouseButton ) {
+        let pressed = state == ElementState::Pressed;
+
+        match button {
+            MouseButton::Left => {
+                if pressed {
+                    self.lmb_drag = 0.0;
+                } else if self.lmb_pressed && self.lmb_drag < CLICK_MAX_DRAG {
+                    self.voxel_action = Some( VoxelAction::Remove );
+                }
+
+                self.lmb_pressed = pressed;
+            }
+
+            MouseButton::Right if pressed => self.voxel_action = Some( VoxelAction::Place ),
+
+            _ => {}
+        }
+    }
+
+    pub fn handle_mouse_wheel( &mut self, delta:MouseScrollDelta ) {
+        if self.palette_size == 0 {
+            return
+        }
+
+        let scroll = match delta {
+            MouseScrollDelta::LineDelta( _, y ) => y,
+            MouseScrollDelta::PixelDelta( position ) => position.y as f32,
+        };
+
+        if scroll > 0.0 {
+            self.palette_index = (self.palette_index + 1) % self.palette_size;
+        } else if scroll < 0.0 {
+            self.palette_index = (self.palette_index + self.palette_size - 1) % self.palette_size;
+        }
+    }
+
     pub fn handle_device_event( &mut self, settings:&AppSettings, event:DeviceEvent ) {
         match event {
             MouseMotion { delta } => {
                 let (dx, dy) = delta;
+
+                if self.lmb_pressed {
+                    self.lmb_drag += (dx * dx + dy * dy).sqrt() as f32;
+                }
+
                 self.rotation.y += dx as f32 * settings.rotation_sensitivity;
                 self.rotation.x -= dy as f32 * settings.rotation_sensitivity;
                 self.rotation.x = self.rotation.x.clamp(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
@@ -4,6 +4,8 @@ pub struct AppSettings {
   pub movement_speed: f32,
   pub sprint_speed_x1: f32,
   pub sprint_speed_x2: f32,
+  pub interaction_distance: f32,
+  pub transparent_voxels_opacity: f32,
 }
 
 impl AppSettings {
@@ -13,6 +15,8 @@ impl AppSettings {
       movement_speed: 3.0,
       sprint_speed_x1: 7.0,
       sprint_speed_x2: 12.0,
+      interaction_distance: 8.0,
+      transparent_voxels_opacity: 0.6,
     }
   }
 }
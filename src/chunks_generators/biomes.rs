@@ -0,0 +1,298 @@
+use std::sync::Arc;
+
+use crate::{
+    chunks_generators::{
+        definition::GeneratorOfDefinition,
+        peaks_and_valleys::GeneratorOfPeaksAndValleys,
+        test_11_height_map::GeneratorOfTest11HeightMap,
+        utilities::create_voxel
+    },
+    noise::{
+        noise_graph::{ BoxedNoiseNode, NoiseNode, NoiseNodeExt, Simplex2d },
+        simplex_noise::{ NoiseStream, SimplexNoise }
+    },
+    world::{
+        world_generator::WorldGenerative,
+        world_holder::{ Color, Material, VoxelDataset, WorldHolding }
+    }
+};
+
+/// Biomes weighted below this fraction of the dominant one are not sampled at all
+const BLEND_EPSILON: f64 = 0.001;
+
+/// Terrain height of a column in world coordinates. Implemented by height map generators so they can be used as biomes
+pub trait HeightSource: Send + Sync {
+    fn get_height( &self, x:i64, z:i64 ) -> f64;
+}
+
+/// Lets one source, eg. eroded heights, be shared by the terrain and the water over it
+impl<T: HeightSource + ?Sized> HeightSource for Arc<T> {
+    fn get_height( &self, x:i64, z:i64 ) -> f64 {
+        (**self).get_height( x, z )
+    }
+}
+
+impl HeightSource for BoxedNoiseNode {
+    fn get_height( &self, x:i64, z:i64 ) -> f64 {
+        self.get( x as f64, 0.0, z as f64 )
+    }
+}
+
+impl HeightSource for GeneratorOfDefinition {
+    fn get_height( &self, x:i64, z:i64 ) -> f64 {
+        GeneratorOfDefinition::get_height( self, x, z )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SurfaceLayer {
+    pub name: String,
+    pub color: Color,
+    pub depth: u32,
+}
+
+impl SurfaceLayer {
+    pub fn new( name:&str, color:(u8, u8, u8), depth:u32 ) -> Self {
+        Self { name:name.to_string(), color:color.into(), depth }
+    }
+}
+
+#[allow(dead_code)]
+pub struct Biome {
+    pub name: String,
+    /// Climate the biome fits best, both values are noise values (-1 - 1)
+    pub temperature: f64,
+    pub humidity: f64,
+    pub height: Box<dyn HeightSource>,
+    /// Layers from the top of a column down, everything below them is filled with the generator base layer
+    pub surface: Vec<SurfaceLayer>,
+}
+
+/// Height map terrain made of biomes picked by temperature and humidity maps.
+/// Heights of neighbouring biomes are blended by their climate distance, surface layers come from the dominant biome
+pub struct GeneratorOfBiomes {
+    biomes: Vec<Biome>,
+    base: SurfaceLayer,
+    temperature: SimplexNoise,
+    humidity: SimplexNoise,
+    climate_frequency: f64,
+    blend_sharpness: f64,
+}
+
+#[allow(dead_code)]
+impl GeneratorOfBiomes {
+    pub fn new( seed:u32, biomes:Vec<Biome>, base:SurfaceLayer ) -> Self {
+        assert!( !biomes.is_empty(), "Biome generator needs at least one biome" );
+
+        Self {
+            biomes,
+            base,
+            temperature: SimplexNoise::with_stream( seed, NoiseStream::Temperature ),
+            humidity: SimplexNoise::with_stream( seed, NoiseStream::Humidity ),
+            climate_frequency: 0.002,
+            blend_sharpness: 40.0,
+        }
+    }
+
+    /// Plains, hills, desert, valleys and tundra, the last two reuse existing height map generators
+    pub fn new_continental( seed:u32 ) -> Self {
+        let plains = Simplex2d::new( seed, 0.01 ).scale_bias( 6.0, 12.0 ).boxed();
+        let hills = Simplex2d::new( seed.wrapping_add( 1 ), 0.008 ).fbm( 3 ).scale_bias( 30.0, 35.0 ).boxed();
+        let desert = Simplex2d::new( seed.wrapping_add( 2 ), 0.03 ).ridged( 2 ).scale_bias( 3.0, 14.0 ).boxed();
+
+        let biomes = vec![
+            Biome {
+                name: String::from( "plains" ),
+                temperature: 0.0,
+                humidity: 0.2,
+                height: Box::new( plains ),
+                surface: vec![ SurfaceLayer::new( "grass", (70, 140, 50), 1 ), SurfaceLayer::new( "dirt", (120, 80, 40), 3 ) ],
+            },
+            Biome {
+                name: String::from( "forest_hills" ),
+                temperature: -0.2,
+                humidity: 0.6,
+                height: Box::new( hills ),
+                surface: vec![ SurfaceLayer::new( "dark_grass", (40, 110, 35), 1 ), SurfaceLayer::new( "dirt", (120, 80, 40), 4 ) ],
+            },
+            Biome {
+                name: String::from( "desert" ),
+                temperature: 0.6,
+                humidity: -0.5,
+                height: Box::new( desert ),
+                surface: vec![ SurfaceLayer::new( "sand", (215, 195, 125), 4 ), SurfaceLayer::new( "sandstone", (190, 160, 100), 4 ) ],
+            },
+            Biome {
+                name: String::from( "valleys" ),
+                temperature: 0.3,
+                humidity: 0.5,
+                height: Box::new( GeneratorOfPeaksAndValleys::new( seed.wrapping_add( 3 ) ) ),
+                surface: vec![ SurfaceLayer::new( "meadow", (110, 170, 60), 1 ), SurfaceLayer::new( "dirt", (120, 80, 40), 2 ) ],
+            },
+            Biome {
+                name: String::from( "tundra" ),
+                temperature: -0.6,
+                humidity: -0.3,
+                height: Box::new( GeneratorOfTest11HeightMap::new( seed.wrapping_add( 4 ) ) ),
+                surface: vec![ SurfaceLayer::new( "snow", (240, 240, 240), 2 ), SurfaceLayer::new( "frozen_dirt", (110, 100, 90), 2 ) ],
+            },
+        ];
+
+        Self::new( seed, biomes, SurfaceLayer::new( "stone", (110, 110, 110), 0 ) )
+    }
+
+    /// Size of climate areas, lower values give wider biomes
+    pub fn with_climate_frequency( mut self, climate_frequency:f64 ) -> Self {
+        self.climate_frequency = climate_frequency;
+        self
+    }
+
+    /// How quickly weight of a biome drops with its climate distance, lower values give wider transitions
+    pub fn with_blend_sharpness( mut self, blend_sharpness:f64 ) -> Self {
+        self.blend_sharpness = blend_sharpness;
+        self
+    }
+
+    pub fn get_biomes( &self ) -> &[Biome] {
+        &self.biomes
+    }
+
+    /// Temperature and humidity (-1 - 1) of a column
+    pub fn get_climate( &self, x:i64, z:i64 ) -> (f64, f64) {
+        let (x, z) = (x as f64 * self.climate_frequency, z as f64 * self.climate_frequency);
+        (self.temperature.noise2d( x, z ), self.humidity.noise2d( x, z ))
+    }
+
+    /// Blended height and index of the dominant biome of a column
+    pub fn get_column( &self, x:i64, z:i64 ) -> (f64, usize) {
+        let (temperature, humidity) = self.get_climate( x, z );
+        let weights = self.biomes.iter().map( |biome| {
+            let distance_squared = (biome.temperature - temperature).powi( 2 ) + (biome.humidity - humidity).powi( 2 );
+            (-distance_squared * self.blend_sharpness).exp()
+        } ).collect::<Vec<_>>();
+
+        let (dominant, max_weight) = weights.iter().copied().enumerate()
+            .fold( (0, f64::MIN), |max, (index, weight)| if weight > max.1 { (index, weight) } else { max } );
+
+        let mut height = 0.0;
+        let mut weights_sum = 0.0;
+
+        for (biome, weight) in self.biomes.iter().zip( weights ) {
+            if weight < max_weight * BLEND_EPSILON { continue }
+
+            height += biome.height.get_height( x, z ) * weight;
+            weights_sum += weight;
+        }
+
+        (height / weights_sum, dominant)
+    }
+}
+
+impl HeightSource for GeneratorOfBiomes {
+    fn get_height( &self, x:i64, z:i64 ) -> f64 {
+        self.get_column( x, z ).0
+    }
+}
+
+impl WorldGenerative for GeneratorOfBiomes {
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
+        let world_origin = (origin.0 * size as i64, origin.1 * size as i64, origin.2 * size as i64);
+        let size_i64 = size as i64;
+
+        let mut create_layer_voxel = |layer:&SurfaceLayer| create_voxel(
+            dataset,
+            (layer.name.clone(), Material { _density:10, ..Default::default() }),
+            (layer.name.clone(), layer.color)
+        );
+
+        let base = create_layer_voxel( &self.base );
+        let surfaces = self.biomes.iter().map( |biome| {
+            biome.surface.iter().map( |layer| (create_layer_voxel( layer ), layer.depth as i64) ).collect::<Vec<_>>()
+        } ).collect::<Vec<_>>();
+
+        for x in 0..size_i64 {
+            for z in 0..size_i64 {
+                let (height, biome) = self.get_column( world_origin.0 + x, world_origin.2 + z );
+                let mut top = height.floor() as i64 - world_origin.1;
+
+                if top < 0 { continue }
+
+                // Layers are filled top down, each one starting below the previous
+                for (voxel, depth) in surfaces[ biome ].iter().map( |(voxel, depth)| (voxel, Some( *depth )) ).chain( [ (&base, None) ] ) {
+                    if top < 0 { break }
+
+                    let bottom = depth.map_or( 0, |depth| top - depth + 1 ).max( 0 );
+
+                    if bottom <= top && bottom < size_i64 {
+                        world_holder.fill_voxels(
+                            (x as u32, bottom as u32, z as u32),
+                            (x as u32, top.min( size_i64 - 1 ) as u32, z as u32),
+                            Some( Arc::clone( voxel ) )
+                        );
+                    }
+
+                    top = bottom - 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::noise::noise_graph::Constant;
+
+    use super::*;
+
+    fn create_flat_biome( name:&str, climate:(f64, f64), height:f64 ) -> Biome {
+        Biome {
+            name: name.to_string(),
+            temperature: climate.0,
+            humidity: climate.1,
+            height: Box::new( Constant( height ).boxed() ),
+            surface: vec![ SurfaceLayer::new( name, (10, 200, 10), 1 ), SurfaceLayer::new( "dirt", (120, 80, 40), 2 ) ],
+        }
+    }
+
+    #[test]
+    fn test_heights_are_blended_between_biomes() {
+        let generator = GeneratorOfBiomes::new(
+            7,
+            vec![ create_flat_biome( "low", (-0.3, 0.0), 10.0 ), create_flat_biome( "high", (0.3, 0.0), 30.0 ) ],
+            SurfaceLayer::new( "stone", (110, 110, 110), 0 )
+        ).with_blend_sharpness( 5.0 );
+
+        for i in 1..200 {
+            let (x, z) = (i * 37, i * -53);
+            let (height, dominant) = generator.get_column( x, z );
+            let (temperature, _) = generator.get_climate( x, z );
+
+            // Both biomes are equally far, either one can win
+            if temperature == 0.0 { continue }
+
+            assert!( (10.0..=30.0).contains( &height ), "height={height}" );
+            assert_eq!( dominant, if temperature < 0.0 { 0 } else { 1 } );
+            assert_eq!( height < 20.0, temperature < 0.0, "height={height} temperature={temperature}" );
+        }
+    }
+
+    #[test]
+    fn test_columns_get_surface_layers_of_dominant_biome() {
+        let generator = GeneratorOfBiomes::new(
+            3,
+            vec![ create_flat_biome( "meadow", (0.0, 0.0), 20.0 ) ],
+            SurfaceLayer::new( "stone", (110, 110, 110), 0 )
+        );
+
+        let mut dataset = VoxelDataset::new();
+        let chunk = generator.generate_chunk( &mut dataset, (0, 0, 0), 32 );
+        let color_at = |y| chunk.get_voxel( 4, y, 9 ).map( |voxel| voxel._common_data.color.green );
+
+        assert_eq!( color_at( 21 ), None );
+        assert_eq!( color_at( 20 ), Some( 200 ) );
+        assert_eq!( color_at( 19 ), Some( 80 ) );
+        assert_eq!( color_at( 18 ), Some( 80 ) );
+        assert_eq!( color_at( 17 ), Some( 110 ) );
+        assert_eq!( color_at( 0 ), Some( 110 ) );
+    }
+}
@@ -0,0 +1,280 @@
+use std::f64::consts::TAU;
+
+use rand::{ rngs::SmallRng, Rng, SeedableRng };
+
+use crate::{
+    noise::simplex_noise::{ NoiseStream, SimplexNoise },
+    world::{
+        world_generator::{ GenerationStage, StageNeighbours, WorldGenerative },
+        world_holder::{ VoxelDataset, WorldHolding }
+    }
+};
+
+/// Worms start in world cells of this size, every cell has its own random generator
+const CAVE_CELL_SIZE: i64 = 64;
+const WORM_STEP_LENGTH: f64 = 1.5;
+/// Height below `max_height` over which caverns fade out, so they don't end with flat ceilings
+const CAVERN_FADE_HEIGHT: i64 = 16;
+
+/// Carves tunnels (noise steered worms) and caverns (3D noise volumes) out of already generated chunks.
+/// Worms are simulated from their starting cell no matter which chunk is carved, so tunnels continue across chunk borders
+pub struct CaveCarver {
+    seed: u32,
+    steering: SimplexNoise,
+    caverns: SimplexNoise,
+    /// World height above which worms don't start and caverns aren't carved
+    max_height: i64,
+    max_worms_per_cell: u32,
+    worm_steps: u32,
+    worm_radius: (f64, f64),
+    cavern_frequency: f64,
+    /// Caverns are carved where noise is above the threshold, `1.0` disables them
+    cavern_threshold: f64,
+}
+
+#[allow(dead_code)]
+impl CaveCarver {
+    pub fn new( seed:u32 ) -> Self {
+        Self {
+            seed,
+            steering: SimplexNoise::with_stream( seed, NoiseStream::CaveSteering ),
+            caverns: SimplexNoise::with_stream( seed, NoiseStream::Caverns ),
+            max_height: 0,
+            max_worms_per_cell: 2,
+            worm_steps: 120,
+            worm_radius: (1.5, 4.0),
+            cavern_frequency: 0.02,
+            cavern_threshold: 0.7,
+        }
+    }
+
+    pub fn with_max_height( mut self, max_height:i64 ) -> Self {
+        self.max_height = max_height;
+        self
+    }
+
+    pub fn with_worms( mut self, max_worms_per_cell:u32, worm_steps:u32, worm_radius:(f64, f64) ) -> Self {
+        self.max_worms_per_cell = max_worms_per_cell;
+        self.worm_steps = worm_steps;
+        self.worm_radius = worm_radius;
+        self
+    }
+
+    pub fn with_caverns( mut self, cavern_frequency:f64, cavern_threshold:f64 ) -> Self {
+        self.cavern_frequency = cavern_frequency;
+        self.cavern_threshold = cavern_threshold;
+        self
+    }
+
+    pub fn carve( &self, world_holder:&mut dyn WorldHolding, world_origin:(i64, i64, i64), size:u32 ) {
+        self.carve_caverns( world_holder, world_origin, size );
+        self.carve_worms( world_holder, world_origin, size );
+    }
+
+    fn carve_caverns( &self, world_holder:&mut dyn WorldHolding, world_origin:(i64, i64, i64), size:u32 ) {
+        if self.cavern_threshold >= 1.0 || world_origin.1 > self.max_height { return }
+
+        let size_usize = size as usize;
+        let noise_grid = self.caverns.noise3d_grid(
+            (world_origin.0 as f64, world_origin.1 as f64, world_origin.2 as f64),
+            (size_usize, size_usize, size_usize),
+            self.cavern_frequency,
+        );
+
+        for x in 0..size {
+            for y in 0..size {
+                let world_y = world_origin.1 + y as i64;
+                if world_y > self.max_height { break }
+
+                let fade = (world_y - (self.max_height - CAVERN_FADE_HEIGHT)).max( 0 ) as f64 / CAVERN_FADE_HEIGHT as f64;
+                let threshold = self.cavern_threshold + (1.0 - self.cavern_threshold) * fade;
+
+                for z in 0..size {
+                    if noise_grid.get( x as usize, y as usize, z as usize ) > threshold {
+                        CaveCarver::remove_voxel( world_holder, x, y, z );
+                    }
+                }
+            }
+        }
+    }
+
+    fn carve_worms( &self, world_holder:&mut dyn WorldHolding, world_origin:(i64, i64, i64), size:u32 ) {
+        let reach = (self.worm_steps as f64 * WORM_STEP_LENGTH + self.worm_radius.1).ceil() as i64;
+        let cell_range = |origin:i64| (origin - reach).div_euclid( CAVE_CELL_SIZE )..=(origin + size as i64 + reach).div_euclid( CAVE_CELL_SIZE );
+
+        for cell_x in cell_range( world_origin.0 ) {
+            for cell_y in cell_range( world_origin.1 ) {
+                if cell_y * CAVE_CELL_SIZE > self.max_height { break }
+
+                for cell_z in cell_range( world_origin.2 ) {
+                    self.carve_cell_worms( world_holder, world_origin, size, (cell_x, cell_y, cell_z) );
+                }
+            }
+        }
+    }
+
+    fn carve_cell_worms( &self, world_holder:&mut dyn WorldHolding, world_origin:(i64, i64, i64), size:u32, cell:(i64, i64, i64) ) {
+        let mut rng = SmallRng::seed_from_u64( self.get_cell_seed( cell ) );
+        let worms_count = rng.random_range( 0..=self.max_worms_per_cell );
+        let chunk_end = (world_origin.0 + size as i64, world_origin.1 + size as i64, world_origin.2 + size as i64);
+
+        for _ in 0..worms_count {
+            let mut position = (
+                (cell.0 * CAVE_CELL_SIZE + rng.random_range( 0..CAVE_CELL_SIZE )) as f64,
+                (cell.1 * CAVE_CELL_SIZE + rng.random_range( 0..CAVE_CELL_SIZE )).min( self.max_height ) as f64,
+                (cell.2 * CAVE_CELL_SIZE + rng.random_range( 0..CAVE_CELL_SIZE )) as f64,
+            );
+
+            let mut yaw = rng.random::<f64>() * TAU;
+            let mut pitch = (rng.random::<f64>() - 0.5) * 0.5;
+            let noise_offset = rng.random::<f64>() * 1000.0;
+
+            for step in 0..self.worm_steps {
+                let progress = noise_offset + step as f64 * 0.04;
+
+                yaw += self.steering.noise2d( progress, 0.0 ) * 0.3;
+                pitch = (pitch * 0.9 + self.steering.noise2d( progress, 50.0 ) * 0.15).clamp( -0.8, 0.8 );
+
+                position.0 += yaw.cos() * pitch.cos() * WORM_STEP_LENGTH;
+                position.1 += pitch.sin() * WORM_STEP_LENGTH;
+                position.2 += yaw.sin() * pitch.cos() * WORM_STEP_LENGTH;
+
+                let radius_progress = (self.steering.noise2d( progress, 100.0 ) + 1.0) * 0.5;
+                let radius = self.worm_radius.0 + (self.worm_radius.1 - self.worm_radius.0) * radius_progress;
+
+                let touches_chunk = position.0 + radius >= world_origin.0 as f64 && position.0 - radius < chunk_end.0 as f64
+                    && position.1 + radius >= world_origin.1 as f64 && position.1 - radius < chunk_end.1 as f64
+                    && position.2 + radius >= world_origin.2 as f64 && position.2 - radius < chunk_end.2 as f64;
+
+                if touches_chunk {
+                    CaveCarver::carve_sphere( world_holder, world_origin, size, position, radius );
+                }
+            }
+        }
+    }
+
+    fn carve_sphere( world_holder:&mut dyn WorldHolding, world_origin:(i64, i64, i64), size:u32, center:(f64, f64, f64), radius:f64 ) {
+        let local_range = |center:f64, origin:i64| {
+            let from = ((center - radius).floor() as i64 - origin).max( 0 );
+            let to = ((center + radius).ceil() as i64 - origin).min( size as i64 - 1 );
+            from..=to
+        };
+
+        for x in local_range( center.0, world_origin.0 ) {
+            for y in local_range( center.1, world_origin.1 ) {
+                for z in local_range( center.2, world_origin.2 ) {
+                    let distance_squared = ((world_origin.0 + x) as f64 - center.0).powi( 2 )
+                        + ((world_origin.1 + y) as f64 - center.1).powi( 2 )
+                        + ((world_origin.2 + z) as f64 - center.2).powi( 2 );
+
+                    if distance_squared <= radius * radius {
+                        CaveCarver::remove_voxel( world_holder, x as u32, y as u32, z as u32 );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removing from an empty leaf would split and merge it back, so emptiness is checked first
+    fn remove_voxel( world_holder:&mut dyn WorldHolding, x:u32, y:u32, z:u32 ) {
+        if world_holder.get_voxel( x, y, z ).is_some() {
+            world_holder.set_voxel( x, y, z, None );
+        }
+    }
+
+    fn get_cell_seed( &self, cell:(i64, i64, i64) ) -> u64 {
+        (self.seed as u64)
+            ^ (cell.0 as u64).wrapping_mul( 0x9e37_79b9_7f4a_7c15 )
+            ^ (cell.1 as u64).wrapping_mul( 0xc2b2_ae3d_27d4_eb4f )
+            ^ (cell.2 as u64).wrapping_mul( 0x1656_67b1_9e37_79f9 )
+    }
+}
+
+/// Runs the cave carver over chunks of any surface generator
+pub struct GeneratorWithCaves {
+    generator: Box<dyn WorldGenerative>,
+    carver: CaveCarver,
+}
+
+#[allow(dead_code)]
+impl GeneratorWithCaves {
+    pub fn new( generator:Box<dyn WorldGenerative>, carver:CaveCarver ) -> Self {
+        Self { generator, carver }
+    }
+}
+
+impl WorldGenerative for GeneratorWithCaves {
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
+        self.generator.generate_chunk_into( dataset, world_holder, origin, size );
+    }
+
+    fn get_last_stage( &self ) -> GenerationStage {
+        self.generator.get_last_stage().max( GenerationStage::Carving )
+    }
+
+    /// Carving depends only on the world position, so it doesn't wait for neighbours
+    fn reads_neighbours( &self, stage:GenerationStage ) -> bool {
+        self.generator.reads_neighbours( stage )
+    }
+
+    fn generate_stage( &self, stage:GenerationStage, dataset:&mut VoxelDataset, chunk:&mut dyn WorldHolding, origin:(i64, i64, i64), neighbours:&StageNeighbours ) {
+        self.generator.generate_stage( stage, dataset, chunk, origin, neighbours );
+
+        if stage == GenerationStage::Carving {
+            let size = neighbours.get_size();
+            let world_origin = (origin.0 * size as i64, origin.1 * size as i64, origin.2 * size as i64);
+
+            self.carver.carve( chunk, world_origin, size );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ chunks_generators::test_6_full::GeneratorOfTest6Full, world::world_generator::generate_chunk_stages };
+
+    use super::*;
+
+    #[test]
+    fn test_carving_does_not_depend_on_chunk_borders() {
+        let generator = GeneratorWithCaves::new(
+            Box::new( GeneratorOfTest6Full::new( 0 ) ),
+            CaveCarver::new( 9 ).with_max_height( 1000 ).with_worms( 3, 120, (1.5, 4.0) )
+        );
+
+        let mut dataset = VoxelDataset::new();
+        let big_chunk = generate_chunk_stages( &generator, &mut dataset, (-1, -1, -1), 64 );
+        let mut carved_count = 0;
+
+        for (chunk_x, chunk_y, chunk_z) in [ (-2, -2, -2), (-1, -1, -1), (-2, -1, -2), (-1, -2, -1) ] {
+            let small_chunk = generate_chunk_stages( &generator, &mut dataset, (chunk_x, chunk_y, chunk_z), 32 );
+            let offset = (((chunk_x + 2) * 32) as u32, ((chunk_y + 2) * 32) as u32, ((chunk_z + 2) * 32) as u32);
+
+            for x in 0..32 {
+                for y in 0..32 {
+                    for z in 0..32 {
+                        let small = small_chunk.get( x, y, z ).is_some();
+                        let big = big_chunk.get( offset.0 + x, offset.1 + y, offset.2 + z ).is_some();
+
+                        assert_eq!( small, big, "chunk ({chunk_x}, {chunk_y}, {chunk_z}) voxel ({x}, {y}, {z})" );
+                        carved_count += !small as u32;
+                    }
+                }
+            }
+        }
+
+        assert!( carved_count > 0, "Caves have to carve something" );
+    }
+
+    #[test]
+    fn test_nothing_is_carved_far_above_max_height() {
+        let generator = GeneratorWithCaves::new(
+            Box::new( GeneratorOfTest6Full::new( 0 ) ),
+            CaveCarver::new( 9 ).with_max_height( -200 ).with_worms( 3, 60, (1.5, 4.0) ).with_caverns( 0.05, 0.0 )
+        );
+
+        let chunk = generate_chunk_stages( &generator, &mut VoxelDataset::new(), (0, 0, 0), 32 );
+
+        assert_eq!( chunk.get_voxels().len(), 32 * 32 * 32 );
+    }
+}
@@ -1,10 +1,9 @@
 use crate::{
     chunks_generators::utilities::create_voxel,
     noise::simplex_noise::SimplexNoise,
-    structure_tests::octree::Octree,
     world::{
         world_generator::WorldGenerative,
-        world_holder::{ Material, Voxel, VoxelDataset, WorldHolding }
+        world_holder::{ Material, VoxelDataset, WorldHolding }
     }
 };
 
@@ -46,11 +45,11 @@ impl GeneratorOfCube {
         }
     }
 
-    pub fn generate( &self, dataset:&mut VoxelDataset, world_holder:Octree<Voxel>, world_origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
+    pub fn generate( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, world_origin:(i64, i64, i64), size:u8 ) {
         self.generate_with_shift( dataset, world_holder, world_origin, (0, 0, 0), size )
     }
 
-    pub fn generate_with_shift( &self, dataset:&mut VoxelDataset, mut world_holder:Octree<Voxel>, world_origin:(i64, i64, i64), shift:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
+    pub fn generate_with_shift( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, world_origin:(i64, i64, i64), shift:(i64, i64, i64), size:u8 ) {
         let size = size as i64;
         let half_max_dim = self.max_dimension as f64 / 2.0;
 
@@ -103,7 +102,7 @@ impl GeneratorOfCube {
 
                         Some( create_voxel(
                             dataset,
-                            (String::from( "grass" ), Material { _density:10 }),
+                            (String::from( "grass" ), Material { _density:10, ..Default::default() }),
                             (format!( "grass-{}", gradient_value ), color.into() ),
                         ) )
                     };
@@ -120,8 +119,6 @@ impl GeneratorOfCube {
                 }
             }
         }
-
-        world_holder
     }
 
     #[allow(dead_code)]
@@ -147,15 +144,13 @@ impl GeneratorOfCube {
 }
 
 impl WorldGenerative for GeneratorOfCube {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
-        let world_holder = Octree::from_max_size( size as u32 );
-
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
         if origin.0 < 0 || origin.1 < 0 || origin.2 < 0 {
-            return world_holder
+            return
         }
 
         if origin.0 >= self.dimensions.0 || origin.1 >= self.dimensions.1 || origin.2 >= self.dimensions.2 {
-            return world_holder
+            return
         }
 
         let world_origin = (origin.0 * size as i64, origin.1 * size as i64, origin.2 * size as i64);
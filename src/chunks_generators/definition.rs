@@ -0,0 +1,235 @@
+use std::{ fs, path::Path, sync::Arc };
+
+use anyhow::{ bail, Context, Result };
+use serde::{ Deserialize, Serialize };
+
+use crate::{
+    chunks_generators::{ heightmap_cache::{ HeightmapCache, HeightmapCacheStats }, utilities::create_voxel },
+    noise::noise_graph::{ Add, BoxedNoiseNode, Constant, NoiseNode, NoiseNodeExt, Simplex2d },
+    structure_tests::quadtree::Quadtree,
+    world::{
+        world_generator::WorldGenerative,
+        world_holder::{ Material, Voxel, VoxelDataset, WorldHolding },
+        world_light::MAX_LIGHT_LEVEL
+    }
+};
+
+/// Declarative description of a height map terrain, loaded from RON or JSON file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorDefinition {
+    pub name: String,
+    pub seed: u32,
+    /// World height of the terrain before noise layers are added
+    #[serde(default)]
+    pub base_height: f64,
+    pub layers: Vec<NoiseLayerDefinition>,
+    /// Checked in order, the first band containing the surface height colours the column
+    pub color_bands: Vec<ColorBandDefinition>,
+    #[serde(default)]
+    pub water: Option<WaterDefinition>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NoiseLayerDefinition {
+    pub frequency: f64,
+    pub amplitude: f64,
+    /// Added to the raw noise (-1 - 1) before thresholding
+    #[serde(default)]
+    pub offset: f64,
+    /// Noise below the threshold is flattened, only the part above it is amplified
+    #[serde(default)]
+    pub threshold: Option<f64>,
+    #[serde(default)]
+    pub seed_offset: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ColorBandDefinition {
+    pub name: String,
+    /// Upper (inclusive) world height of the band, `None` means no limit
+    #[serde(default)]
+    pub max_height: Option<f64>,
+    pub color: (u8, u8, u8),
+    /// Colour used for every second height level
+    #[serde(default)]
+    pub stripe_color: Option<(u8, u8, u8)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WaterDefinition {
+    /// World height of the water surface
+    pub level: f64,
+    pub color: (u8, u8, u8),
+    #[serde(default = "WaterDefinition::default_opacity")]
+    pub opacity: u8,
+}
+
+impl WaterDefinition {
+    fn default_opacity() -> u8 {
+        1
+    }
+}
+
+impl GeneratorDefinition {
+    pub fn load( path:impl AsRef<Path> ) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string( path ).with_context( || format!( "Can't read generator definition {path:?}" ) )?;
+
+        match path.extension().and_then( |extension| extension.to_str() ) {
+            Some( "ron" ) => Self::from_ron( &content ),
+            Some( "json" ) => Self::from_json( &content ),
+            _ => bail!( "Unknown generator definition format of {path:?} (expected .ron or .json)" ),
+        }.with_context( || format!( "Invalid generator definition {path:?}" ) )
+    }
+
+    pub fn from_ron( content:&str ) -> Result<Self> {
+        Ok( ron::from_str( content )? )
+    }
+
+    pub fn from_json( content:&str ) -> Result<Self> {
+        Ok( serde_json::from_str( content )? )
+    }
+
+    pub fn compile( self ) -> Result<GeneratorOfDefinition> {
+        if self.color_bands.is_empty() {
+            bail!( "Generator definition \"{}\" needs at least one colour band", self.name )
+        }
+
+        if self.water.as_ref().is_some_and( |water| water.opacity >= MAX_LIGHT_LEVEL ) {
+            bail!( "Water opacity of \"{}\" has to be lower than {MAX_LIGHT_LEVEL}", self.name )
+        }
+
+        Ok( GeneratorOfDefinition { height:self.build_height_graph(), definition:self, heightmaps:HeightmapCache::default() } )
+    }
+
+    fn build_height_graph( &self ) -> BoxedNoiseNode {
+        let layers = self.layers.iter().map( |layer| {
+            let noise = Simplex2d::new( self.seed.wrapping_add( layer.seed_offset ), layer.frequency ).scale_bias( 1.0, layer.offset );
+            let noise = match layer.threshold {
+                Some( threshold ) => noise.scale_bias( 1.0, -threshold ).clamp( 0.0, f64::MAX ).boxed(),
+                None => noise.boxed(),
+            };
+
+            noise.scale_bias( layer.amplitude, 0.0 ).boxed()
+        } );
+
+        Add( std::iter::once( Constant( self.base_height ).boxed() ).chain( layers ).collect() ).boxed()
+    }
+}
+
+pub struct GeneratorOfDefinition {
+    definition: GeneratorDefinition,
+    height: BoxedNoiseNode,
+    heightmaps: HeightmapCache,
+}
+
+impl GeneratorOfDefinition {
+    pub fn get_height( &self, x:i64, z:i64 ) -> f64 {
+        self.height.get( x as f64, 0.0, z as f64 )
+    }
+
+    fn get_band_voxel( &self, voxels:&[(Arc<Voxel>, Option<Arc<Voxel>>)], height:i64 ) -> Arc<Voxel> {
+        let index = self.definition.color_bands.iter()
+            .position( |band| band.max_height.is_none_or( |max_height| height as f64 <= max_height ) )
+            .unwrap_or( self.definition.color_bands.len() - 1 );
+
+        match voxels[ index ] {
+            (_, Some( ref stripe )) if height.rem_euclid( 2 ) == 1 => Arc::clone( stripe ),
+            (ref voxel, _) => Arc::clone( voxel ),
+        }
+    }
+}
+
+impl WorldGenerative for GeneratorOfDefinition {
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
+        let world_origin = (origin.0 * size as i64, origin.1 * size as i64, origin.2 * size as i64);
+        let size_u32 = size as u32;
+
+        let voxels = self.definition.color_bands.iter().map( |band| {
+            let material = || (band.name.clone(), Material { _density:10, ..Default::default() });
+            let voxel = create_voxel( dataset, material(), (band.name.clone(), band.color.into()) );
+            let stripe = band.stripe_color.map( |color| {
+                create_voxel( dataset, material(), (format!( "{}_stripe", band.name ), color.into()) )
+            } );
+
+            (voxel, stripe)
+        } ).collect::<Vec<_>>();
+
+        let water = self.definition.water.as_ref().map( |water| {
+            let level = water.level.floor() as i64 - world_origin.1;
+            let voxel = create_voxel(
+                dataset,
+                (format!( "{}_water", self.definition.name ), Material { _density:1, opacity:water.opacity, is_solid:false, ..Default::default() }),
+                (format!( "{}_water", self.definition.name ), water.color.into())
+            );
+
+            (level, voxel)
+        } );
+
+        let quadtree = self.heightmaps.get_or_generate( (origin.0, origin.2), size, || Quadtree::from_terrain_generation( Quadtree::get_max_depth_for( size_u32 ), &|x, z| {
+            self.get_height( world_origin.0 + x as i64, world_origin.2 + z as i64 ).floor()
+        } ) );
+
+        quadtree.proces_entire_tree( &mut |offset, size, height| {
+            let current_min = height as i64 - world_origin.1;
+
+            // Terrain of deeper nodes overrides the water filled here
+            if let Some( (water_level, ref water) ) = water {
+                let water_from = (current_min + 1).max( 0 );
+                let water_to = water_level.min( size_u32 as i64 - 1 );
+
+                if water_from <= water_to {
+                    world_holder.fill_voxels(
+                        (offset.0, water_from as u32, offset.2),
+                        (offset.0 + size - 1, water_to as u32, offset.2 + size - 1),
+                        Some( Arc::clone( water ) )
+                    );
+                }
+            }
+
+            if current_min < 0 || current_min < offset.1 as i64 || offset.1 >= size_u32 { return offset.1 }
+
+            let to = (offset.0 + size - 1, current_min.min( size_u32 as i64 - 1 ) as u32, offset.2 + size - 1);
+            let voxel = self.get_band_voxel( &voxels, height as i64 );
+
+            world_holder.fill_voxels( offset, to, Some( voxel ) );
+
+            to.1 + 1
+        } );
+    }
+
+    fn get_heightmap_cache_stats( &self ) -> Option<HeightmapCacheStats> {
+        Some( self.heightmaps.get_stats() )
+    }
+
+    fn set_heightmap_cache_capacity( &self, columns:usize ) {
+        self.heightmaps.set_capacity( columns );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_definitions_generate_terrain() {
+        for path in [ "generators/plains.ron", "generators/hills.json" ] {
+            let generator = GeneratorDefinition::load( path ).unwrap().compile().unwrap();
+            let mut dataset = VoxelDataset::new();
+            let chunk = generator.generate_chunk( &mut dataset, (0, 0, 0), 64 );
+            let height = generator.get_height( 5, 7 ).floor() as u32;
+
+            assert!( height < 64, "{path}: test column has to be inside the chunk" );
+            assert!( chunk.get_voxel( 5, height, 7 ).is_some_and( |voxel| voxel.get_material().is_solid ), "{path}" );
+            assert!( chunk.get_voxel( 5, height + 1, 7 ).is_none_or( |voxel| !voxel.get_material().is_solid ), "{path}" );
+        }
+    }
+
+    #[test]
+    fn test_definition_without_color_bands_is_rejected() {
+        let definition = GeneratorDefinition::from_json( r#"{ "name":"empty", "seed":1, "layers":[], "color_bands":[] }"# ).unwrap();
+
+        assert!( definition.compile().is_err() );
+        assert!( GeneratorDefinition::from_ron( "( name: \"broken\" )" ).is_err() );
+    }
+}
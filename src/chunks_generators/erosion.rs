@@ -0,0 +1,471 @@
+use std::sync::Arc;
+
+use rand::{ rngs::SmallRng, Rng, SeedableRng };
+
+use crate::{
+    chunks_generators::{ biomes::HeightSource, utilities::{ create_voxel, get_region_weights, LruCache } },
+    world::{
+        world_generator::WorldGenerative,
+        world_holder::{ Material, VoxelDataset, WorldHolding }
+    }
+};
+
+/// Heights are eroded per square region of this size, every region has its own random generator
+const EROSION_REGION_SIZE: i64 = 128;
+/// Columns simulated around every region. Neighbouring regions overlap by it and are cross-faded
+/// over its inner half, so edge effects of the simulation never reach the terrain
+const EROSION_REGION_MARGIN: i64 = 32;
+const CACHED_REGIONS: usize = 64;
+
+/// Dense height map of a square area, `x` is the outer and `z` the inner axis
+pub struct HeightGrid {
+    origin: (i64, i64),
+    size: usize,
+    heights: Vec<f64>,
+}
+
+#[allow(dead_code)]
+impl HeightGrid {
+    pub fn new( origin:(i64, i64), size:usize, heights:Vec<f64> ) -> Self {
+        assert_eq!( heights.len(), size * size, "Height grid needs size * size heights" );
+        Self { origin, size, heights }
+    }
+
+    pub fn from_source( source:&dyn HeightSource, origin:(i64, i64), size:usize ) -> Self {
+        let heights = (0..size * size)
+            .map( |index| source.get_height( origin.0 + (index / size) as i64, origin.1 + (index % size) as i64 ) )
+            .collect();
+
+        Self { origin, size, heights }
+    }
+
+    pub fn get_origin( &self ) -> (i64, i64) {
+        self.origin
+    }
+
+    pub fn get_size( &self ) -> usize {
+        self.size
+    }
+
+    #[inline]
+    pub fn get( &self, x:usize, z:usize ) -> f64 {
+        self.heights[ x * self.size + z ]
+    }
+
+    /// Height of a world column, it has to be inside the grid
+    pub fn get_world( &self, x:i64, z:i64 ) -> f64 {
+        self.get( (x - self.origin.0) as usize, (z - self.origin.1) as usize )
+    }
+
+    pub fn get_heights( &self ) -> &[f64] {
+        &self.heights
+    }
+
+    /// Bilinearly interpolated height and its gradient at a local position inside `0..size - 1`
+    fn get_interpolated( &self, x:f64, z:f64 ) -> (f64, (f64, f64)) {
+        let (cell_x, cell_z) = (x as usize, z as usize);
+        let (u, v) = (x - cell_x as f64, z - cell_z as f64);
+
+        let h00 = self.get( cell_x, cell_z );
+        let h10 = self.get( cell_x + 1, cell_z );
+        let h01 = self.get( cell_x, cell_z + 1 );
+        let h11 = self.get( cell_x + 1, cell_z + 1 );
+
+        let gradient_x = (h10 - h00) * (1.0 - v) + (h11 - h01) * v;
+        let gradient_z = (h01 - h00) * (1.0 - u) + (h11 - h10) * u;
+        let height = h00 * (1.0 - u) * (1.0 - v) + h10 * u * (1.0 - v) + h01 * (1.0 - u) * v + h11 * u * v;
+
+        (height, (gradient_x, gradient_z))
+    }
+
+    /// Spreads `amount` over the 4 cells around the position with bilinear weights, negative amounts erode
+    fn add_interpolated( &mut self, x:f64, z:f64, amount:f64 ) {
+        let (cell_x, cell_z) = (x as usize, z as usize);
+        let (u, v) = (x - cell_x as f64, z - cell_z as f64);
+        let size = self.size;
+
+        self.heights[ cell_x * size + cell_z ]           += amount * (1.0 - u) * (1.0 - v);
+        self.heights[ (cell_x + 1) * size + cell_z ]     += amount * u * (1.0 - v);
+        self.heights[ cell_x * size + cell_z + 1 ]       += amount * (1.0 - u) * v;
+        self.heights[ (cell_x + 1) * size + cell_z + 1 ] += amount * u * v;
+    }
+}
+
+/// Droplets rolling down the slope, they pick sediment up when fast and drop it when slowing down
+#[derive(Clone, Debug)]
+pub struct HydraulicErosion {
+    pub droplets_per_column: f64,
+    pub max_lifetime: u32,
+    /// How much a droplet keeps its direction (0 - 1) instead of following the slope
+    pub inertia: f64,
+    pub sediment_capacity: f64,
+    pub min_sediment_capacity: f64,
+    pub erode_speed: f64,
+    pub deposit_speed: f64,
+    pub evaporate_speed: f64,
+    pub gravity: f64,
+}
+
+impl Default for HydraulicErosion {
+    fn default() -> Self {
+        Self {
+            droplets_per_column: 0.5,
+            max_lifetime: 30,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_sediment_capacity: 0.01,
+            erode_speed: 0.3,
+            deposit_speed: 0.3,
+            evaporate_speed: 0.02,
+            gravity: 4.0,
+        }
+    }
+}
+
+impl HydraulicErosion {
+    pub fn erode( &self, grid:&mut HeightGrid, rng:&mut SmallRng ) {
+        if grid.size < 2 { return }
+
+        let max_position = (grid.size - 1) as f64;
+        let droplets = (grid.size * grid.size) as f64 * self.droplets_per_column;
+
+        for _ in 0..droplets as u64 {
+            let mut position = (rng.random::<f64>() * max_position, rng.random::<f64>() * max_position);
+            let mut direction = (0.0, 0.0);
+            let mut speed = 1.0;
+            let mut water = 1.0;
+            let mut sediment = 0.0;
+
+            for _ in 0..self.max_lifetime {
+                let (height, gradient) = grid.get_interpolated( position.0, position.1 );
+
+                direction.0 = direction.0 * self.inertia - gradient.0 * (1.0 - self.inertia);
+                direction.1 = direction.1 * self.inertia - gradient.1 * (1.0 - self.inertia);
+
+                let length = (direction.0 * direction.0 + direction.1 * direction.1).sqrt();
+                if length < f64::EPSILON { break }
+
+                let new_position = (position.0 + direction.0 / length, position.1 + direction.1 / length);
+                if new_position.0 < 0.0 || new_position.0 >= max_position || new_position.1 < 0.0 || new_position.1 >= max_position { break }
+
+                let height_delta = grid.get_interpolated( new_position.0, new_position.1 ).0 - height;
+                let capacity = (-height_delta * speed * water * self.sediment_capacity).max( self.min_sediment_capacity );
+
+                if sediment > capacity || height_delta > 0.0 {
+                    // Uphill the droplet fills the pit it has left, at most up to the new height
+                    let amount = if height_delta > 0.0 { height_delta.min( sediment ) } else { (sediment - capacity) * self.deposit_speed };
+
+                    sediment -= amount;
+                    grid.add_interpolated( position.0, position.1, amount );
+                } else {
+                    // Never deeper than the height difference, so droplets don't dig holes
+                    let amount = ((capacity - sediment) * self.erode_speed).min( -height_delta );
+
+                    sediment += amount;
+                    grid.add_interpolated( position.0, position.1, -amount );
+                }
+
+                speed = (speed * speed - height_delta * self.gravity).max( 0.0 ).sqrt();
+                water *= 1.0 - self.evaporate_speed;
+                position = new_position;
+            }
+        }
+    }
+}
+
+/// Material sliding from slopes steeper than the talus angle down to lower neighbours
+#[derive(Clone, Debug)]
+pub struct ThermalErosion {
+    /// Largest stable height difference of neighbouring columns
+    pub talus: f64,
+    /// Part (0 - 0.5) of the excess difference moved per iteration
+    pub rate: f64,
+    pub iterations: u32,
+}
+
+impl Default for ThermalErosion {
+    fn default() -> Self {
+        Self { talus:1.5, rate:0.4, iterations:30 }
+    }
+}
+
+impl ThermalErosion {
+    /// Changes of an iteration are collected first, so the result doesn't depend on the order of columns
+    pub fn erode( &self, grid:&mut HeightGrid ) {
+        let size = grid.size;
+        let mut changes = vec![ 0.0; size * size ];
+
+        for _ in 0..self.iterations {
+            changes.fill( 0.0 );
+
+            for x in 0..size {
+                for z in 0..size {
+                    let height = grid.get( x, z );
+                    let neighbours = [ (x.wrapping_sub( 1 ), z), (x + 1, z), (x, z.wrapping_sub( 1 )), (x, z + 1) ];
+                    let excesses = neighbours.map( |(nx, nz)| {
+                        if nx >= size || nz >= size { return 0.0 }
+                        (height - grid.get( nx, nz ) - self.talus).max( 0.0 )
+                    } );
+
+                    let excess_sum = excesses.iter().sum::<f64>();
+                    let max_excess = excesses.iter().copied().fold( 0.0, f64::max );
+                    if excess_sum == 0.0 { continue }
+
+                    let moved = max_excess * self.rate;
+                    changes[ x * size + z ] -= moved;
+
+                    for ((nx, nz), excess) in neighbours.into_iter().zip( excesses ) {
+                        if excess > 0.0 {
+                            changes[ nx * size + nz ] += moved * excess / excess_sum;
+                        }
+                    }
+                }
+            }
+
+            grid.heights.iter_mut().zip( &changes ).for_each( |(height, change)| *height += change );
+        }
+    }
+}
+
+/// Heights of any source eroded region by region. Regions are simulated with a margin and cross-faded
+/// with their neighbours, so the result is continuous and the same no matter which chunk asks first
+pub struct ErodedHeights {
+    seed: u32,
+    source: Box<dyn HeightSource>,
+    hydraulic: Option<HydraulicErosion>,
+    thermal: Option<ThermalErosion>,
+    regions: LruCache<(i64, i64), HeightGrid>,
+}
+
+#[allow(dead_code)]
+impl ErodedHeights {
+    pub fn new( seed:u32, source:Box<dyn HeightSource> ) -> Self {
+        Self {
+            seed,
+            source,
+            hydraulic: Some( HydraulicErosion::default() ),
+            thermal: Some( ThermalErosion::default() ),
+            regions: LruCache::new( CACHED_REGIONS ),
+        }
+    }
+
+    pub fn with_hydraulic( mut self, hydraulic:Option<HydraulicErosion> ) -> Self {
+        self.hydraulic = hydraulic;
+        self
+    }
+
+    pub fn with_thermal( mut self, thermal:Option<ThermalErosion> ) -> Self {
+        self.thermal = thermal;
+        self
+    }
+
+    pub fn get_source( &self ) -> &dyn HeightSource {
+        self.source.as_ref()
+    }
+
+    /// Eroded region with its margin, computed on first use
+    pub fn get_region( &self, region:(i64, i64) ) -> Arc<HeightGrid> {
+        self.regions.get_or_compute( region, || self.erode_region( region ) )
+    }
+
+    fn erode_region( &self, region:(i64, i64) ) -> HeightGrid {
+        let origin = (region.0 * EROSION_REGION_SIZE - EROSION_REGION_MARGIN, region.1 * EROSION_REGION_SIZE - EROSION_REGION_MARGIN);
+        let size = (EROSION_REGION_SIZE + EROSION_REGION_MARGIN * 2) as usize;
+        let mut grid = HeightGrid::from_source( self.source.as_ref(), origin, size );
+        let mut rng = SmallRng::seed_from_u64( self.get_region_seed( region ) );
+
+        if let Some( ref hydraulic ) = self.hydraulic {
+            hydraulic.erode( &mut grid, &mut rng );
+        }
+
+        if let Some( ref thermal ) = self.thermal {
+            thermal.erode( &mut grid );
+        }
+
+        grid
+    }
+
+    fn get_axis_weights( coordinate:i64 ) -> [(i64, f64); 2] {
+        get_region_weights( coordinate, EROSION_REGION_SIZE, EROSION_REGION_MARGIN )
+    }
+
+    fn get_region_seed( &self, region:(i64, i64) ) -> u64 {
+        (self.seed as u64)
+            ^ (region.0 as u64).wrapping_mul( 0x9e37_79b9_7f4a_7c15 )
+            ^ (region.1 as u64).wrapping_mul( 0xc2b2_ae3d_27d4_eb4f )
+    }
+}
+
+impl HeightSource for ErodedHeights {
+    fn get_height( &self, x:i64, z:i64 ) -> f64 {
+        let mut height = 0.0;
+
+        for (region_x, weight_x) in ErodedHeights::get_axis_weights( x ) {
+            for (region_z, weight_z) in ErodedHeights::get_axis_weights( z ) {
+                let weight = weight_x * weight_z;
+                if weight == 0.0 { continue }
+
+                height += self.get_region( (region_x, region_z) ).get_world( x, z ) * weight;
+            }
+        }
+
+        height
+    }
+}
+
+/// Terrain of eroded heights. Columns get surface voxels by what erosion did to them:
+/// sediment where material has been deposited and bare rock where it has been carried away
+pub struct GeneratorOfErodedTerrain {
+    heights: Arc<ErodedHeights>,
+    /// Height change above which a column counts as deposited or eroded
+    change_threshold: f64,
+}
+
+#[allow(dead_code)]
+impl GeneratorOfErodedTerrain {
+    pub fn new( heights:Arc<ErodedHeights> ) -> Self {
+        Self { heights, change_threshold:0.5 }
+    }
+
+    pub fn get_heights( &self ) -> &ErodedHeights {
+        &self.heights
+    }
+}
+
+impl HeightSource for GeneratorOfErodedTerrain {
+    fn get_height( &self, x:i64, z:i64 ) -> f64 {
+        self.heights.get_height( x, z )
+    }
+}
+
+impl WorldGenerative for GeneratorOfErodedTerrain {
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
+        let world_origin = (origin.0 * size as i64, origin.1 * size as i64, origin.2 * size as i64);
+        let size_i64 = size as i64;
+
+        let mut create_surface_voxel = |name:&str, color:(u8, u8, u8)| create_voxel(
+            dataset,
+            (String::from( name ), Material { _density:10, ..Default::default() }),
+            (String::from( name ), color.into())
+        );
+
+        let stone = create_surface_voxel( "stone", (110, 110, 110) );
+        let rock = create_surface_voxel( "rock", (90, 85, 80) );
+        let sediment = create_surface_voxel( "sediment", (175, 150, 105) );
+        let grass = create_surface_voxel( "grass", (70, 140, 50) );
+
+        for x in 0..size_i64 {
+            for z in 0..size_i64 {
+                let (world_x, world_z) = (world_origin.0 + x, world_origin.2 + z);
+                let height = self.heights.get_height( world_x, world_z );
+                let top = height.floor() as i64 - world_origin.1;
+
+                if top < 0 { continue }
+
+                if top > 0 {
+                    world_holder.fill_voxels( (x as u32, 0, z as u32), (x as u32, (top - 1).min( size_i64 - 1 ) as u32, z as u32), Some( Arc::clone( &stone ) ) );
+                }
+
+                if top < size_i64 {
+                    let change = height - self.heights.get_source().get_height( world_x, world_z );
+                    let surface = if change > self.change_threshold { &sediment }
+                        else if change < -self.change_threshold { &rock }
+                        else { &grass };
+
+                    world_holder.set_voxel( x as u32, top as u32, z as u32, Some( Arc::clone( surface ) ) );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chunks_generators::test_11_height_map::GeneratorOfTest11HeightMap;
+
+    use super::*;
+
+    fn create_cone_grid( size:usize ) -> HeightGrid {
+        let center = size as f64 / 2.0;
+        let heights = (0..size * size).map( |index| {
+            let (x, z) = ((index / size) as f64, (index % size) as f64);
+            (30.0 - ((x - center).powi( 2 ) + (z - center).powi( 2 )).sqrt() * 2.0).max( 0.0 )
+        } ).collect();
+
+        HeightGrid::new( (0, 0), size, heights )
+    }
+
+    #[test]
+    fn test_thermal_erosion_keeps_mass_and_flattens_slopes() {
+        let mut grid = HeightGrid::new( (0, 0), 9, (0..81).map( |index| if index == 40 { 20.0 } else { 0.0 } ).collect() );
+        let mass = grid.get_heights().iter().sum::<f64>();
+
+        ThermalErosion { talus:1.0, rate:0.4, iterations:200 }.erode( &mut grid );
+
+        assert!( (grid.get_heights().iter().sum::<f64>() - mass).abs() < 1e-9 );
+        assert!( grid.get( 4, 4 ) < 5.0, "peak={}", grid.get( 4, 4 ) );
+
+        for x in 0..8 {
+            for z in 0..8 {
+                assert!( (grid.get( x, z ) - grid.get( x + 1, z )).abs() < 1.5 );
+                assert!( (grid.get( x, z ) - grid.get( x, z + 1 )).abs() < 1.5 );
+            }
+        }
+    }
+
+    #[test]
+    fn test_hydraulic_erosion_moves_material_downhill() {
+        let mut grid = create_cone_grid( 48 );
+        let original = create_cone_grid( 48 );
+
+        HydraulicErosion::default().erode( &mut grid, &mut SmallRng::seed_from_u64( 5 ) );
+
+        // Mean original height of the eroded material has to be above the mean height of the deposited one
+        let mean_height_of = |sign:f64| {
+            let weighted = grid.get_heights().iter().zip( original.get_heights() )
+                .map( |(eroded, raw)| ((eroded - raw) * sign).max( 0.0 ) )
+                .zip( original.get_heights() )
+                .fold( (0.0, 0.0), |sum, (amount, raw)| (sum.0 + amount * raw, sum.1 + amount) );
+
+            weighted.0 / weighted.1
+        };
+
+        let (eroded_height, deposited_height) = (mean_height_of( -1.0 ), mean_height_of( 1.0 ));
+        assert!( eroded_height > deposited_height, "eroded={eroded_height} deposited={deposited_height}" );
+    }
+
+    #[test]
+    fn test_eroded_heights_do_not_depend_on_query_order() {
+        let create_heights = || ErodedHeights::new( 3, Box::new( GeneratorOfTest11HeightMap::new( 8 ) ) )
+            .with_hydraulic( Some( HydraulicErosion { droplets_per_column:0.05, ..Default::default() } ) )
+            .with_thermal( Some( ThermalErosion { iterations:5, ..Default::default() } ) );
+        // Columns of the first region and its overlap with the second one
+        let columns = [ (5, 5), (140, 40), (120, 64), (0, 0) ];
+        let forward = create_heights();
+        let backward = create_heights();
+
+        let forward_heights = columns.iter().map( |(x, z)| forward.get_height( *x, *z ) ).collect::<Vec<_>>();
+        let mut backward_heights = columns.iter().rev().map( |(x, z)| backward.get_height( *x, *z ) ).collect::<Vec<_>>();
+        backward_heights.reverse();
+
+        assert_eq!( forward_heights, backward_heights );
+    }
+
+    #[test]
+    fn test_region_weights_are_continuous_and_sum_to_one() {
+        let weight_of = |coordinate:i64, region:i64| ErodedHeights::get_axis_weights( coordinate ).iter()
+            .filter( |(weight_region, _)| *weight_region == region )
+            .map( |(_, weight)| weight )
+            .sum::<f64>();
+
+        for coordinate in -300..300 {
+            let weights = ErodedHeights::get_axis_weights( coordinate );
+            assert!( (weights[ 0 ].1 + weights[ 1 ].1 - 1.0).abs() < 1e-12 );
+
+            for region in -4..4 {
+                let step = (weight_of( coordinate, region ) - weight_of( coordinate + 1, region )).abs();
+                assert!( step <= 1.0 / EROSION_REGION_MARGIN as f64 + 1e-12, "coordinate={coordinate} region={region}" );
+            }
+        }
+    }
+}
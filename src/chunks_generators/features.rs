@@ -0,0 +1,489 @@
+use std::{ fs, path::Path, sync::Arc };
+
+use anyhow::{ bail, Context, Result };
+use rand::{ rngs::SmallRng, Rng, SeedableRng };
+use serde::{ Deserialize, Serialize };
+
+use crate::{
+    chunks_generators::{ biomes::HeightSource, utilities::create_voxel },
+    world::{
+        world_generator::{ GenerationStage, StageNeighbours, WorldGenerative },
+        world_holder::{ Material, Voxel, VoxelDataset, WorldHolding }
+    }
+};
+
+/// Features are placed per square region of this size, every region has its own random generator
+const FEATURE_REGION_SIZE: i64 = 32;
+
+/// Prefabricated voxel structure built from boxes. Coordinates are relative to the anchor,
+/// which is placed one voxel above the terrain surface
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StructureTemplate {
+    pub name: String,
+    pub palette: Vec<PaletteEntry>,
+    pub boxes: Vec<TemplateBox>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PaletteEntry {
+    pub name: String,
+    pub color: (u8, u8, u8),
+}
+
+/// Box of voxels, both corners are inclusive
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct TemplateBox {
+    pub from: (i32, i32, i32),
+    pub to: (i32, i32, i32),
+    /// Index into the template palette
+    pub voxel: usize,
+}
+
+impl TemplateBox {
+    /// Box turned around the y axis by `quarters` * 90 degrees
+    fn rotated( &self, quarters:u8 ) -> Self {
+        let rotate = |(x, y, z):(i32, i32, i32)| match quarters % 4 {
+            0 => (x, y, z),
+            1 => (-z, y, x),
+            2 => (-x, y, -z),
+            _ => (z, y, -x),
+        };
+
+        let (a, b) = (rotate( self.from ), rotate( self.to ));
+
+        Self {
+            from: (a.0.min( b.0 ), a.1.min( b.1 ), a.2.min( b.2 )),
+            to: (a.0.max( b.0 ), a.1.max( b.1 ), a.2.max( b.2 )),
+            voxel: self.voxel,
+        }
+    }
+}
+
+impl StructureTemplate {
+    /// Largest horizontal distance of any voxel from the anchor
+    pub fn get_horizontal_reach( &self ) -> i64 {
+        self.boxes.iter()
+            .flat_map( |template_box| [ template_box.from, template_box.to ] )
+            .map( |(x, _, z)| x.abs().max( z.abs() ) as i64 )
+            .max()
+            .unwrap_or( 0 )
+    }
+}
+
+/// Decides where structures of a template are placed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeatureRule {
+    pub template: String,
+    /// Placement attempts per region
+    pub attempts: u32,
+    /// Probability of every attempt to succeed
+    pub chance: f64,
+    /// World heights of the terrain surface where structure can be placed
+    #[serde(default)]
+    pub min_height: Option<f64>,
+    #[serde(default)]
+    pub max_height: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StructureLibrary {
+    pub templates: Vec<StructureTemplate>,
+    pub rules: Vec<FeatureRule>,
+}
+
+#[allow(dead_code)]
+impl StructureLibrary {
+    pub fn load( path:impl AsRef<Path> ) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string( path ).with_context( || format!( "Can't read structure library {path:?}" ) )?;
+
+        match path.extension().and_then( |extension| extension.to_str() ) {
+            Some( "ron" ) => Self::from_ron( &content ),
+            Some( "json" ) => Self::from_json( &content ),
+            _ => bail!( "Unknown structure library format of {path:?} (expected .ron or .json)" ),
+        }.with_context( || format!( "Invalid structure library {path:?}" ) )
+    }
+
+    pub fn from_ron( content:&str ) -> Result<Self> {
+        Ok( ron::from_str( content )? )
+    }
+
+    pub fn from_json( content:&str ) -> Result<Self> {
+        Ok( serde_json::from_str( content )? )
+    }
+
+    /// Trees, boulders and ruins
+    pub fn new_default() -> Self {
+        let box_of = |from, to, voxel| TemplateBox { from, to, voxel };
+        let palette = |entries:&[(&str, (u8, u8, u8))]| entries.iter()
+            .map( |(name, color)| PaletteEntry { name:name.to_string(), color:*color } )
+            .collect::<Vec<_>>();
+
+        let tree = StructureTemplate {
+            name: String::from( "tree" ),
+            palette: palette( &[ ("log", (175, 40, 20)), ("leaves", (20, 100, 20)) ] ),
+            boxes: vec![
+                box_of( (-2, 5, -2), (2, 7, 2), 1 ),
+                box_of( (-1, 8, -1), (1, 8, 1), 1 ),
+                box_of( (0, 0, 0), (0, 5, 0), 0 ),
+            ],
+        };
+
+        let boulder = StructureTemplate {
+            name: String::from( "boulder" ),
+            palette: palette( &[ ("boulder", (125, 125, 120)) ] ),
+            boxes: vec![
+                box_of( (-2, -1, -1), (2, 1, 1), 0 ),
+                box_of( (-1, -1, -2), (1, 2, 2), 0 ),
+            ],
+        };
+
+        let ruin = StructureTemplate {
+            name: String::from( "ruin" ),
+            palette: palette( &[ ("cobblestone", (100, 100, 95)), ("mossy_cobblestone", (80, 110, 70)) ] ),
+            boxes: vec![
+                box_of( (-4, -1, -4), (4, -1, 4), 0 ),
+                box_of( (-4, 0, -4), (4, 3, -4), 0 ),
+                box_of( (-4, 0, -3), (-4, 2, 4), 1 ),
+                box_of( (4, 0, -3), (4, 1, 1), 0 ),
+                box_of( (-3, 0, 4), (0, 1, 4), 1 ),
+            ],
+        };
+
+        let rule = |template:&str, attempts, chance, max_height| FeatureRule {
+            template: template.to_string(),
+            attempts,
+            chance,
+            min_height: Some( 13.0 ),
+            max_height,
+        };
+
+        Self {
+            templates: vec![ tree, boulder, ruin ],
+            rules: vec![
+                rule( "tree", 4, 0.5, Some( 40.0 ) ),
+                rule( "boulder", 2, 0.25, None ),
+                rule( "ruin", 1, 0.02, Some( 30.0 ) ),
+            ],
+        }
+    }
+}
+
+/// Single structure placed in the world
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub template: usize,
+    pub anchor: (i64, i64, i64),
+    pub rotation: u8,
+}
+
+/// Places structures of a library on top of the terrain. Placements depend only on the seed, region and terrain height,
+/// so every chunk computes the same structures and writes its own part of them, in the same order
+pub struct FeaturePlacer {
+    seed: u32,
+    height: Box<dyn HeightSource>,
+    library: StructureLibrary,
+    /// Template index of every rule
+    rule_templates: Vec<usize>,
+    reach: i64,
+}
+
+#[allow(dead_code)]
+impl FeaturePlacer {
+    pub fn new( seed:u32, height:Box<dyn HeightSource>, library:StructureLibrary ) -> Result<Self> {
+        let rule_templates = library.rules.iter().map( |rule| {
+            library.templates.iter().position( |template| template.name == rule.template )
+                .with_context( || format!( "Feature rule uses unknown template \"{}\"", rule.template ) )
+        } ).collect::<Result<Vec<_>>>()?;
+
+        for template in &library.templates {
+            if let Some( template_box ) = template.boxes.iter().find( |template_box| template_box.voxel >= template.palette.len() ) {
+                bail!( "Template \"{}\" uses voxel {} outside of its palette", template.name, template_box.voxel )
+            }
+        }
+
+        let reach = library.templates.iter().map( StructureTemplate::get_horizontal_reach ).max().unwrap_or( 0 );
+
+        Ok( Self { seed, height, library, rule_templates, reach } )
+    }
+
+    /// Placements started in the region, in the order they are written
+    pub fn get_region_placements( &self, region:(i64, i64) ) -> Vec<Placement> {
+        let mut rng = SmallRng::seed_from_u64( self.get_region_seed( region ) );
+        let mut placements = vec![];
+
+        for (rule, &template) in self.library.rules.iter().zip( &self.rule_templates ) {
+            for _ in 0..rule.attempts {
+                // Values are drawn for failed attempts too, so changing one rule doesn't move other structures
+                let success = rng.random::<f64>() < rule.chance;
+                let x = region.0 * FEATURE_REGION_SIZE + rng.random_range( 0..FEATURE_REGION_SIZE );
+                let z = region.1 * FEATURE_REGION_SIZE + rng.random_range( 0..FEATURE_REGION_SIZE );
+                let rotation = rng.random_range( 0..4 );
+
+                if !success { continue }
+
+                let height = self.height.get_height( x, z ).floor();
+
+                if rule.min_height.is_some_and( |min| height < min ) || rule.max_height.is_some_and( |max| height > max ) {
+                    continue
+                }
+
+                placements.push( Placement { template, anchor:(x, height as i64 + 1, z), rotation } );
+            }
+        }
+
+        placements
+    }
+
+    /// Structures whose anchor lost its ground in the earlier stages (caves, water) are skipped, every chunk
+    /// they touch reads the same ground from itself or its `neighbours`. All anchors are checked before any structure
+    /// is written, so the ground is never one of the structures placed by the same stage
+    pub fn place( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, world_origin:(i64, i64, i64), size:u32, neighbours:&StageNeighbours ) {
+        let size = size as i64;
+        let region_range = |origin:i64| (origin - self.reach).div_euclid( FEATURE_REGION_SIZE )..=(origin + size + self.reach).div_euclid( FEATURE_REGION_SIZE );
+        let mut voxels = vec![ None; self.library.templates.len() ];
+
+        let placements = region_range( world_origin.0 )
+            .flat_map( |region_x| region_range( world_origin.2 ).map( move |region_z| (region_x, region_z) ) )
+            .flat_map( |region| self.get_region_placements( region ) )
+            .filter( |placement| Self::has_ground( world_holder, world_origin, size, neighbours, placement.anchor ) )
+            .collect::<Vec<_>>();
+
+        for placement in placements {
+            let template = &self.library.templates[ placement.template ];
+            let palette:&Vec<Arc<Voxel>> = voxels[ placement.template ].get_or_insert_with( || template.palette.iter().map( |entry| create_voxel(
+                dataset,
+                (entry.name.clone(), Material { _density:10, ..Default::default() }),
+                (entry.name.clone(), entry.color.into())
+            ) ).collect() );
+
+            for template_box in template.boxes.iter().map( |template_box| template_box.rotated( placement.rotation ) ) {
+                let clip = |axis_from:i32, axis_to:i32, anchor:i64, origin:i64| {
+                    let from = (anchor + axis_from as i64 - origin).max( 0 );
+                    let to = (anchor + axis_to as i64 - origin).min( size - 1 );
+                    (from <= to).then_some( (from as u32, to as u32) )
+                };
+
+                let (Some( x ), Some( y ), Some( z )) = (
+                    clip( template_box.from.0, template_box.to.0, placement.anchor.0, world_origin.0 ),
+                    clip( template_box.from.1, template_box.to.1, placement.anchor.1, world_origin.1 ),
+                    clip( template_box.from.2, template_box.to.2, placement.anchor.2, world_origin.2 ),
+                ) else { continue };
+
+                world_holder.fill_voxels( (x.0, y.0, z.0), (x.1, y.1, z.1), Some( Arc::clone( &palette[ template_box.voxel ] ) ) );
+            }
+        }
+    }
+
+    /// Solid voxel under the anchor and no water at it. Anchors in missing neighbours (generation without them) are kept
+    fn has_ground( world_holder:&dyn WorldHolding, world_origin:(i64, i64, i64), size:i64, neighbours:&StageNeighbours, anchor:(i64, i64, i64) ) -> bool {
+        let get_voxel = |y:i64| {
+            let position = (anchor.0 - world_origin.0, y - world_origin.1, anchor.2 - world_origin.2);
+            let is_inside = [ position.0, position.1, position.2 ].iter().all( |axis| (0..size).contains( axis ) );
+
+            if is_inside {
+                Some( world_holder.get_voxel( position.0 as u32, position.1 as u32, position.2 as u32 ) )
+            } else {
+                neighbours.get_neighbour_voxel( position.0, position.1, position.2 )
+            }
+        };
+
+        let (Some( ground ), Some( anchor )) = (get_voxel( anchor.1 - 1 ), get_voxel( anchor.1 )) else { return true };
+
+        ground.is_some_and( |voxel| voxel.get_material().is_solid ) && anchor.is_none_or( |voxel| voxel.get_material().is_solid )
+    }
+
+    fn get_region_seed( &self, region:(i64, i64) ) -> u64 {
+        (self.seed as u64)
+            ^ (region.0 as u64).wrapping_mul( 0x9e37_79b9_7f4a_7c15 )
+            ^ (region.1 as u64).wrapping_mul( 0xc2b2_ae3d_27d4_eb4f )
+    }
+}
+
+/// Runs the feature placer over chunks of any surface generator
+pub struct GeneratorWithFeatures {
+    generator: Box<dyn WorldGenerative>,
+    placer: FeaturePlacer,
+}
+
+#[allow(dead_code)]
+impl GeneratorWithFeatures {
+    pub fn new( generator:Box<dyn WorldGenerative>, placer:FeaturePlacer ) -> Self {
+        Self { generator, placer }
+    }
+}
+
+impl WorldGenerative for GeneratorWithFeatures {
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
+        self.generator.generate_chunk_into( dataset, world_holder, origin, size );
+    }
+
+    fn get_last_stage( &self ) -> GenerationStage {
+        self.generator.get_last_stage().max( GenerationStage::Features )
+    }
+
+    /// Structures check the ground under their anchors, which can be in a neighbour
+    fn reads_neighbours( &self, stage:GenerationStage ) -> bool {
+        stage == GenerationStage::Features || self.generator.reads_neighbours( stage )
+    }
+
+    fn generate_stage( &self, stage:GenerationStage, dataset:&mut VoxelDataset, chunk:&mut dyn WorldHolding, origin:(i64, i64, i64), neighbours:&StageNeighbours ) {
+        self.generator.generate_stage( stage, dataset, chunk, origin, neighbours );
+
+        if stage == GenerationStage::Features {
+            let size = neighbours.get_size();
+            let world_origin = (origin.0 * size as i64, origin.1 * size as i64, origin.2 * size as i64);
+
+            self.placer.place( dataset, chunk, world_origin, size, neighbours );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chunks_generators::{ biomes::{ Biome, GeneratorOfBiomes, SurfaceLayer }, utilities::create_water_voxel },
+        noise::noise_graph::{ Constant, NoiseNodeExt },
+        structure_tests::octree::Octree,
+        world::{ world_generator::generate_chunk_stages, world_holder::fill }
+    };
+
+    use super::*;
+
+    /// Flat ground with its surface at y = 20
+    fn create_generator() -> GeneratorWithFeatures {
+        let mut library = StructureLibrary::new_default();
+        library.rules.iter_mut().for_each( |rule| rule.chance = 1.0 );
+
+        let plain = Biome {
+            name: String::from( "plain" ),
+            temperature: 0.0,
+            humidity: 0.0,
+            height: Box::new( Constant( 20.0 ).boxed() ),
+            surface: vec![],
+        };
+
+        let terrain = GeneratorOfBiomes::new( 1, vec![ plain ], SurfaceLayer::new( "stone", (110, 110, 110), 0 ) );
+        let placer = FeaturePlacer::new( 4, Box::new( Constant( 20.0 ).boxed() ), library ).unwrap();
+        GeneratorWithFeatures::new( Box::new( terrain ), placer )
+    }
+
+    #[test]
+    fn test_structures_are_consistent_across_chunk_borders() {
+        let generator = create_generator();
+        let mut dataset = VoxelDataset::new();
+        let big_chunk = generate_chunk_stages( &generator, &mut dataset, (-1, 0, -1), 64 );
+        let mut placed_count = 0;
+
+        // Small chunks are generated in reverse order, the result can't depend on it
+        for (chunk_x, chunk_z) in [ (-1, -1), (-1, -2), (-2, -1), (-2, -2) ] {
+            let small_chunk = generate_chunk_stages( &generator, &mut dataset, (chunk_x, 0, chunk_z), 32 );
+            let offset = (((chunk_x + 2) * 32) as u32, ((chunk_z + 2) * 32) as u32);
+
+            for x in 0..32 {
+                for y in 0..32 {
+                    for z in 0..32 {
+                        let small = small_chunk.get( x, y, z ).map( |voxel| voxel._common_data.color.red );
+                        let big = big_chunk.get( offset.0 + x, y, offset.1 + z ).map( |voxel| voxel._common_data.color.red );
+
+                        assert_eq!( small, big, "chunk ({chunk_x}, {chunk_z}) voxel ({x}, {y}, {z})" );
+                        placed_count += (y > 20 && small.is_some()) as u32;
+                    }
+                }
+            }
+        }
+
+        assert!( placed_count > 0, "Some structures have to be placed" );
+    }
+
+    #[test]
+    fn test_anchors_need_ground_in_the_chunk_or_its_neighbours() {
+        let chunk_with_ground = || {
+            let mut chunk = Octree::<Voxel>::from_max_size( 32 );
+            fill( (0, 0, 0), (31, 20, 31), &mut chunk );
+            chunk
+        };
+
+        let mut chunk = chunk_with_ground();
+        let mut neighbour = chunk_with_ground();
+        let empty = StageNeighbours::empty( 32 );
+        let has_ground = |chunk:&Octree<Voxel>, neighbours:&StageNeighbours, anchor| FeaturePlacer::has_ground( chunk, (0, 0, 0), 32, neighbours, anchor );
+
+        assert!( has_ground( &chunk, &empty, (5, 21, 5) ) );
+        assert!( !has_ground( &chunk, &empty, (5, 22, 5) ) );
+
+        chunk.set_voxel( 5, 20, 5, None );
+        assert!( !has_ground( &chunk, &empty, (5, 21, 5) ) );
+
+        // Anchor in the neighbour on the -x side
+        assert!( has_ground( &chunk, &empty, (-3, 21, 5) ) );
+        assert!( has_ground( &chunk, &StageNeighbours::new( vec![ ((-1, 0, 0), &neighbour) ], 32 ), (-3, 21, 5) ) );
+
+        neighbour.set_voxel( 29, 20, 5, None );
+        assert!( !has_ground( &chunk, &StageNeighbours::new( vec![ ((-1, 0, 0), &neighbour) ], 32 ), (-3, 21, 5) ) );
+    }
+
+    #[test]
+    fn test_anchors_ignore_structures_placed_by_the_same_stage() {
+        let library = StructureLibrary::from_ron( r#"(
+            templates: [
+                ( name: "slab", palette: [ ( name: "slab", color: (200, 200, 200) ) ], boxes: [ ( from: (-4, -1, -4), to: (4, -1, 4), voxel: 0 ) ] ),
+                ( name: "pillar", palette: [ ( name: "pillar", color: (50, 50, 50) ) ], boxes: [ ( from: (0, 0, 0), to: (0, 3, 0), voxel: 0 ) ] ),
+            ],
+            rules: [ ( template: "slab", attempts: 40, chance: 1.0 ), ( template: "pillar", attempts: 40, chance: 1.0 ) ],
+        )"# ).unwrap();
+        let placer = FeaturePlacer::new( 9, Box::new( Constant( 20.0 ).boxed() ), library ).unwrap();
+
+        // Ground only on every other 8x8 square, water on the rest
+        let mut dataset = VoxelDataset::new();
+        let water = create_water_voxel( &mut dataset );
+        let has_water = |x:i64, z:i64| (x.div_euclid( 8 ) + z.div_euclid( 8 )).rem_euclid( 2 ) == 1;
+        let mut chunk = Octree::<Voxel>::from_max_size( 64 );
+        fill( (0, 0, 0), (63, 20, 63), &mut chunk );
+
+        for x in 0..64 {
+            for z in 0..64 {
+                if has_water( x, z ) { chunk.set_voxel( x as u32, 20, z as u32, Some( Arc::clone( &water ) ) ) }
+            }
+        }
+
+        placer.place( &mut dataset, &mut chunk, (0, 0, 0), 64, &StageNeighbours::empty( 64 ) );
+
+        let pillars = (0..=1).flat_map( |x| (0..=1).map( move |z| (x, z) ) )
+            .flat_map( |region| placer.get_region_placements( region ) )
+            .filter( |placement| placement.template == 1 )
+            .filter( |placement| (0..64).contains( &placement.anchor.0 ) && (0..64).contains( &placement.anchor.2 ) );
+        let mut checked = 0;
+
+        for Placement { anchor:(x, y, z), .. } in pillars {
+            let is_placed = chunk.get( x as u32, y as u32 + 1, z as u32 ).is_some();
+
+            assert_eq!( is_placed, !has_water( x, z ), "pillar at ({x}, {z})" );
+            checked += has_water( x, z ) as u32;
+        }
+
+        assert!( checked > 0, "Some pillars have to stand over water" );
+    }
+
+    #[test]
+    fn test_rotation_keeps_box_size() {
+        let template_box = TemplateBox { from:(-1, 0, 2), to:(3, 4, 5), voxel:0 };
+
+        for quarters in 0..4 {
+            let rotated = template_box.rotated( quarters );
+            let sizes = (rotated.to.0 - rotated.from.0, rotated.to.1 - rotated.from.1, rotated.to.2 - rotated.from.2);
+
+            assert_eq!( sizes, if quarters % 2 == 0 { (4, 4, 3) } else { (3, 4, 4) } );
+        }
+    }
+
+    #[test]
+    fn test_library_with_unknown_template_is_rejected() {
+        let library = StructureLibrary::from_ron( r#"(
+            templates: [ ( name: "pillar", palette: [ ( name: "stone", color: (100, 100, 100) ) ], boxes: [ ( from: (0, 0, 0), to: (0, 3, 0), voxel: 0 ) ] ) ],
+            rules: [ ( template: "tower", attempts: 1, chance: 1.0 ) ],
+        )"# ).unwrap();
+
+        assert!( FeaturePlacer::new( 1, Box::new( Constant( 0.0 ).boxed() ), library ).is_err() );
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{
-    chunks_generators::utilities::create_voxel, noise::simplex_noise::SimplexNoise, structure_tests::octree::Octree, world::{
+    chunks_generators::utilities::create_voxel, noise::simplex_noise::SimplexNoise, world::{
         world_generator::WorldGenerative,
-        world_holder::{ Material, Voxel, VoxelDataset, WorldHolding }
+        world_holder::{ Material, VoxelDataset, WorldHolding }
     }
 };
 
@@ -30,24 +30,24 @@ impl GeneratorOfFloatings {
         self.color_top = color_top;
     }
 
-    pub fn generate( &self, dataset:&mut VoxelDataset, mut world_holder:Octree<Voxel>, world_origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
+    pub fn generate( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, world_origin:(i64, i64, i64), size:u8 ) {
         let size = size as i64;
 
+        // One extra layer on top for the values above the chunk
+        let noise_grid = self.noise.noise3d_grid(
+            (world_origin.0 as f64 + 1.0, world_origin.1 as f64 + 1.0, world_origin.2 as f64 + 1.0),
+            (size as usize, size as usize + 1, size as usize),
+            self.noise_frequency,
+        );
+
         for x in world_origin.0..world_origin.0 + size {
             for y in (world_origin.1..world_origin.1 + size).rev() {
                 for z in world_origin.2..world_origin.2 + size {
-                    let value = self.noise.noise3d(
-                        (x as f64 + 1.0) * self.noise_frequency,
-                        (y as f64 + 1.0) * self.noise_frequency,
-                        (z as f64 + 1.0) * self.noise_frequency,
-                    );
+                    let local = ((x - world_origin.0) as usize, (y - world_origin.1) as usize, (z - world_origin.2) as usize);
+                    let value = noise_grid.get( local.0, local.1, local.2 );
 
                     let voxel = if value < self.generation_treeeshold { None } else {
-                        let value_above = self.noise.noise3d(
-                            (x as f64 + 1.0) * self.noise_frequency,
-                            (y as f64 + 2.0) * self.noise_frequency,
-                            (z as f64 + 1.0) * self.noise_frequency,
-                        );
+                        let value_above = noise_grid.get( local.0, local.1 + 1, local.2 );
 
                         let (color, density) = if value_above < self.generation_treeeshold {
                             (self.color_top, 10)
@@ -57,7 +57,7 @@ impl GeneratorOfFloatings {
 
                         Some( create_voxel(
                             dataset,
-                            (String::from( "grass" ), Material { _density:density }),
+                            (String::from( "grass" ), Material { _density:density, ..Default::default() }),
                             (format!( "pastel-r={},g={},b={}", color.0, color.1, color.2 ), color.into() ),
                         ) )
                     };
@@ -71,14 +71,11 @@ impl GeneratorOfFloatings {
                 }
             }
         }
-
-        world_holder
     }
 }
 
 impl WorldGenerative for GeneratorOfFloatings {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
-        let world_holder = Octree::from_max_size( size as u32 );
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
         let world_origin = (origin.0 * size as i64, origin.1 * size as i64, origin.2 * size as i64);
 
         self.generate( dataset, world_holder, world_origin, size )
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use crate::{
+    chunks_generators::utilities::{ CacheStats, LruCache },
+    flags::RENDER_DISTANCE,
+    structure_tests::quadtree::Quadtree
+};
+
+/// Chunk column (x, z) and the size of its chunks
+type ColumnKey = (i64, i64, u8);
+
+/// Heightmaps of chunk columns shared by all chunks stacked in a column, so the 2D terrain noise
+/// is sampled once per column instead of once per chunk.
+/// Heightmaps are generated without holding the lock, workers asking for the same missing column at once both generate it
+pub type HeightmapCache = LruCache<ColumnKey, Quadtree>;
+pub type HeightmapCacheStats = CacheStats;
+
+/// Columns loaded around a loader, the same square `World::load_chunks` loads, with a spare ring for loader moves
+pub fn get_heightmap_cache_capacity( render_distance:u8, generation_margin:u32 ) -> usize {
+    ((render_distance as usize + 2 + generation_margin as usize) * 2 + 1).pow( 2 )
+}
+
+impl HeightmapCache {
+    /// Heightmap of the chunk column `(x, z)`, `generate` is called only when the column isn't cached
+    pub fn get_or_generate( &self, column:(i64, i64), size:u8, generate:impl FnOnce() -> Quadtree ) -> Arc<Quadtree> {
+        self.get_or_compute( (column.0, column.1, size), generate )
+    }
+}
+
+/// Sized for the default render distance until a world sizes it for its loaders
+impl Default for HeightmapCache {
+    fn default() -> Self {
+        Self::new( get_heightmap_cache_capacity( RENDER_DISTANCE, 0 ) )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use crate::{
+        chunks_generators::test_11_height_map::GeneratorOfTest11HeightMap,
+        world::{ world_generator::WorldGenerative, world_holder::{ VoxelDataset, WorldHolding } }
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_least_recently_used_column_is_evicted() {
+        let cache = HeightmapCache::new( 2 );
+        let generated = Cell::new( 0 );
+        let get = |column| cache.get_or_generate( column, 16, || {
+            generated.set( generated.get() + 1 );
+            Quadtree::from_terrain_generation( 4, &|x, z| (x + z) as f64 )
+        } );
+
+        get( (0, 0) );
+        get( (1, 0) );
+        get( (0, 0) );
+        get( (2, 0) );
+        assert_eq!( cache.len(), 2 );
+        assert_eq!( generated.get(), 3 );
+
+        get( (0, 0) );
+        assert_eq!( generated.get(), 3 );
+
+        get( (1, 0) );
+        assert_eq!( generated.get(), 4 );
+        assert_eq!( cache.get_stats(), HeightmapCacheStats { hits:2, misses:4 } );
+    }
+
+    #[test]
+    fn test_chunks_of_a_column_share_the_heightmap() {
+        let generator = GeneratorOfTest11HeightMap::new( 50 );
+        let chunks = [ (3, 1, -2), (3, 0, -2), (3, -1, -2) ]
+            .map( |origin| generator.generate_chunk( &mut VoxelDataset::new(), origin, 16 ) );
+
+        let stats = generator.get_heightmap_cache_stats().unwrap();
+        assert_eq!( stats, HeightmapCacheStats { hits:2, misses:1 } );
+        assert!( (stats.get_hit_rate() - 2.0 / 3.0).abs() < 1e-9 );
+
+        let uncached_chunk = GeneratorOfTest11HeightMap::new( 50 ).generate_chunk( &mut VoxelDataset::new(), (3, 0, -2), 16 );
+        let cached_voxels = chunks[ 1 ].get_all_voxels();
+        let uncached_voxels = uncached_chunk.get_all_voxels();
+        assert_eq!( cached_voxels.len(), uncached_voxels.len() );
+
+        for ((x, y, z, voxel), (other_x, other_y, other_z, other_voxel)) in cached_voxels.iter().zip( &uncached_voxels ) {
+            assert_eq!( (x, y, z), (other_x, other_y, other_z) );
+            assert_eq!( voxel._common_data.material, other_voxel._common_data.material );
+            assert_eq!( voxel._common_data.color, other_voxel._common_data.color );
+        }
+    }
+}
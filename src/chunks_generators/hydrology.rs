@@ -0,0 +1,370 @@
+use std::{ cmp::Ordering, collections::BinaryHeap, sync::Arc };
+
+use crate::{
+    chunks_generators::{ biomes::HeightSource, utilities::{ create_water_voxel, get_region_weights, LruCache } },
+    world::{
+        world_generator::{ GenerationStage, StageNeighbours, WorldGenerative },
+        world_holder::{ VoxelDataset, WorldHolding }
+    }
+};
+
+/// Water is computed per square region of this size
+const HYDROLOGY_REGION_SIZE: i64 = 128;
+/// Columns around a region taken into account. Lakes and river catchments wider than it are cut at its edge,
+/// so neighbouring regions can disagree about them and are cross-faded over the inner half of the margin
+const HYDROLOGY_REGION_MARGIN: i64 = 64;
+/// Columns along a side of a region kept after its flood, the core and the part of the margin it is cross-faded over
+const HYDROLOGY_STORED_SIZE: i64 = HYDROLOGY_REGION_SIZE + HYDROLOGY_REGION_MARGIN;
+const CACHED_REGIONS: usize = 64;
+/// Rivers carrying that many times more water than `river_threshold` are two voxels deep
+const DEEP_RIVER_FACTOR: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WaterColumn {
+    /// Highest terrain voxel
+    pub ground: i64,
+    /// Highest water voxel of seas and lakes, they fill everything between the ground and it
+    pub water_level: Option<i64>,
+    /// Terrain voxels from the ground down replaced by a river
+    pub river_depth: u32,
+}
+
+/// Flood of a single column as seen by one region
+#[derive(Clone, Copy)]
+struct FloodColumn {
+    height: f64,
+    level: f64,
+    accumulation: u32,
+}
+
+/// Cell of the priority flood, the lowest one is popped first and ties are broken by index to stay deterministic
+#[derive(PartialEq)]
+struct FloodCell {
+    level: f64,
+    index: usize,
+}
+
+impl Eq for FloodCell {}
+
+impl Ord for FloodCell {
+    fn cmp( &self, other:&Self ) -> Ordering {
+        other.level.total_cmp( &self.level ).then_with( || other.index.cmp( &self.index ) )
+    }
+}
+
+impl PartialOrd for FloodCell {
+    fn partial_cmp( &self, other:&Self ) -> Option<Ordering> {
+        Some( self.cmp( other ) )
+    }
+}
+
+/// Seas, lakes and rivers over a height map.
+/// Terrain is flooded from region edges and the sea (priority flood), every basin below its spill level becomes a lake
+/// and the flood order gives water its way down, so rivers appear where enough of it accumulates
+pub struct Hydrology {
+    height: Box<dyn HeightSource>,
+    sea_level: i64,
+    /// Columns draining through a column needed to start a river there, `u32::MAX` disables rivers
+    river_threshold: u32,
+    lakes: bool,
+    regions: LruCache<(i64, i64), Vec<FloodColumn>>,
+}
+
+#[allow(dead_code)]
+impl Hydrology {
+    pub fn new( height:Box<dyn HeightSource> ) -> Self {
+        Self {
+            height,
+            sea_level: 4,
+            river_threshold: 400,
+            lakes: true,
+            regions: LruCache::new( CACHED_REGIONS ),
+        }
+    }
+
+    pub fn with_sea_level( mut self, sea_level:i64 ) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    pub fn with_rivers( mut self, river_threshold:u32 ) -> Self {
+        self.river_threshold = river_threshold;
+        self
+    }
+
+    pub fn with_lakes( mut self, lakes:bool ) -> Self {
+        self.lakes = lakes;
+        self
+    }
+
+    /// Water levels and flows of the regions covering the column are blended, so lakes and rivers cut differently
+    /// by neighbouring regions meet without steps at their border
+    pub fn get_column( &self, x:i64, z:i64 ) -> WaterColumn {
+        let mut height = 0.0;
+        let mut level = 0.0;
+        let mut accumulation = 0.0;
+
+        for (region_x, weight_x) in get_region_weights( x, HYDROLOGY_REGION_SIZE, HYDROLOGY_REGION_MARGIN ) {
+            for (region_z, weight_z) in get_region_weights( z, HYDROLOGY_REGION_SIZE, HYDROLOGY_REGION_MARGIN ) {
+                let weight = weight_x * weight_z;
+                if weight == 0.0 { continue }
+
+                let region = (region_x, region_z);
+                let columns = self.regions.get_or_compute( region, || self.compute_region( region ) );
+                let stored_origin = (
+                    region_x * HYDROLOGY_REGION_SIZE - HYDROLOGY_REGION_MARGIN / 2,
+                    region_z * HYDROLOGY_REGION_SIZE - HYDROLOGY_REGION_MARGIN / 2,
+                );
+                let column = columns[ ((x - stored_origin.0) * HYDROLOGY_STORED_SIZE + z - stored_origin.1) as usize ];
+
+                height += column.height * weight;
+                level += column.level * weight;
+                accumulation += column.accumulation as f64 * weight;
+            }
+        }
+
+        let ground = height.floor() as i64;
+        let level = level.floor() as i64;
+        let is_sea = ground < self.sea_level;
+        let water_level = (level > ground && (is_sea || self.lakes)).then_some( level );
+        let river_threshold = self.river_threshold as f64;
+
+        let river_depth = match accumulation {
+            _ if water_level.is_some() || self.river_threshold == u32::MAX => 0,
+            flow if flow >= river_threshold * DEEP_RIVER_FACTOR as f64 => 2,
+            flow if flow >= river_threshold => 1,
+            _ => 0,
+        };
+
+        WaterColumn { ground, water_level, river_depth }
+    }
+
+    /// Flood of the region core and the inner half of its margin, `x` is the outer and `z` the inner axis
+    fn compute_region( &self, region:(i64, i64) ) -> Vec<FloodColumn> {
+        let size = (HYDROLOGY_REGION_SIZE + HYDROLOGY_REGION_MARGIN * 2) as usize;
+        let origin = (region.0 * HYDROLOGY_REGION_SIZE - HYDROLOGY_REGION_MARGIN, region.1 * HYDROLOGY_REGION_SIZE - HYDROLOGY_REGION_MARGIN);
+        let sea_level = self.sea_level as f64;
+
+        let heights = (0..size * size)
+            .map( |index| self.height.get_height( origin.0 + (index / size) as i64, origin.1 + (index % size) as i64 ) )
+            .collect::<Vec<_>>();
+
+        // Sea is flat at its level, so it is flooded like any other basin and rivers end in it
+        let mut filled = vec![ f64::NAN; size * size ];
+        let mut receivers = vec![ usize::MAX; size * size ];
+        let mut flood_order = Vec::with_capacity( size * size );
+        let mut queue = BinaryHeap::new();
+
+        for index in 0..size * size {
+            let (x, z) = (index / size, index % size);
+
+            if x == 0 || z == 0 || x == size - 1 || z == size - 1 {
+                filled[ index ] = heights[ index ].max( sea_level );
+                queue.push( FloodCell { level:filled[ index ], index } );
+            }
+        }
+
+        while let Some( FloodCell { level, index } ) = queue.pop() {
+            flood_order.push( index );
+
+            let (x, z) = (index / size, index % size);
+            let neighbours = [ (x.wrapping_sub( 1 ), z), (x + 1, z), (x, z.wrapping_sub( 1 )), (x, z + 1) ];
+
+            for (nx, nz) in neighbours {
+                if nx >= size || nz >= size { continue }
+
+                let neighbour = nx * size + nz;
+                if !filled[ neighbour ].is_nan() { continue }
+
+                filled[ neighbour ] = heights[ neighbour ].max( sea_level ).max( level );
+                receivers[ neighbour ] = index;
+                queue.push( FloodCell { level:filled[ neighbour ], index:neighbour } );
+            }
+        }
+
+        // Upstream columns are flooded last, so the reversed order passes water down to the receivers
+        let mut accumulation = vec![ 1u32; size * size ];
+
+        for &index in flood_order.iter().rev() {
+            if receivers[ index ] != usize::MAX {
+                accumulation[ receivers[ index ] ] = accumulation[ receivers[ index ] ].saturating_add( accumulation[ index ] );
+            }
+        }
+
+        let stored_offset = (HYDROLOGY_REGION_MARGIN / 2) as usize;
+        let stored_size = HYDROLOGY_STORED_SIZE as usize;
+        let mut columns = Vec::with_capacity( stored_size * stored_size );
+
+        for x in stored_offset..stored_offset + stored_size {
+            for z in stored_offset..stored_offset + stored_size {
+                let index = x * size + z;
+                columns.push( FloodColumn { height:heights[ index ], level:filled[ index ], accumulation:accumulation[ index ] } );
+            }
+        }
+
+        columns
+    }
+
+    /// Fills chunk voxels with water. Seas and lakes fill only empty voxels above the ground, so caves stay dry
+    pub fn fill_water( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, world_origin:(i64, i64, i64), size:u32 ) {
+        let size_i64 = size as i64;
+        let water = create_water_voxel( dataset );
+
+        for x in 0..size_i64 {
+            for z in 0..size_i64 {
+                let column = self.get_column( world_origin.0 + x, world_origin.2 + z );
+                let river_bottom = column.ground - column.river_depth as i64 + 1;
+                let top = column.water_level.unwrap_or( column.ground );
+
+                for world_y in river_bottom.min( column.ground + 1 )..=top {
+                    let y = world_y - world_origin.1;
+                    if y < 0 || y >= size_i64 { continue }
+
+                    let (x, y, z) = (x as u32, y as u32, z as u32);
+                    let is_river_bed = world_y <= column.ground;
+
+                    if is_river_bed || world_holder.get_voxel( x, y, z ).is_none() {
+                        world_holder.set_voxel( x, y, z, Some( Arc::clone( &water ) ) );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Runs hydrology in its own stage over chunks of any generator
+pub struct GeneratorWithWater {
+    generator: Box<dyn WorldGenerative>,
+    hydrology: Hydrology,
+}
+
+#[allow(dead_code)]
+impl GeneratorWithWater {
+    pub fn new( generator:Box<dyn WorldGenerative>, hydrology:Hydrology ) -> Self {
+        Self { generator, hydrology }
+    }
+}
+
+impl WorldGenerative for GeneratorWithWater {
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
+        self.generator.generate_chunk_into( dataset, world_holder, origin, size );
+    }
+
+    fn get_last_stage( &self ) -> GenerationStage {
+        self.generator.get_last_stage().max( GenerationStage::Hydrology )
+    }
+
+    /// Water columns depend only on the height source, so they don't wait for neighbours
+    fn reads_neighbours( &self, stage:GenerationStage ) -> bool {
+        self.generator.reads_neighbours( stage )
+    }
+
+    fn generate_stage( &self, stage:GenerationStage, dataset:&mut VoxelDataset, chunk:&mut dyn WorldHolding, origin:(i64, i64, i64), neighbours:&StageNeighbours ) {
+        self.generator.generate_stage( stage, dataset, chunk, origin, neighbours );
+
+        if stage == GenerationStage::Hydrology {
+            let size = neighbours.get_size();
+            let world_origin = (origin.0 * size as i64, origin.1 * size as i64, origin.2 * size as i64);
+
+            self.hydrology.fill_water( dataset, chunk, world_origin, size );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chunks_generators::biomes::{ Biome, GeneratorOfBiomes, SurfaceLayer },
+        noise::noise_graph::{ Constant, NoiseNodeExt },
+        world::world_generator::generate_chunk_stages
+    };
+
+    use super::*;
+
+    /// Crater with its rim at height 10 around (64, 64) and terrain falling down outside
+    struct Crater;
+
+    impl HeightSource for Crater {
+        fn get_height( &self, x:i64, z:i64 ) -> f64 {
+            let distance = (((x - 64).pow( 2 ) + (z - 64).pow( 2 )) as f64).sqrt();
+            if distance < 20.0 { distance / 2.0 } else { (10.0 - (distance - 20.0) / 2.0).max( -20.0 ) }
+        }
+    }
+
+    struct FnHeight<F>( F );
+
+    impl<F: Fn( i64, i64 ) -> f64 + Send + Sync> HeightSource for FnHeight<F> {
+        fn get_height( &self, x:i64, z:i64 ) -> f64 {
+            (self.0)( x, z )
+        }
+    }
+
+    /// Valley along the `x` axis with its bottom at `z = 64`, sloping down to the sea
+    struct Valley;
+
+    impl HeightSource for Valley {
+        fn get_height( &self, x:i64, z:i64 ) -> f64 {
+            x as f64 * 0.1 + (z - 64).abs() as f64 * 0.5
+        }
+    }
+
+    #[test]
+    fn test_lake_is_filled_up_to_its_rim() {
+        let hydrology = Hydrology::new( Box::new( Crater ) ).with_sea_level( -100 ).with_rivers( u32::MAX );
+
+        assert_eq!( hydrology.get_column( 64, 64 ), WaterColumn { ground:0, water_level:Some( 9 ), river_depth:0 } );
+        assert_eq!( hydrology.get_column( 70, 64 ).water_level, Some( 9 ) );
+        assert_eq!( hydrology.get_column( 64, 100 ).water_level, None );
+        assert_eq!( Hydrology::new( Box::new( Crater ) ).with_sea_level( -100 ).with_lakes( false ).get_column( 64, 64 ).water_level, None );
+    }
+
+    #[test]
+    fn test_rivers_follow_valley_bottom() {
+        let hydrology = Hydrology::new( Box::new( Valley ) ).with_sea_level( -1000 ).with_rivers( 1000 );
+        let mut river_columns = 0;
+
+        for x in 0..128 {
+            for z in 0..128 {
+                if hydrology.get_column( x, z ).river_depth > 0 {
+                    assert!( (z - 64).abs() <= 2, "river at ({x}, {z})" );
+                    river_columns += 1;
+                }
+            }
+        }
+
+        assert!( river_columns >= 100, "river_columns={river_columns}" );
+    }
+
+    #[test]
+    fn test_lakes_cut_differently_by_regions_meet_without_steps() {
+        // Basin wider than the region margin, its lowest rim is closer to the edge of the first region
+        let basin = |x:i64, z:i64| (((x - 140).pow( 2 ) + (z - 64).pow( 2 )) as f64).sqrt() / 10.0;
+        let hydrology = Hydrology::new( Box::new( FnHeight( basin ) ) ).with_sea_level( -100 ).with_rivers( u32::MAX );
+        let levels = (100..190).map( |x| hydrology.get_column( x, 64 ).water_level.unwrap() ).collect::<Vec<_>>();
+
+        assert!( levels[ 0 ] < levels[ levels.len() - 1 ], "levels={levels:?}" );
+        assert!( levels.windows( 2 ).all( |pair| (pair[ 1 ] - pair[ 0 ]).abs() <= 1 ), "levels={levels:?}" );
+    }
+
+    #[test]
+    fn test_sea_fills_chunks_with_non_solid_water() {
+        let lowland = Biome {
+            name: String::from( "lowland" ),
+            temperature: 0.0,
+            humidity: 0.0,
+            height: Box::new( Constant( -20.0 ).boxed() ),
+            surface: vec![ SurfaceLayer::new( "sand", (215, 195, 125), 2 ) ],
+        };
+
+        let terrain = GeneratorOfBiomes::new( 1, vec![ lowland ], SurfaceLayer::new( "stone", (110, 110, 110), 0 ) );
+        let generator = GeneratorWithWater::new( Box::new( terrain ), Hydrology::new( Box::new( Constant( -20.0 ).boxed() ) ).with_sea_level( 0 ) );
+        let chunk = generate_chunk_stages( &generator, &mut VoxelDataset::new(), (0, -1, 0), 32 );
+
+        let ground = chunk.get( 5, 12, 5 ).unwrap();
+        let water = chunk.get( 5, 13, 5 ).unwrap();
+
+        assert!( ground.get_material().is_solid );
+        assert!( !water.get_material().is_solid && water.get_material().is_transparent() );
+        assert!( chunk.get( 5, 31, 5 ).is_some_and( |voxel| !voxel.get_material().is_solid ) );
+    }
+}
@@ -2,6 +2,13 @@ pub mod peaks_and_valleys;
 pub mod cube;
 pub mod floatings;
 pub mod utilities;
+pub mod heightmap_cache;
+pub mod definition;
+pub mod biomes;
+pub mod caves;
+pub mod features;
+pub mod erosion;
+pub mod hydrology;
 pub mod test_1_empty;
 pub mod test_2_single;
 pub mod test_3_half;
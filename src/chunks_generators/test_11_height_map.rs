@@ -1,11 +1,12 @@
 use crate::{
+    chunks_generators::{ biomes::HeightSource, heightmap_cache::{ HeightmapCache, HeightmapCacheStats } },
     noise::simplex_noise::SimplexNoise, structure_tests::{
-        octree::Octree, quadtree::Quadtree
+        quadtree::Quadtree
     },
     world::{
         world_generator::WorldGenerative,
         world_holder::{
-            fill_with, Color, Voxel, VoxelDataset,
+            fill_with, Color, VoxelDataset, WorldHolding,
         }
     }
 };
@@ -14,6 +15,7 @@ pub struct GeneratorOfTest11HeightMap {
     noise: SimplexNoise,
     noise_frequency: f64,
     noise_amplitude: f64,
+    heightmaps: HeightmapCache,
 }
 
 impl GeneratorOfTest11HeightMap {
@@ -30,32 +32,44 @@ impl GeneratorOfTest11HeightMap {
             noise_amplitude: 10.0,
             // noise_amplitude: 15.0,
             // noise_amplitude: 20.0,
+            heightmaps: HeightmapCache::default(),
         }
     }
+
+    /// Terrain noise of the column `(x, z)` in world voxels
+    fn get_noise_at( &self, x:i64, z:i64 ) -> f64 {
+        self.noise.noise2d( x as f64 * self.noise_frequency, z as f64 * self.noise_frequency )
+    }
+
+    /// Highest solid voxel of a column with `noise_value`, relative to the grass level of its chunk
+    fn get_surface_level( &self, grass_level:i64, noise_value:f64 ) -> i64 {
+        grass_level + (noise_value * self.noise_amplitude) as i64
+    }
+}
+
+impl HeightSource for GeneratorOfTest11HeightMap {
+    fn get_height( &self, x:i64, z:i64 ) -> f64 {
+        self.get_surface_level( 8, self.get_noise_at( x, z ) ) as f64
+    }
 }
 
 impl WorldGenerative for GeneratorOfTest11HeightMap {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
         // println!( "Chunk generation {:?}, size={}", origin, size );
+        let column = (origin.0, origin.2);
         let origin = (origin.0 * size as i64, origin.1 * size as i64, origin.2 * size as i64);
         let size = size as u32;
         let max_depth = Quadtree::get_max_depth_for( size );
-        let mut world_holder = Octree::from_max_size( size );
         let grass_level = 8 - origin.1;
 
-        let quadtree = Quadtree::from_terrain_generation( max_depth, &|x, z| {
-            self.noise.noise3d(
-                (origin.0 + x as i64) as f64 * self.noise_frequency,
-                1.0,
-                (origin.2 + z as i64) as f64 * self.noise_frequency,
-            )
-        } );
+        let quadtree = self.heightmaps.get_or_generate( column, size as u8, || Quadtree::from_terrain_generation( max_depth, &|x, z| {
+            self.get_noise_at( origin.0 + x as i64, origin.2 + z as i64 )
+        } ) );
 
         quadtree.proces_entire_tree( &mut |offset, size, noise_value| {
             // if size < 2 { return offset.1 }
 
-            let multiplied_noise = noise_value * self.noise_amplitude;
-            let current_min = grass_level + multiplied_noise as i64;
+            let current_min = self.get_surface_level( grass_level, noise_value );
             if current_min < 0 || current_min < offset.1 as i64 { return offset.1 }
 
             let size = size - 1;
@@ -103,12 +117,18 @@ impl WorldGenerative for GeneratorOfTest11HeightMap {
                 //     blue: 10,
                 // };
 
-                dataset.expand( fill_with( offset, to, &mut world_holder, (&format!( "grass_{}", current_min ), grass_color) ) );
+                dataset.expand( fill_with( offset, to, world_holder, (&format!( "grass_{}", current_min ), grass_color) ) );
             }
 
             to.1 + 1
         } );
+    }
+
+    fn get_heightmap_cache_stats( &self ) -> Option<HeightmapCacheStats> {
+        Some( self.heightmaps.get_stats() )
+    }
 
-        world_holder
+    fn set_heightmap_cache_capacity( &self, columns:usize ) {
+        self.heightmaps.set_capacity( columns );
     }
 }
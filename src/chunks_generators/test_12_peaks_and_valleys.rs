@@ -1,10 +1,9 @@
 use crate::{
-    chunks_generators::utilities::create_voxel,
+    chunks_generators::{ biomes::HeightSource, utilities::create_voxel },
     noise::simplex_noise::SimplexNoise,
-    structure_tests::octree::Octree,
     world::{
         world_generator::WorldGenerative,
-        world_holder::{ Color, Material, Voxel, VoxelDataset, WorldHolding }
+        world_holder::{ Color, Material, VoxelDataset, WorldHolding }
     }
 };
 
@@ -30,11 +29,34 @@ impl GeneratorOfTest12PeaksAndValleys {
             // noise_amplitude: 20.0,
         }
     }
+
+    /// Terrain noise of the voxel `(x, y, z)` in world voxels
+    fn get_noise_at( &self, x:i64, y:i64, z:i64 ) -> f64 {
+        let frequency = self.noise_frequency;
+        self.noise.noise3d( (x + 1) as f64 * frequency, (y + 1) as f64 * frequency, (z + 1) as f64 * frequency )
+    }
+
+    /// Voxels up to this level are solid where the noise is `noise_value`, relative to the grass level of their chunk
+    fn get_ground_level( &self, grass_level:i64, noise_value:f64 ) -> i64 {
+        grass_level + (noise_value * self.noise_amplitude) as i64
+    }
+}
+
+/// Highest solid voxel of a column in the chunk at height 0, overhangs are flattened
+impl HeightSource for GeneratorOfTest12PeaksAndValleys {
+    fn get_height( &self, x:i64, z:i64 ) -> f64 {
+        let amplitude = self.noise_amplitude as i64;
+
+        for y in ((8 - amplitude)..=(8 + amplitude)).rev() {
+            if y <= self.get_ground_level( 8, self.get_noise_at( x, y, z ) ) { return y as f64 }
+        }
+
+        (8 - amplitude) as f64
+    }
 }
 
 impl WorldGenerative for GeneratorOfTest12PeaksAndValleys {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
-        let mut world_holder = Octree::from_max_size( size as u32 );
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
         let grass_level = 8 - origin.1;
 
         let world_origin = (origin.0 * size as i64, origin.1 * size as i64, origin.2 * size as i64);
@@ -45,30 +67,32 @@ impl WorldGenerative for GeneratorOfTest12PeaksAndValleys {
             let color = Color { red:204, green:204, blue:196 };
             let voxel = Some( create_voxel(
                 dataset,
-                (String::from( "grass" ), Material { _density:10 }),
+                (String::from( "grass" ), Material { _density:10, ..Default::default() }),
                 (format!( "pastel-r={},g={},b={}", color.red, color.green, color.blue ), color ),
             ) );
 
             world_holder.fill_voxels( (0, 0, 0), (size - 1, size - 1, size -1), voxel );
 
-            return world_holder;
+            return;
         }
 
         if (world_origin.1 as f64) > 1.0 * self.noise_amplitude {
-            return world_holder;
+            return;
         }
 
+        let noise_grid = self.noise.noise3d_grid(
+            (world_origin.0 as f64 + 1.0, world_origin.1 as f64 + 1.0, world_origin.2 as f64 + 1.0),
+            (size as usize, size as usize, size as usize),
+            self.noise_frequency,
+        );
+
         for x in world_origin.0..world_origin.0 + size {
             for y in (world_origin.1..world_origin.1 + size).rev() {
                 for z in world_origin.2..world_origin.2 + size {
-                    let noise_value = self.noise.noise3d(
-                        (x as f64 + 1.0) * self.noise_frequency,
-                        (y as f64 + 1.0) * self.noise_frequency,
-                        (z as f64 + 1.0) * self.noise_frequency,
-                    );
+                    let noise_value = noise_grid.get( (x - world_origin.0) as usize, (y - world_origin.1) as usize, (z - world_origin.2) as usize );
 
                     let multiplied_noise = noise_value * self.noise_amplitude;
-                    let current_min = grass_level + multiplied_noise as i64;
+                    let current_min = self.get_ground_level( grass_level, noise_value );
 
                     if y > current_min { continue }
 
@@ -87,7 +111,7 @@ impl WorldGenerative for GeneratorOfTest12PeaksAndValleys {
 
                         Some( create_voxel(
                             dataset,
-                            (String::from( "grass" ), Material { _density:10 }),
+                            (String::from( "grass" ), Material { _density:10, ..Default::default() }),
                             (format!( "pastel-r={},g={},b={}", color.red, color.green, color.blue ), color ),
                         ) )
                     };
@@ -101,7 +125,5 @@ impl WorldGenerative for GeneratorOfTest12PeaksAndValleys {
                 }
             }
         }
-
-        world_holder
     }
 }
@@ -5,14 +5,15 @@ use rand::{ rngs::SmallRng, Rng, SeedableRng };
 use crate::{
     chunks_generators::{
         floatings::GeneratorOfFloatings,
-        utilities::{create_voxel, generate_unique}
+        heightmap_cache::{ HeightmapCache, HeightmapCacheStats },
+        utilities::{create_voxel, create_water_voxel, generate_unique}
     },
-    noise::simplex_noise::SimplexNoise,
-    structure_tests::{ octree::Octree, quadtree::Quadtree },
+    noise::simplex_noise::{ NoiseStream, SimplexNoise },
+    structure_tests::quadtree::Quadtree,
     world::{
         world::CHUNK_SIZE,
         world_generator::WorldGenerative,
-        world_holder::{ fill_with, Color, Material, Voxel, VoxelDataset, WorldHolding }
+        world_holder::{ fill_with, Color, Material, VoxelDataset, WorldHolding }
     }
 };
 
@@ -27,6 +28,7 @@ pub struct GeneratorOfTest13PlainsWithFloatings {
     noise_amplitude: f64,
     noise_amplitude_hills: f64,
     hills_smoothing_length: i64,
+    heightmaps: HeightmapCache,
 }
 
 impl GeneratorOfTest13PlainsWithFloatings {
@@ -41,7 +43,7 @@ impl GeneratorOfTest13PlainsWithFloatings {
         Self {
             clouds_generator,
             noise: SimplexNoise::new( seed ),
-            hills_noise: SimplexNoise::new( !seed ),
+            hills_noise: SimplexNoise::with_stream( seed, NoiseStream::Hills ),
 
             // noise_frequency: 0.1,
             // noise_frequency: 0.05,
@@ -56,47 +58,61 @@ impl GeneratorOfTest13PlainsWithFloatings {
             noise_amplitude_hills: 100.0,
             // noise_amplitude_hills: 50.0,
             hills_smoothing_length: 100,
+            heightmaps: HeightmapCache::default(),
         }
     }
 }
 
 impl WorldGenerative for GeneratorOfTest13PlainsWithFloatings {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
         // println!( "Chunk generation {:?}, size={}", origin, size );
         let world_origin = (origin.0 * size as i64, origin.1 * size as i64, origin.2 * size as i64);
         let size_u32 = size as u32;
         let max_depth = Quadtree::get_max_depth_for( size_u32 );
-        let mut world_holder = Octree::from_max_size( size_u32 );
         let grass_level = 8 - world_origin.1;
 
-        let quadtree = Quadtree::from_terrain_generation( max_depth, &|x, z| {
+        let quadtree = self.heightmaps.get_or_generate( (origin.0, origin.2), size, || Quadtree::from_terrain_generation( max_depth, &|x, z| {
             let coords = (
                 (world_origin.0 + x as i64) as f64,
-                1.0,
                 (world_origin.2 + z as i64) as f64,
             );
 
-            let mut noise = self.noise.noise3d( coords.0 * self.noise_frequency, coords.1, coords.2 * self.noise_frequency )
+            let mut noise = self.noise.noise2d( coords.0 * self.noise_frequency, coords.1 * self.noise_frequency )
                 * self.noise_amplitude;
 
             if world_origin.2 >= 0 {
                 let min = world_origin.2 + z as i64;
                 let mul = min.clamp( 0, self.hills_smoothing_length ) as f64 / self.hills_smoothing_length as f64;
-                noise += (self.hills_noise.noise3d( coords.0 * self.noise_frequency_hills, coords.1, coords.2 * self.noise_frequency_hills ) + 0.5)
+                noise += (self.hills_noise.noise2d( coords.0 * self.noise_frequency_hills, coords.1 * self.noise_frequency_hills ) + 0.5)
                     * self.noise_amplitude_hills * mul;
             }
 
             noise
-        } );
+        } ) );
+
+        let water_level = grass_level - 8;
+        let water = create_water_voxel( dataset );
 
         quadtree.proces_entire_tree( &mut |offset, size, noise_value| {
             let current_min = grass_level + noise_value as i64;
+            let water_from = (current_min + 1).max( 0 );
+            let water_to = water_level.min( size_u32 as i64 - 1 );
+
+            // Terrain of deeper nodes overrides the water filled here
+            if water_from <= water_to {
+                world_holder.fill_voxels(
+                    (offset.0, water_from as u32, offset.2),
+                    (offset.0 + size - 1, water_to as u32, offset.2 + size - 1),
+                    Some( Arc::clone( &water ) )
+                );
+            }
+
             if current_min < 0 || current_min < offset.1 as i64 { return offset.1 }
 
             let size = size - 1;
             let to = (offset.0 + size, current_min as u32, offset.2 + size);
 
-            let below_water = current_min < grass_level - 8;
+            let below_water = current_min < water_level;
             let high = current_min > grass_level + 15;
             let peak = current_min > grass_level + 50;
 
@@ -106,7 +122,7 @@ impl WorldGenerative for GeneratorOfTest13PlainsWithFloatings {
                     } else if high && noise_value > 0.7 {
                         0.max( 50 - (noise_value * 1.0) as i32 ) as u8
                     } else if below_water {
-                        if current_min % 2 == 0 { 10 } else { 25 }
+                        if current_min % 2 == 0 { 190 } else { 205 }
                     } else {
                         30
                     },
@@ -115,7 +131,7 @@ impl WorldGenerative for GeneratorOfTest13PlainsWithFloatings {
                     } else if high && noise_value > 0.7 {
                         0.max( 220 - (noise_value * 4.0) as i32 ) as u8
                     } else if below_water {
-                        255.min( (noise_value * 10.0) as i32 ) as u8
+                        if current_min % 2 == 0 { 175 } else { 190 }
                     } else {
                         if current_min % 2 == 0 { 125 } else { 145 }
                     },
@@ -124,13 +140,13 @@ impl WorldGenerative for GeneratorOfTest13PlainsWithFloatings {
                     } else if high && noise_value > 0.7 {
                         0.max( 50 - (noise_value * 1.0) as i32 ) as u8
                     } else if below_water {
-                        if current_min % 2 == 0 { 175 } else { 200 }
+                        if current_min % 2 == 0 { 120 } else { 130 }
                     } else {
                         20
                     },
             };
 
-            dataset.expand( fill_with( offset, to, &mut world_holder, (&format!( "grass_{}", current_min ), color) ) );
+            dataset.expand( fill_with( offset, to, world_holder, (&format!( "grass_{}", current_min ), color) ) );
 
             to.1 + 1
         } );
@@ -158,7 +174,7 @@ impl WorldGenerative for GeneratorOfTest13PlainsWithFloatings {
                     loop {
                         div_size /= 2;
 
-                        if world_holder.get( x, y, z ).is_some() {
+                        if world_holder.get_voxel( x, y, z ).is_some_and( |voxel| voxel.get_material().is_solid ) {
                             y += div_size;
                         } else {
                             y -= div_size;
@@ -170,7 +186,7 @@ impl WorldGenerative for GeneratorOfTest13PlainsWithFloatings {
                     }
 
                     if y > 0 {
-                        plant_tree( dataset, &mut world_holder, (x, y, z) );
+                        plant_tree( dataset, world_holder, (x, y, z) );
                     }
                 }
             }
@@ -192,22 +208,28 @@ impl WorldGenerative for GeneratorOfTest13PlainsWithFloatings {
             if origin.1 >= 5 && rng_val > 250 {
                 // world_holder = self.cube_generator.generate( dataset, world_holder, world_origin, size );
             } else if rng_val > 175 {
-                world_holder = self.clouds_generator.generate( dataset, world_holder, world_origin, size );
+                self.clouds_generator.generate( dataset, world_holder, world_origin, size );
                 // world_holder = self.clouds_generator.generate( dataset, world_holder, world_origin, size );
             }
         }
+    }
+
+    fn get_heightmap_cache_stats( &self ) -> Option<HeightmapCacheStats> {
+        Some( self.heightmaps.get_stats() )
+    }
 
-        world_holder
+    fn set_heightmap_cache_capacity( &self, columns:usize ) {
+        self.heightmaps.set_capacity( columns );
     }
 }
 
-fn plant_tree( dataset:&mut VoxelDataset, world_holder:&mut Octree<Voxel>, coords:(u32, u32, u32) ) {
+fn plant_tree( dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, coords:(u32, u32, u32) ) {
     if coords.0 < 2 || coords.1 > CHUNK_SIZE_U32 - 8 || coords.2 < 2 {
         return
     }
 
-    let log = create_voxel( dataset, (String::from( "log" ), Material { _density:10 }), (String::from( "log" ), Color { red:175, green:40, blue:20 }) );
-    let leaves = create_voxel( dataset, (String::from( "leaves" ), Material { _density:10 }), (String::from( "leaves" ), Color { red:20, green:100, blue:20 }) );
+    let log = create_voxel( dataset, (String::from( "log" ), Material { _density:10, ..Default::default() }), (String::from( "log" ), Color { red:175, green:40, blue:20 }) );
+    let leaves = create_voxel( dataset, (String::from( "leaves" ), Material { _density:10, ..Default::default() }), (String::from( "leaves" ), Color { red:20, green:100, blue:20 }) );
 
     world_holder.fill_voxels(
         (coords.0, coords.1, coords.2),
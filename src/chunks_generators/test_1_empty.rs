@@ -1,9 +1,6 @@
-use crate::{
-    structure_tests::octree::Octree,
-    world::{
-        world_generator::WorldGenerative,
-        world_holder::{ Voxel, VoxelDataset }
-    }
+use crate::world::{
+    world_generator::WorldGenerative,
+    world_holder::{ VoxelDataset, WorldHolding }
 };
 
 pub struct GeneratorOfTest1Empty {
@@ -17,7 +14,5 @@ impl GeneratorOfTest1Empty {
 }
 
 impl WorldGenerative for GeneratorOfTest1Empty {
-    fn generate_chunk( &self, _dataset:&mut VoxelDataset, _origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
-        Octree::from_max_size( size as u32 )
-    }
+    fn generate_chunk_into( &self, _dataset:&mut VoxelDataset, _world_holder:&mut dyn WorldHolding, _origin:(i64, i64, i64), _size:u8 ) {}
 }
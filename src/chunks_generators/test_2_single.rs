@@ -1,9 +1,8 @@
 use crate::{
     chunks_generators::utilities::create_voxel,
-    structure_tests::octree::Octree,
     world::{
         world_generator::WorldGenerative,
-        world_holder::{ Material, Voxel, VoxelDataset, WorldHolding }
+        world_holder::{ Material, VoxelDataset, WorldHolding }
     }
 };
 
@@ -21,9 +20,7 @@ impl GeneratorOfTest2Single {
 }
 
 impl WorldGenerative for GeneratorOfTest2Single {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
-        let mut world_holder = Octree::from_max_size( size as u32 );
-
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), _size:u8 ) {
         if origin.0 == 0 && origin.1 == 0 && origin.2 == 0 {
             world_holder.set_voxel(
                 0,
@@ -31,12 +28,10 @@ impl WorldGenerative for GeneratorOfTest2Single {
                 0,
                 Some( create_voxel(
                     dataset,
-                    (String::from( "grass" ), Material { _density:10 }),
+                    (String::from( "grass" ), Material { _density:10, ..Default::default() }),
                     (String::from( "grass" ), self.color.into() ),
                 ) )
             );
         }
-
-        world_holder
     }
 }
@@ -1,8 +1,7 @@
 use crate::{
     chunks_generators::utilities::create_voxel,
-    structure_tests::octree::Octree,
     world::{
-        world_generator::WorldGenerative, world_holder::{ Material, Voxel, VoxelDataset, WorldHolding }
+        world_generator::WorldGenerative, world_holder::{ Material, VoxelDataset, WorldHolding }
     }
 };
 
@@ -20,9 +19,7 @@ impl GeneratorOfTest3Half {
 }
 
 impl WorldGenerative for GeneratorOfTest3Half {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
-        let mut world_holder = Octree::from_max_size( size as u32 );
-
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
         if origin.1 == 0 {
             let size = size as u32;
             world_holder.fill_voxels(
@@ -30,12 +27,10 @@ impl WorldGenerative for GeneratorOfTest3Half {
                 (size, size, size),
                 Some( create_voxel(
                     dataset,
-                    (String::from( "grass" ), Material { _density:10 }),
+                    (String::from( "grass" ), Material { _density:10, ..Default::default() }),
                     (String::from( "grass" ), self.color.into() ),
                 ) )
             );
         }
-
-        world_holder
     }
 }
@@ -2,10 +2,9 @@ use std::sync::Arc;
 
 use crate::{
     chunks_generators::utilities::{create_voxel, generate_unique},
-    structure_tests::octree::Octree,
     world::{
         world_generator::WorldGenerative,
-        world_holder::{ Material, Voxel, VoxelDataset, WorldHolding }
+        world_holder::{ Material, VoxelDataset, WorldHolding }
     }
 };
 
@@ -23,8 +22,7 @@ impl GeneratorOfTest4HalfRandom {
 }
 
 impl WorldGenerative for GeneratorOfTest4HalfRandom {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
-        let mut world_holder = Octree::from_max_size( size as u32 );
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
         let size = size as usize;
         let randoms = generate_unique(
             (origin.0.abs() as u64) << 6 | (origin.1.abs() as u64) << 3 | (origin.2.abs() as u64),
@@ -34,7 +32,7 @@ impl WorldGenerative for GeneratorOfTest4HalfRandom {
         let size = size as u32;
         let voxel = create_voxel(
             dataset,
-            (String::from( "grass" ), Material { _density:10 }),
+            (String::from( "grass" ), Material { _density:10, ..Default::default() }),
             (String::from( "grass" ), self.color.into() ),
         );
 
@@ -50,7 +48,5 @@ impl WorldGenerative for GeneratorOfTest4HalfRandom {
                 Some( Arc::clone( &voxel ) ),
             );
         }
-
-        world_holder
     }
 }
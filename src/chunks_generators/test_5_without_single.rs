@@ -1,9 +1,8 @@
 use crate::{
     chunks_generators::utilities::create_voxel,
-    structure_tests::octree::Octree,
     world::{
         world_generator::WorldGenerative,
-        world_holder::{ Material, Voxel, VoxelDataset, WorldHolding }
+        world_holder::{ Material, VoxelDataset, WorldHolding }
     }
 };
 
@@ -21,8 +20,7 @@ impl GeneratorOfTest5WithoutSingle {
 }
 
 impl WorldGenerative for GeneratorOfTest5WithoutSingle {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
-        let mut world_holder = Octree::from_max_size( size as u32 );
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
         let size = size as u32;
 
         world_holder.fill_voxels(
@@ -30,7 +28,7 @@ impl WorldGenerative for GeneratorOfTest5WithoutSingle {
             (size, size, size),
             Some( create_voxel(
                 dataset,
-                (String::from( "grass" ), Material { _density:10 }),
+                (String::from( "grass" ), Material { _density:10, ..Default::default() }),
                 (String::from( "grass" ), self.color.into() ),
             ) )
         );
@@ -38,7 +36,5 @@ impl WorldGenerative for GeneratorOfTest5WithoutSingle {
         if origin.0 == 0 && origin.1 == 0 && origin.2 == 0 {
             world_holder.set_voxel( 0, 0, 0, None );
         }
-
-        world_holder
     }
 }
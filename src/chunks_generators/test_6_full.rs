@@ -1,9 +1,8 @@
 use crate::{
     chunks_generators::utilities::create_voxel,
-    structure_tests::octree::Octree,
     world::{
         world_generator::WorldGenerative,
-        world_holder::{ Material, Voxel, VoxelDataset, WorldHolding }
+        world_holder::{ Material, VoxelDataset, WorldHolding }
     }
 };
 
@@ -21,8 +20,7 @@ impl GeneratorOfTest6Full {
 }
 
 impl WorldGenerative for GeneratorOfTest6Full {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, _origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
-        let mut world_holder = Octree::from_max_size( size as u32 );
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, _origin:(i64, i64, i64), size:u8 ) {
         let size = size as u32;
 
         world_holder.fill_voxels(
@@ -30,11 +28,9 @@ impl WorldGenerative for GeneratorOfTest6Full {
             (size, size, size),
             Some( create_voxel(
                 dataset,
-                (String::from( "grass" ), Material { _density:10 }),
+                (String::from( "grass" ), Material { _density:10, ..Default::default() }),
                 (String::from( "grass" ), self.color.into() ),
             ) )
         );
-
-        world_holder
     }
 }
@@ -1,9 +1,8 @@
 use crate::{
     chunks_generators::utilities::{ create_voxel, get_pastel_color, generate_woksel_index, hash_u32 },
-    structure_tests::octree::Octree,
     world::{
         world_generator::WorldGenerative,
-        world_holder::{ Material, Voxel, VoxelDataset, WorldHolding }
+        world_holder::{ Material, VoxelDataset, WorldHolding }
     }
 };
 
@@ -21,8 +20,7 @@ impl GeneratorOfTest8FullWithDifferenties {
 }
 
 impl WorldGenerative for GeneratorOfTest8FullWithDifferenties {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
-        let mut world_holder = Octree::from_max_size( size as u32 );
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
         let size = size as u32;
         let colors_count = self.colors_count as u32;
 
@@ -38,7 +36,7 @@ impl WorldGenerative for GeneratorOfTest8FullWithDifferenties {
                         z,
                         Some( create_voxel(
                             dataset,
-                            (String::from( "pastel" ), Material { _density:10 }),
+                            (String::from( "pastel" ), Material { _density:10, ..Default::default() }),
                             (format!( "pastel-r={},g={},b={}", color.0, color.1, color.2 ), color.into() ),
                         ) ),
                     );
@@ -47,7 +45,5 @@ impl WorldGenerative for GeneratorOfTest8FullWithDifferenties {
         }
 
         println!( "dataset lengths | voxels = {}, colors = {}", dataset.voxels.len(), dataset.colors.len() );
-
-        world_holder
     }
 }
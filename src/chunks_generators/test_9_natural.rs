@@ -1,9 +1,8 @@
 use crate::{
     chunks_generators::cube::GeneratorOfCube,
-    structure_tests::octree::Octree,
     world::{
         world_generator::WorldGenerative,
-        world_holder::{ Voxel, VoxelDataset }
+        world_holder::{ VoxelDataset, WorldHolding }
     }
 };
 
@@ -21,9 +20,7 @@ impl GeneratorOfTest9Natural {
 }
 
 impl WorldGenerative for GeneratorOfTest9Natural {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
-        let world_holder = self.cube_generator.generate_chunk( dataset, origin, size );
-
-        world_holder
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
+        self.cube_generator.generate_chunk_into( dataset, world_holder, origin, size );
     }
 }
@@ -1,4 +1,8 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    sync::{ atomic::{ AtomicU64, AtomicUsize, Ordering }, Arc, Mutex }
+};
 
 use rand::{ rngs::StdRng, Rng, SeedableRng };
 
@@ -20,13 +24,21 @@ pub fn create_voxel( dataset:&mut VoxelDataset, material:(String, Material), col
     } ) );
 
     dataset.voxels.insert( composed_key.clone(), Arc::new( Voxel {
-        _individual_data: vec![],
         _common_data: Arc::clone( dataset.common_voxel_dataset.get( &composed_key ).unwrap() ),
     } ) );
 
     Arc::clone( dataset.voxels.get( &composed_key ).unwrap() )
 }
 
+/// Water of seas, lakes and rivers, every generator uses it so water looks and dims the light the same everywhere
+pub fn create_water_voxel( dataset:&mut VoxelDataset ) -> Arc<Voxel> {
+    create_voxel(
+        dataset,
+        (String::from( "water" ), Material { _density:1, opacity:2, is_solid:false, ..Default::default() }),
+        (String::from( "water" ), Color { red:40, green:90, blue:200 })
+    )
+}
+
 pub fn generate_unique( seed:u64, n:usize ) -> Vec<u32> {
     let mut rng = StdRng::seed_from_u64( seed );
     let mut set = HashSet::with_capacity( n );
@@ -95,4 +107,138 @@ pub fn get_pastel_color( index:u32, max_colors:u32 ) -> (u8, u8, u8) {
     let value = 0.75;
 
     hsv_to_rgb( hue, saturation, value )
-}
\ No newline at end of file
+}
+
+/// Regions covering the coordinate with their weights, which always sum up to 1.
+/// Neighbouring regions are cross-faded over the `margin` columns around their border
+pub fn get_region_weights( coordinate:i64, region_size:i64, margin:i64 ) -> [(i64, f64); 2] {
+    let half_margin = margin / 2;
+    let region = coordinate.div_euclid( region_size );
+    let local = coordinate.rem_euclid( region_size );
+
+    if local < half_margin {
+        let t = (local + half_margin) as f64 / margin as f64;
+        [ (region - 1, 1.0 - t), (region, t) ]
+    } else if local >= region_size - half_margin {
+        let t = (local - (region_size - half_margin)) as f64 / margin as f64;
+        [ (region, 1.0 - t), (region + 1, t) ]
+    } else {
+        [ (region, 1.0), (region + 1, 0.0) ]
+    }
+}
+
+/// Hits and misses of an `LruCache` since its creation or the last reset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    pub fn get_hit_rate( &self ) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            requests => self.hits as f64 / requests as f64,
+        }
+    }
+}
+
+struct LruCacheState<K, T> {
+    entries: HashMap<K, (Arc<T>, u64)>,
+    last_use: u64,
+}
+
+/// Results shared by all chunks asking for them, computed on first use.
+/// The least recently used entries are dropped when the cache is full
+pub struct LruCache<K, T> {
+    state: Mutex<LruCacheState<K, T>>,
+    capacity: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K:Copy + Eq + Hash, T> LruCache<K, T> {
+    pub fn new( capacity:usize ) -> Self {
+        Self {
+            state: Mutex::new( LruCacheState { entries:HashMap::new(), last_use:0 } ),
+            capacity: AtomicUsize::new( capacity.max( 1 ) ),
+            hits: AtomicU64::new( 0 ),
+            misses: AtomicU64::new( 0 ),
+        }
+    }
+
+    /// Computation runs without the lock, two threads can compute the same entry, so it has to be deterministic
+    pub fn get_or_compute( &self, key:K, compute:impl FnOnce() -> T ) -> Arc<T> {
+        if let Some( value ) = self.get( &key ) {
+            self.hits.fetch_add( 1, Ordering::Relaxed );
+            return value
+        }
+
+        self.misses.fetch_add( 1, Ordering::Relaxed );
+        let value = Arc::new( compute() );
+        let capacity = self.get_capacity();
+        let mut state = self.state.lock().unwrap();
+
+        if !state.entries.contains_key( &key ) {
+            while state.entries.len() >= capacity {
+                let least_recent = state.entries.iter()
+                    .min_by_key( |(_, (_, last_use))| *last_use )
+                    .map( |(&key, _)| key );
+
+                match least_recent {
+                    Some( least_recent ) => state.entries.remove( &least_recent ),
+                    None => break,
+                };
+            }
+        }
+
+        state.last_use += 1;
+        let last_use = state.last_use;
+        state.entries.insert( key, (Arc::clone( &value ), last_use) );
+
+        value
+    }
+
+    pub fn get_capacity( &self ) -> usize {
+        self.capacity.load( Ordering::Relaxed )
+    }
+
+    /// Entries above a smaller capacity are dropped on the next insert
+    pub fn set_capacity( &self, capacity:usize ) {
+        self.capacity.store( capacity.max( 1 ), Ordering::Relaxed );
+    }
+
+    #[allow(dead_code)]
+    pub fn len( &self ) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty( &self ) -> bool {
+        self.state.lock().unwrap().entries.is_empty()
+    }
+
+    pub fn get_stats( &self ) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load( Ordering::Relaxed ),
+            misses: self.misses.load( Ordering::Relaxed ),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn reset_stats( &self ) {
+        self.hits.store( 0, Ordering::Relaxed );
+        self.misses.store( 0, Ordering::Relaxed );
+    }
+
+    fn get( &self, key:&K ) -> Option<Arc<T>> {
+        let mut state = self.state.lock().unwrap();
+        state.last_use += 1;
+        let last_use = state.last_use;
+
+        state.entries.get_mut( key ).map( |(value, value_last_use)| {
+            *value_last_use = last_use;
+            Arc::clone( value )
+        } )
+    }
+}
@@ -1,4 +1,6 @@
 pub const SIMULATED_TEST_WORLD_ID:u8 = 12;
+/// Environment variable with a path to RON/JSON generator definition, used instead of `SIMULATED_TEST_WORLD_ID` when set
+pub const GENERATOR_DEFINITION_ENV:&str = "GENERATOR_DEFINITION";
 pub const RENDER_DISTANCE:u8 = 4;
 pub const CPUS_COUNT:u8 = 8;
 
@@ -4,12 +4,11 @@ mod structure_tests;
 mod chunks_generators;
 mod rendering;
 mod app;
+mod measurements;
 pub mod flags;
 
 use crate::app::app::App;
 
-// use crate::measurements::measure;
-// mod measurements;
 // #[allow(unused_imports)]
 // use crate::{ app::app::App, structure_tests::octree::Octree, world::chunk_region_iterator::ChunkRegionIterator };
 
@@ -18,7 +17,19 @@ use crate::app::app::App;
 static ALLOC: dhat::Alloc = dhat::Alloc;
 
 fn main() {
-    // measure()
+    // measurements::measure()
+
+    let args = std::env::args().collect::<Vec<_>>();
+
+    if args.get( 1 ).map( String::as_str ) == Some( "compare" ) {
+        measurements::compare( &args[ 2.. ] );
+        return
+    }
+
+    if args.get( 1 ).map( String::as_str ) == Some( "concurrency" ) {
+        measurements::concurrency::measure_concurrency( &args[ 2.. ] );
+        return
+    }
 
     pretty_env_logger::init();
 
@@ -0,0 +1,199 @@
+use std::{ fmt::Write as _, fs, time::{ Duration, Instant } };
+
+use rand::{ rngs::SmallRng, Rng, SeedableRng };
+
+use crate::{
+    chunks_generators::test_11_height_map::GeneratorOfTest11HeightMap,
+    flags::CPUS_COUNT,
+    world::{
+        chunk_storage::Backend,
+        chunks_map::ChunksLockWait,
+        world::{ VoxelPosition, World, CHUNK_SIZE }
+    }
+};
+
+/// One run of the worker pool
+#[derive(Debug, Clone, Copy)]
+struct ConcurrencyCase {
+    /// Structure holding the voxels of the chunks
+    storage: Backend,
+    workers_count: u8,
+    /// Part of the voxel batches which are reads, the rest are writes
+    read_ratio: f32,
+    /// Part of the voxels taken from the chunk of the loader, the rest comes from the whole rendered area
+    contention: f32,
+}
+
+struct ConcurrencyRecord {
+    case: ConcurrencyCase,
+    time: Duration,
+    lock_wait: ChunksLockWait,
+}
+
+struct ConcurrencySettings {
+    operations: u32,
+    batch_size: u32,
+    render_distance: u8,
+    /// Loader moves by one chunk that many times during the run, every move adds and removes chunks of the map
+    moves: u32,
+}
+
+/// Runs the `World` worker pool with batches of voxel reads and writes queued at once and reports
+/// the throughput and the time spent waiting for the shard locks of `ChunksDataset::chunks`.
+/// Arguments: `[--storages name,name] [--workers n,n] [--read-ratios r,r] [--contention c,c] [--operations n] [--batch n] [--render-distance n] [--moves n] [--out file]`.
+/// The worker pool of every run is stopped before the next one starts
+pub fn measure_concurrency( args:&[String] ) {
+    let mut storages = vec![ Backend::Octree ];
+    let mut workers_counts = vec![ CPUS_COUNT - 1 ];
+    let mut read_ratios = vec![ 0.9 ];
+    let mut contentions = vec![ 0.0 ];
+    let mut settings = ConcurrencySettings { operations:100_000, batch_size:64, render_distance:1, moves:0 };
+    let mut out = String::from( "concurrency.csv" );
+
+    for pair in args.chunks( 2 ) {
+        let [key, value] = pair else { panic!( "Missing value of the argument {}", pair[ 0 ] ) };
+        let numbers = || value.split( ',' ).map( |number| number.parse::<f32>().unwrap_or_else( |_| panic!( "{key} should be numbers" ) ) ).collect::<Vec<_>>();
+        let number = || value.parse::<u32>().unwrap_or_else( |_| panic!( "{key} should be a number" ) );
+
+        match key.as_str() {
+            "--storages" => storages = value.split( ',' ).map( |name| Backend::from_name( name ).unwrap_or_else( || panic!( "Unknown storage: {name}" ) ) ).collect(),
+            "--workers" => workers_counts = numbers().into_iter().map( |count| count as u8 ).collect(),
+            "--read-ratios" => read_ratios = numbers(),
+            "--contention" => contentions = numbers(),
+            "--operations" => settings.operations = number(),
+            "--batch" => settings.batch_size = number().max( 1 ),
+            "--render-distance" => settings.render_distance = number() as u8,
+            "--moves" => settings.moves = number(),
+            "--out" => out = value.clone(),
+            _ => panic!( "Unknown argument: {key}" ),
+        }
+    }
+
+    let mut records = vec![];
+
+    for &storage in &storages {
+        for &workers_count in &workers_counts {
+            for &read_ratio in &read_ratios {
+                for &contention in &contentions {
+                    let case = ConcurrencyCase { storage, workers_count, read_ratio, contention };
+
+                    println!( "Running {} / {workers_count} workers / read ratio {read_ratio} / contention {contention}", storage.get_name() );
+                    records.push( run_case( case, &settings ) );
+                }
+            }
+        }
+    }
+
+    println!( "{}", to_markdown( &records, &settings ) );
+
+    fs::write( &out, to_csv( &records, &settings ) ).unwrap_or_else( |error| panic!( "Can't write the concurrency report: {error}" ) );
+    println!( "Concurrency report written to {out}" );
+}
+
+fn run_case( case:ConcurrencyCase, settings:&ConcurrencySettings ) -> ConcurrencyRecord {
+    let mut world = World::with_storage( Box::new( GeneratorOfTest11HeightMap::new( 50 ) ), None, case.workers_count.max( 1 ), case.storage );
+    let chunk_loader = world.create_chunk_loader( (0.0, 0.0, 0.0), settings.render_distance );
+
+    wait_for_workers( &mut world );
+
+    let mut rng = SmallRng::seed_from_u64( 50 );
+    let voxel = world.get_palette()[ 0 ].clone();
+    let batches_count = settings.operations.div_ceil( settings.batch_size );
+    let move_every = batches_count / (settings.moves + 1);
+    let mut moves = 0;
+
+    world.set_chunks_lock_wait_collection( true );
+    world.reset_chunks_lock_wait();
+    let time_start = Instant::now();
+
+    for batch in 0..batches_count {
+        let positions = (0..settings.batch_size)
+            .map( |_| get_random_position( &mut rng, case.contention, settings.render_distance, moves ) )
+            .collect::<Vec<_>>();
+
+        if rng.random::<f32>() < case.read_ratio {
+            world.queue_voxel_reads( positions );
+        } else {
+            world.queue_voxel_writes( positions.into_iter().map( |position| (position, rng.random_bool( 0.5 ).then( || voxel.clone() )) ).collect() );
+        }
+
+        if moves < settings.moves && batch > 0 && batch % move_every.max( 1 ) == 0 {
+            moves += 1;
+            world.move_chunk_loader_to( &chunk_loader, ((moves as usize * CHUNK_SIZE) as f32, 0.0, 0.0), false );
+        }
+
+        world.update();
+    }
+
+    wait_for_workers( &mut world );
+
+    let record = ConcurrencyRecord { case, time:time_start.elapsed(), lock_wait:world.get_chunks_lock_wait() };
+    world.shutdown();
+
+    record
+}
+
+fn wait_for_workers( world:&mut World ) {
+    while !world.is_idle() {
+        world.update();
+        std::thread::yield_now();
+    }
+}
+
+/// Voxel of the rendered area around the loader, which is moved `moves` chunks along the x axis
+fn get_random_position( rng:&mut SmallRng, contention:f32, render_distance:u8, moves:u32 ) -> VoxelPosition {
+    let chunk_size = CHUNK_SIZE as i64;
+    let render_distance = render_distance as i64;
+    let chunk = if rng.random::<f32>() < contention {
+        (moves as i64, 0, 0)
+    } else {
+        (
+            moves as i64 + rng.random_range( -render_distance..=render_distance ),
+            rng.random_range( -render_distance..=render_distance ),
+            rng.random_range( -render_distance..=render_distance ),
+        )
+    };
+
+    (
+        chunk.0 * chunk_size + rng.random_range( 0..chunk_size ),
+        chunk.1 * chunk_size + rng.random_range( 0..chunk_size ),
+        chunk.2 * chunk_size + rng.random_range( 0..chunk_size ),
+    )
+}
+
+fn get_throughput( record:&ConcurrencyRecord, settings:&ConcurrencySettings ) -> f64 {
+    settings.operations as f64 / record.time.as_secs_f64()
+}
+
+fn to_csv( records:&[ConcurrencyRecord], settings:&ConcurrencySettings ) -> String {
+    let mut csv = String::from( "storage,workers,read_ratio,contention,operations,time_ns,throughput_ops_per_s,map_reads,map_read_wait_ns,map_writes,map_write_wait_ns\n" );
+
+    for record in records {
+        let (case, lock_wait) = (record.case, record.lock_wait);
+
+        writeln!(
+            csv, "{},{},{},{},{},{},{:.0},{},{},{},{}",
+            case.storage.get_name(), case.workers_count, case.read_ratio, case.contention, settings.operations, record.time.as_nanos(), get_throughput( record, settings ),
+            lock_wait.reads, lock_wait.read_wait.as_nanos(), lock_wait.writes, lock_wait.write_wait.as_nanos()
+        ).unwrap();
+    }
+
+    csv
+}
+
+fn to_markdown( records:&[ConcurrencyRecord], settings:&ConcurrencySettings ) -> String {
+    let mut markdown = String::from( "| storage | workers | read ratio | contention | time | ops/s | map reads (wait) | map writes (wait) |\n" );
+    markdown.push_str( "|---|---|---|---|---|---|---|---|\n" );
+
+    for record in records {
+        let (case, lock_wait) = (record.case, record.lock_wait);
+
+        writeln!(
+            markdown, "| {} | {} | {} | {} | {:?} | {:.0} | {} ({:?}) | {} ({:?}) |",
+            case.storage.get_name(), case.workers_count, case.read_ratio, case.contention, record.time, get_throughput( record, settings ),
+            lock_wait.reads, lock_wait.read_wait, lock_wait.writes, lock_wait.write_wait
+        ).unwrap();
+    }
+
+    markdown
+}
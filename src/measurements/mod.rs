@@ -1,19 +1,99 @@
-use std::hint::black_box;
+pub mod concurrency;
 
-use praca_magisterska::{
+use std::{ fs, hint::black_box, path::Path };
+
+#[allow(unused_imports)]
+use crate::{
     chunks_generators::utilities::create_voxel,
     structure_tests::{
+        comparison::{ ComparisonHarness, Operation, Workload },
         octree::{ Octree, OctreeNode },
         voxel_hasher::VoxelHashMap,
         voxel_list::{ VoxelInWorld, VoxelList },
-        voxel_map::VoxelMap
+        voxel_map::VoxelMap,
+        write_trace::WriteTrace
     },
     world::{
+        chunk_storage::Backend,
         world::Position,
         world_holder::{ Color, Material, Voxel, VoxelDataset, WorldHolding }
     }
 };
 
+/// Runs the storage structures comparison and writes its reports.
+/// Arguments: `[--backends a,b] [--workloads a,b] [--operations a,b] [--traces file,file] [--repetitions n] [--samples n] [--out directory]`.
+/// Write traces are added as workloads named after their files
+/// Peak heap is measured only when built with the `dhat-heap` feature
+pub fn compare( args:&[String] ) {
+    let mut harness_args = (None, None, None);
+    let mut repetitions = 3;
+    let mut samples = 100_000;
+    let mut out = String::from( "comparison" );
+    let mut traces = vec![];
+
+    for pair in args.chunks( 2 ) {
+        let [key, value] = pair else { panic!( "Missing value of the argument {}", pair[ 0 ] ) };
+        let names = || value.split( ',' ).map( String::from ).collect::<Vec<_>>();
+
+        match key.as_str() {
+            "--backends" => harness_args.0 = Some( names() ),
+            "--workloads" => harness_args.1 = Some( names() ),
+            "--operations" => harness_args.2 = Some( names() ),
+            "--traces" => traces = names(),
+            "--repetitions" => repetitions = value.parse().expect( "Repetitions should be a number" ),
+            "--samples" => samples = value.parse().expect( "Samples should be a number" ),
+            "--out" => out = value.clone(),
+            _ => panic!( "Unknown argument: {key}" ),
+        }
+    }
+
+    let (backends, workloads, operations) = harness_args;
+    let mut all_workloads = Workload::get_defaults();
+
+    for path in traces {
+        let path = Path::new( &path );
+        let trace = fs::read( path ).map_err( anyhow::Error::from )
+            .and_then( |bytes| WriteTrace::read( &bytes ) )
+            .unwrap_or_else( |error| panic!( "Can't read the write trace {path:?}: {error}" ) );
+        let name = path.file_stem().map_or( String::from( "trace" ), |name| name.to_string_lossy().into_owned() );
+
+        all_workloads.push( Workload::from_trace( &name, trace ) );
+    }
+
+    if let Some( names ) = workloads {
+        for name in &names {
+            if !all_workloads.iter().any( |workload| workload.get_name() == name ) {
+                panic!( "Unknown workload: {name}" )
+            }
+        }
+
+        all_workloads.retain( |workload| names.iter().any( |name| name == workload.get_name() ) );
+    }
+
+    let mut harness = ComparisonHarness::new( all_workloads )
+        .with_repetitions( repetitions )
+        .with_random_samples( samples );
+
+    if let Some( names ) = backends {
+        harness = harness.with_backends( names.iter().map( |name|
+            Backend::from_name( name ).unwrap_or_else( || panic!( "Unknown backend: {name}" ) )
+        ).collect() );
+    }
+
+    if let Some( names ) = operations {
+        harness = harness.with_operations( names.iter().map( |name|
+            Operation::from_name( name ).unwrap_or_else( || panic!( "Unknown operation: {name}" ) )
+        ).collect() );
+    }
+
+    harness.run()
+        .write_to( Path::new( &out ) )
+        .unwrap_or_else( |error| panic!( "Can't write the comparison reports: {error}" ) );
+
+    println!( "Comparison reports written to {out}" );
+}
+
+#[allow(dead_code)]
 pub fn measure() {
     #[cfg(feature = "dhat-heap")]
     let _profiler = dhat::Profiler::new_heap();
@@ -40,7 +120,7 @@ fn initialization() {
 fn fill_half() {
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
@@ -49,18 +129,20 @@ fn fill_half() {
     // let mut world_holder = VoxelHashMap::<Voxel>::new();
     // let mut world_holder = Octree::<Voxel>::from_max_size( black_box( 100 ) );
 
-    black_box( world_holder.fill_voxels(
+    world_holder.fill_voxels(
         black_box( (0, 0, 0) ),
         black_box( (99, 49, 99) ),
         black_box( Some( voxel ) )
-    ) );
+    );
+
+    black_box( world_holder );
 }
 
 #[allow(dead_code)]
 fn fill_whole() {
     let voxel= create_voxel(
         &mut VoxelDataset::new(),
-        (String::from( "material" ), Material { _density:100 }),
+        (String::from( "material" ), Material { _density:100, ..Default::default() }),
         (String::from( "color" ), Color { red:250, green:10, blue:10 }),
     );
 
@@ -70,7 +152,7 @@ fn fill_whole() {
     // let mut world_holder = Octree::<Voxel>::from_max_size( black_box( 100 ) );
     // let mut world_holder = Octree::<Voxel>::from_max_size( black_box( 100 ) );
 
-    black_box( world_holder.fill_voxels(
+    world_holder.fill_voxels(
         black_box( (0, 0, 0) ),
         black_box( (99, 99, 99) ),
 
@@ -78,5 +160,7 @@ fn fill_whole() {
         // black_box( (126, 126, 126) ),
 
         black_box( Some( voxel ) )
-    ) );
+    );
+
+    black_box( world_holder );
 }
@@ -1,4 +1,6 @@
 pub mod simplex_noise;
+pub mod noise_graph;
+pub mod batched_noise;
 
 use simplex_noise::SimplexNoise;
 
@@ -14,7 +16,7 @@ pub fn generate_simplex_noise_image<F:FnMut(u32, u32, f64)>( width:u32, height:u
             let nx = x as f64 * FREQUENCY;
             let ny = y as f64 * FREQUENCY;
 
-            let noise_value = noise.noise3d( nx, ny, 0.0 );
+            let noise_value = noise.noise2d( nx, ny );
             // let slope = (dx * dx + dy * dy + dz * dz).sqrt();
             // noise_value = (1.0 as f64).powf( 20.0 ).clamp( 0.0, 1.0 );
             // noise_value = ((noise_value + 0.2) - 0.0).powf( 5.0 ).clamp( -1.0, 1.0 );
@@ -0,0 +1,355 @@
+use crate::noise::simplex_noise::SimplexNoise;
+
+/// Shift applied to coordinates of every next octave, so octaves of the same source don't line up at the origin
+const OCTAVE_SHIFT: f64 = 19.19;
+
+pub type BoxedNoiseNode = Box<dyn NoiseNode>;
+
+/// Single node of a noise graph. Nodes own their inputs, so a whole graph is a tree built from the leaves up.
+pub trait NoiseNode: Send + Sync {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64;
+}
+
+impl NoiseNode for BoxedNoiseNode {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64 {
+        self.as_ref().get( x, y, z )
+    }
+}
+
+/// Builder methods available on every sized node
+#[allow(dead_code)]
+pub trait NoiseNodeExt: NoiseNode + Sized + 'static {
+    fn boxed( self ) -> BoxedNoiseNode {
+        Box::new( self )
+    }
+
+    fn scale_bias( self, scale:f64, bias:f64 ) -> ScaleBias {
+        ScaleBias { source:self.boxed(), scale, bias }
+    }
+
+    fn remap( self, from:(f64, f64), to:(f64, f64) ) -> Remap {
+        Remap { source:self.boxed(), from, to }
+    }
+
+    fn clamp( self, min:f64, max:f64 ) -> Clamp {
+        Clamp { source:self.boxed(), min, max }
+    }
+
+    fn fbm( self, octaves:u8 ) -> Fractal {
+        Fractal::new( self.boxed(), FractalKind::Fbm, octaves )
+    }
+
+    fn ridged( self, octaves:u8 ) -> Fractal {
+        Fractal::new( self.boxed(), FractalKind::Ridged, octaves )
+    }
+
+    fn billow( self, octaves:u8 ) -> Fractal {
+        Fractal::new( self.boxed(), FractalKind::Billow, octaves )
+    }
+
+    fn warp( self, warp:[BoxedNoiseNode; 3], strength:f64 ) -> DomainWarp {
+        DomainWarp { source:self.boxed(), warp, strength }
+    }
+}
+
+impl<T:NoiseNode + 'static> NoiseNodeExt for T {}
+
+pub struct Constant( pub f64 );
+
+impl NoiseNode for Constant {
+    fn get( &self, _x:f64, _y:f64, _z:f64 ) -> f64 {
+        self.0
+    }
+}
+
+/// Leaf node sampling raw simplex noise (-1 - 1) at scaled coordinates
+#[allow(dead_code)]
+pub struct Simplex {
+    noise: SimplexNoise,
+    frequency: f64,
+}
+
+#[allow(dead_code)]
+impl Simplex {
+    pub fn new( seed:u32, frequency:f64 ) -> Self {
+        Self { noise:SimplexNoise::new( seed ), frequency }
+    }
+}
+
+impl NoiseNode for Simplex {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64 {
+        self.noise.noise3d( x * self.frequency, y * self.frequency, z * self.frequency )
+    }
+}
+
+/// Leaf node sampling raw 2D simplex noise (-1 - 1) on the x/z plane, `y` is ignored. Meant for heightmaps
+pub struct Simplex2d {
+    noise: SimplexNoise,
+    frequency: f64,
+}
+
+impl Simplex2d {
+    pub fn new( seed:u32, frequency:f64 ) -> Self {
+        Self { noise:SimplexNoise::new( seed ), frequency }
+    }
+}
+
+impl NoiseNode for Simplex2d {
+    fn get( &self, x:f64, _y:f64, z:f64 ) -> f64 {
+        self.noise.noise2d( x * self.frequency, z * self.frequency )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FractalKind {
+    /// Plain sum of octaves
+    Fbm,
+    /// Inverted absolute value of octaves, each weighted by the previous one - sharp crests
+    Ridged,
+    /// Absolute value of octaves - rounded, puffy shapes
+    Billow,
+}
+
+/// Sums octaves of the source, result is normalised back to (-1 - 1)
+pub struct Fractal {
+    source: BoxedNoiseNode,
+    kind: FractalKind,
+    octaves: u8,
+    lacunarity: f64,
+    persistence: f64,
+}
+
+#[allow(dead_code)]
+impl Fractal {
+    pub fn new( source:BoxedNoiseNode, kind:FractalKind, octaves:u8 ) -> Self {
+        Self { source, kind, octaves:octaves.max( 1 ), lacunarity:2.0, persistence:0.5 }
+    }
+
+    /// Frequency multiplier between octaves
+    pub fn with_lacunarity( mut self, lacunarity:f64 ) -> Self {
+        self.lacunarity = lacunarity;
+        self
+    }
+
+    /// Amplitude multiplier between octaves
+    pub fn with_persistence( mut self, persistence:f64 ) -> Self {
+        self.persistence = persistence;
+        self
+    }
+}
+
+impl NoiseNode for Fractal {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64 {
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        let mut amplitudes_sum = 0.0;
+        let mut weight = 1.0;
+        let mut sum = 0.0;
+
+        for octave in 0..self.octaves {
+            let shift = octave as f64 * OCTAVE_SHIFT;
+            let value = self.source.get( x * frequency + shift, y * frequency + shift, z * frequency + shift );
+
+            let value = match self.kind {
+                FractalKind::Fbm => value,
+                FractalKind::Billow => value.abs() * 2.0 - 1.0,
+                FractalKind::Ridged => {
+                    let signal = (1.0 - value.abs()).powi( 2 ) * weight;
+
+                    weight = (signal * 2.0).clamp( 0.0, 1.0 );
+                    signal * 2.0 - 1.0
+                },
+            };
+
+            sum += value * amplitude;
+            amplitudes_sum += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.persistence;
+        }
+
+        sum / amplitudes_sum
+    }
+}
+
+/// Samples the source at coordinates displaced by three warp nodes (x, y, z)
+pub struct DomainWarp {
+    source: BoxedNoiseNode,
+    warp: [BoxedNoiseNode; 3],
+    strength: f64,
+}
+
+impl NoiseNode for DomainWarp {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64 {
+        let [ warp_x, warp_y, warp_z ] = &self.warp;
+
+        self.source.get(
+            x + warp_x.get( x, y, z ) * self.strength,
+            y + warp_y.get( x, y, z ) * self.strength,
+            z + warp_z.get( x, y, z ) * self.strength,
+        )
+    }
+}
+
+pub struct ScaleBias {
+    source: BoxedNoiseNode,
+    scale: f64,
+    bias: f64,
+}
+
+impl NoiseNode for ScaleBias {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64 {
+        self.source.get( x, y, z ) * self.scale + self.bias
+    }
+}
+
+/// Linearly maps `from` range into `to` range, values outside of `from` are extrapolated
+pub struct Remap {
+    source: BoxedNoiseNode,
+    from: (f64, f64),
+    to: (f64, f64),
+}
+
+impl NoiseNode for Remap {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64 {
+        let progress = (self.source.get( x, y, z ) - self.from.0) / (self.from.1 - self.from.0);
+        self.to.0 + progress * (self.to.1 - self.to.0)
+    }
+}
+
+pub struct Clamp {
+    source: BoxedNoiseNode,
+    min: f64,
+    max: f64,
+}
+
+impl NoiseNode for Clamp {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64 {
+        self.source.get( x, y, z ).clamp( self.min, self.max )
+    }
+}
+
+/// Sum of any number of nodes
+pub struct Add( pub Vec<BoxedNoiseNode> );
+
+impl NoiseNode for Add {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64 {
+        self.0.iter().map( |node| node.get( x, y, z ) ).sum()
+    }
+}
+
+#[allow(dead_code)]
+pub struct Multiply( pub BoxedNoiseNode, pub BoxedNoiseNode );
+
+impl NoiseNode for Multiply {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64 {
+        self.0.get( x, y, z ) * self.1.get( x, y, z )
+    }
+}
+
+/// Interpolates between `low` and `high`, control value of -1 gives `low`, 1 gives `high`
+#[allow(dead_code)]
+pub struct Blend {
+    pub low: BoxedNoiseNode,
+    pub high: BoxedNoiseNode,
+    pub control: BoxedNoiseNode,
+}
+
+impl NoiseNode for Blend {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64 {
+        let weight = ((self.control.get( x, y, z ) + 1.0) * 0.5).clamp( 0.0, 1.0 );
+        lerp( self.low.get( x, y, z ), self.high.get( x, y, z ), weight )
+    }
+}
+
+/// Picks `low` below the threshold and `high` above it, with smooth transition of `falloff` width on both sides
+#[allow(dead_code)]
+pub struct Select {
+    pub low: BoxedNoiseNode,
+    pub high: BoxedNoiseNode,
+    pub control: BoxedNoiseNode,
+    pub threshold: f64,
+    pub falloff: f64,
+}
+
+impl NoiseNode for Select {
+    fn get( &self, x:f64, y:f64, z:f64 ) -> f64 {
+        let control = self.control.get( x, y, z );
+        let (from, to) = (self.threshold - self.falloff, self.threshold + self.falloff);
+
+        if control <= from { return self.low.get( x, y, z ) }
+        if control >= to { return self.high.get( x, y, z ) }
+
+        let progress = (control - from) / (to - from);
+        let weight = progress * progress * (3.0 - 2.0 * progress);
+
+        lerp( self.low.get( x, y, z ), self.high.get( x, y, z ), weight )
+    }
+}
+
+#[allow(dead_code)]
+fn lerp( from:f64, to:f64, weight:f64 ) -> f64 {
+    from + (to - from) * weight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fractals_stay_in_range_and_are_deterministic() {
+        let graphs = [
+            Simplex::new( 3, 0.02 ).fbm( 5 ),
+            Simplex::new( 3, 0.02 ).ridged( 5 ),
+            Simplex::new( 3, 0.02 ).billow( 5 ),
+        ];
+
+        for graph in &graphs {
+            for i in 0..1000 {
+                let (x, y, z) = (i as f64 * 1.7, i as f64 * 0.3, i as f64 * -2.9);
+                let value = graph.get( x, y, z );
+
+                assert!( (-1.0..=1.0).contains( &value ), "value={value}" );
+                assert_eq!( value, graph.get( x, y, z ) );
+            }
+        }
+    }
+
+    #[test]
+    fn test_combinators() {
+        let remapped = Constant( 0.0 ).remap( (-1.0, 1.0), (10.0, 20.0) );
+        assert_eq!( remapped.get( 0.0, 0.0, 0.0 ), 15.0 );
+
+        let clamped = Constant( 5.0 ).scale_bias( 2.0, 1.0 ).clamp( 0.0, 8.0 );
+        assert_eq!( clamped.get( 0.0, 0.0, 0.0 ), 8.0 );
+
+        let select = |control:f64| Select {
+            low: Constant( 1.0 ).boxed(),
+            high: Constant( 3.0 ).boxed(),
+            control: Constant( control ).boxed(),
+            threshold: 0.0,
+            falloff: 0.25,
+        }.get( 0.0, 0.0, 0.0 );
+
+        assert_eq!( select( -0.5 ), 1.0 );
+        assert_eq!( select( 0.0 ), 2.0 );
+        assert_eq!( select( 0.5 ), 3.0 );
+
+        let blend = Blend { low:Constant( 0.0 ).boxed(), high:Constant( 4.0 ).boxed(), control:Constant( 0.5 ).boxed() };
+        assert_eq!( blend.get( 0.0, 0.0, 0.0 ), 3.0 );
+    }
+
+    #[test]
+    fn test_domain_warp_moves_sampling_point() {
+        let source = Simplex::new( 1, 0.05 );
+        let expected = source.get( 12.0, 0.0, 4.0 );
+        let warped = Simplex::new( 1, 0.05 ).warp( [ Constant( 1.0 ).boxed(), Constant( 0.0 ).boxed(), Constant( -1.0 ).boxed() ], 2.0 );
+
+        assert_eq!( warped.get( 10.0, 0.0, 6.0 ), expected );
+    }
+
+    #[test]
+    fn test_simplex2d_ignores_height() {
+        let node = Simplex2d::new( 4, 0.03 );
+        assert_eq!( node.get( 7.0, -20.0, 3.0 ), node.get( 7.0, 55.0, 3.0 ) );
+    }
+}
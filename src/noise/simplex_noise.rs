@@ -1,16 +1,62 @@
 use std::num::Wrapping;
 
-const GRAD3: [(i8, i8, i8); 12] = [
+pub(super) const GRAD3: [(i8, i8, i8); 12] = [
     (1,1,0), (-1,1,0), (1,-1,0), (-1,-1,0),
     (1,0,1), (-1,0,1), (1,0,-1), (-1,0,-1),
     (0,1,1), (0,-1,1), (0,1,-1), (0,-1,-1)
 ];
 
-const F3: f64 = 1.0 / 3.0;
-const G3: f64 = 1.0 / 6.0;
+const GRAD4: [(i8, i8, i8, i8); 32] = [
+    (0,1,1,1), (0,1,1,-1), (0,1,-1,1), (0,1,-1,-1),
+    (0,-1,1,1), (0,-1,1,-1), (0,-1,-1,1), (0,-1,-1,-1),
+    (1,0,1,1), (1,0,1,-1), (1,0,-1,1), (1,0,-1,-1),
+    (-1,0,1,1), (-1,0,1,-1), (-1,0,-1,1), (-1,0,-1,-1),
+    (1,1,0,1), (1,1,0,-1), (1,-1,0,1), (1,-1,0,-1),
+    (-1,1,0,1), (-1,1,0,-1), (-1,-1,0,1), (-1,-1,0,-1),
+    (1,1,1,0), (1,1,-1,0), (1,-1,1,0), (1,-1,-1,0),
+    (-1,1,1,0), (-1,1,-1,0), (-1,-1,1,0), (-1,-1,-1,0)
+];
+
+/// (sqrt(3) - 1) / 2
+const F2: f64 = 0.366_025_403_784_438_6;
+/// (3 - sqrt(3)) / 6
+const G2: f64 = 0.211_324_865_405_187_1;
+pub(super) const F3: f64 = 1.0 / 3.0;
+pub(super) const G3: f64 = 1.0 / 6.0;
+/// (sqrt(5) - 1) / 4
+const F4: f64 = 0.309_016_994_374_947_4;
+/// (5 - sqrt(5)) / 20
+const G4: f64 = 0.138_196_601_125_010_5;
+
+/// Scales bringing sums of corner contributions back to (-1 - 1)
+const SCALE2: f64 = 70.0;
+const SCALE4: f64 = 27.0;
+
+type Corner2d = (f64, f64, usize);
+type Corner4d = (f64, f64, f64, f64, usize);
+
+/// Noise streams of `SimplexNoise::with_stream`. Every noise of the generators has its own, so no two of them repeat each other
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum NoiseStream {
+    /// Same as `SimplexNoise::new( seed )`
+    Base = 0,
+    Temperature = 1,
+    Humidity = 2,
+    CaveSteering = 3,
+    Caverns = 4,
+    Hills = 5,
+}
+
+#[allow(dead_code)]
+impl NoiseStream {
+    pub const ALL: [NoiseStream; 6] = [
+        NoiseStream::Base, NoiseStream::Temperature, NoiseStream::Humidity, NoiseStream::CaveSteering, NoiseStream::Caverns, NoiseStream::Hills
+    ];
+}
 
 pub struct SimplexNoise {
-    perm: [u8; 512],
+    pub(super) perm: [u8; 512],
 }
 
 #[allow(dead_code)]
@@ -38,6 +84,50 @@ impl SimplexNoise {
         Self { perm }
     }
 
+    /// Independent noise stream of the same seed, so generators don't have to derive extra seeds by hand.
+    pub fn with_stream( seed:u32, stream:NoiseStream ) -> Self {
+        Self::new( get_stream_seed( seed, stream as u32 ) )
+    }
+
+    pub fn noise2d( &self, x:f64, y:f64 ) -> f64 {
+        let mut n = 0.0;
+
+        for (x, y, gi) in self.get_corners2d( x, y ) {
+            let t = 0.5 - x*x - y*y;
+
+            if t > 0.0 {
+                let t2 = t * t;
+                n += t2 * t2 * SimplexNoise::dot2( GRAD3[ gi ], x, y );
+            }
+        }
+
+        SCALE2 * n
+    }
+
+    pub fn noise2d_with_gradient( &self, x:f64, y:f64 ) -> (f64, (f64, f64)) {
+        let mut n = 0.0;
+        let mut dx = 0.0;
+        let mut dy = 0.0;
+
+        for (x, y, gi) in self.get_corners2d( x, y ) {
+            let t = 0.5 - x*x - y*y;
+
+            if t > 0.0 {
+                let g = GRAD3[ gi ];
+                let dot = SimplexNoise::dot2( g, x, y );
+                let t2 = t * t;
+                let t4 = t2 * t2;
+                let grad_factor = 8.0 * t2 * t * dot;
+
+                n += t4 * dot;
+                dx += t4 * g.0 as f64 - grad_factor * x;
+                dy += t4 * g.1 as f64 - grad_factor * y;
+            }
+        }
+
+        (SCALE2 * n, (SCALE2 * dx, SCALE2 * dy))
+    }
+
     pub fn noise3d( &self, x:f64, y:f64, z:f64 ) -> f64 {
         let s = (x + y + z) * F3;
         let i = (x + s).floor() as isize;
@@ -238,8 +328,226 @@ impl SimplexNoise {
         (32.0 * n, laplacian)
     }
 
+    /// Noise of looping or animated fields, eg. 2D tiles sampled on a torus or 3D fields changing over time
+    pub fn noise4d( &self, x:f64, y:f64, z:f64, w:f64 ) -> f64 {
+        let mut n = 0.0;
+
+        for (x, y, z, w, gi) in self.get_corners4d( x, y, z, w ) {
+            let t = 0.6 - x*x - y*y - z*z - w*w;
+
+            if t > 0.0 {
+                let t2 = t * t;
+                n += t2 * t2 * SimplexNoise::dot4( GRAD4[ gi ], x, y, z, w );
+            }
+        }
+
+        SCALE4 * n
+    }
+
+    pub fn noise4d_with_gradient( &self, x:f64, y:f64, z:f64, w:f64 ) -> (f64, (f64, f64, f64, f64)) {
+        let mut n = 0.0;
+        let mut dx = 0.0;
+        let mut dy = 0.0;
+        let mut dz = 0.0;
+        let mut dw = 0.0;
+
+        for (x, y, z, w, gi) in self.get_corners4d( x, y, z, w ) {
+            let t = 0.6 - x*x - y*y - z*z - w*w;
+
+            if t > 0.0 {
+                let g = GRAD4[ gi ];
+                let dot = SimplexNoise::dot4( g, x, y, z, w );
+                let t2 = t * t;
+                let t4 = t2 * t2;
+                let grad_factor = 8.0 * t2 * t * dot;
+
+                n += t4 * dot;
+                dx += t4 * g.0 as f64 - grad_factor * x;
+                dy += t4 * g.1 as f64 - grad_factor * y;
+                dz += t4 * g.2 as f64 - grad_factor * z;
+                dw += t4 * g.3 as f64 - grad_factor * w;
+            }
+        }
+
+        (SCALE4 * n, (SCALE4 * dx, SCALE4 * dy, SCALE4 * dz, SCALE4 * dw))
+    }
+
+    /// Offsets of the point from the 3 corners of its simplex, with gradient index of every corner
+    #[inline(always)]
+    fn get_corners2d( &self, x:f64, y:f64 ) -> [Corner2d; 3] {
+        let s = (x + y) * F2;
+        let i = (x + s).floor() as isize;
+        let j = (y + s).floor() as isize;
+        let t = ((i + j) as f64) * G2;
+        let x0 = x - (i as f64 - t);
+        let y0 = y - (j as f64 - t);
+
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+
+        let ii = (i & 255) as usize;
+        let jj = (j & 255) as usize;
+
+        let gi0 = self.perm[ ii      + self.perm[ jj      ] as usize] as usize % 12;
+        let gi1 = self.perm[ ii + i1 + self.perm[ jj + j1 ] as usize] as usize % 12;
+        let gi2 = self.perm[ ii + 1  + self.perm[ jj + 1  ] as usize] as usize % 12;
+
+        [
+            (x0, y0, gi0),
+            (x0 - i1 as f64 + G2, y0 - j1 as f64 + G2, gi1),
+            (x0 - 1.0 + 2.0 * G2, y0 - 1.0 + 2.0 * G2, gi2),
+        ]
+    }
+
+    /// Offsets of the point from the 5 corners of its simplex, with gradient index of every corner
+    #[inline(always)]
+    fn get_corners4d( &self, x:f64, y:f64, z:f64, w:f64 ) -> [Corner4d; 5] {
+        let s = (x + y + z + w) * F4;
+        let i = (x + s).floor() as isize;
+        let j = (y + s).floor() as isize;
+        let k = (z + s).floor() as isize;
+        let l = (w + s).floor() as isize;
+        let t = ((i + j + k + l) as f64) * G4;
+        let x0 = x - (i as f64 - t);
+        let y0 = y - (j as f64 - t);
+        let z0 = z - (k as f64 - t);
+        let w0 = w - (l as f64 - t);
+
+        // Rank of every axis decides in which order the simplex corners step through them
+        let mut rank = [ 0usize; 4 ];
+        let offsets = [ x0, y0, z0, w0 ];
+
+        for a in 0..4 {
+            for b in (a + 1)..4 {
+                if offsets[ a ] > offsets[ b ] { rank[ a ] += 1 } else { rank[ b ] += 1 }
+            }
+        }
+
+        let step = |threshold:usize| rank.map( |axis_rank| (axis_rank >= threshold) as usize );
+        let [ i1, j1, k1, l1 ] = step( 3 );
+        let [ i2, j2, k2, l2 ] = step( 2 );
+        let [ i3, j3, k3, l3 ] = step( 1 );
+
+        let ii = (i & 255) as usize;
+        let jj = (j & 255) as usize;
+        let kk = (k & 255) as usize;
+        let ll = (l & 255) as usize;
+
+        let gradient_index = |i:usize, j:usize, k:usize, l:usize| {
+            self.perm[ ii + i + self.perm[ jj + j + self.perm[ kk + k + self.perm[ ll + l ] as usize] as usize] as usize] as usize % 32
+        };
+
+        [
+            (x0, y0, z0, w0, gradient_index( 0, 0, 0, 0 )),
+            (x0 - i1 as f64 + G4, y0 - j1 as f64 + G4, z0 - k1 as f64 + G4, w0 - l1 as f64 + G4, gradient_index( i1, j1, k1, l1 )),
+            (x0 - i2 as f64 + 2.0 * G4, y0 - j2 as f64 + 2.0 * G4, z0 - k2 as f64 + 2.0 * G4, w0 - l2 as f64 + 2.0 * G4, gradient_index( i2, j2, k2, l2 )),
+            (x0 - i3 as f64 + 3.0 * G4, y0 - j3 as f64 + 3.0 * G4, z0 - k3 as f64 + 3.0 * G4, w0 - l3 as f64 + 3.0 * G4, gradient_index( i3, j3, k3, l3 )),
+            (x0 - 1.0 + 4.0 * G4, y0 - 1.0 + 4.0 * G4, z0 - 1.0 + 4.0 * G4, w0 - 1.0 + 4.0 * G4, gradient_index( 1, 1, 1, 1 )),
+        ]
+    }
+
+    #[inline(always)]
+    fn dot2( g:(i8, i8, i8), x:f64, y:f64 ) -> f64 {
+        (g.0 as f64) * x + (g.1 as f64) * y
+    }
+
+    #[inline(always)]
+    fn dot4( g:(i8, i8, i8, i8), x:f64, y:f64, z:f64, w:f64 ) -> f64 {
+        (g.0 as f64) * x + (g.1 as f64) * y + (g.2 as f64) * z + (g.3 as f64) * w
+    }
+
     #[inline(always)]
     fn dot(g: (i8, i8, i8), x: f64, y: f64, z: f64) -> f64 {
         (g.0 as f64) * x + (g.1 as f64) * y + (g.2 as f64) * z
     }
 }
+
+/// Seed of a noise stream, a splitmix hash of both the seed and the stream, so streams of different seeds don't line up
+fn get_stream_seed( seed:u32, stream:u32 ) -> u32 {
+    if stream == 0 { return seed }
+
+    let mut value = ((seed as u64) << 32 | stream as u64).wrapping_add( 0x9e37_79b9_7f4a_7c15 );
+    value = (value ^ (value >> 30)).wrapping_mul( 0xbf58_476d_1ce4_e5b9 );
+    value = (value ^ (value >> 27)).wrapping_mul( 0x94d0_49bb_1331_11eb );
+    value ^= value >> 31;
+
+    (value ^ (value >> 32)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-6;
+
+    fn sample_points() -> impl Iterator<Item = (f64, f64, f64, f64)> {
+        (0..2000).map( |i| {
+            let i = i as f64;
+            (i * 0.137 - 50.0, i * -0.291 + 13.0, i * 0.053, (i * 0.71).sin() * 40.0)
+        } )
+    }
+
+    #[test]
+    fn test_noise_stays_in_range() {
+        let noise = SimplexNoise::new( 7 );
+
+        for (x, y, z, w) in sample_points() {
+            let value2d = noise.noise2d( x, y );
+            let value4d = noise.noise4d( x, y, z, w );
+
+            assert!( (-1.0..=1.0).contains( &value2d ), "value2d={value2d}" );
+            assert!( (-1.0..=1.0).contains( &value4d ), "value4d={value4d}" );
+        }
+    }
+
+    #[test]
+    fn test_gradients_match_values_and_finite_differences() {
+        let noise = SimplexNoise::new( 11 );
+        let assert_close = |analytic:f64, numeric:f64| assert!( (analytic - numeric).abs() < 1e-4, "analytic={analytic} numeric={numeric}" );
+
+        for (x, y, z, w) in sample_points().step_by( 7 ) {
+            let (value, (dx, dy)) = noise.noise2d_with_gradient( x, y );
+            assert_eq!( value, noise.noise2d( x, y ) );
+            assert_close( dx, (noise.noise2d( x + EPSILON, y ) - noise.noise2d( x - EPSILON, y )) / (2.0 * EPSILON) );
+            assert_close( dy, (noise.noise2d( x, y + EPSILON ) - noise.noise2d( x, y - EPSILON )) / (2.0 * EPSILON) );
+
+            let (value, (dx, dy, dz, dw)) = noise.noise4d_with_gradient( x, y, z, w );
+            let numeric = |offset:(f64, f64, f64, f64)| {
+                (noise.noise4d( x + offset.0, y + offset.1, z + offset.2, w + offset.3 )
+                    - noise.noise4d( x - offset.0, y - offset.1, z - offset.2, w - offset.3 )) / (2.0 * EPSILON)
+            };
+
+            assert_eq!( value, noise.noise4d( x, y, z, w ) );
+            assert_close( dx, numeric( (EPSILON, 0.0, 0.0, 0.0) ) );
+            assert_close( dy, numeric( (0.0, EPSILON, 0.0, 0.0) ) );
+            assert_close( dz, numeric( (0.0, 0.0, EPSILON, 0.0) ) );
+            assert_close( dw, numeric( (0.0, 0.0, 0.0, EPSILON) ) );
+        }
+    }
+
+    #[test]
+    fn test_streams_are_independent() {
+        let base = SimplexNoise::new( 5 );
+        let same = SimplexNoise::with_stream( 5, NoiseStream::Base );
+        let streams = NoiseStream::ALL[ 1.. ].iter().map( |&stream| SimplexNoise::with_stream( 5, stream ) ).collect::<Vec<_>>();
+
+        assert_eq!( base.perm, same.perm );
+
+        for (i, stream) in streams.iter().enumerate() {
+            assert_ne!( base.perm, stream.perm );
+            assert!( streams[ i + 1.. ].iter().all( |other| other.perm != stream.perm ) );
+        }
+    }
+
+    #[test]
+    fn test_streams_of_different_seeds_do_not_collide() {
+        let mut stream_seeds = std::collections::HashSet::new();
+
+        for seed in 0..256 {
+            for stream in 1..16 {
+                stream_seeds.insert( get_stream_seed( seed, stream ) );
+            }
+        }
+
+        assert_eq!( stream_seeds.len(), 256 * 15 );
+    }
+}
@@ -521,7 +521,7 @@ impl Renderer {
       size_of::<Mat4>()
     );
 
-    let opacity = 1.0 as f32;
+    let opacity = model.get_opacity();
     let elapsed = Instant::now().elapsed().as_secs_f32();
 
     let opacity_bytes = &opacity.to_ne_bytes()[..];
@@ -25,6 +25,9 @@ pub trait Renderable {
   fn get_draw_mode( &self ) -> &DrawMode {
     &DrawMode::FULL
   }
+  fn get_opacity( &self ) -> f32 {
+    1.0
+  }
 }
 
 pub trait RendererModelDescriptions {
@@ -0,0 +1,434 @@
+use std::{ cell::RefCell, hint::black_box, path::Path, sync::{ Arc, RwLock, RwLockReadGuard } };
+
+use anyhow::{ bail, Result };
+
+use crate::world::{
+    memory_footprint::{ Footprint, MemoryFootprint, SharedAllocations },
+    serialization::{ write_length_u32, ByteReader },
+    world::CHUNK_SIZE,
+    world_chunk::{ ChunkBitmask, WorldChunk },
+    world_holder::{ Voxel, VoxelLeaf, VoxelSide, WorldHolding },
+    world_light::ChunkLight
+};
+
+use super::{
+    octree::Octree,
+    write_trace::{
+        load_or_record_file, read_palette, read_position, read_voxel_id, write_palette, write_position, write_voxel_id, TracePalette, TracePosition
+    }
+};
+
+const ACCESS_TRACE_FORMAT_VERSION: u8 = 1;
+
+/// Single call of the `WorldHolding` interface. Voxels are indices into the trace palette, `None` removes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessCall {
+    GetVoxel( TracePosition ),
+    GetAllVoxels,
+    GetAllVisibleVoxelsFrom( TracePosition ),
+    GetAllLeaves,
+    ToBitmask,
+    SetVoxel { position:TracePosition, voxel:Option<u32> },
+    FillVoxels { from:TracePosition, to:TracePosition, voxel:Option<u32> },
+}
+
+/// Wrapper recording every call made to the wrapped structure, in order
+pub struct TracingHolder<'a> {
+    world_holder: &'a mut dyn WorldHolding,
+    palette: TracePalette,
+    calls: RefCell<Vec<AccessCall>>,
+}
+
+#[allow(dead_code)]
+impl<'a> TracingHolder<'a> {
+    pub fn new( world_holder:&'a mut dyn WorldHolding ) -> Self {
+        Self::with_palette( world_holder, TracePalette::default() )
+    }
+
+    /// Continues the palette of an earlier trace, so voxels keep their indices
+    pub fn with_palette( world_holder:&'a mut dyn WorldHolding, palette:TracePalette ) -> Self {
+        Self { world_holder, palette, calls:RefCell::new( vec![] ) }
+    }
+
+    /// Access to the wrapped structure which doesn't end up in the trace
+    pub fn get_untraced( &self ) -> &dyn WorldHolding {
+        self.world_holder
+    }
+
+    pub fn into_trace( self, size:(u32, u32, u32) ) -> AccessTrace {
+        let (palette, calls) = self.into_parts();
+        AccessTrace { size, palette:palette.into_voxels(), content:vec![], calls }
+    }
+
+    pub fn into_parts( self ) -> (TracePalette, Vec<AccessCall>) {
+        (self.palette, self.calls.into_inner())
+    }
+
+    fn record( &self, call:AccessCall ) {
+        self.calls.borrow_mut().push( call );
+    }
+}
+
+impl MemoryFootprint for TracingHolder<'_> {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        self.world_holder.add_heap_footprint( footprint, seen );
+    }
+}
+
+impl WorldHolding for TracingHolder<'_> {
+    fn get_voxel( &self, x:u32, y:u32, z:u32 ) -> Option<Arc<Voxel>> {
+        self.record( AccessCall::GetVoxel( (x, y, z) ) );
+        self.world_holder.get_voxel( x, y, z )
+    }
+
+    fn get_all_voxels( &self ) -> Vec<(u32, u32, u32, Arc<Voxel>)> {
+        self.record( AccessCall::GetAllVoxels );
+        self.world_holder.get_all_voxels()
+    }
+
+    fn get_all_visible_voxels_from( &self, from:(u32, u32, u32) ) -> Vec<VoxelSide> {
+        self.record( AccessCall::GetAllVisibleVoxelsFrom( from ) );
+        self.world_holder.get_all_visible_voxels_from( from )
+    }
+
+    fn get_all_leaves( &self ) -> Vec<VoxelLeaf> {
+        self.record( AccessCall::GetAllLeaves );
+        self.world_holder.get_all_leaves()
+    }
+
+    fn set_voxel( &mut self, x:u32, y:u32, z:u32, voxel:Option<Arc<Voxel>> ) {
+        let call = AccessCall::SetVoxel { position:(x, y, z), voxel:self.palette.get_id( &voxel ) };
+
+        self.record( call );
+        self.world_holder.set_voxel( x, y, z, voxel )
+    }
+
+    fn fill_voxels( &mut self, from:(u32, u32, u32), to:(u32, u32, u32), voxel:Option<Arc<Voxel>> ) {
+        let call = AccessCall::FillVoxels { from, to, voxel:self.palette.get_id( &voxel ) };
+
+        self.record( call );
+        self.world_holder.fill_voxels( from, to, voxel )
+    }
+
+    fn to_bitmask( &self ) -> ChunkBitmask {
+        self.record( AccessCall::ToBitmask );
+        self.world_holder.to_bitmask()
+    }
+}
+
+/// Reads done by the engine on a single chunk, recorded by running the engine code on the traced chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadPattern {
+    /// Voxels of the visible faces read by `WorldChunk::collect_renderables` when remeshing
+    Remesh,
+    /// `get_all_visible_voxels_from` the top corner, `Octree::get_visible_with_flood` for the octree
+    Flood,
+    /// Leaves read by `ChunkLight::calculate` for the opacity and emission of voxels
+    Light,
+}
+
+#[allow(dead_code)]
+impl ReadPattern {
+    pub const ALL: [ReadPattern; 3] = [ ReadPattern::Remesh, ReadPattern::Flood, ReadPattern::Light ];
+
+    pub fn get_name( self ) -> &'static str {
+        match self {
+            ReadPattern::Remesh => "remesh",
+            ReadPattern::Flood => "flood",
+            ReadPattern::Light => "light",
+        }
+    }
+
+    pub fn from_name( name:&str ) -> Option<Self> {
+        Self::ALL.into_iter().find( |pattern| pattern.get_name() == name )
+    }
+
+    fn read( self, world_holder:&dyn WorldHolding, chunk:&TracedChunk ) {
+        match self {
+            ReadPattern::Remesh => {
+                black_box( WorldChunk::collect_renderables( world_holder, (&chunk.opaque_mask, &chunk.transparents_mask, &chunk.non_solids_mask), &chunk.light, (0, 0, 0), &chunk.neighbours ) );
+            },
+            ReadPattern::Flood => {
+                black_box( world_holder.get_all_visible_voxels_from( (0, CHUNK_SIZE as u32 - 1, 0) ) );
+            },
+            ReadPattern::Light => {
+                black_box( ChunkLight::calculate( world_holder, &chunk.opaque_mask, &chunk.neighbours ) );
+            },
+        }
+    }
+}
+
+/// What the engine keeps next to the chunk data, so reading it isn't traced. Neighbours are disabled chunks
+struct TracedChunk<'a> {
+    opaque_mask: ChunkBitmask,
+    transparents_mask: ChunkBitmask,
+    non_solids_mask: ChunkBitmask,
+    light: ChunkLight,
+    neighbours: Vec<RwLockReadGuard<'a, WorldChunk>>,
+}
+
+/// Every call made to a chunk, which can be repeated on any other structure holding the same voxels
+pub struct AccessTrace {
+    size: (u32, u32, u32),
+    palette: Vec<Arc<Voxel>>,
+    /// Fills rebuilding the traced chunk, see `prepare`
+    content: Vec<AccessCall>,
+    calls: Vec<AccessCall>,
+}
+
+#[allow(dead_code)]
+impl AccessTrace {
+    /// Copies the first chunk (`CHUNK_SIZE` voxels along every axis) of the structure into an octree, like the engine keeps it,
+    /// and records the calls of the read patterns made to it. `size` limits the copied part
+    pub fn record( world_holder:&dyn WorldHolding, size:(u32, u32, u32), patterns:&[ReadPattern] ) -> Self {
+        let chunk_size = CHUNK_SIZE as u32;
+        let mut chunk = Octree::<Voxel>::from_max_size( chunk_size );
+
+        for x in 0..size.0.min( chunk_size ) {
+            for y in 0..size.1.min( chunk_size ) {
+                for z in 0..size.2.min( chunk_size ) {
+                    if let Some( voxel ) = world_holder.get_voxel( x, y, z ) {
+                        chunk.set_voxel( x, y, z, Some( voxel ) );
+                    }
+                }
+            }
+        }
+
+        let disabled_chunks = (0..26).map( |_| RwLock::new( WorldChunk::new_disabled() ) ).collect::<Vec<_>>();
+        let neighbours = disabled_chunks.iter().map( |neighbour| neighbour.read().unwrap() ).collect::<Vec<_>>();
+        let opaque_mask = chunk.to_bitmask();
+        let traced_chunk = TracedChunk {
+            light: ChunkLight::calculate( &chunk, &opaque_mask, &neighbours ),
+            transparents_mask: chunk.to_transparents_bitmask(),
+            non_solids_mask: chunk.to_non_solids_bitmask(),
+            opaque_mask,
+            neighbours,
+        };
+
+        let mut palette = TracePalette::default();
+        let content = chunk.get_leaves().into_iter()
+            .map( |(from, size, voxel)| AccessCall::FillVoxels {
+                from,
+                to: (from.0 + size - 1, from.1 + size - 1, from.2 + size - 1),
+                voxel: palette.get_id( &Some( voxel ) ),
+            } )
+            .collect();
+
+        let tracing_holder = TracingHolder::with_palette( &mut chunk, palette );
+
+        for pattern in patterns {
+            pattern.read( &tracing_holder, &traced_chunk );
+        }
+
+        let (palette, calls) = tracing_holder.into_parts();
+
+        Self { size:(chunk_size, chunk_size, chunk_size), palette:palette.into_voxels(), content, calls }
+    }
+
+    /// Reads the trace from `path`, or records it and saves it there when the file doesn't exist
+    pub fn load_or_record( path:&Path, record:impl FnOnce() -> Self ) -> Result<Self> {
+        load_or_record_file( path, record, Self::write, Self::read )
+    }
+
+    pub fn get_size( &self ) -> (u32, u32, u32) {
+        self.size
+    }
+
+    pub fn get_calls( &self ) -> &[AccessCall] {
+        &self.calls
+    }
+
+    /// Fills the structure with the voxels of the traced chunk
+    pub fn prepare( &self, world_holder:&mut dyn WorldHolding ) {
+        for call in &self.content {
+            self.replay_call( world_holder, call );
+        }
+    }
+
+    /// Repeats the calls on the structure prepared with `prepare`. Returns how many `get_voxel` calls have found a voxel
+    pub fn replay( &self, world_holder:&mut dyn WorldHolding ) -> usize {
+        self.calls.iter().filter( |call| self.replay_call( world_holder, call ) ).count()
+    }
+
+    /// Palette keeps materials and colours, voxels are created anew when reading
+    pub fn write( &self ) -> Result<Vec<u8>> {
+        let mut out = vec![ ACCESS_TRACE_FORMAT_VERSION ];
+
+        write_position( &mut out, self.size );
+        write_palette( &mut out, &self.palette )?;
+
+        for calls in [ &self.content, &self.calls ] {
+            write_length_u32( &mut out, calls.len() )?;
+
+            for call in calls {
+                Self::write_call( &mut out, call );
+            }
+        }
+
+        Ok( out )
+    }
+
+    pub fn read( bytes:&[u8] ) -> Result<Self> {
+        let mut reader = ByteReader::new( bytes );
+        let version = reader.read_u8()?;
+
+        if version != ACCESS_TRACE_FORMAT_VERSION {
+            bail!( "Unsupported access trace format version: {version}" )
+        }
+
+        let size = read_position( &mut reader )?;
+        let palette = read_palette( &mut reader )?;
+        let mut read_calls = || -> Result<Vec<AccessCall>> {
+            let calls_len = reader.read_u32()?;
+            let mut calls = Vec::with_capacity( calls_len as usize );
+
+            for _ in 0..calls_len {
+                calls.push( Self::read_call( &mut reader, palette.len() as u32 )? );
+            }
+
+            Ok( calls )
+        };
+
+        let content = read_calls()?;
+        let calls = read_calls()?;
+
+        Ok( Self { size, palette, content, calls } )
+    }
+
+    /// Returns whether the call has found a voxel
+    fn replay_call( &self, world_holder:&mut dyn WorldHolding, call:&AccessCall ) -> bool {
+        let get_voxel = |voxel:Option<u32>| voxel.map( |id| Arc::clone( &self.palette[ id as usize ] ) );
+
+        match *call {
+            AccessCall::GetVoxel( (x, y, z) ) => return black_box( world_holder.get_voxel( x, y, z ) ).is_some(),
+            AccessCall::GetAllVoxels => {
+                black_box( world_holder.get_all_voxels() );
+            },
+            AccessCall::GetAllVisibleVoxelsFrom( from ) => {
+                black_box( world_holder.get_all_visible_voxels_from( from ) );
+            },
+            AccessCall::GetAllLeaves => {
+                black_box( world_holder.get_all_leaves() );
+            },
+            AccessCall::ToBitmask => {
+                black_box( world_holder.to_bitmask() );
+            },
+            AccessCall::SetVoxel { position, voxel } => world_holder.set_voxel( position.0, position.1, position.2, get_voxel( voxel ) ),
+            AccessCall::FillVoxels { from, to, voxel } => world_holder.fill_voxels( from, to, get_voxel( voxel ) ),
+        }
+
+        false
+    }
+
+    fn write_call( out:&mut Vec<u8>, call:&AccessCall ) {
+        match *call {
+            AccessCall::GetVoxel( position ) => {
+                out.push( 0 );
+                write_position( out, position );
+            },
+            AccessCall::GetAllVoxels => out.push( 1 ),
+            AccessCall::GetAllVisibleVoxelsFrom( from ) => {
+                out.push( 2 );
+                write_position( out, from );
+            },
+            AccessCall::GetAllLeaves => out.push( 3 ),
+            AccessCall::ToBitmask => out.push( 4 ),
+            AccessCall::SetVoxel { position, voxel } => {
+                out.push( 5 );
+                write_position( out, position );
+                write_voxel_id( out, voxel );
+            },
+            AccessCall::FillVoxels { from, to, voxel } => {
+                out.push( 6 );
+                write_position( out, from );
+                write_position( out, to );
+                write_voxel_id( out, voxel );
+            },
+        }
+    }
+
+    fn read_call( reader:&mut ByteReader, palette_len:u32 ) -> Result<AccessCall> {
+        let call = match reader.read_u8()? {
+            0 => AccessCall::GetVoxel( read_position( reader )? ),
+            1 => AccessCall::GetAllVoxels,
+            2 => AccessCall::GetAllVisibleVoxelsFrom( read_position( reader )? ),
+            3 => AccessCall::GetAllLeaves,
+            4 => AccessCall::ToBitmask,
+            5 => AccessCall::SetVoxel { position:read_position( reader )?, voxel:read_voxel_id( reader, palette_len )? },
+            6 => AccessCall::FillVoxels { from:read_position( reader )?, to:read_position( reader )?, voxel:read_voxel_id( reader, palette_len )? },
+            kind => bail!( "Unknown access call kind: {kind}" ),
+        };
+
+        Ok( call )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        structure_tests::{ octree::Octree, voxel_hasher::VoxelHashMap, voxel_list::VoxelList, voxel_map::VoxelMap },
+        world::world_holder::fill
+    };
+
+    use super::*;
+
+    fn filled_octree() -> Octree<Voxel> {
+        let mut octree = Octree::<Voxel>::from_max_size( 8 );
+        fill( (0, 0, 0), (7, 3, 7), &mut octree );
+        octree
+    }
+
+    #[test]
+    fn test_tracing_holder_records_calls_in_order() {
+        let mut octree = filled_octree();
+        let mut tracing_holder = TracingHolder::new( &mut octree );
+
+        tracing_holder.get_voxel( 1, 2, 3 );
+        tracing_holder.set_voxel( 1, 2, 3, None );
+        tracing_holder.get_untraced().get_voxel( 0, 0, 0 );
+
+        let trace = tracing_holder.into_trace( (8, 8, 8) );
+
+        assert_eq!( trace.get_calls(), &[
+            AccessCall::GetVoxel( (1, 2, 3) ),
+            AccessCall::SetVoxel { position:(1, 2, 3), voxel:None },
+        ] );
+        assert!( octree.get_voxel( 1, 2, 3 ).is_none() );
+    }
+
+    #[test]
+    fn test_read_patterns_replay_the_same_on_every_backend() {
+        let trace = AccessTrace::record( &filled_octree(), (8, 8, 8), &ReadPattern::ALL );
+        let size = CHUNK_SIZE as u32;
+
+        assert_eq!( trace.get_size(), (size, size, size) );
+        assert!( trace.get_calls().contains( &AccessCall::GetAllLeaves ) );
+
+        // Remesh reads the top, bottom and side faces of the filled layers
+        let mut octree = Octree::<Voxel>::from_max_size( size );
+        trace.prepare( &mut octree );
+        let found = trace.replay( &mut octree );
+        assert_eq!( found, 8 * 8 * 2 + 8 * 4 * 4 );
+
+        let mut voxel_map = VoxelMap::<Voxel>::from_max_sizes( size, size, size );
+        let mut voxel_list = VoxelList::<Voxel>::new();
+        let mut voxel_hash_map = VoxelHashMap::<Voxel>::new();
+        let backends:[&mut dyn WorldHolding; 3] = [ &mut voxel_map, &mut voxel_list, &mut voxel_hash_map ];
+
+        for world_holder in backends {
+            trace.prepare( world_holder );
+            assert_eq!( trace.replay( world_holder ), found );
+        }
+    }
+
+    #[test]
+    fn test_trace_survives_writing_and_reading() {
+        let trace = AccessTrace::record( &filled_octree(), (8, 8, 8), &[ ReadPattern::Remesh ] );
+        let read = AccessTrace::read( &trace.write().unwrap() ).unwrap();
+
+        assert_eq!( read.get_size(), trace.get_size() );
+        assert_eq!( read.get_calls(), trace.get_calls() );
+        assert_eq!( read.content, trace.content );
+        assert!( AccessTrace::read( &trace.write().unwrap()[ ..20 ] ).is_err() );
+    }
+}
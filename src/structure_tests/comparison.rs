@@ -0,0 +1,507 @@
+use std::{ cell::OnceCell, fmt::Write as _, fs, hint::black_box, io, path::Path, sync::Arc, time::{ Duration, Instant } };
+
+use rand::{ rngs::SmallRng, Rng, SeedableRng };
+
+use crate::{
+    chunks_generators::{
+        biomes::GeneratorOfBiomes,
+        erosion::{ ErodedHeights, GeneratorOfErodedTerrain },
+        hydrology::{ GeneratorWithWater, Hydrology },
+        test_11_height_map::GeneratorOfTest11HeightMap,
+        test_9_natural::GeneratorOfTest9Natural,
+    },
+    world::{
+        chunk_storage::Backend,
+        memory_footprint::Footprint,
+        world::CHUNK_SIZE,
+        world_generator::WorldGenerative,
+        world_holder::WorldHolding
+    }
+};
+
+use super::{
+    access_trace::{ AccessTrace, ReadPattern },
+    tester::{ TestDataset, Tester, WORLD_X, WORLD_Y, WORLD_Z },
+    write_trace::WriteTrace,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Creating the structure and filling it with the workload
+    Build,
+    GetAll,
+    GetRandom,
+    RemoveRandom,
+    /// Replaying the reads of the engine recorded on the first chunk of the workload (see `Workload::get_read_trace`)
+    ReadTrace,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 5] = [ Operation::Build, Operation::GetAll, Operation::GetRandom, Operation::RemoveRandom, Operation::ReadTrace ];
+
+    pub fn get_name( self ) -> &'static str {
+        match self {
+            Operation::Build => "build",
+            Operation::GetAll => "get_all",
+            Operation::GetRandom => "get_random",
+            Operation::RemoveRandom => "remove_random",
+            Operation::ReadTrace => "read_trace",
+        }
+    }
+
+    pub fn from_name( name:&str ) -> Option<Self> {
+        Self::ALL.into_iter().find( |operation| operation.get_name() == name )
+    }
+}
+
+enum WorkloadSource {
+    Tester( fn( &mut dyn WorldHolding ) -> TestDataset ),
+    Chunks {
+        generator: Box<dyn WorldGenerative>,
+        origin: (i64, i64, i64),
+        chunks: (u32, u32, u32),
+        chunk_size: u8,
+        trace: OnceCell<WriteTrace>,
+    },
+    Trace( WriteTrace ),
+}
+
+/// Content written into every compared structure
+pub struct Workload {
+    name: String,
+    size: (u32, u32, u32),
+    source: WorkloadSource,
+    read_trace: OnceCell<AccessTrace>,
+}
+
+impl Workload {
+    /// `Tester` workloads use the whole tester world (`WORLD_X` × `WORLD_Y` × `WORLD_Z`)
+    pub fn from_tester( name:&str, fill:fn( &mut dyn WorldHolding ) -> TestDataset ) -> Self {
+        Self { name:name.to_string(), size:(WORLD_X, WORLD_Y, WORLD_Z), source:WorkloadSource::Tester( fill ), read_trace:OnceCell::new() }
+    }
+
+    /// Writes of a real generator into its chunks (see `WriteTrace::record`). They are recorded once, on the first use
+    pub fn from_generator( name:&str, generator:Box<dyn WorldGenerative>, origin:(i64, i64, i64), chunks:(u32, u32, u32), chunk_size:u8 ) -> Self {
+        let size = (chunks.0 * chunk_size as u32, chunks.1 * chunk_size as u32, chunks.2 * chunk_size as u32);
+        let source = WorkloadSource::Chunks { generator, origin, chunks, chunk_size, trace:OnceCell::new() };
+
+        Self { name:name.to_string(), size, source, read_trace:OnceCell::new() }
+    }
+
+    /// Previously recorded writes, eg. read from a file
+    pub fn from_trace( name:&str, trace:WriteTrace ) -> Self {
+        Self { name:name.to_string(), size:trace.get_size(), source:WorkloadSource::Trace( trace ), read_trace:OnceCell::new() }
+    }
+
+    pub fn get_defaults() -> Vec<Self> {
+        let chunk_size = CHUNK_SIZE as u8;
+        let eroded = Arc::new( ErodedHeights::new( 50, Box::new( GeneratorOfTest11HeightMap::new( 50 ) ) ) );
+
+        vec![
+            Self::from_tester( "set_0", |world_holder| Tester::set_0( world_holder ) ),
+            Self::from_tester( "set_1", Tester::set_1 ),
+            Self::from_tester( "set_50pc", Tester::set_50pc ),
+            Self::from_tester( "set_100pc", Tester::set_100pc ),
+            Self::from_tester( "set_50pc_random", Tester::set_50pc_random ),
+            Self::from_tester( "set_50pc_uniques", Tester::set_50pc_uniques ),
+            Self::from_tester( "set_99pc", Tester::set_99pc ),
+            Self::from_tester( "set_100_uniques", Tester::set_100_uniques ),
+            Self::from_tester( "fill_50pc", Tester::fill_50pc ),
+            Self::from_tester( "fill_50pc_realistically_flat", Tester::fill_50pc_realistically_flat ),
+            Self::from_tester( "fill_50pc_realistically", Tester::fill_50pc_realistically ),
+            Self::from_tester( "fill_100pc", Tester::fill_100pc ),
+            Self::from_generator( "chunks_natural", Box::new( GeneratorOfTest9Natural::new( 50 ) ), (0, -1, 0), (3, 2, 3), chunk_size ),
+            Self::from_generator( "chunks_biomes", Box::new( GeneratorOfBiomes::new_continental( 50 ) ), (0, -1, 0), (3, 2, 3), chunk_size ),
+            Self::from_generator(
+                "chunks_eroded_with_water",
+                Box::new( GeneratorWithWater::new( Box::new( GeneratorOfErodedTerrain::new( Arc::clone( &eroded ) ) ), Hydrology::new( Box::new( eroded ) ) ) ),
+                (0, -1, 0), (3, 2, 3), chunk_size
+            ),
+        ]
+    }
+
+    pub fn get_name( &self ) -> &str {
+        &self.name
+    }
+
+    pub fn get_size( &self ) -> (u32, u32, u32) {
+        self.size
+    }
+
+    pub fn fill( &self, world_holder:&mut dyn WorldHolding ) {
+        match &self.source {
+            WorkloadSource::Tester( fill ) => {
+                black_box( fill( world_holder ) );
+            },
+            WorkloadSource::Chunks { generator, origin, chunks, chunk_size, trace } => {
+                trace.get_or_init( || WriteTrace::record( generator.as_ref(), *origin, *chunks, *chunk_size ) ).replay( world_holder );
+            },
+            WorkloadSource::Trace( trace ) => trace.replay( world_holder ),
+        }
+    }
+
+    /// Every read pattern recorded on the first chunk of the workload. Recorded once, on the first use
+    pub fn get_read_trace( &self ) -> &AccessTrace {
+        self.read_trace.get_or_init( || {
+            let mut world_holder = Backend::Octree.create( self.size );
+
+            self.fill( world_holder.as_mut() );
+            AccessTrace::record( world_holder.as_ref(), self.size, &ReadPattern::ALL )
+        } )
+    }
+}
+
+/// Result of one cell of the comparison matrix
+#[derive(Debug, Clone)]
+pub struct ComparisonRecord {
+    pub backend: Backend,
+    pub workload: String,
+    pub operation: Operation,
+    /// Mean of all repetitions
+    pub time: Duration,
+    /// Highest heap usage while running the operation. Measured only with the `dhat-heap` feature
+    pub peak_heap: Option<usize>,
+    /// Memory footprint of the structure after the operation
+    pub footprint: Footprint,
+}
+
+/// Runs every operation of every workload on every backend
+pub struct ComparisonHarness {
+    backends: Vec<Backend>,
+    workloads: Vec<Workload>,
+    operations: Vec<Operation>,
+    repetitions: u32,
+    random_samples: u32,
+}
+
+#[allow(dead_code)]
+impl ComparisonHarness {
+    pub fn new( workloads:Vec<Workload> ) -> Self {
+        Self {
+            backends: Backend::ALL.to_vec(),
+            workloads,
+            operations: Operation::ALL.to_vec(),
+            repetitions: 3,
+            random_samples: 100_000,
+        }
+    }
+
+    pub fn with_backends( mut self, backends:Vec<Backend> ) -> Self {
+        self.backends = backends;
+        self
+    }
+
+    pub fn with_operations( mut self, operations:Vec<Operation> ) -> Self {
+        self.operations = operations;
+        self
+    }
+
+    pub fn with_repetitions( mut self, repetitions:u32 ) -> Self {
+        self.repetitions = repetitions.max( 1 );
+        self
+    }
+
+    /// Count of voxels read or removed by the random operations
+    pub fn with_random_samples( mut self, random_samples:u32 ) -> Self {
+        self.random_samples = random_samples;
+        self
+    }
+
+    pub fn run( &self ) -> ComparisonReport {
+        let mut records = vec![];
+
+        for workload in &self.workloads {
+            for &backend in &self.backends {
+                for &operation in &self.operations {
+                    println!( "Comparing {} / {} / {}", backend.get_name(), workload.get_name(), operation.get_name() );
+                    records.push( self.run_case( backend, workload, operation ) );
+                }
+            }
+        }
+
+        ComparisonReport { records }
+    }
+
+    fn run_case( &self, backend:Backend, workload:&Workload, operation:Operation ) -> ComparisonRecord {
+        let mut time = Duration::ZERO;
+        let mut footprint = Footprint::default();
+        let positions = self.get_random_positions( workload.get_size() );
+
+        // Timing runs go without the profiler, it slows every allocation down
+        for _ in 0..self.repetitions {
+            let world_holder = self.prepare( backend, workload, operation );
+            let (world_holder, duration) = self.run_operation( backend, workload, operation, &positions, world_holder );
+
+            time += duration;
+            footprint = world_holder.get_footprint();
+        }
+
+        let world_holder = self.prepare( backend, workload, operation );
+        let (_, peak_heap) = measure_peak_heap( || self.run_operation( backend, workload, operation, &positions, world_holder ) );
+
+        ComparisonRecord {
+            backend,
+            workload: workload.get_name().to_string(),
+            operation,
+            time: time / self.repetitions,
+            peak_heap,
+            footprint,
+        }
+    }
+
+    /// Structure the operation starts with, it isn't a part of the measurement
+    fn prepare( &self, backend:Backend, workload:&Workload, operation:Operation ) -> Option<Box<dyn WorldHolding>> {
+        if operation == Operation::Build {
+            return None
+        }
+
+        // Reads are replayed on the traced chunk only
+        if operation == Operation::ReadTrace {
+            let read_trace = workload.get_read_trace();
+            let mut world_holder = backend.create( read_trace.get_size() );
+
+            read_trace.prepare( world_holder.as_mut() );
+            return Some( world_holder )
+        }
+
+        let mut world_holder = backend.create( workload.get_size() );
+        workload.fill( world_holder.as_mut() );
+
+        Some( world_holder )
+    }
+
+    /// Positions for the random operations, the same for every backend
+    fn get_random_positions( &self, size:(u32, u32, u32) ) -> Vec<(u32, u32, u32)> {
+        let mut rng = SmallRng::seed_from_u64( 50 );
+
+        (0..self.random_samples)
+            .map( |_| (rng.random_range( 0..size.0 ), rng.random_range( 0..size.1 ), rng.random_range( 0..size.2 )) )
+            .collect()
+    }
+
+    fn run_operation(
+        &self, backend:Backend, workload:&Workload, operation:Operation, positions:&[(u32, u32, u32)], world_holder:Option<Box<dyn WorldHolding>>
+    ) -> (Box<dyn WorldHolding>, Duration) {
+        let time_start = Instant::now();
+
+        let world_holder = match world_holder {
+            None => {
+                let mut world_holder = backend.create( workload.get_size() );
+                workload.fill( world_holder.as_mut() );
+                world_holder
+            },
+            Some( mut world_holder ) => {
+                match operation {
+                    Operation::Build => unreachable!(),
+                    Operation::GetAll => {
+                        black_box( world_holder.get_all_voxels() );
+                    },
+                    Operation::GetRandom => for &(x, y, z) in positions {
+                        black_box( world_holder.get_voxel( x, y, z ) );
+                    },
+                    Operation::RemoveRandom => for &(x, y, z) in positions {
+                        world_holder.set_voxel( x, y, z, None );
+                    },
+                    Operation::ReadTrace => {
+                        black_box( workload.get_read_trace().replay( world_holder.as_mut() ) );
+                    },
+                }
+
+                world_holder
+            },
+        };
+
+        (world_holder, time_start.elapsed())
+    }
+}
+
+#[cfg(feature = "dhat-heap")]
+fn measure_peak_heap<R>( run:impl FnOnce() -> R ) -> (R, Option<usize>) {
+    let _profiler = dhat::Profiler::builder().testing().build();
+    let result = run();
+
+    (result, Some( dhat::HeapStats::get().max_bytes ))
+}
+
+#[cfg(not(feature = "dhat-heap"))]
+fn measure_peak_heap<R>( run:impl FnOnce() -> R ) -> (R, Option<usize>) {
+    (run(), None)
+}
+
+pub struct ComparisonReport {
+    records: Vec<ComparisonRecord>,
+}
+
+impl ComparisonReport {
+    #[allow(dead_code)]
+    pub fn get_records( &self ) -> &[ComparisonRecord] {
+        &self.records
+    }
+
+    pub fn to_csv( &self ) -> String {
+        let mut csv = String::from( "backend,workload,operation,time_ns,peak_heap_bytes,inline_bytes,heap_bytes,shared_bytes\n" );
+
+        for record in &self.records {
+            let peak_heap = record.peak_heap.map( |bytes| bytes.to_string() ).unwrap_or_default();
+
+            writeln!(
+                csv, "{},{},{},{},{},{},{},{}",
+                record.backend.get_name(), record.workload, record.operation.get_name(), record.time.as_nanos(), peak_heap,
+                record.footprint.inline, record.footprint.heap, record.footprint.shared
+            ).unwrap();
+        }
+
+        csv
+    }
+
+    /// One table per operation, workloads in rows and backends in columns
+    pub fn to_markdown( &self ) -> String {
+        let mut markdown = String::from( "# Storage structures comparison\n\nCells: mean time / peak heap / memory footprint\n" );
+
+        for (operation, workloads, backends) in self.get_tables() {
+            writeln!( markdown, "\n## {}\n", operation.get_name() ).unwrap();
+            writeln!( markdown, "| workload | {} |", backends.iter().map( |backend| backend.get_name() ).collect::<Vec<_>>().join( " | " ) ).unwrap();
+            writeln!( markdown, "|---|{}", "---|".repeat( backends.len() ) ).unwrap();
+
+            for workload in workloads {
+                let cells = backends.iter()
+                    .map( |&backend| self.get_cell( backend, workload, operation ) )
+                    .collect::<Vec<_>>();
+
+                writeln!( markdown, "| {workload} | {} |", cells.join( " | " ) ).unwrap();
+            }
+        }
+
+        markdown
+    }
+
+    pub fn to_html( &self ) -> String {
+        let mut html = String::from( "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Storage structures comparison</title></head>\n<body>\n" );
+        html.push_str( "<h1>Storage structures comparison</h1>\n<p>Cells: mean time / peak heap / memory footprint</p>\n" );
+
+        for (operation, workloads, backends) in self.get_tables() {
+            writeln!( html, "<h2>{}</h2>\n<table border=\"1\">", operation.get_name() ).unwrap();
+            writeln!( html, "<tr><th>workload</th>{}</tr>", backends.iter().map( |backend| format!( "<th>{}</th>", backend.get_name() ) ).collect::<String>() ).unwrap();
+
+            for workload in workloads {
+                let cells = backends.iter()
+                    .map( |&backend| format!( "<td>{}</td>", self.get_cell( backend, workload, operation ) ) )
+                    .collect::<String>();
+
+                writeln!( html, "<tr><td>{workload}</td>{cells}</tr>" ).unwrap();
+            }
+
+            html.push_str( "</table>\n" );
+        }
+
+        html.push_str( "</body>\n</html>\n" );
+        html
+    }
+
+    /// Writes `comparison.csv`, `comparison.md` and `comparison.html`
+    pub fn write_to( &self, directory:&Path ) -> io::Result<()> {
+        fs::create_dir_all( directory )?;
+        fs::write( directory.join( "comparison.csv" ), self.to_csv() )?;
+        fs::write( directory.join( "comparison.md" ), self.to_markdown() )?;
+        fs::write( directory.join( "comparison.html" ), self.to_html() )
+    }
+
+    /// Operations with workloads and backends which appear in them, in the order of measuring
+    fn get_tables( &self ) -> Vec<(Operation, Vec<&str>, Vec<Backend>)> {
+        let mut tables:Vec<(Operation, Vec<&str>, Vec<Backend>)> = vec![];
+
+        for record in &self.records {
+            let table_index = match tables.iter().position( |(operation, _, _)| *operation == record.operation ) {
+                Some( index ) => index,
+                None => {
+                    tables.push( (record.operation, vec![], vec![]) );
+                    tables.len() - 1
+                },
+            };
+            let (_, workloads, backends) = &mut tables[ table_index ];
+
+            if !workloads.contains( &record.workload.as_str() ) {
+                workloads.push( &record.workload );
+            }
+
+            if !backends.contains( &record.backend ) {
+                backends.push( record.backend );
+            }
+        }
+
+        tables
+    }
+
+    fn get_cell( &self, backend:Backend, workload:&str, operation:Operation ) -> String {
+        let record = self.records.iter().find( |record|
+            record.backend == backend && record.workload == workload && record.operation == operation
+        );
+
+        match record {
+            Some( record ) => format!(
+                "{:?} / {} / {}",
+                record.time,
+                record.peak_heap.map( format_bytes ).unwrap_or( String::from( "n/a" ) ),
+                format_bytes( record.footprint.get_total() ),
+            ),
+            None => String::from( "-" ),
+        }
+    }
+}
+
+fn format_bytes( bytes:usize ) -> String {
+    match bytes {
+        bytes if bytes >= 1024 * 1024 * 1024 => format!( "{:.2} GiB", bytes as f64 / (1024.0 * 1024.0 * 1024.0) ),
+        bytes if bytes >= 1024 * 1024 => format!( "{:.2} MiB", bytes as f64 / (1024.0 * 1024.0) ),
+        bytes if bytes >= 1024 => format!( "{:.2} KiB", bytes as f64 / 1024.0 ),
+        bytes => format!( "{bytes} B" ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::world::world_holder::fill;
+
+    use super::*;
+
+    fn small_workload() -> Workload {
+        let mut workload = Workload::from_tester( "small", |world_holder| {
+            fill( (0, 0, 0), (7, 3, 7), world_holder );
+            TestDataset::new()
+        } );
+
+        workload.size = (8, 8, 8);
+        workload
+    }
+
+    #[test]
+    fn test_every_backend_reads_the_same_workload() {
+        let workload = small_workload();
+
+        for backend in Backend::ALL {
+            let mut world_holder = backend.create( workload.get_size() );
+            workload.fill( world_holder.as_mut() );
+
+            assert_eq!( world_holder.get_all_voxels().len(), 8 * 4 * 8, "{}", backend.get_name() );
+        }
+    }
+
+    #[test]
+    fn test_report_has_a_cell_for_every_case() {
+        let report = ComparisonHarness::new( vec![ small_workload() ] )
+            .with_repetitions( 1 )
+            .with_random_samples( 10 )
+            .run();
+
+        assert_eq!( report.get_records().len(), Backend::ALL.len() * Operation::ALL.len() );
+        assert_eq!( report.to_csv().lines().count(), report.get_records().len() + 1 );
+
+        let markdown = report.to_markdown();
+        let build_table = markdown.split( "## build" ).nth( 1 ).unwrap().split( "## " ).next().unwrap();
+
+        assert!( build_table.contains( "| workload | voxel_map | voxel_list | voxel_hash_map | octree |" ) );
+        assert!( build_table.contains( "| small |" ) );
+        assert!( !build_table.contains( "| - |" ) );
+        assert_eq!( report.to_html().matches( "<table" ).count(), Operation::ALL.len() );
+    }
+}
@@ -4,8 +4,11 @@ pub mod voxel_list;
 pub mod octree;
 pub mod quadtree;
 pub mod voxel_hasher;
+pub mod comparison;
+pub mod write_trace;
+pub mod access_trace;
 
-use std::{time::Instant};
+use std::{ sync::Arc, time::Instant };
 
 use cgmath::Point3;
 use tester::{Tester, WORLD_X};
@@ -22,12 +25,17 @@ use voxel_list::VoxelList;
 #[allow(unused_imports)]
 use voxel_map::VoxelMap;
 
-use crate::{chunks_generators::test_13_plains_with_floatings::GeneratorOfTest13PlainsWithFloatings, flags::{RENDER_DISTANCE, SIMULATED_TEST_WORLD_ID}, world::{
+use crate::{chunks_generators::{definition::GeneratorDefinition, test_13_plains_with_floatings::GeneratorOfTest13PlainsWithFloatings}, flags::{GENERATOR_DEFINITION_ENV, RENDER_DISTANCE, SIMULATED_TEST_WORLD_ID}, world::{
     world::{ ChunkLoaderhandle, World }, world_generator::WorldGenerative, world_holder::{ Voxel, WorldHolding }
 }};
 
 #[allow(unused_imports)]
 use crate::chunks_generators::{
+    biomes::GeneratorOfBiomes,
+    caves::{ CaveCarver, GeneratorWithCaves },
+    erosion::{ ErodedHeights, GeneratorOfErodedTerrain },
+    features::{ FeaturePlacer, GeneratorWithFeatures, StructureLibrary },
+    hydrology::{ GeneratorWithWater, Hydrology },
     peaks_and_valleys::GeneratorOfPeaksAndValleys,
     cube::GeneratorOfCube,
     test_1_empty::GeneratorOfTest1Empty,
@@ -45,6 +53,17 @@ use crate::chunks_generators::{
 };
 
 pub fn generate_world_as_world( position:Point3<f32> ) -> (World, ChunkLoaderhandle) {
+    if let Ok( path ) = std::env::var( GENERATOR_DEFINITION_ENV ) {
+        let world_generator = GeneratorDefinition::load( &path )
+            .and_then( |definition| definition.compile() )
+            .unwrap_or_else( |error| panic!( "Can't use generator definition: {error:?}" ) );
+
+        let mut world = World::new( Box::new( world_generator ), None );
+        let chunk_loader = world.create_chunk_loader( (position.x, position.y, position.z), RENDER_DISTANCE );
+
+        return (world, chunk_loader)
+    }
+
     let world_generator:Box<dyn WorldGenerative> = match SIMULATED_TEST_WORLD_ID {
         1  => Box::new( GeneratorOfTest1Empty::new( 50 ) ),
         2  => Box::new( GeneratorOfTest2Single::new( 50 ) ),
@@ -59,6 +78,26 @@ pub fn generate_world_as_world( position:Point3<f32> ) -> (World, ChunkLoaderhan
         10 => Box::new( GeneratorOfTest11HeightMap::new( 50 ) ),
         11 => Box::new( GeneratorOfTest12PeaksAndValleys::new( 50 ) ),
         12 => Box::new( GeneratorOfTest13PlainsWithFloatings::new( 50 ) ),
+        13 => Box::new( GeneratorOfBiomes::new_continental( 50 ) ),
+        14 => Box::new( GeneratorWithCaves::new( Box::new( GeneratorOfBiomes::new_continental( 50 ) ), CaveCarver::new( 50 ).with_max_height( 8 ) ) ),
+        15 => {
+            let biomes = Arc::new( GeneratorOfBiomes::new_continental( 50 ) );
+
+            Box::new( GeneratorWithFeatures::new(
+                Box::new( GeneratorWithCaves::new( Box::new( Arc::clone( &biomes ) ), CaveCarver::new( 50 ).with_max_height( 8 ) ) ),
+                FeaturePlacer::new( 50, Box::new( biomes ), StructureLibrary::new_default() ).unwrap()
+            ) )
+        }
+        16 => Box::new( GeneratorOfErodedTerrain::new( Arc::new( ErodedHeights::new( 50, Box::new( GeneratorOfTest11HeightMap::new( 50 ) ) ) ) ) ),
+        17 => Box::new( GeneratorOfErodedTerrain::new( Arc::new( ErodedHeights::new( 50, Box::new( GeneratorOfTest12PeaksAndValleys::new( 50 ) ) ) ) ) ),
+        18 => {
+            let heights = Arc::new( ErodedHeights::new( 50, Box::new( GeneratorOfTest11HeightMap::new( 50 ) ) ) );
+
+            Box::new( GeneratorWithWater::new(
+                Box::new( GeneratorOfErodedTerrain::new( Arc::clone( &heights ) ) ),
+                Hydrology::new( Box::new( heights ) )
+            ) )
+        }
         _ => panic!( "Unknown WORLD_ID: {SIMULATED_TEST_WORLD_ID}" ),
     };
 
@@ -1,11 +1,175 @@
 use std::{ collections::{ HashMap, HashSet, VecDeque }, sync::Arc};
-use crate::world::{world::{CHUNK_SIZE, CHUNK_SIZE_X2}, world_chunk::ChunkBitmask, world_holder::{ Voxel, VoxelSide, WorldHolding }};
+use anyhow::{ bail, Result };
+use crate::world::{
+    memory_footprint::{ Footprint, MemoryFootprint, SharedAllocations },
+    world::{CHUNK_SIZE, CHUNK_SIZE_X2}, world_chunk::ChunkBitmask, world_holder::{ Voxel, VoxelSide, WorldHolding }, serialization::{ write_u32, ByteReader }
+};
+
+const NODE_EMPTY: u8 = 0;
+const NODE_FILLED: u8 = 1;
+const NODE_BRANCH: u8 = 2;
+
+/// Filled leaf as (offset, size, value)
+pub type OctreeLeaf<V> = ((u32, u32, u32), u32, V);
+
+/// Decides which leaves of a branch are compressed into one. Empty leaves are merged only with empty ones
+pub trait LeafMerging<V> {
+    /// Leaf with the value `a` is kept, instead of being split, when `b` is inserted into it
+    fn can_merge( &self, a:&V, b:&V ) -> bool;
+
+    /// Value of the leaf replacing all children of a branch, `None` keeps the branch
+    fn merge( &self, values:&[&V; 8] ) -> Option<V>;
+}
 
-struct Direction;
+/// Values behind `Arc`s, merged only when they are the same allocation. Used by the voxels of chunks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SharedValues;
+
+/// Plain values, like light levels, merged when they are equal
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExactValues;
+
+/// Lossy merging of fields like densities. Leaves which differ by at most `threshold` are replaced by their average
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThresholdValues {
+    pub threshold: f32,
+}
+
+impl<T> LeafMerging<Arc<T>> for SharedValues {
+    fn can_merge( &self, a:&Arc<T>, b:&Arc<T> ) -> bool {
+        Arc::ptr_eq( a, b )
+    }
+
+    fn merge( &self, values:&[&Arc<T>; 8] ) -> Option<Arc<T>> {
+        values[ 1.. ].iter().all( |value| Arc::ptr_eq( value, values[ 0 ] ) ).then( || Arc::clone( values[ 0 ] ) )
+    }
+}
+
+impl<V:Clone + PartialEq> LeafMerging<V> for ExactValues {
+    fn can_merge( &self, a:&V, b:&V ) -> bool {
+        a == b
+    }
+
+    fn merge( &self, values:&[&V; 8] ) -> Option<V> {
+        values[ 1.. ].iter().all( |value| *value == values[ 0 ] ).then( || values[ 0 ].clone() )
+    }
+}
+
+impl LeafMerging<f32> for ThresholdValues {
+    fn can_merge( &self, a:&f32, b:&f32 ) -> bool {
+        (a - b).abs() <= self.threshold
+    }
+
+    fn merge( &self, values:&[&f32; 8] ) -> Option<f32> {
+        let min = values.iter().fold( f32::INFINITY, |min, &&value| min.min( value ) );
+        let max = values.iter().fold( f32::NEG_INFINITY, |max, &&value| max.max( value ) );
+
+        (max - min <= self.threshold).then( || values.iter().copied().sum::<f32>() / 8.0 )
+    }
+}
+
+/// Step (-1 - 1 on every axis) from a node to its neighbour, the same as chunk offsets of `StageNeighbours`
+pub type NeighbourDirection = (i8, i8, i8);
+
+/// All 26 directions, 6 faces first, then 12 edges and 8 corners
+pub const NEIGHBOUR_DIRECTIONS: [NeighbourDirection; 26] = [
+    (-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1),
+
+    (-1, -1, 0), (-1, 1, 0), (1, -1, 0), (1, 1, 0),
+    (-1, 0, -1), (-1, 0, 1), (1, 0, -1), (1, 0, 1),
+    (0, -1, -1), (0, -1, 1), (0, 1, -1), (0, 1, 1),
+
+    (-1, -1, -1), (-1, -1, 1), (-1, 1, -1), (-1, 1, 1),
+    (1, -1, -1), (1, -1, 1), (1, 1, -1), (1, 1, 1),
+];
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeighbourKind {
+    Face,
+    Edge,
+    Corner,
+}
+
+#[allow(dead_code)]
+impl NeighbourKind {
+    pub fn from_direction( direction:NeighbourDirection ) -> Self {
+        match [ direction.0, direction.1, direction.2 ].iter().filter( |&&step| step != 0 ).count() {
+            1 => NeighbourKind::Face,
+            2 => NeighbourKind::Edge,
+            3 => NeighbourKind::Corner,
+            _ => unreachable!( "Neighbour direction can't be zero" )
+        }
+    }
+}
+
+/// Side of the filled cube at `offset` with `size`, turned in `direction` (1 - 6, like `VoxelSide`)
+#[derive(Debug)]
+pub struct OctreeFace<V> {
+    pub offset: (u32, u32, u32),
+    pub size: u32,
+    pub direction: u8,
+    pub value: V,
+}
+
+impl OctreeFace<Arc<Voxel>> {
+    /// Splits the face into sides of single voxels
+    pub fn add_voxel_sides( &self, out:&mut Vec<VoxelSide> ) {
+        let vector = Direction::get_vector( self.direction );
+        let last = self.size - 1;
+        let get_range = |coord:u32, step:i8| match step {
+            -1 => coord..=coord,
+            1 => coord + last..=coord + last,
+            _ => coord..=coord + last,
+        };
+
+        for x in get_range( self.offset.0, vector.0 ) {
+            for y in get_range( self.offset.1, vector.1 ) {
+                for z in get_range( self.offset.2, vector.2 ) {
+                    out.push( VoxelSide::from_voxel_rc( x as i64, y as i64, z as i64, self.direction, &self.value ) );
+                }
+            }
+        }
+    }
+}
 
+/// Node found next to another one, a leaf or a whole subtree.
+/// It is never smaller than the node it was searched for, a bigger one is a leaf covering the neighbouring area
 #[allow(dead_code)]
+pub struct OctreeNeighbour<'a, V> {
+    /// Offset of the chunk holding the node, (0, 0, 0) when it is in the searched octree
+    pub chunk_offset: (i8, i8, i8),
+    pub offset: (u32, u32, u32),
+    pub size: u32,
+    pub node: &'a OctreeNode<V>,
+}
+
+#[allow(dead_code)]
+impl<V:Clone> OctreeNeighbour<'_, V> {
+    pub fn is_leaf( &self ) -> bool {
+        matches!( self.node, OctreeNode::Leaf( _ ) )
+    }
+
+    /// Value of a leaf, `None` for empty leaves and subtrees
+    pub fn get_value( &self ) -> Option<&V> {
+        match self.node {
+            OctreeNode::Leaf( value ) => value.as_ref(),
+            OctreeNode::Branch( _ ) => None,
+        }
+    }
+
+    /// Leaves of the neighbour, empty ones included, which touch the node it was searched for.
+    /// `direction` is the one used in the search, leaves are given as (offset, size, value)
+    pub fn for_each_touching_leaf( &self, direction:NeighbourDirection, mut processor:impl FnMut( (u32, u32, u32), u32, &Option<V> ) ) {
+        self.node.for_each_touching_leaf( self.offset, self.size, direction, &mut processor );
+    }
+}
+
+struct Direction;
+
 impl Direction {
-    pub const UNSPECIFIED: u8 = 0;
     pub const LEFT:        u8 = 1;
     pub const RIGHT:       u8 = 2;
     pub const BOTTOM:      u8 = 3;
@@ -13,53 +177,24 @@ impl Direction {
     pub const BACK:        u8 = 5;
     pub const FRONT:       u8 = 6;
 
-    pub const OCTREE_NODE_INDICES: [[usize; 4]; 6] = [
-        /* opposite to LEFT   */  [1, 3, 5, 7],
-        /* opposite to RIGHT  */  [0, 2, 4, 6],
-        /* opposite to BOTTOM */  [2, 3, 6, 7],
-        /* opposite to TOP    */  [0, 1, 4, 5],
-        /* opposite to BACK   */  [4, 5, 6, 7],
-        /* opposite to FRONT  */  [0, 1, 2, 3],
-    ];
-
-    fn get_octree_node_indices_for_opposite_side( direction:u8 ) -> [usize; 4] {
-        Direction::OCTREE_NODE_INDICES[ direction as usize - 1 ]
-    }
+    pub const ALL: [u8; 6] = [ Direction::LEFT, Direction::RIGHT, Direction::BOTTOM, Direction::TOP, Direction::BACK, Direction::FRONT ];
 
-    fn split_branch_point_into_chilren_by_direction( direction:u8, point:(u32, u32, u32), size:u32 ) -> [(u32, u32, u32); 4] {
+    fn get_vector( direction:u8 ) -> NeighbourDirection {
         match direction {
-            1 | 2 => [
-                point,
-                (point.0,           point.1 + size,     point.2),
-                (point.0,           point.1,            point.2 + size),
-                (point.0,           point.1 + size,     point.2 + size),
-            ],
-            3 | 4 => [
-                point,
-                (point.0 + size,    point.1,            point.2),
-                (point.0,           point.1,            point.2 + size),
-                (point.0 + size,    point.1,            point.2 + size),
-            ],
-            5 | 6 => [
-                point,
-                (point.0 + size,    point.1,            point.2),
-                (point.0,           point.1 + size,     point.2),
-                (point.0 + size,    point.1 + size,     point.2),
-            ],
+            1 => (-1,  0,  0),
+            2 => ( 1,  0,  0),
+            3 => ( 0, -1,  0),
+            4 => ( 0,  1,  0),
+            5 => ( 0,  0, -1),
+            6 => ( 0,  0,  1),
             _ => unreachable!( "Unknown direction value" )
         }
     }
 
-    fn get_sibling_leaf( direction:u8, offset:(u32, u32, u32), size:u32 ) -> (u32, u32, u32) {
-        match direction {
-            1 => (offset.0 - 1,     offset.1, offset.2),
-            2 => (offset.0 + size,  offset.1, offset.2),
-            3 => (offset.0,         offset.1, offset.2),
-            4 => (offset.0,         offset.1, offset.2),
-            5 => (offset.0,         offset.1, offset.2),
-            6 => (offset.0,         offset.1, offset.2),
-            _ => unreachable!( "Unknown direction value" )
-        }
+    /// Node behind the side of the node at `offset` with `size`, a leaf or a subtree at least as big as it.
+    /// `None` when it lies outside of the octree
+    fn get_sibling_leaf<V:Clone, M:LeafMerging<V>>( octree:&GenericOctree<V, M>, direction:u8, offset:(u32, u32, u32), size:u32 ) -> Option<OctreeNeighbour<'_, V>> {
+        octree.get_neighbour( offset, size, Direction::get_vector( direction ) )
     }
 
     fn get_opposite( direction:u8 ) -> u8 {
@@ -73,48 +208,51 @@ impl Direction {
             _ => unreachable!( "Unknown direction value" )
         }
     }
-
-    fn get_dir_name( direction:u8 ) -> String {
-        match direction {
-            0 => String::from( "UNSPECIFIED" ),
-            1 => String::from( "LEFT" ),
-            2 => String::from( "RIGHT" ),
-            3 => String::from( "BOTTOM" ),
-            4 => String::from( "TOP" ),
-            5 => String::from( "BACK" ),
-            6 => String::from( "FRONT" ),
-            _ => unreachable!( "Unknown direction value" )
-        }
-    }
 }
 
 #[derive(Debug)]
-pub enum OctreeNode<T> {
-    Leaf( Option<Arc<T>> ),
-    Branch( Box<OctreeBranch<T>> ),
+pub enum OctreeNode<V> {
+    Leaf( Option<V> ),
+    Branch( Box<OctreeBranch<V>> ),
 }
 
-impl<T> OctreeNode<T> {
-    fn insert(&mut self, reversed_depth:u8, x:u32, y:u32, z:u32, value:Arc<T> ) {
+/// Only the nodes are copied, `Arc` values stay shared
+impl<V:Clone> Clone for OctreeNode<V> {
+    fn clone( &self ) -> Self {
+        match self {
+            OctreeNode::Leaf( value ) => OctreeNode::Leaf( value.clone() ),
+            OctreeNode::Branch( branch ) => OctreeNode::Branch( branch.clone() ),
+        }
+    }
+}
+
+impl<V:Clone> OctreeNode<V> {
+    fn insert( &mut self, reversed_depth:u8, x:u32, y:u32, z:u32, value:V, merging:&impl LeafMerging<V> ) {
         match self {
             OctreeNode::Leaf( leaf ) => {
+                // Splitting a compressed leaf for the value it already has would leave an uncompressed branch
+                if leaf.as_ref().is_some_and( |leaf| merging.can_merge( leaf, &value ) ) {
+                    return
+                }
+
                 if reversed_depth == 0 {
                     *self = OctreeNode::Leaf( Some( value ) );
                     return;
                 }
 
                 let mut branch = OctreeBranch::new_filled_by( leaf.clone() );
-                branch.insert( reversed_depth, x, y, z, value );
+                branch.insert( reversed_depth, x, y, z, value, merging );
                 *self = OctreeNode::Branch( Box::new( branch ) );
             }
 
             OctreeNode::Branch( branch ) => {
-                branch.insert( reversed_depth, x, y, z, value );
-                self.try_compress();
+                branch.insert( reversed_depth, x, y, z, value, merging );
+                self.try_compress( merging );
             }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn fill_at(
         &mut self,
         depth: u8,
@@ -122,7 +260,8 @@ impl<T> OctreeNode<T> {
         size: u32,
         fill_from: (u32, u32, u32),
         fill_to: (u32, u32, u32),
-        value: Option<Arc<T>>,
+        value: Option<V>,
+        merging: &impl LeafMerging<V>,
     ) {
         let (from_x, from_y, from_z) = fill_from;
         let (to_x, to_y, to_z) = fill_to;
@@ -169,24 +308,24 @@ impl<T> OctreeNode<T> {
                 let cy = origin_y + if (child_index >> 1) & 1 == 1 { child_size } else { 0 };
                 let cz = origin_z + if (child_index >> 2) & 1 == 1 { child_size } else { 0 };
 
-                branch.children[ child_index ].fill_at( depth - 1, (cx, cy, cz), child_size, fill_from, fill_to, value.clone() );
+                branch.children[ child_index ].fill_at( depth - 1, (cx, cy, cz), child_size, fill_from, fill_to, value.clone(), merging );
             }
 
-            self.try_compress();
+            self.try_compress( merging );
         }
     }
 
-    fn get( &self, reversed_depth:u8, x:u32, y:u32, z:u32 ) -> Option<Arc<T>> {
+    fn get( &self, reversed_depth:u8, x:u32, y:u32, z:u32 ) -> Option<V> {
         match self {
             OctreeNode::Leaf( value ) => value.clone(),
             OctreeNode::Branch( branch ) => {
-                let child_index = OctreeBranch::<T>::get_child_index( reversed_depth, &(x, y, z) );
+                let child_index = OctreeBranch::<V>::get_child_index( reversed_depth, &(x, y, z) );
                 branch.children[ child_index ].get( reversed_depth - 1, x, y, z )
             }
         }
     }
 
-    fn collect_voxels( &self, offset:(u32, u32, u32), depth:u8, out:&mut Vec<(u32, u32, u32, Arc<T>)> ) {
+    fn collect_voxels( &self, offset:(u32, u32, u32), depth:u8, out:&mut Vec<(u32, u32, u32, V)> ) {
         match self {
             OctreeNode::Leaf( Some( voxel ) ) => {
                 let size = 1 << depth;
@@ -197,7 +336,7 @@ impl<T> OctreeNode<T> {
                                 offset.0 + x,
                                 offset.1 + y,
                                 offset.2 + z,
-                                Arc::clone(voxel),
+                                voxel.clone(),
                             ));
                         }
                     }
@@ -218,7 +357,56 @@ impl<T> OctreeNode<T> {
         }
     }
 
-    fn remove( &mut self, reversed_depth:u8, x:u32, y:u32, z:u32 ) -> Option<Arc<T>> {
+    fn collect_leaves( &self, offset:(u32, u32, u32), depth:u8, out:&mut Vec<OctreeLeaf<V>> ) {
+        match self {
+            OctreeNode::Leaf( Some( voxel ) ) => out.push( (offset, 1 << depth, voxel.clone()) ),
+            OctreeNode::Leaf( None ) => {}
+            OctreeNode::Branch( branch ) => {
+                for (i, child) in branch.children.iter().enumerate() {
+                    let child_offset = OctreeNode::<V>::get_child_offset( offset, 1 << depth, i );
+                    child.collect_leaves( child_offset, depth - 1, out );
+                }
+            }
+        }
+    }
+
+    fn write_nodes( &self, out:&mut Vec<u8>, value_id:&mut impl FnMut( &V ) -> u32 ) {
+        match self {
+            OctreeNode::Leaf( None ) => out.push( NODE_EMPTY ),
+            OctreeNode::Leaf( Some( value ) ) => {
+                out.push( NODE_FILLED );
+                write_u32( out, value_id( value ) );
+            }
+            OctreeNode::Branch( branch ) => {
+                out.push( NODE_BRANCH );
+                branch.children.iter().for_each( |child| child.write_nodes( out, value_id ) );
+            }
+        }
+    }
+
+    fn read_nodes( reader:&mut ByteReader, reversed_depth:u8, value_of:&impl Fn( u32 ) -> Option<V> ) -> Result<Self> {
+        match reader.read_u8()? {
+            NODE_EMPTY => Ok( OctreeNode::Leaf( None ) ),
+            NODE_FILLED => {
+                let id = reader.read_u32()?;
+                let Some( value ) = value_of( id ) else { bail!( "Unknown octree value id {id}" ) };
+                Ok( OctreeNode::Leaf( Some( value ) ) )
+            }
+            NODE_BRANCH if reversed_depth > 0 => {
+                let mut children = Vec::with_capacity( 8 );
+
+                for _ in 0..8 {
+                    children.push( Self::read_nodes( reader, reversed_depth - 1, value_of )? );
+                }
+
+                let Ok( children ) = children.try_into() else { unreachable!() };
+                Ok( OctreeNode::Branch( Box::new( OctreeBranch { children } ) ) )
+            }
+            tag => bail!( "Invalid octree node tag {tag} at reversed depth {reversed_depth}" ),
+        }
+    }
+
+    fn remove( &mut self, reversed_depth:u8, x:u32, y:u32, z:u32, merging:&impl LeafMerging<V> ) -> Option<V> {
         match self {
             OctreeNode::Leaf( value ) => {
                 let value = value.take();
@@ -230,16 +418,16 @@ impl<T> OctreeNode<T> {
 
                     *self = OctreeNode::Branch( Box::new( branch ) );
 
-                    self.remove( reversed_depth, x, y, z )
+                    self.remove( reversed_depth, x, y, z, merging )
                 }
             }
 
             OctreeNode::Branch( branch ) => {
-                let child_index = OctreeBranch::<T>::get_child_index( reversed_depth, &(x, y, z) );
-                let result = branch.children[child_index].remove( reversed_depth - 1, x, y, z );
+                let child_index = OctreeBranch::<V>::get_child_index( reversed_depth, &(x, y, z) );
+                let result = branch.children[child_index].remove( reversed_depth - 1, x, y, z, merging );
 
-                branch.children[child_index].try_compress();
-                self.try_compress();
+                branch.children[child_index].try_compress( merging );
+                self.try_compress( merging );
 
                 result
             }
@@ -253,40 +441,28 @@ impl<T> OctreeNode<T> {
         }
     }
 
-    fn try_compress( &mut self ) {
-        let branch = match self {
-            OctreeNode::Branch( branch ) => branch,
-            _ => return
-        };
+    fn try_compress( &mut self, merging:&impl LeafMerging<V> ) {
+        let OctreeNode::Branch( branch ) = self else { return };
+        let mut values = [ None; 8 ];
 
-        let first_value = match branch.children.iter_mut().next().unwrap() {
-            OctreeNode::Leaf( v ) => v.clone(),
-            _ => return
-        };
+        for (value, child) in values.iter_mut().zip( &branch.children ) {
+            let OctreeNode::Leaf( leaf ) = child else { return };
+            *value = leaf.as_ref();
+        }
 
-        let are_all_the_same = match first_value.clone() {
-            Some( value ) => branch.children.iter().skip( 1 ).all( |child| match child {
-                OctreeNode::Leaf( val_opt ) => match val_opt {
-                    Some( val ) => Arc::ptr_eq( val, &value ),
-                    _ => false
-                }
-                _ => false,
-            } ),
+        // Empty leaves are merged only with empty ones
+        let merged = if values.iter().all( Option::is_none ) {
+            None
+        } else {
+            if values.iter().any( Option::is_none ) {
+                return
+            }
 
-            None => branch.children.iter().skip( 1 ).all( |child| match child {
-                OctreeNode::Leaf( val_opt ) => match val_opt {
-                    None => true,
-                    _ => false,
-                }
-                _ => false,
-            } )
+            let Some( value ) = merging.merge( &values.map( Option::unwrap ) ) else { return };
+            Some( value )
         };
 
-        if !are_all_the_same {
-            return
-        }
-
-        *self = OctreeNode::Leaf( first_value );
+        *self = OctreeNode::Leaf( merged );
     }
 
     fn get_child_offset( parent_offset:(u32, u32, u32), parent_size:u32, child_index:usize ) -> (u32, u32, u32) {
@@ -299,12 +475,12 @@ impl<T> OctreeNode<T> {
         (parent_offset.0 + dx, parent_offset.1 + dy, parent_offset.2 + dz)
     }
 
-    fn fill_bitmask( &self, mask:&mut ChunkBitmask, max_depth:u8, reversed_depth:u8, node_offset:(u32, u32, u32) ) {
+    fn fill_bitmask( &self, mask:&mut ChunkBitmask, max_depth:u8, reversed_depth:u8, node_offset:(u32, u32, u32), filter:&impl Fn( &V ) -> bool ) {
         let node_size = 1 << reversed_depth;
 
         match self {
             OctreeNode::Leaf( voxel ) => {
-                if voxel.is_some() {
+                if voxel.as_ref().is_some_and( filter ) {
                     let nx = node_offset.0 as usize;
                     let ny = node_offset.1 as usize;
                     let nz = node_offset.2 as usize;
@@ -328,34 +504,49 @@ impl<T> OctreeNode<T> {
 
             OctreeNode::Branch( branch ) => {
                 for (i, child) in branch.children.iter().enumerate() {
-                    let child_offset = OctreeNode::<Voxel>::get_child_offset( node_offset, node_size, i );
-                    child.fill_bitmask( mask, max_depth, reversed_depth - 1, child_offset );
+                    let child_offset = Self::get_child_offset( node_offset, node_size, i );
+                    child.fill_bitmask( mask, max_depth, reversed_depth - 1, child_offset, filter );
                 }
             }
         }
     }
 
-    #[allow(dead_code)]
-    fn contains_point( offset:&(u32, u32, u32), size:u32, point:&(u32, u32, u32) ) -> bool {
-        point.0 >= offset.0 && point.0 < offset.0 + size &&
-        point.1 >= offset.1 && point.1 < offset.1 + size &&
-        point.2 >= offset.2 && point.2 < offset.2 + size
+    fn for_each_touching_leaf( &self, offset:(u32, u32, u32), size:u32, direction:NeighbourDirection, processor:&mut impl FnMut( (u32, u32, u32), u32, &Option<V> ) ) {
+        match self {
+            OctreeNode::Leaf( value ) => processor( offset, size, value ),
+            OctreeNode::Branch( branch ) => {
+                // Neighbour on the positive side touches with its lower half, and the other way around
+                let is_touching = |step:i8, bit:usize| step == 0 || (step > 0) == (bit == 0);
+
+                for (i, child) in branch.children.iter().enumerate() {
+                    if is_touching( direction.0, i & 1 ) && is_touching( direction.1, (i >> 1) & 1 ) && is_touching( direction.2, (i >> 2) & 1 ) {
+                        child.for_each_touching_leaf( OctreeNode::<V>::get_child_offset( offset, size, i ), size >> 1, direction, processor );
+                    }
+                }
+            }
+        }
     }
 }
 
 #[derive(Debug)]
-pub struct OctreeBranch<T> {
-    children: [OctreeNode<T>; 8],
+pub struct OctreeBranch<V> {
+    children: [OctreeNode<V>; 8],
+}
+
+impl<V:Clone> Clone for OctreeBranch<V> {
+    fn clone( &self ) -> Self {
+        Self { children:self.children.clone() }
+    }
 }
 
-impl<T> OctreeBranch<T> {
-    fn new_filled_by( value:Option<Arc<T>> ) -> Self {
+impl<V:Clone> OctreeBranch<V> {
+    fn new_filled_by( value:Option<V> ) -> Self {
         Self {
             children: std::array::from_fn( |_| OctreeNode::Leaf( value.clone() ) ),
         }
     }
 
-    fn insert( &mut self, depth:u8, x:u32, y:u32, z:u32, value:Arc<T> ) {
+    fn insert( &mut self, depth:u8, x:u32, y:u32, z:u32, value:V, merging:&impl LeafMerging<V> ) {
         let child_index = Self::get_child_index( depth, &(x, y, z) );
 
         if depth == 1 {
@@ -363,8 +554,8 @@ impl<T> OctreeBranch<T> {
             return
         }
 
-        self.children[ child_index ].insert( depth - 1, x, y, z, value );
-        self.children[ child_index ].try_compress();
+        self.children[ child_index ].insert( depth - 1, x, y, z, value, merging );
+        self.children[ child_index ].try_compress( merging );
     }
 
     fn get_child_index( reversed_depth:u8, point:&(u32, u32, u32) ) -> usize {
@@ -377,40 +568,108 @@ impl<T> OctreeBranch<T> {
     }
 }
 
-pub struct Octree<T> {
-    root: OctreeNode<T>,
+/// Octree over any leaf values, `merging` decides which leaves are compressed into one
+pub struct GenericOctree<V, M> {
+    root: OctreeNode<V>,
     max_depth: u8,
+    merging: M,
+}
+
+/// Octree of shared values, like the voxels of chunks
+pub type Octree<T> = GenericOctree<Arc<T>, SharedValues>;
+
+impl<V:Clone, M:Clone> Clone for GenericOctree<V, M> {
+    fn clone( &self ) -> Self {
+        Self { root:self.root.clone(), max_depth:self.max_depth, merging:self.merging.clone() }
+    }
+}
+
+/// Boxed branches are the heap, `Arc` values are shared
+impl<V:MemoryFootprint> MemoryFootprint for OctreeNode<V> {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        match self {
+            OctreeNode::Leaf( Some( value ) ) => value.add_heap_footprint( footprint, seen ),
+            OctreeNode::Leaf( None ) => {},
+            OctreeNode::Branch( branch ) => {
+                footprint.heap += size_of::<OctreeBranch<V>>();
+
+                for child in &branch.children {
+                    child.add_heap_footprint( footprint, seen );
+                }
+            },
+        }
+    }
+}
+
+impl<V:MemoryFootprint, M> MemoryFootprint for GenericOctree<V, M> {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        self.root.add_heap_footprint( footprint, seen );
+    }
 }
 
 #[allow(dead_code)]
-impl<T> Octree<T> {
+impl<V:Clone, M:LeafMerging<V> + Default> GenericOctree<V, M> {
     pub fn new( max_depth:u8 ) -> Self {
-        Self {
-            max_depth,
-            root: OctreeNode::Leaf( None )
-        }
+        Self::new_with_merging( max_depth, M::default() )
     }
 
     pub fn from_max_size( max_size:u32 ) -> Self {
         Self::new( Self::get_max_depth_for( max_size ) )
     }
 
-    pub fn insert( &mut self, x:u32, y:u32, z:u32, value:Arc<T> ) {
-        self.root.insert( self.max_depth, x, y, z, value )
+    pub fn read_nodes( reader:&mut ByteReader, value_of:&impl Fn( u32 ) -> Option<V> ) -> Result<Self> {
+        let max_depth = reader.read_u8()?;
+        let root = OctreeNode::read_nodes( reader, max_depth, value_of )?;
+
+        Ok( Self { root, max_depth, merging:M::default() } )
+    }
+}
+
+#[allow(dead_code)]
+impl<V:Clone, M:LeafMerging<V>> GenericOctree<V, M> {
+    pub fn new_with_merging( max_depth:u8, merging:M ) -> Self {
+        Self {
+            max_depth,
+            root: OctreeNode::Leaf( None ),
+            merging,
+        }
+    }
+
+    pub fn insert( &mut self, x:u32, y:u32, z:u32, value:V ) {
+        self.root.insert( self.max_depth, x, y, z, value, &self.merging )
     }
 
-    pub fn get( &self, x: u32, y: u32, z: u32) -> Option<Arc<T>> {
+    pub fn get( &self, x: u32, y: u32, z: u32) -> Option<V> {
         self.root.get( self.max_depth, x, y, z )
     }
 
-    pub fn get_voxels(&self) -> Vec<(u32, u32, u32, Arc<T>)> {
+    pub fn get_voxels(&self) -> Vec<(u32, u32, u32, V)> {
         let mut result = Vec::new();
         self.root.collect_voxels( (0, 0, 0), self.max_depth, &mut result );
         result
     }
 
-    pub fn remove( &mut self, x:u32, y:u32, z:u32 ) -> Option<Arc<T>>{
-        self.root.remove( self.max_depth, x, y, z )
+    /// Returns filled leaves as (offset, size, value) without expanding them into single voxels
+    pub fn get_leaves( &self ) -> Vec<OctreeLeaf<V>> {
+        let mut result = Vec::new();
+        self.root.collect_leaves( (0, 0, 0), self.max_depth, &mut result );
+        result
+    }
+
+    /// Writes nodes in pre-order. Values are stored as ids given by `value_id`, so shared `Arc`s stay shared after reading
+    pub fn write_nodes( &self, out:&mut Vec<u8>, value_id:&mut impl FnMut( &V ) -> u32 ) {
+        out.push( self.max_depth );
+        self.root.write_nodes( out, value_id );
+    }
+
+    pub fn remove( &mut self, x:u32, y:u32, z:u32 ) -> Option<V>{
+        self.root.remove( self.max_depth, x, y, z, &self.merging )
+    }
+
+    /// Fills the box between the corners (both included), the part outside of the octree is skipped
+    pub fn fill( &mut self, from:(u32, u32, u32), to:(u32, u32, u32), value:Option<V> ) {
+        let size = 1u32 << self.max_depth;
+        self.root.fill_at( self.max_depth, (0, 0, 0), size, from, to, value, &self.merging );
     }
 
     pub fn count_leaves( &self ) -> usize {
@@ -424,280 +683,148 @@ impl<T> Octree<T> {
     pub fn get_max_depth_for( n:u32 ) -> u8 {
         (32 - (n - 1).leading_zeros()) as u8
     }
-}
-
-impl Octree<Voxel> {
-    #[allow(dead_code)]
-    pub fn get_visible_with_flood( &self, initial_point:(u32,u32,u32) ) -> Vec<VoxelSide> {
-        struct Point {
-            coords: (u32,u32,u32),
-            depth: u8,
-            check_dir: u8,
-            source_size: u32,
-        }
-
-        let mut result = HashMap::new();
-        let mut points_memory = HashSet::<(u32, u32, u32)>::new();
-        let mut points = VecDeque::with_capacity( (1 << self.max_depth) * (1 << self.max_depth) );
-        let mut path = vec![(&self.root, (0, 0, 0))];
-
-        points.push_front( Point { coords:initial_point, depth:self.max_depth, check_dir:Direction::UNSPECIFIED, source_size:0 } );
-
-        'points: while let Some( point ) = points.pop_front() {
-            let (mut node_ptr, mut node_offset) = path.last().unwrap();
-            let mut depth = path.len() as u8 - 1;
-            let mut reversed_depth = self.max_depth - depth;
-            let mut node_size = 1 << reversed_depth;
-
-
-
-            // Going up in path vector
-            while !OctreeNode::<Voxel>::contains_point( &node_offset, node_size, &point.coords ) {
-                path.pop().unwrap();
-
-                depth = path.len() as u8 - 1;
-                (node_ptr, node_offset) = *path.last().unwrap();
-                reversed_depth = self.max_depth - depth;
-                node_size = 1 << reversed_depth;
-            }
 
+    /// Node of the given size containing the point, or a bigger leaf when the tree is compressed there
+    pub fn get_node( &self, point:(u32, u32, u32), size:u32 ) -> OctreeNeighbour<'_, V> {
+        let mut node = &self.root;
+        let mut offset = (0, 0, 0);
+        let mut reversed_depth = self.max_depth;
 
-
-            // Going down in path vector
-            while let OctreeNode::Branch( branch ) = node_ptr {
-                if depth >= point.depth {
-                    let deeper_leaf_size = 1 << (reversed_depth - 1);
-
-                    for coords in Direction::split_branch_point_into_chilren_by_direction( point.check_dir, point.coords, deeper_leaf_size ) {
-                        points.push_front( Point {
-                            coords,
-                            check_dir: point.check_dir,
-                            depth: point.depth + 1,
-                            source_size: deeper_leaf_size,
-                        } );
-                    }
-
-                    continue 'points;
-                }
-
-                let child_index = OctreeBranch::<Voxel>::get_child_index( reversed_depth, &point.coords );
-                let child_offset = OctreeNode::<Voxel>::get_child_offset( node_offset, node_size, child_index );
-
-                node_ptr = &branch.children[ child_index as usize ];
-                node_offset = child_offset;
-
-                path.push( (node_ptr, child_offset) );
-
-                depth = path.len() as u8 - 1;
-                reversed_depth = self.max_depth - depth;
-                node_size = 1 << reversed_depth;
+        while let OctreeNode::Branch( branch ) = node {
+            if 1 << reversed_depth <= size {
+                break
             }
 
+            let child_index = OctreeBranch::<V>::get_child_index( reversed_depth, &point );
 
+            offset = OctreeNode::<V>::get_child_offset( offset, 1 << reversed_depth, child_index );
+            node = &branch.children[ child_index ];
+            reversed_depth -= 1;
+        }
 
-            // Handling the leaf
-            let leaf_value = match node_ptr {
-                OctreeNode::Leaf( opt ) => opt,
-                _ => unreachable!()
-            };
-
-
-
-            // Fill results if have value
-            if let Some( data ) = leaf_value {
-                match point.check_dir {
-                    1 | 2 => {
-                        let x = node_offset.0 + if point.check_dir == 2 { 0 } else { node_size - 1 };
+        OctreeNeighbour { chunk_offset:(0, 0, 0), offset, size:1 << reversed_depth, node }
+    }
 
-                        for y in 0..point.source_size {
-                            for z in 0..point.source_size {
-                                let coords = (x, point.coords.1 + y, point.coords.2 + z);
-                                result.entry( (coords, point.check_dir) ).or_insert_with( || VoxelSide::from_voxel_rc( coords.0 as i64, coords.1 as i64, coords.2 as i64, Direction::get_opposite( point.check_dir ), &data ) );
-                                // result.entry( (coords, point.check_dir) ).or_insert_with( || VoxelSide::from_voxel_rc( coords.0 as i64, coords.1 as i64, coords.2 as i64, point.check_dir, &data ) );
-                            }
-                        }
-                    }
+    /// Neighbour of the node at `offset` with `size` (a leaf from `get_leaves` or any aligned node) across a face, edge or corner.
+    /// `None` when the neighbour lies outside of the octree
+    pub fn get_neighbour( &self, offset:(u32, u32, u32), size:u32, direction:NeighbourDirection ) -> Option<OctreeNeighbour<'_, V>> {
+        self.get_neighbour_across( offset, size, direction, |_| None )
+    }
 
-                    3 | 4 => {
-                        let y = node_offset.1 + if point.check_dir == 3 { 0 } else { node_size - 1 };
+    /// Like `get_neighbour`, but neighbours outside of the octree are searched in the octrees of adjacent chunks given by `get_chunk`.
+    /// Adjacent octrees have to be of the same size
+    pub fn get_neighbour_across<'a>(
+        &'a self,
+        offset: (u32, u32, u32),
+        size: u32,
+        direction: NeighbourDirection,
+        get_chunk: impl Fn( (i8, i8, i8) ) -> Option<&'a GenericOctree<V, M>>,
+    ) -> Option<OctreeNeighbour<'a, V>> {
+        debug_assert!( size.is_power_of_two() && offset.0.is_multiple_of( size ) && offset.1.is_multiple_of( size ) && offset.2.is_multiple_of( size ), "Node should be aligned to its size" );
+
+        let root_size = 1i64 << self.max_depth;
+        let get_coord = |coord:u32, step:i8| {
+            let coord = coord as i64 + step as i64 * size as i64;
+            (coord.div_euclid( root_size ) as i8, coord.rem_euclid( root_size ) as u32)
+        };
 
-                        for x in 0..point.source_size {
-                            for z in 0..point.source_size {
-                                let coords = (point.coords.0 + x, y, point.coords.2 + z);
-                                result.entry( (coords, point.check_dir) ).or_insert_with( || VoxelSide::from_voxel_rc( coords.0 as i64, coords.1 as i64, coords.2 as i64, Direction::get_opposite( point.check_dir ), &data ) );
-                                // result.entry( (coords, point.check_dir) ).or_insert_with( || VoxelSide::from_voxel_rc( coords.0 as i64, coords.1 as i64, coords.2 as i64, point.check_dir, &data ) );
-                            }
-                        }
-                    }
+        let (chunk_x, x) = get_coord( offset.0, direction.0 );
+        let (chunk_y, y) = get_coord( offset.1, direction.1 );
+        let (chunk_z, z) = get_coord( offset.2, direction.2 );
+        let chunk_offset = (chunk_x, chunk_y, chunk_z);
 
-                    5 | 6 => {
-                        let z = node_offset.2 + if point.check_dir == 5 { 0 } else { node_size - 1 };
+        let octree = if chunk_offset == (0, 0, 0) { self } else { get_chunk( chunk_offset )? };
+        debug_assert_eq!( octree.max_depth, self.max_depth, "Adjacent octrees should be of the same size" );
 
-                        for x in 0..point.source_size {
-                            for y in 0..point.source_size {
-                                let coords = (point.coords.0 + x, point.coords.1 + y, z);
-                                result.entry( (coords, point.check_dir) ).or_insert_with( || VoxelSide::from_voxel_rc( coords.0 as i64, coords.1 as i64, coords.2 as i64, Direction::get_opposite( point.check_dir ), &data ) );
-                                // result.entry( (coords, point.check_dir) ).or_insert_with( || VoxelSide::from_voxel_rc( coords.0 as i64, coords.1 as i64, coords.2 as i64, point.check_dir, &data ) );
-                            }
-                        }
-                    }
+        Some( OctreeNeighbour { chunk_offset, ..octree.get_node( (x, y, z), size ) } )
+    }
 
-                    _ => {}
-                }
+    /// Sides of filled leaves facing the empty space reachable from `initial_point`, found by flooding whole empty leaves.
+    /// Side of a leaf visible from all of its empty neighbours is given as a single face, partly covered sides are split
+    /// into parts as big as the smaller leaf of each touching pair. Space outside of the octree isn't flooded
+    pub fn get_visible_faces_with_flood( &self, initial_point:(u32, u32, u32) ) -> Vec<OctreeFace<V>> {
+        struct LeafSide<V> {
+            size: u32,
+            value: V,
+            parts: Vec<((u32, u32, u32), u32)>,
+        }
 
-                continue
-            }
+        let start = self.get_node( initial_point, 1 );
 
+        if !matches!( start.node, OctreeNode::Leaf( None ) ) {
+            return vec![]
+        }
 
-            if points_memory.contains( &point.coords ) || result.contains_key( &(point.coords, point.check_dir) ) {
-                continue;
+        let mut visited = HashSet::from([ start.offset ]);
+        let mut leaves = VecDeque::from([ (start.offset, start.size) ]);
+        let mut sides = HashMap::<((u32, u32, u32), u8), LeafSide<V>>::new();
+
+        while let Some( (offset, size) ) = leaves.pop_front() {
+            for direction in Direction::ALL {
+                let vector = Direction::get_vector( direction );
+                let Some( neighbour ) = Direction::get_sibling_leaf( self, direction, offset, size ) else { continue };
+
+                neighbour.for_each_touching_leaf( vector, |leaf_offset, leaf_size, value| match value {
+                    None => if visited.insert( leaf_offset ) {
+                        leaves.push_back( (leaf_offset, leaf_size) );
+                    },
+
+                    Some( value ) => {
+                        let part = if leaf_size <= size {
+                            (leaf_offset, leaf_size)
+                        } else {
+                            (Self::get_moved_offset( offset, size, vector ), size)
+                        };
+
+                        sides.entry( (leaf_offset, Direction::get_opposite( direction )) )
+                            .or_insert_with( || LeafSide { size:leaf_size, value:value.clone(), parts:vec![] } )
+                            .parts.push( part );
+                    },
+                } );
             }
+        }
 
-            // Generate siblings for empty cell
-            let root_size = 1 << self.max_depth;
-            let mut next_points = Vec::with_capacity( 6 );
-
+        let mut faces = Vec::with_capacity( sides.len() );
 
-            if point.check_dir != Direction::RIGHT && node_offset.0 > 0 {
-                next_points.push( Point { depth, source_size:node_size, coords:(node_offset.0 - 1,         node_offset.1, node_offset.2), check_dir:Direction::LEFT } )
-            }
-            if point.check_dir != Direction::LEFT && node_offset.0 < root_size - node_size {
-                next_points.push( Point { depth, source_size:node_size, coords:(node_offset.0 + node_size, node_offset.1, node_offset.2), check_dir:Direction::RIGHT } )
-            }
+        for ((offset, direction), side) in sides {
+            let visible_area = side.parts.iter().map( |(_, size)| size * size ).sum::<u32>();
 
-            if point.check_dir != Direction::TOP && node_offset.1 > 0 {
-                next_points.push( Point { depth, source_size:node_size, coords:(node_offset.0, node_offset.1 - 1,         node_offset.2), check_dir:Direction::BOTTOM } )
-            }
-            if point.check_dir != Direction::BOTTOM && node_offset.1 < root_size - node_size {
-                next_points.push( Point { depth, source_size:node_size, coords:(node_offset.0, node_offset.1 + node_size,  node_offset.2), check_dir:Direction::TOP } )
+            if visible_area == side.size * side.size {
+                faces.push( OctreeFace { offset, size:side.size, direction, value:side.value } );
+                continue
             }
 
-            if point.check_dir != Direction::FRONT && node_offset.2 > 0 {
-                next_points.push( Point { depth, source_size:node_size, coords:(node_offset.0, node_offset.1, node_offset.2 - 1        ), check_dir:Direction::BACK } )
-            }
-            if point.check_dir != Direction::BACK && node_offset.2 < root_size - node_size {
-                next_points.push( Point { depth, source_size:node_size, coords:(node_offset.0, node_offset.1, node_offset.2 + node_size), check_dir:Direction::FRONT } )
-            }
-
-
-
-            // Memoize outer children
-            if node_size == 1 {
-                points_memory.insert( node_offset );
-            } else if node_size == 2 {
-                points_memory.extend([
-                    node_offset,
-                    (node_offset.0 + 1,   node_offset.1,      node_offset.2),
-                    (node_offset.0,       node_offset.1 + 1,  node_offset.2),
-                    (node_offset.0 + 1,   node_offset.1 + 1,  node_offset.2),
-                    (node_offset.0,       node_offset.1,      node_offset.2 + 1),
-                    (node_offset.0 + 1,   node_offset.1,      node_offset.2 + 1),
-                    (node_offset.0,       node_offset.1 + 1,  node_offset.2 + 1),
-                    (node_offset.0 + 1,   node_offset.1 + 1,  node_offset.2 + 1),
-                ]);
-            } else {
-                let boundary_size = root_size - node_size;
-                let max_leaf_offset = node_size - 1;
-                let boundaries = [0, max_leaf_offset];
-
-                let internal_size = node_size - 2;
-                points_memory.reserve( (node_size * node_size * node_size) as usize - (internal_size * internal_size * internal_size) as usize );
-
-                if node_offset.0 != 0 {
-                    for y in 1..max_leaf_offset {
-                        for z in 1..max_leaf_offset {
-                            points_memory.insert( (node_offset.0, node_offset.1 + y, node_offset.2 + z) );
-                        }
-                    }
-                }
-                if node_offset.0 != boundary_size {
-                    for y in 1..max_leaf_offset {
-                        for z in 1..max_leaf_offset {
-                            points_memory.insert( (node_offset.0 + max_leaf_offset, node_offset.1 + y, node_offset.2 + z) );
-                        }
-                    }
-                }
-
-                if node_offset.1 != 0 {
-                    for x in 1..max_leaf_offset {
-                        for z in 1..max_leaf_offset {
-                            points_memory.insert( (node_offset.0 + x, node_offset.1, node_offset.2 + z ) );
-                        }
-                    }
-                }
-                if node_offset.1 != boundary_size {
-                    for x in 1..max_leaf_offset {
-                        for z in 1..max_leaf_offset {
-                            points_memory.insert( (node_offset.0 + x, node_offset.1 + max_leaf_offset, node_offset.2 + z ) );
-                        }
-                    }
-                }
-
-                if node_offset.2 != boundary_size {
-                    for x in 1..max_leaf_offset {
-                        for y in 1..max_leaf_offset {
-                            points_memory.insert( (node_offset.0 + x, node_offset.1 + y, node_offset.2 + max_leaf_offset ) );
-                        }
-                    }
-                }
-                if node_offset.2 != 0 {
-                    for x in 1..max_leaf_offset {
-                        for y in 1..max_leaf_offset {
-                            points_memory.insert( (node_offset.0 + x, node_offset.1 + y, node_offset.2 ) );
-                        }
-                    }
-                }
-
-                for x in boundaries {
-                    for y in boundaries {
-                        for z in 1..max_leaf_offset {
-                            points_memory.insert( (node_offset.0 + x, node_offset.1 + y, node_offset.2 + z ) );
-                        }
-                    }
-                }
-
-                for x in boundaries {
-                    for z in boundaries {
-                        for y in 1..max_leaf_offset {
-                            points_memory.insert( (node_offset.0 + x, node_offset.1 + y, node_offset.2 + z ) );
-                        }
-                    }
-                }
-
-                for y in boundaries {
-                    for z in boundaries {
-                        for x in 1..max_leaf_offset {
-                            points_memory.insert( (node_offset.0 + x, node_offset.1 + y, node_offset.2 + z ) );
-                        }
-                    }
-                }
-
-                for x in boundaries {
-                    for y in boundaries {
-                        for z in boundaries {
-                            points_memory.insert( (node_offset.0 + x, node_offset.1 + y, node_offset.2 + z ) );
-                        }
-                    }
-                }
+            for (offset, size) in side.parts {
+                faces.push( OctreeFace { offset, size, direction, value:side.value.clone() } );
             }
+        }
 
+        faces
+    }
 
+    fn get_moved_offset( offset:(u32, u32, u32), size:u32, vector:NeighbourDirection ) -> (u32, u32, u32) {
+        let get_coord = |coord:u32, step:i8| (coord as i64 + step as i64 * size as i64) as u32;
+        (get_coord( offset.0, vector.0 ), get_coord( offset.1, vector.1 ), get_coord( offset.2, vector.2 ))
+    }
 
-            // Process siblings
-            points.reserve( next_points.len() );
+    /// Neighbours in all 26 directions (in `NEIGHBOUR_DIRECTIONS` order) which lie inside of the octree
+    pub fn get_neighbours( &self, offset:(u32, u32, u32), size:u32 ) -> Vec<(NeighbourDirection, OctreeNeighbour<'_, V>)> {
+        NEIGHBOUR_DIRECTIONS.iter()
+            .filter_map( |&direction| Some( (direction, self.get_neighbour( offset, size, direction )?) ) )
+            .collect()
+    }
+}
 
-            for next_point in next_points {
-                // if next_point.depth != self.max_depth || (!points_memory.contains( &point.coords ) && !result.contains_key( &point.coords )) {
-                // }
-                points.push_back( next_point );
-            }
+impl Octree<Voxel> {
+    /// Unit sides of `get_visible_faces_with_flood` faces, in the form used for rendering
+    pub fn get_visible_with_flood( &self, initial_point:(u32,u32,u32) ) -> Vec<VoxelSide> {
+        let mut sides = vec![];
 
-            // if should_print {
-            //     println!( "{:?}", points.iter().map( |p| p.coords ).collect::<Vec<_>>() );
-            // }
+        for face in self.get_visible_faces_with_flood( initial_point ) {
+            face.add_voxel_sides( &mut sides );
         }
 
-        result.drain().map( |pair| pair.1 ).collect()
+        sides
     }
 }
 
@@ -714,6 +841,10 @@ impl WorldHolding for Octree<Voxel> {
         self.get_visible_with_flood( from )
     }
 
+    fn get_all_leaves( &self ) -> Vec<OctreeLeaf<Arc<Voxel>>> {
+        self.get_leaves()
+    }
+
     fn set_voxel( &mut self, x:u32, y:u32, z:u32, voxel:Option<Arc<Voxel>> ) {
         if let Some( voxel ) = voxel {
             self.insert( x, y, z, voxel );
@@ -723,8 +854,7 @@ impl WorldHolding for Octree<Voxel> {
     }
 
     fn fill_voxels( &mut self, from:(u32, u32, u32), to:(u32, u32, u32), voxel:Option<Arc<Voxel>> ) {
-        let size = 1u32 << self.max_depth;
-        self.root.fill_at( self.max_depth, (0, 0, 0), size, from, to, voxel );
+        self.fill( from, to, voxel );
     }
 
     fn to_bitmask( &self ) -> ChunkBitmask {
@@ -734,22 +864,40 @@ impl WorldHolding for Octree<Voxel> {
         // println!( "size={size}" );
         // println!( "World chunk to bitmask. Max depth = {}, chunk size = {}", self.max_depth, 1 << self.max_depth );
 
-        self.root.fill_bitmask( &mut mask, self.max_depth, self.max_depth, (0, 0, 0) );
+        self.root.fill_bitmask( &mut mask, self.max_depth, self.max_depth, (0, 0, 0), &|voxel| !voxel.get_material().is_transparent() );
+        mask
+    }
+
+    /// Bitmask of voxels with transparent materials (`to_bitmask` contains only opaque ones)
+    fn to_transparents_bitmask( &self ) -> ChunkBitmask {
+        let mut mask = ChunkBitmask::new( 1 << (self.max_depth * 3) );
+        self.root.fill_bitmask( &mut mask, self.max_depth, self.max_depth, (0, 0, 0), &|voxel| voxel.get_material().is_transparent() );
         mask
     }
 
-    fn get_size( &self ) {
-        println!( "Leaves count = {}", self.count_leaves() )
+    fn to_non_solids_bitmask( &self ) -> ChunkBitmask {
+        let mut mask = ChunkBitmask::new( 1 << (self.max_depth * 3) );
+        self.root.fill_bitmask( &mut mask, self.max_depth, self.max_depth, (0, 0, 0), &|voxel| !voxel.get_material().is_solid );
+        mask
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        chunks_generators::utilities::create_voxel,
+        structure_tests::voxel_map::VoxelMap,
+        world::world_holder::{ get_visible_sides_with_flood, Color, Material, VoxelDataset }
+    };
 
     #[derive(Debug, PartialEq)]
     struct TestVoxel(i32);
 
+    impl MemoryFootprint for TestVoxel {
+        fn add_heap_footprint( &self, _footprint:&mut Footprint, _seen:&mut SharedAllocations ) {}
+    }
+
     #[test]
     fn test_insert_and_get() {
         let mut octree = Octree::new( 4 );
@@ -827,4 +975,224 @@ mod tests {
 
         assert_eq!( octree.count_leaves(), 1 );
     }
+
+    #[test]
+    fn test_inserting_the_value_of_a_compressed_leaf_keeps_it() {
+        let mut octree = Octree::new( 2 );
+        let voxel = Arc::new( TestVoxel( 3 ) );
+        octree.fill( (0, 0, 0), (3, 3, 3), Some( voxel.clone() ) );
+        assert_eq!( octree.count_leaves(), 1 );
+
+        octree.insert( 1, 2, 3, voxel.clone() );
+        assert_eq!( octree.count_leaves(), 1 );
+        assert_eq!( octree.get( 1, 2, 3 ), Some( voxel.clone() ) );
+
+        // An equal but not shared value is a different one
+        octree.insert( 1, 2, 3, Arc::new( TestVoxel( 3 ) ) );
+        assert_eq!( octree.count_leaves(), 15 );
+        assert!( !Arc::ptr_eq( &octree.get( 1, 2, 3 ).unwrap(), &voxel ) );
+    }
+
+    #[test]
+    fn test_footprint_counts_branches_and_shared_values_once() {
+        let mut octree = Octree::new( 2 );
+        let voxel = Arc::new( TestVoxel( 7 ) );
+        let shared_voxel_size = 2 * size_of::<usize>() + size_of::<TestVoxel>();
+
+        octree.insert( 0, 0, 0, voxel.clone() );
+        octree.insert( 3, 3, 3, voxel.clone() );
+
+        let footprint = octree.get_footprint();
+        assert_eq!( footprint.inline, size_of::<Octree<TestVoxel>>() );
+        assert_eq!( footprint.heap, 3 * size_of::<OctreeBranch<Arc<TestVoxel>>>() );
+        assert_eq!( footprint.shared, shared_voxel_size );
+
+        for x in 0..(1 << 2) {
+            for y in 0..(1 << 2) {
+                for z in 0..(1 << 2) {
+                    octree.insert( x, y, z, voxel.clone() );
+                }
+            }
+        }
+
+        assert_eq!( octree.count_leaves(), 1 );
+        assert_eq!( octree.get_footprint().heap, 0 );
+    }
+
+    #[test]
+    fn test_direction_sibling_leaves() {
+        let mut octree = Octree::new( 3 );
+        let voxel = Arc::new( TestVoxel( 1 ) );
+
+        for (x, y, z) in (0..8).flat_map( |x| (0..8).flat_map( move |y| (0..8).map( move |z| (x, y, z) ) ) ) {
+            if (x + y + z) % 2 == 0 {
+                octree.insert( x, y, z, voxel.clone() );
+            }
+        }
+
+        let expected = [ (0, 2, 2), (4, 2, 2), (2, 0, 2), (2, 4, 2), (2, 2, 0), (2, 2, 4) ];
+
+        for (direction, expected) in Direction::ALL.into_iter().zip( expected ) {
+            let sibling = Direction::get_sibling_leaf( &octree, direction, (2, 2, 2), 2 ).unwrap();
+            assert_eq!( (sibling.offset, sibling.size), (expected, 2) );
+        }
+
+        assert!( Direction::get_sibling_leaf( &octree, Direction::BOTTOM, (2, 0, 2), 2 ).is_none() );
+        assert!( Direction::get_sibling_leaf( &octree, Direction::FRONT, (2, 2, 6), 2 ).is_none() );
+    }
+
+    #[test]
+    fn test_neighbours_across_faces_edges_and_corners() {
+        let mut octree = Octree::new( 2 );
+        let voxel = Arc::new( TestVoxel( 1 ) );
+
+        octree.insert( 2, 0, 0, voxel.clone() );
+        octree.insert( 3, 1, 1, voxel.clone() );
+
+        let face = octree.get_neighbour( (2, 0, 0), 1, (-1, 0, 0) ).unwrap();
+        assert_eq!( (face.offset, face.size, face.is_leaf(), face.get_value()), ((0, 0, 0), 2, true, None) );
+
+        let corner = octree.get_neighbour( (2, 0, 0), 1, (1, 1, 1) ).unwrap();
+        assert_eq!( (corner.offset, corner.size), ((3, 1, 1), 1) );
+        assert_eq!( corner.get_value(), Some( &voxel ) );
+        assert_eq!( NeighbourKind::from_direction( (1, 1, 1) ), NeighbourKind::Corner );
+
+        let edge = octree.get_neighbour( (2, 0, 0), 1, (-1, 1, 0) ).unwrap();
+        assert_eq!( (edge.offset, edge.size), ((0, 0, 0), 2) );
+        assert_eq!( NeighbourKind::from_direction( (-1, 1, 0) ), NeighbourKind::Edge );
+
+        assert!( octree.get_neighbour( (2, 0, 0), 1, (0, -1, 0) ).is_none() );
+        assert_eq!( octree.get_neighbours( (0, 0, 0), 2 ).len(), 7 );
+    }
+
+    #[test]
+    fn test_subtree_neighbour_gives_touching_leaves() {
+        let mut octree = Octree::new( 2 );
+        let voxel = Arc::new( TestVoxel( 1 ) );
+
+        octree.insert( 2, 0, 0, voxel.clone() );
+        octree.insert( 3, 0, 0, voxel.clone() );
+
+        let subtree = octree.get_neighbour( (0, 0, 0), 2, (1, 0, 0) ).unwrap();
+        assert!( !subtree.is_leaf() );
+        assert_eq!( (subtree.offset, subtree.size), ((2, 0, 0), 2) );
+
+        let mut leaves = vec![];
+        subtree.for_each_touching_leaf( (1, 0, 0), |offset, size, value| leaves.push( (offset, size, value.is_some()) ) );
+        leaves.sort();
+
+        assert_eq!( leaves, vec![ ((2, 0, 0), 1, true), ((2, 0, 1), 1, false), ((2, 1, 0), 1, false), ((2, 1, 1), 1, false) ] );
+    }
+
+    #[test]
+    fn test_neighbours_in_adjacent_chunks() {
+        let chunk = Octree::<TestVoxel>::new( 2 );
+        let mut right_chunk = Octree::new( 2 );
+        let voxel = Arc::new( TestVoxel( 1 ) );
+
+        right_chunk.insert( 0, 3, 0, voxel.clone() );
+
+        let get_chunk = |offset| (offset == (1, 0, 0)).then_some( &right_chunk );
+        let neighbour = chunk.get_neighbour_across( (3, 3, 0), 1, (1, 0, 0), get_chunk ).unwrap();
+
+        assert_eq!( (neighbour.chunk_offset, neighbour.offset, neighbour.size), ((1, 0, 0), (0, 3, 0), 1) );
+        assert_eq!( neighbour.get_value(), Some( &voxel ) );
+
+        assert!( chunk.get_neighbour_across( (3, 3, 0), 1, (1, 1, 0), get_chunk ).is_none() );
+        assert!( chunk.get_neighbour( (3, 3, 0), 1, (1, 0, 0) ).is_none() );
+    }
+
+    #[test]
+    fn test_plain_values_are_merged_when_equal() {
+        let mut light = GenericOctree::<u8, ExactValues>::new( 2 );
+
+        light.fill( (0, 0, 0), (3, 3, 3), Some( 15 ) );
+        assert_eq!( light.count_leaves(), 1 );
+
+        light.insert( 1, 2, 3, 7 );
+        assert_eq!( (light.get( 1, 2, 3 ), light.get( 0, 0, 0 )), (Some( 7 ), Some( 15 )) );
+        assert_eq!( light.count_leaves(), 15 );
+
+        light.insert( 1, 2, 3, 15 );
+        assert_eq!( light.count_leaves(), 1 );
+        assert_eq!( light.get_footprint().heap, 0 );
+    }
+
+    #[test]
+    fn test_threshold_merges_close_values_into_average() {
+        let mut density = GenericOctree::new_with_merging( 1, ThresholdValues { threshold:0.1 } );
+
+        for (i, value) in [ 0.50, 0.52, 0.54, 0.56, 0.50, 0.52, 0.54, 0.56 ].into_iter().enumerate() {
+            density.insert( i as u32 & 1, (i as u32 >> 1) & 1, (i as u32 >> 2) & 1, value );
+        }
+
+        assert_eq!( density.count_leaves(), 1 );
+        let average = density.get( 0, 0, 0 ).unwrap();
+        assert!( (average - 0.53).abs() < 1e-6 );
+
+        // Close value is absorbed by the compressed leaf it is written into
+        density.insert( 1, 1, 1, average + 0.05 );
+        assert_eq!( (density.count_leaves(), density.get( 1, 1, 1 )), (1, Some( average )) );
+
+        density.insert( 0, 0, 0, 2.0 );
+        assert_eq!( density.count_leaves(), 8 );
+        assert_eq!( density.get( 0, 0, 0 ), Some( 2.0 ) );
+    }
+
+    fn create_test_voxel() -> Arc<Voxel> {
+        create_voxel( &mut VoxelDataset::new(), (String::from( "stone" ), Material::default()), (String::from( "stone" ), Color { red:1, green:2, blue:3 }) )
+    }
+
+    fn get_sorted_sides( sides:Vec<VoxelSide> ) -> Vec<(i64, i64, i64, u8)> {
+        let mut sides = sides.iter()
+            .map( |side| (side.get_position().x as i64, side.get_position().y as i64, side.get_position().z as i64, side.get_direction()) )
+            .collect::<Vec<_>>();
+
+        sides.sort();
+        sides
+    }
+
+    #[test]
+    fn test_flood_gives_whole_faces_of_uniform_leaves() {
+        let mut octree = Octree::<Voxel>::new( 3 );
+        octree.fill_voxels( (0, 0, 0), (7, 3, 7), Some( create_test_voxel() ) );
+
+        let faces = octree.get_visible_faces_with_flood( (0, 7, 0) );
+
+        assert_eq!( faces.len(), 4 );
+        assert!( faces.iter().all( |face| face.size == 4 && face.direction == Direction::TOP ) );
+        assert_eq!( octree.get_visible_with_flood( (0, 7, 0) ).len(), 64 );
+
+        assert!( octree.get_visible_faces_with_flood( (0, 0, 0) ).is_empty() );
+    }
+
+    #[test]
+    fn test_flood_matches_voxel_flood() {
+        let voxel = create_test_voxel();
+        let mut octree = Octree::<Voxel>::new( 4 );
+        let mut voxel_map = VoxelMap::<Voxel>::from_max_size( 16 );
+
+        for x in 0..16 {
+            for z in 0..16 {
+                let height = if x < 8 && z < 8 { 8 } else { (x * 7 + z * 3) % 11 + 2 };
+                octree.fill_voxels( (x, 0, z), (x, height - 1, z), Some( voxel.clone() ) );
+            }
+        }
+
+        // Pit in a big uniform face and a cavity which can't be reached
+        octree.set_voxel( 3, 7, 3, None );
+        octree.set_voxel( 5, 2, 5, None );
+
+        for (x, y, z, voxel) in octree.get_voxels() {
+            voxel_map.set_voxel( x, y, z, Some( voxel ) );
+        }
+
+        let expected = get_sorted_sides( get_visible_sides_with_flood( &octree, (16, 16, 16), (0, 15, 0) ) );
+        let faces = octree.get_visible_faces_with_flood( (0, 15, 0) );
+
+        assert!( faces.len() < expected.len() );
+        assert!( !expected.contains( &(5, 1, 5, Direction::TOP) ) );
+        assert_eq!( get_sorted_sides( octree.get_visible_with_flood( (0, 15, 0) ) ), expected );
+        assert_eq!( get_sorted_sides( voxel_map.get_all_visible_voxels_from( (0, 15, 0) ) ), expected );
+    }
 }
@@ -1,3 +1,5 @@
+use crate::world::memory_footprint::{ Footprint, MemoryFootprint, SharedAllocations };
+
 type NoiseValue = f64;
 type TreeSize = u32;
 
@@ -174,3 +176,19 @@ impl Quadtree {
         (32 - (n - 1).leading_zeros()) as u8
     }
 }
+
+impl QuadtreeNode {
+    /// Values are stored inline, so only the boxed branches take heap
+    fn get_branches_heap( &self ) -> usize {
+        match self {
+            QuadtreeNode::Leaf( _ ) => 0,
+            QuadtreeNode::Branch( branch ) => size_of::<QuadtreeBranch>() + branch.children.iter().map( |child| child.get_branches_heap() ).sum::<usize>(),
+        }
+    }
+}
+
+impl MemoryFootprint for Quadtree {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, _seen:&mut SharedAllocations ) {
+        footprint.heap += self.root.get_branches_heap();
+    }
+}
@@ -94,7 +94,7 @@ impl Tester {
 
     pub fn set_1( world_holder:&mut dyn WorldHolding ) -> TestDataset {
         let key = String::from( "default" );
-        let materials = HashMap::from([ (key.clone(), Arc::new( Material { _density:100 } )) ]);
+        let materials = HashMap::from([ (key.clone(), Arc::new( Material { _density:100, ..Default::default() } )) ]);
 
         let colors = HashMap::from([ (key.clone(), Arc::new( Color { red:50, green:100, blue:200 } )) ]);
         let common_voxel_dataset = HashMap::from([ (key.clone(), Arc::new( CommonVoxelData {
@@ -104,7 +104,6 @@ impl Tester {
 
         let voxels = HashMap::from([ (key.clone(), Arc::new( Voxel {
             _common_data: common_voxel_dataset.get( &key ).unwrap().clone(),
-            _individual_data: vec![],
         }) ) ]);
 
         world_holder.set_voxel( 0, 0, 0, Some( voxels.get( &key ).unwrap().clone() ) );
@@ -166,7 +165,7 @@ impl Tester {
         let noise = SimplexNoise::new( 50 );
         let max_depth = Quadtree::get_max_depth_for( WORLD_X );
         let noise_frequency = 0.025;
-        let generate_value = |x, z| noise.noise3d( x as f64 * noise_frequency, 1.0, z as f64  * noise_frequency );
+        let generate_value = |x, z| noise.noise2d( x as f64 * noise_frequency, z as f64 * noise_frequency );
 
         println!( "Filling quadtree with max depth = {}...", max_depth );
         let quadtree = Quadtree::from_terrain_generation( max_depth, &generate_value );
@@ -178,9 +177,9 @@ impl Tester {
         let grass_key = String::from( "grass" );
 
         let materials = HashMap::from([
-            (&stone_key, Arc::new( Material { _density:100 } )),
-            (&dirt_key, Arc::new( Material { _density:2 } )),
-            (&grass_key, Arc::new( Material { _density:4 } )),
+            (&stone_key, Arc::new( Material { _density:100, ..Default::default() } )),
+            (&dirt_key, Arc::new( Material { _density:2, ..Default::default() } )),
+            (&grass_key, Arc::new( Material { _density:4, ..Default::default() } )),
         ]);
 
         let colors = HashMap::from([
@@ -207,15 +206,12 @@ impl Tester {
         let voxels = HashMap::from([
             (&stone_key, Arc::new( Voxel {
                 _common_data: common_voxel_dataset.get( &stone_key ).unwrap().clone(),
-                _individual_data: vec![],
             }) ),
             (&dirt_key, Arc::new( Voxel {
                 _common_data: common_voxel_dataset.get( &dirt_key ).unwrap().clone(),
-                _individual_data: vec![],
             }) ),
             (&grass_key, Arc::new( Voxel {
                 _common_data: common_voxel_dataset.get( &grass_key ).unwrap().clone(),
-                _individual_data: vec![],
             }) ),
         ]);
 
@@ -270,7 +266,7 @@ impl Tester {
 
     fn set_n( n:u32, world_holder:&mut dyn WorldHolding ) -> TestDataset {
         let key = String::from( "default" );
-        let materials = HashMap::from([ (key.clone(), Arc::new( Material { _density:100 } )) ]);
+        let materials = HashMap::from([ (key.clone(), Arc::new( Material { _density:100, ..Default::default() } )) ]);
         let colors = HashMap::from([ (key.clone(), Arc::new( Color { red:50, green:100, blue:200 } )) ]);
 
         let common_voxel_dataset = HashMap::from([ (key.clone(), Arc::new( CommonVoxelData {
@@ -280,7 +276,6 @@ impl Tester {
 
         let voxels = HashMap::from([ (key.clone(), Arc::new( Voxel {
             _common_data: common_voxel_dataset.get( &key ).unwrap().clone(),
-            _individual_data: vec![],
         }) ) ]);
 
         let voxel = voxels.get( &key ).unwrap();
@@ -298,7 +293,7 @@ impl Tester {
 
     fn set_n_random( n:u32, world_holder:&mut dyn WorldHolding ) -> TestDataset {
         let key = String::from( "default" );
-        let materials = HashMap::from([ (key.clone(), Arc::new( Material { _density:100 } )) ]);
+        let materials = HashMap::from([ (key.clone(), Arc::new( Material { _density:100, ..Default::default() } )) ]);
         let colors = HashMap::from([ (key.clone(), Arc::new( Color { red:50, green:100, blue:200 } )) ]);
 
         let common_voxel_dataset = HashMap::from([ (key.clone(), Arc::new( CommonVoxelData {
@@ -308,7 +303,6 @@ impl Tester {
 
         let voxels = HashMap::from([ (key.clone(), Arc::new( Voxel {
             _common_data: common_voxel_dataset.get( &key ).unwrap().clone(),
-            _individual_data: vec![],
         }) ) ]);
 
         let mut rng = rand::rng();
@@ -349,7 +343,7 @@ impl Tester {
             let material = match materials.get( &material_key ) {
                 Some( material ) => material,
                 None => {
-                    materials.insert( material_key.clone(), Arc::new( Material { _density: density } ) );
+                    materials.insert( material_key.clone(), Arc::new( Material { _density: density, ..Default::default() } ) );
                     materials.get( &material_key ).unwrap()
                 }
             };
@@ -367,7 +361,7 @@ impl Tester {
             let voxel = match voxels.get( &voxel_key ) {
                 Some( voxel ) => voxel,
                 None => {
-                    voxels.insert( voxel_key.clone(), Arc::new( Voxel { _common_data:common_data.clone(), _individual_data:vec![ material_key ] } ) );
+                    voxels.insert( voxel_key.clone(), Arc::new( Voxel { _common_data:common_data.clone() } ) );
                     voxels.get( &voxel_key ).unwrap()
                 }
             };
@@ -381,7 +375,7 @@ impl Tester {
     }
 
     fn fill_with( from:(u32, u32, u32), to:(u32, u32, u32), world_holder:&mut dyn WorldHolding, setup:(String, Color) ) -> TestDataset {
-        let materials = HashMap::from([ (setup.0.clone(), Arc::new( Material { _density:100 } )) ]);
+        let materials = HashMap::from([ (setup.0.clone(), Arc::new( Material { _density:100, ..Default::default() } )) ]);
         let colors = HashMap::from([ (setup.0.clone(), Arc::new( setup.1 )) ]);
 
         let common_voxel_dataset = HashMap::from([ (setup.0.clone(), Arc::new( CommonVoxelData {
@@ -391,7 +385,6 @@ impl Tester {
 
         let voxels = HashMap::from([ (setup.0.clone(), Arc::new( Voxel {
             _common_data: common_voxel_dataset.get( &setup.0 ).unwrap().clone(),
-            _individual_data: vec![],
         }) ) ]);
 
         let voxel = voxels.get( &setup.0 ).unwrap();
@@ -420,7 +413,7 @@ impl Tester {
 
         println!( "Filling deposits..." );
         dataset.colors.insert( coal_key.clone(), Arc::new( coal_color ) );
-        dataset.materials.insert( coal_key.clone(), Arc::new( Material { _density:125 } ) );
+        dataset.materials.insert( coal_key.clone(), Arc::new( Material { _density:125, ..Default::default() } ) );
 
         dataset.common_voxel_dataset.insert( coal_key.clone(), Arc::new( CommonVoxelData {
             color: dataset.colors.get( &coal_key ).unwrap().clone(),
@@ -429,7 +422,6 @@ impl Tester {
 
         dataset.voxels.insert( coal_key.clone(), Arc::new( Voxel {
             _common_data: dataset.common_voxel_dataset.get( &coal_key ).unwrap().clone(),
-            _individual_data:vec![]
         } ) );
 
         let coal = dataset.voxels.get( &coal_key ).unwrap().clone();
@@ -470,7 +462,7 @@ impl Tester {
             ("z", Box::new( |n| Color { red:0, green:0, blue:(255 / (axies_length + 1)) * (n + 2) } ), Box::new( |n| (0, 0, n - axies_length as i32 / 2) )),
         ];
 
-        dataset.materials.insert( axies_key.clone(), Arc::new( Material { _density:1000 } ) );
+        dataset.materials.insert( axies_key.clone(), Arc::new( Material { _density:1000, ..Default::default() } ) );
 
         if WORLD_X > 15 {
             for axis in axies_makers {
@@ -488,7 +480,6 @@ impl Tester {
 
                     dataset.voxels.insert( key.clone(), Arc::new( Voxel {
                         _common_data: dataset.common_voxel_dataset.get( &key ).unwrap().clone(),
-                        _individual_data:vec![]
                     } ) );
 
                     world_holder.set_voxel(
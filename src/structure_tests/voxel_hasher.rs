@@ -1,13 +1,12 @@
 use std::collections::HashMap;
 use std::hash::{ Hash, Hasher };
-use std::mem::{ size_of, size_of_val };
 use std::sync::Arc;
 
-use crate::world::world_chunk::ChunkBitmask;
-use crate::world::world_holder::{Voxel, VoxelSide, WorldHolding};
+use crate::world::memory_footprint::{ add_shared_footprint, get_hash_map_heap, Footprint, MemoryFootprint, SharedAllocations };
+use crate::world::world_holder::{ get_flood_size, get_visible_sides_with_flood, Voxel, VoxelSide, WorldHolding };
 
 
-#[derive( Debug, PartialEq, Eq )]
+#[derive( Debug, Clone, Copy, PartialEq, Eq )]
 struct Position {
     x: u32,
     y: u32,
@@ -28,6 +27,12 @@ pub struct VoxelHashMap<T> {
     voxels: HashMap<Position, Arc<T>>,
 }
 
+impl<T> Clone for VoxelHashMap<T> {
+    fn clone( &self ) -> Self {
+        Self { voxels:self.voxels.clone() }
+    }
+}
+
 #[allow(dead_code)]
 impl<T> VoxelHashMap<T> {
     pub fn new() -> Self {
@@ -43,6 +48,16 @@ impl<T> VoxelHashMap<T> {
     }
 }
 
+impl<T:MemoryFootprint> MemoryFootprint for VoxelHashMap<T> {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        footprint.heap += get_hash_map_heap( &self.voxels );
+
+        for voxel in self.voxels.values() {
+            add_shared_footprint( voxel, footprint, seen );
+        }
+    }
+}
+
 impl WorldHolding for VoxelHashMap<Voxel> {
     fn get_voxel(&self, x:u32, y:u32, z:u32) -> Option<Arc<Voxel>> {
         let pos = Position { x, y, z };
@@ -55,15 +70,11 @@ impl WorldHolding for VoxelHashMap<Voxel> {
     }
 
     fn get_all_voxels( &self ) -> Vec<(u32, u32, u32, Arc<Voxel>)> {
-        todo!()
+        self.voxels.iter().map( |(pos, voxel)| (pos.x, pos.y, pos.z, voxel.clone()) ).collect()
     }
 
-    fn get_all_visible_voxels_from( &self, _from:(u32, u32, u32) ) -> Vec<VoxelSide> {
-        todo!()
-    }
-
-    fn to_bitmask( &self ) -> ChunkBitmask {
-        todo!()
+    fn get_all_visible_voxels_from( &self, from:(u32, u32, u32) ) -> Vec<VoxelSide> {
+        get_visible_sides_with_flood( self, get_flood_size( self.voxels.keys().map( |pos| (pos.x, pos.y, pos.z) ), from ), from )
     }
 
     fn set_voxel( &mut self, x:u32, y:u32, z:u32, voxel:Option<Arc<Voxel>> ) {
@@ -108,24 +119,4 @@ impl WorldHolding for VoxelHashMap<Voxel> {
             ) );
         }
     }
-
-    fn get_size( &self ) {
-        let its_size = size_of::<Self>();
-        println!( " - its size = {}", its_size );
-
-        let voxel_size = size_of::<Arc<Voxel>>();
-        println!( " - voxel size = {}", voxel_size );
-
-        let stored_voxels = self.voxels.iter().len();
-        println!( " - stored voxels count = {}", stored_voxels );
-
-        let voxels_hashmap_size = size_of_val( &self.voxels );
-        println!( " - voxels hashmap size = {}", voxels_hashmap_size );
-
-        let full_size = its_size + stored_voxels * voxel_size;
-        println!(
-            " - full size = {} [its size] + {} [voxels] * {} [voxel size] = {}",
-            its_size, stored_voxels, voxel_size, self.get_bytes_with_prefixes( full_size ),
-        );
-    }
 }
@@ -1,6 +1,9 @@
-use std::{ mem::size_of, sync::Arc };
+use std::sync::Arc;
 
-use crate::world::{ world_chunk::ChunkBitmask, world_holder::{ Voxel, VoxelSide, WorldHolding } };
+use crate::world::{
+    memory_footprint::{ add_shared_footprint, get_vec_heap, Footprint, MemoryFootprint, SharedAllocations },
+    world_holder::{ get_flood_size, get_visible_sides_with_flood, Voxel, VoxelSide, WorldHolding }
+};
 
 pub struct VoxelInWorld<T> {
     x: u32,
@@ -9,10 +12,22 @@ pub struct VoxelInWorld<T> {
     voxel: Arc<T>
 }
 
+impl<T> Clone for VoxelInWorld<T> {
+    fn clone( &self ) -> Self {
+        Self { voxel:Arc::clone( &self.voxel ), ..*self }
+    }
+}
+
 pub struct VoxelList<T> {
     pub data: Vec<VoxelInWorld<T>>,
 }
 
+impl<T> Clone for VoxelList<T> {
+    fn clone( &self ) -> Self {
+        Self { data:self.data.clone() }
+    }
+}
+
 impl<T> VoxelList<T> {
     #[allow(dead_code)]
     pub fn new() -> Self {
@@ -22,6 +37,16 @@ impl<T> VoxelList<T> {
     }
 }
 
+impl<T:MemoryFootprint> MemoryFootprint for VoxelList<T> {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        footprint.heap += get_vec_heap( &self.data );
+
+        for voxel_in_world in &self.data {
+            add_shared_footprint( &voxel_in_world.voxel, footprint, seen );
+        }
+    }
+}
+
 impl WorldHolding for VoxelList<Voxel> {
     fn get_voxel( &self, x:u32, y:u32, z:u32 ) -> Option<Arc<Voxel>> {
         match self.data.iter().find( |v| v.x == x && v.y == y && v.z == z ) {
@@ -31,11 +56,11 @@ impl WorldHolding for VoxelList<Voxel> {
     }
 
     fn get_all_voxels( &self ) -> Vec<(u32, u32, u32, Arc<Voxel>)> {
-        todo!()
+        self.data.iter().map( |v| (v.x, v.y, v.z, v.voxel.clone()) ).collect()
     }
 
-    fn get_all_visible_voxels_from( &self, _from:(u32, u32, u32) ) -> Vec<VoxelSide> {
-        todo!()
+    fn get_all_visible_voxels_from( &self, from:(u32, u32, u32) ) -> Vec<VoxelSide> {
+        get_visible_sides_with_flood( self, get_flood_size( self.data.iter().map( |v| (v.x, v.y, v.z) ), from ), from )
     }
 
     fn set_voxel( &mut self, x:u32, y:u32, z:u32, voxel:Option<Arc<Voxel>> ) {
@@ -51,10 +76,6 @@ impl WorldHolding for VoxelList<Voxel> {
         }
     }
 
-    fn to_bitmask( &self ) -> ChunkBitmask {
-        todo!()
-    }
-
     fn fill_voxels( &mut self, from:(u32, u32, u32), to:(u32, u32, u32), voxel:Option<Arc<Voxel>> ) {
         let (x_min, x_max) = (from.0.min( to.0 ), from.0.max( to.0 ));
         let (y_min, y_max) = (from.1.min( to.1 ), from.1.max( to.1 ));
@@ -82,29 +103,4 @@ impl WorldHolding for VoxelList<Voxel> {
             }
         }
     }
-
-    fn get_size( &self ) {
-        println!( "VoxelList sizes (in bytes by default):" );
-
-        let its_size = size_of::<Self>();
-        println!( " - its size = {}", its_size );
-
-        let root_vec_size = size_of::<Vec<VoxelInWorld<Voxel>>>();
-        println!( " - root vector size = {}", root_vec_size );
-
-        let voxel_in_world_size = size_of::<VoxelInWorld<Voxel>>();
-        println!( " - voxel in world size = {}", voxel_in_world_size );
-
-        let voxel_size = size_of::<Arc<Voxel>>();
-        println!( " - voxel size = {}", voxel_size );
-
-        let list_size = self.data.len() * voxel_in_world_size * voxel_size;
-        println!( " - list size = {} [data length] * {} [voxel in world size] * {} [voxel size] = {}", self.data.len(), voxel_in_world_size, voxel_size, list_size );
-
-        let full_size = its_size + list_size;
-        print!(
-            " - full size = {} [its size] + {} [list size] = {}",
-            its_size, list_size, self.get_bytes_with_prefixes( full_size )
-        );
-    }
 }
@@ -1,6 +1,9 @@
-use std::{ mem::size_of, sync::Arc };
+use std::sync::Arc;
 
-use crate::world::{ world_chunk::ChunkBitmask, world_holder::{ Voxel, VoxelSide, WorldHolding } };
+use crate::world::{
+    memory_footprint::{ add_shared_footprint, get_vec_heap, Footprint, MemoryFootprint, SharedAllocations },
+    world_holder::{ get_visible_sides_with_flood, Voxel, VoxelSide, WorldHolding }
+};
 
 use super::tester::*;
 
@@ -8,10 +11,17 @@ pub struct VoxelMap<T> {
     pub data: Vec<Option<Arc<T>>>,
     pub size_x: usize,
     pub size_y: usize,
-    #[allow(dead_code)] pub size_z: usize,
+    pub size_z: usize,
     pub filled_cells: u32,
 }
 
+/// Only the cells are copied, `Arc` values stay shared
+impl<T> Clone for VoxelMap<T> {
+    fn clone( &self ) -> Self {
+        Self { data:self.data.clone(), ..*self }
+    }
+}
+
 impl<T> VoxelMap<T> {
     #[allow(dead_code)]
     pub fn new() -> Self {
@@ -58,21 +68,38 @@ impl<T> VoxelMap<T> {
     }
 }
 
+impl<T:MemoryFootprint> MemoryFootprint for VoxelMap<T> {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        footprint.heap += get_vec_heap( &self.data );
+
+        for voxel in self.data.iter().flatten() {
+            add_shared_footprint( voxel, footprint, seen );
+        }
+    }
+}
+
 impl WorldHolding for VoxelMap<Voxel> {
     fn get_voxel( &self, x:u32, y:u32, z:u32 ) -> Option<Arc<Voxel>> {
         self.data[ self.index( x as usize, y as usize, z as usize ) ].clone()
     }
 
     fn get_all_voxels( &self ) -> Vec<(u32, u32, u32, Arc<Voxel>)> {
-        todo!()
-    }
+        let mut result = Vec::with_capacity( self.filled_cells as usize );
+
+        for (index, voxel) in self.data.iter().enumerate() {
+            let Some( voxel ) = voxel else { continue };
+            let x = index % self.size_x;
+            let y = (index / self.size_x) % self.size_y;
+            let z = index / (self.size_x * self.size_y);
 
-    fn get_all_visible_voxels_from( &self, _from:(u32, u32, u32) ) -> Vec<VoxelSide> {
-        todo!()
+            result.push( (x as u32, y as u32, z as u32, voxel.clone()) );
+        }
+
+        result
     }
 
-    fn to_bitmask( &self ) -> ChunkBitmask {
-        todo!()
+    fn get_all_visible_voxels_from( &self, from:(u32, u32, u32) ) -> Vec<VoxelSide> {
+        get_visible_sides_with_flood( self, (self.size_x as u32, self.size_y as u32, self.size_z as u32), from )
     }
 
     fn set_voxel( &mut self, x:u32, y:u32, z:u32, voxel:Option<Arc<Voxel>> ) {
@@ -92,32 +119,4 @@ impl WorldHolding for VoxelMap<Voxel> {
             }
         }
     }
-
-    fn get_size( &self ) {
-        println!( "VoxelMap sizes (in bytes by default):" );
-
-        let its_size = size_of::<Self>();
-        println!( " - its size = {}", its_size );
-
-        let root_vec_size = size_of::<Vec<Vec<Vec<Option<Arc<Voxel>>>>>>();
-        println!( " - root vector size = {}", root_vec_size );
-
-        let depth_vec_size = size_of::<Vec<Vec<Option<Arc<Voxel>>>>>();
-        println!( " - depth vectors size = {} * {}", self.size_z, depth_vec_size );
-
-        let row_vec_size = size_of::<Vec<Option<Arc<Voxel>>>>();
-        println!( " - row vectors size = {} * {} * {}", self.size_z, self.size_y, row_vec_size );
-
-        let cell_size = size_of::<Option<Arc<Voxel>>>();
-        println!( " - column vectors size = {} * {} * {} * {}", self.size_z, self.size_y, self.size_x, cell_size, );
-
-        let depths_size = self.size_z * depth_vec_size;
-        let rows_size = self.size_z * self.size_y * depth_vec_size;
-        let cells_size = self.size_z * self.size_y * self.size_x * cell_size;
-        let full_size = its_size + root_vec_size + depths_size + rows_size + cells_size;
-        println!(
-            " - full size = {} [its size] + {} [root] + {} [depths] + {} [rows] + {} [columns] = {}",
-            its_size, root_vec_size, depths_size, rows_size, cells_size, self.get_bytes_with_prefixes( full_size )
-        );
-    }
 }
@@ -0,0 +1,367 @@
+use std::{ collections::HashMap, fs, path::Path, sync::Arc };
+
+use anyhow::{ bail, Result };
+
+use crate::world::{
+    serialization::{ write_length_u32, write_u32, ByteReader },
+    world::VoxelPosition,
+    world_edit_journal::WorldEdit,
+    world_generator::{ generate_chunk_stages_into, WorldGenerative },
+    world_holder::{ get_clipped_range, Color, CommonVoxelData, Material, Voxel, VoxelDataset, WorldHolding }
+};
+
+use super::{ access_trace::{ AccessCall, TracingHolder }, octree::Octree };
+
+const WRITE_TRACE_FORMAT_VERSION: u8 = 1;
+const WRITE_SET: u8 = 0;
+const WRITE_FILL: u8 = 1;
+const NO_VOXEL: u32 = u32::MAX;
+
+pub type TracePosition = (u32, u32, u32);
+
+/// Single write of a generator. Voxels are indices into the trace palette, `None` removes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceWrite {
+    Set { position:(u32, u32, u32), voxel:Option<u32> },
+    Fill { from:(u32, u32, u32), to:(u32, u32, u32), voxel:Option<u32> },
+}
+
+/// Voxels referenced by a trace. Every distinct `Arc` gets its own index
+#[derive(Default)]
+pub struct TracePalette {
+    ids: HashMap<usize, u32>,
+    voxels: Vec<Arc<Voxel>>,
+}
+
+impl TracePalette {
+    pub fn get_id( &mut self, voxel:&Option<Arc<Voxel>> ) -> Option<u32> {
+        let voxel = voxel.as_ref()?;
+        let next_id = self.voxels.len() as u32;
+        let id = *self.ids.entry( Arc::as_ptr( voxel ) as usize ).or_insert( next_id );
+
+        if id == next_id {
+            self.voxels.push( Arc::clone( voxel ) );
+        }
+
+        Some( id )
+    }
+
+    pub fn into_voxels( self ) -> Vec<Arc<Voxel>> {
+        self.voxels
+    }
+}
+
+/// Exact sequence of writes a generator does for a region of chunks, which can be replayed into any structure
+pub struct WriteTrace {
+    size: (u32, u32, u32),
+    palette: Vec<Arc<Voxel>>,
+    writes: Vec<TraceWrite>,
+}
+
+#[allow(dead_code)]
+impl WriteTrace {
+    /// Generates (with all stages) `chunks` chunks starting at the `origin` chunk and records every write into their octrees through `TracingHolder`.
+    /// Chunks are placed side by side, so the trace covers `chunks * chunk_size` voxels
+    pub fn record( generator:&dyn WorldGenerative, origin:(i64, i64, i64), chunks:(u32, u32, u32), chunk_size:u8 ) -> Self {
+        let size = chunk_size as u32;
+        let mut dataset = VoxelDataset::new();
+        let mut palette = TracePalette::default();
+        let mut writes = vec![];
+
+        for x in 0..chunks.0 {
+            for y in 0..chunks.1 {
+                for z in 0..chunks.2 {
+                    let mut chunk = Octree::<Voxel>::from_max_size( size );
+                    let mut tracing_holder = TracingHolder::with_palette( &mut chunk, palette );
+                    let chunk_origin = (origin.0 + x as i64, origin.1 + y as i64, origin.2 + z as i64);
+
+                    generate_chunk_stages_into( generator, &mut dataset, &mut tracing_holder, chunk_origin, chunk_size );
+
+                    let (chunk_palette, calls) = tracing_holder.into_parts();
+                    let offset = |position:TracePosition| (x * size + position.0, y * size + position.1, z * size + position.2);
+
+                    palette = chunk_palette;
+                    writes.extend( calls.into_iter().filter_map( |call| match call {
+                        AccessCall::SetVoxel { position, voxel } => Some( TraceWrite::Set { position:offset( position ), voxel } ),
+                        // Octree ignores the part of a fill outside of the chunk, other structures would write it into the neighbours
+                        AccessCall::FillVoxels { from, to, voxel } => get_clipped_range( from, to, size )
+                            .map( |(from, to)| TraceWrite::Fill { from:offset( from ), to:offset( to ), voxel } ),
+                        _ => None,
+                    } ) );
+                }
+            }
+        }
+
+        Self {
+            size: (chunks.0 * size, chunks.1 * size, chunks.2 * size),
+            palette: palette.into_voxels(),
+            writes,
+        }
+    }
+
+    /// Edits logged by `World` inside of the box of `size` voxels starting at `origin`, the parts outside of it are dropped
+    pub fn from_edits( edits:&[WorldEdit], origin:VoxelPosition, size:(u32, u32, u32) ) -> Self {
+        let mut palette = TracePalette::default();
+        let max = (origin.0 + size.0 as i64 - 1, origin.1 + size.1 as i64 - 1, origin.2 + size.2 as i64 - 1);
+        let to_local = |position:VoxelPosition| ((position.0 - origin.0) as u32, (position.1 - origin.1) as u32, (position.2 - origin.2) as u32);
+
+        let writes = edits.iter().filter_map( |edit| {
+            let (from, to, voxel) = match edit {
+                WorldEdit::SetVoxel( position, voxel ) => (*position, *position, voxel),
+                WorldEdit::FillVoxels( from, to, voxel ) => (*from, *to, voxel),
+            };
+
+            let from = (from.0.max( origin.0 ), from.1.max( origin.1 ), from.2.max( origin.2 ));
+            let to = (to.0.min( max.0 ), to.1.min( max.1 ), to.2.min( max.2 ));
+            if from.0 > to.0 || from.1 > to.1 || from.2 > to.2 { return None }
+
+            let voxel = palette.get_id( voxel );
+
+            Some( match edit {
+                WorldEdit::SetVoxel( .. ) => TraceWrite::Set { position:to_local( from ), voxel },
+                WorldEdit::FillVoxels( .. ) => TraceWrite::Fill { from:to_local( from ), to:to_local( to ), voxel },
+            } )
+        } ).collect();
+
+        Self { size, palette:palette.into_voxels(), writes }
+    }
+
+    /// Reads the trace from `path`, or records it and saves it there when the file doesn't exist
+    pub fn load_or_record( path:&Path, record:impl FnOnce() -> Self ) -> Result<Self> {
+        load_or_record_file( path, record, Self::write, Self::read )
+    }
+
+    pub fn get_size( &self ) -> (u32, u32, u32) {
+        self.size
+    }
+
+    pub fn get_writes( &self ) -> &[TraceWrite] {
+        &self.writes
+    }
+
+    pub fn replay( &self, world_holder:&mut dyn WorldHolding ) {
+        let get_voxel = |voxel:Option<u32>| voxel.map( |id| Arc::clone( &self.palette[ id as usize ] ) );
+
+        for write in &self.writes {
+            match *write {
+                TraceWrite::Set { position, voxel } => world_holder.set_voxel( position.0, position.1, position.2, get_voxel( voxel ) ),
+                TraceWrite::Fill { from, to, voxel } => world_holder.fill_voxels( from, to, get_voxel( voxel ) ),
+            }
+        }
+    }
+
+    /// Palette keeps materials and colours, voxels are created anew when reading
+    pub fn write( &self ) -> Result<Vec<u8>> {
+        let mut out = vec![ WRITE_TRACE_FORMAT_VERSION ];
+
+        write_position( &mut out, self.size );
+        write_palette( &mut out, &self.palette )?;
+        write_length_u32( &mut out, self.writes.len() )?;
+
+        for write in &self.writes {
+            let voxel = match *write {
+                TraceWrite::Set { position, voxel } => {
+                    out.push( WRITE_SET );
+                    write_position( &mut out, position );
+                    voxel
+                },
+                TraceWrite::Fill { from, to, voxel } => {
+                    out.push( WRITE_FILL );
+                    write_position( &mut out, from );
+                    write_position( &mut out, to );
+                    voxel
+                },
+            };
+
+            write_voxel_id( &mut out, voxel );
+        }
+
+        Ok( out )
+    }
+
+    pub fn read( bytes:&[u8] ) -> Result<Self> {
+        let mut reader = ByteReader::new( bytes );
+        let version = reader.read_u8()?;
+
+        if version != WRITE_TRACE_FORMAT_VERSION {
+            bail!( "Unsupported write trace format version: {version}" )
+        }
+
+        let size = read_position( &mut reader )?;
+        let palette = read_palette( &mut reader )?;
+        let palette_len = palette.len() as u32;
+        let writes_len = reader.read_u32()?;
+        let mut writes = Vec::with_capacity( writes_len as usize );
+
+        for _ in 0..writes_len {
+            let kind = reader.read_u8()?;
+            let write = match kind {
+                WRITE_SET => {
+                    let position = read_position( &mut reader )?;
+                    TraceWrite::Set { position, voxel:read_voxel_id( &mut reader, palette_len )? }
+                },
+                WRITE_FILL => {
+                    let from = read_position( &mut reader )?;
+                    let to = read_position( &mut reader )?;
+                    TraceWrite::Fill { from, to, voxel:read_voxel_id( &mut reader, palette_len )? }
+                },
+                _ => bail!( "Unknown write kind: {kind}" ),
+            };
+
+            writes.push( write );
+        }
+
+        Ok( Self { size, palette, writes } )
+    }
+}
+
+/// Reads a trace from `path`, or records it and saves it there when the file doesn't exist
+pub fn load_or_record_file<T>( path:&Path, record:impl FnOnce() -> T, write:impl Fn( &T ) -> Result<Vec<u8>>, read:impl Fn( &[u8] ) -> Result<T> ) -> Result<T> {
+    if path.exists() {
+        return read( &fs::read( path )? )
+    }
+
+    let trace = record();
+
+    if let Some( directory ) = path.parent() {
+        fs::create_dir_all( directory )?;
+    }
+
+    fs::write( path, write( &trace )? )?;
+    Ok( trace )
+}
+
+pub fn write_position( out:&mut Vec<u8>, position:TracePosition ) {
+    write_u32( out, position.0 );
+    write_u32( out, position.1 );
+    write_u32( out, position.2 );
+}
+
+pub fn read_position( reader:&mut ByteReader ) -> Result<TracePosition> {
+    Ok( (reader.read_u32()?, reader.read_u32()?, reader.read_u32()?) )
+}
+
+pub fn write_voxel_id( out:&mut Vec<u8>, voxel:Option<u32> ) {
+    write_u32( out, voxel.unwrap_or( NO_VOXEL ) );
+}
+
+pub fn read_voxel_id( reader:&mut ByteReader, palette_len:u32 ) -> Result<Option<u32>> {
+    match reader.read_u32()? {
+        NO_VOXEL => Ok( None ),
+        id if id < palette_len => Ok( Some( id ) ),
+        id => bail!( "Voxel id {id} is outside of the palette ({palette_len} voxels)" ),
+    }
+}
+
+/// Materials and colours of the palette voxels
+pub fn write_palette( out:&mut Vec<u8>, palette:&[Arc<Voxel>] ) -> Result<()> {
+    write_length_u32( out, palette.len() )?;
+
+    for voxel in palette {
+        let material = voxel.get_material();
+        let color = &voxel._common_data.color;
+
+        write_u32( out, material._density );
+        out.extend_from_slice( &[ material.emission, material.opacity, material.is_solid as u8, color.red, color.green, color.blue ] );
+    }
+
+    Ok( () )
+}
+
+pub fn read_palette( reader:&mut ByteReader ) -> Result<Vec<Arc<Voxel>>> {
+    let palette_len = reader.read_u32()?;
+    let mut palette = Vec::with_capacity( palette_len as usize );
+
+    for _ in 0..palette_len {
+        let _density = reader.read_u32()?;
+        let [emission, opacity, is_solid, red, green, blue] = reader.read_bytes( 6 )?.try_into()?;
+
+        palette.push( Arc::new( Voxel {
+            _common_data: Arc::new( CommonVoxelData {
+                material: Arc::new( Material { _density, emission, opacity, is_solid:is_solid != 0 } ),
+                color: Arc::new( Color { red, green, blue } ),
+            } ),
+        } ) );
+    }
+
+    Ok( palette )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chunks_generators::{ caves::{ CaveCarver, GeneratorWithCaves }, test_11_height_map::GeneratorOfTest11HeightMap, utilities::create_voxel },
+        structure_tests::voxel_hasher::VoxelHashMap,
+        world::world_generator::generate_chunk_stages
+    };
+
+    use super::*;
+
+    fn get_sorted_voxels( world_holder:&dyn WorldHolding ) -> Vec<(TracePosition, (u8, u8, u8))> {
+        let mut voxels = world_holder.get_all_voxels().into_iter()
+            .map( |(x, y, z, voxel)| {
+                let color = &voxel._common_data.color;
+                ((x, y, z), (color.red, color.green, color.blue))
+            } )
+            .collect::<Vec<_>>();
+
+        voxels.sort();
+        voxels
+    }
+
+    #[test]
+    fn test_replay_rebuilds_generated_chunks() {
+        let generator = GeneratorWithCaves::new( Box::new( GeneratorOfTest11HeightMap::new( 50 ) ), CaveCarver::new( 50 ).with_max_height( 8 ) );
+        let trace = WriteTrace::record( &generator, (0, -1, 0), (2, 1, 1), 16 );
+
+        assert_eq!( trace.get_size(), (32, 16, 16) );
+        assert!( !trace.get_writes().is_empty() );
+
+        let mut expected = Octree::<Voxel>::from_max_size( 32 );
+        let mut dataset = VoxelDataset::new();
+
+        for x in 0..2 {
+            let chunk = generate_chunk_stages( &generator, &mut dataset, (x, -1, 0), 16 );
+
+            for ((leaf_x, y, z), size, voxel) in chunk.get_leaves() {
+                let from = (x as u32 * 16 + leaf_x, y, z);
+                expected.fill_voxels( from, (from.0 + size - 1, from.1 + size - 1, from.2 + size - 1), Some( voxel ) );
+            }
+        }
+
+        let mut replayed = VoxelHashMap::<Voxel>::new();
+        trace.replay( &mut replayed );
+
+        assert_eq!( get_sorted_voxels( &replayed ), get_sorted_voxels( &expected ) );
+    }
+
+    #[test]
+    fn test_edits_are_clipped_to_the_traced_box() {
+        let stone = Some( create_voxel( &mut VoxelDataset::new(), (String::from( "stone" ), Material::default()), (String::from( "stone" ), Color { red:1, green:2, blue:3 }) ) );
+        let edits = [
+            WorldEdit::FillVoxels( (-20, 0, 0), (3, 1, 1), stone.clone() ),
+            WorldEdit::SetVoxel( (0, 0, 0), None ),
+            WorldEdit::SetVoxel( (16, 0, 0), stone.clone() ),
+        ];
+
+        let trace = WriteTrace::from_edits( &edits, (-10, 0, 0), (16, 16, 16) );
+        let mut replayed = Octree::<Voxel>::from_max_size( 16 );
+        trace.replay( &mut replayed );
+
+        assert_eq!( trace.get_writes().len(), 2 );
+        assert_eq!( trace.palette.len(), 1 );
+        assert_eq!( replayed.get_all_voxels().len(), 14 * 2 * 2 - 1 );
+        assert!( replayed.get_voxel( 10, 0, 0 ).is_none() );
+    }
+
+    #[test]
+    fn test_trace_survives_writing_and_reading() {
+        let trace = WriteTrace::record( &GeneratorOfTest11HeightMap::new( 50 ), (0, -1, 0), (1, 1, 1), 16 );
+        let read = WriteTrace::read( &trace.write().unwrap() ).unwrap();
+
+        assert_eq!( read.get_size(), trace.get_size() );
+        assert_eq!( read.get_writes(), trace.get_writes() );
+        assert_eq!( read.palette.len(), trace.palette.len() );
+        assert!( WriteTrace::read( &trace.write().unwrap()[ ..20 ] ).is_err() );
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{
+    structure_tests::{ octree::Octree, voxel_hasher::VoxelHashMap, voxel_list::VoxelList, voxel_map::VoxelMap },
+    world::{
+        world::CHUNK_SIZE,
+        world_holder::{ ChunkHolding, Voxel, WorldHolding }
+    }
+};
+
+/// Voxel structure storing the world chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    VoxelMap,
+    VoxelList,
+    VoxelHashMap,
+    Octree,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 4] = [ Backend::VoxelMap, Backend::VoxelList, Backend::VoxelHashMap, Backend::Octree ];
+
+    pub fn get_name( self ) -> &'static str {
+        match self {
+            Backend::VoxelMap => "voxel_map",
+            Backend::VoxelList => "voxel_list",
+            Backend::VoxelHashMap => "voxel_hash_map",
+            Backend::Octree => "octree",
+        }
+    }
+
+    pub fn from_name( name:&str ) -> Option<Self> {
+        Self::ALL.into_iter().find( |backend| backend.get_name() == name )
+    }
+
+    pub fn create( self, size:(u32, u32, u32) ) -> Box<dyn WorldHolding> {
+        match self {
+            Backend::VoxelMap => Box::new( VoxelMap::<Voxel>::from_max_sizes( size.0, size.1, size.2 ) ),
+            Backend::VoxelList => Box::new( VoxelList::<Voxel>::new() ),
+            Backend::VoxelHashMap => Box::new( VoxelHashMap::<Voxel>::new() ),
+            Backend::Octree => Box::new( Octree::<Voxel>::from_max_size( size.0.max( size.1 ).max( size.2 ) ) ),
+        }
+    }
+
+    /// Empty structure for the voxels of a single world chunk
+    pub fn create_chunk( self ) -> Box<dyn ChunkHolding> {
+        let size = CHUNK_SIZE as u32;
+
+        match self {
+            Backend::VoxelMap => Box::new( VoxelMap::<Voxel>::from_max_size( size ) ),
+            Backend::VoxelList => Box::new( VoxelList::<Voxel>::new() ),
+            Backend::VoxelHashMap => Box::new( VoxelHashMap::<Voxel>::new() ),
+            Backend::Octree => Box::new( Octree::<Voxel>::from_max_size( size ) ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::world::world_holder::{ fill, ClippedHolder };
+
+    use super::*;
+
+    #[test]
+    fn test_every_clipped_chunk_storage_has_the_octree_bitmasks() {
+        let mut octree = Backend::Octree.create_chunk();
+        fill( (3, 0, 5), (40, 9, 63), octree.as_mut() );
+
+        // The octree masks are sized by its depth, only the first three axes are used
+        let mask_size = CHUNK_SIZE * CHUNK_SIZE * 3;
+        let masks = |chunk:&dyn ChunkHolding| [ chunk.to_bitmask(), chunk.to_transparents_bitmask(), chunk.to_non_solids_bitmask() ].map( |mask| mask.data[ ..mask_size ].to_vec() );
+
+        for backend in Backend::ALL {
+            let mut chunk = backend.create_chunk();
+            fill( (3, 0, 5), (40, 9, 90), &mut ClippedHolder::new( chunk.as_mut(), CHUNK_SIZE as u32 ) );
+            let copy = chunk.clone_boxed();
+            chunk.set_voxel( 3, 0, 5, None );
+
+            assert!( masks( copy.as_ref() ) == masks( octree.as_ref() ), "{}", backend.get_name() );
+            assert!( masks( chunk.as_ref() ) != masks( copy.as_ref() ), "{}", backend.get_name() );
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::HashMap,
+    sync::{ atomic::{ AtomicBool, AtomicU64, Ordering }, Arc, RwLock },
+    time::{ Duration, Instant }
+};
+
+use crate::world::{ world::GridPosition, world_chunk::WorldChunk };
+
+/// Power of two, so the shard is picked with a mask
+const SHARDS_COUNT: usize = 64;
+
+pub type ChunkLock = Arc<RwLock<WorldChunk>>;
+
+/// Waiting for the shard locks of `ChunksMap`, summed over all threads. Collected only after `ChunksMap::set_lock_wait_collection`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChunksLockWait {
+    pub reads: u64,
+    pub read_wait: Duration,
+    pub writes: u64,
+    pub write_wait: Duration,
+}
+
+#[derive(Default)]
+struct ChunksLockStats {
+    is_collected: AtomicBool,
+    reads: AtomicU64,
+    read_wait_ns: AtomicU64,
+    writes: AtomicU64,
+    write_wait_ns: AtomicU64,
+}
+
+impl ChunksLockStats {
+    fn add( count:&AtomicU64, wait_ns:&AtomicU64, wait:Duration ) {
+        count.fetch_add( 1, Ordering::Relaxed );
+        wait_ns.fetch_add( wait.as_nanos() as u64, Ordering::Relaxed );
+    }
+}
+
+/// Chunks split between independently locked shards. Every call locks a single shard only for the time of the lookup,
+/// so inserting and removing chunks never waits for the workers reading other chunks.
+/// Chunks are handed out as `Arc`s, a removed chunk stays alive until the last worker using it drops it
+pub struct ChunksMap {
+    shards: Vec<RwLock<HashMap<GridPosition, ChunkLock>>>,
+    lock_stats: ChunksLockStats,
+}
+
+impl ChunksMap {
+    pub fn new() -> Self {
+        Self {
+            shards: (0..SHARDS_COUNT).map( |_| RwLock::new( HashMap::new() ) ).collect(),
+            lock_stats: ChunksLockStats::default(),
+        }
+    }
+
+    pub fn get( &self, position:&GridPosition ) -> Option<ChunkLock> {
+        self.read_shard( position, |shard| shard.get( position ).cloned() )
+    }
+
+    pub fn contains_key( &self, position:&GridPosition ) -> bool {
+        self.read_shard( position, |shard| shard.contains_key( position ) )
+    }
+
+    pub fn insert( &self, position:GridPosition, chunk:RwLock<WorldChunk> ) -> Option<ChunkLock> {
+        self.write_shard( &position, |shard| shard.insert( position, Arc::new( chunk ) ) )
+    }
+
+    pub fn remove( &self, position:&GridPosition ) -> Option<ChunkLock> {
+        self.write_shard( position, |shard| shard.remove( position ) )
+    }
+
+    #[allow(dead_code)]
+    pub fn len( &self ) -> usize {
+        self.shards.iter().map( |shard| shard.read().unwrap().len() ).sum()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty( &self ) -> bool {
+        self.shards.iter().all( |shard| shard.read().unwrap().is_empty() )
+    }
+
+    pub fn get_lock_wait( &self ) -> ChunksLockWait {
+        let stats = &self.lock_stats;
+
+        ChunksLockWait {
+            reads: stats.reads.load( Ordering::Relaxed ),
+            read_wait: Duration::from_nanos( stats.read_wait_ns.load( Ordering::Relaxed ) ),
+            writes: stats.writes.load( Ordering::Relaxed ),
+            write_wait: Duration::from_nanos( stats.write_wait_ns.load( Ordering::Relaxed ) ),
+        }
+    }
+
+    /// Timing every shard lock costs a clock read per lookup, so it is off until a measurement turns it on
+    pub fn set_lock_wait_collection( &self, is_collected:bool ) {
+        self.lock_stats.is_collected.store( is_collected, Ordering::Relaxed );
+    }
+
+    pub fn reset_lock_wait( &self ) {
+        let stats = &self.lock_stats;
+
+        for counter in [ &stats.reads, &stats.read_wait_ns, &stats.writes, &stats.write_wait_ns ] {
+            counter.store( 0, Ordering::Relaxed );
+        }
+    }
+
+    fn read_shard<R>( &self, position:&GridPosition, reader:impl FnOnce( &HashMap<GridPosition, ChunkLock> ) -> R ) -> R {
+        if !self.lock_stats.is_collected.load( Ordering::Relaxed ) {
+            return reader( &self.shards[ Self::get_shard_index( position ) ].read().unwrap() )
+        }
+
+        let wait_start = Instant::now();
+        let shard = self.shards[ Self::get_shard_index( position ) ].read().unwrap();
+
+        ChunksLockStats::add( &self.lock_stats.reads, &self.lock_stats.read_wait_ns, wait_start.elapsed() );
+        reader( &shard )
+    }
+
+    fn write_shard<R>( &self, position:&GridPosition, writer:impl FnOnce( &mut HashMap<GridPosition, ChunkLock> ) -> R ) -> R {
+        if !self.lock_stats.is_collected.load( Ordering::Relaxed ) {
+            return writer( &mut self.shards[ Self::get_shard_index( position ) ].write().unwrap() )
+        }
+
+        let wait_start = Instant::now();
+        let mut shard = self.shards[ Self::get_shard_index( position ) ].write().unwrap();
+
+        ChunksLockStats::add( &self.lock_stats.writes, &self.lock_stats.write_wait_ns, wait_start.elapsed() );
+        writer( &mut shard )
+    }
+
+    /// Neighbouring chunks land in different shards, so workers processing one region spread over all of them
+    fn get_shard_index( position:&GridPosition ) -> usize {
+        let hash = (position.0.wrapping_mul( 73_856_093 ) ^ position.1.wrapping_mul( 19_349_663 ) ^ position.2.wrapping_mul( 83_492_791 )) as usize;
+        hash & (SHARDS_COUNT - 1)
+    }
+}
+
+impl Default for ChunksMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ sync::mpsc, thread, time::Duration };
+
+    use super::*;
+
+    #[test]
+    fn test_removed_chunk_stays_alive_for_its_users() {
+        let chunks = ChunksMap::new();
+
+        assert!( chunks.insert( (1, -2, 3), RwLock::new( WorldChunk::new() ) ).is_none() );
+        assert!( chunks.contains_key( &(1, -2, 3) ) );
+
+        let chunk = chunks.get( &(1, -2, 3) ).unwrap();
+        assert!( chunks.remove( &(1, -2, 3) ).is_some() );
+
+        assert!( chunks.get( &(1, -2, 3) ).is_none() );
+        assert!( chunk.read().unwrap().renderables.is_empty() );
+        assert_eq!( chunks.len(), 0 );
+    }
+
+    #[test]
+    fn test_inserting_does_not_wait_for_locked_chunks() {
+        let chunks = Arc::new( ChunksMap::new() );
+        chunks.insert( (0, 0, 0), RwLock::new( WorldChunk::new() ) );
+
+        let chunk = chunks.get( &(0, 0, 0) ).unwrap();
+        let chunk_guard = chunk.write().unwrap();
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn( {
+            let chunks = Arc::clone( &chunks );
+
+            move || {
+                for x in 1..100 {
+                    chunks.insert( (x, 0, 0), RwLock::new( WorldChunk::new() ) );
+                }
+
+                chunks.remove( &(0, 0, 0) );
+                tx.send( chunks.len() ).unwrap();
+            }
+        } );
+
+        assert_eq!( rx.recv_timeout( Duration::from_secs( 10 ) ), Ok( 99 ) );
+        drop( chunk_guard );
+    }
+
+    #[test]
+    fn test_lock_wait_is_collected_only_when_turned_on() {
+        let chunks = ChunksMap::new();
+        chunks.insert( (0, 0, 0), RwLock::new( WorldChunk::new() ) );
+        chunks.get( &(0, 0, 0) );
+
+        assert_eq!( chunks.get_lock_wait(), ChunksLockWait::default() );
+
+        chunks.set_lock_wait_collection( true );
+        chunks.insert( (1, 0, 0), RwLock::new( WorldChunk::new() ) );
+        chunks.get( &(0, 0, 0) );
+        chunks.get( &(1, 0, 0) );
+
+        let lock_wait = chunks.get_lock_wait();
+        assert_eq!( (lock_wait.reads, lock_wait.writes), (2, 1) );
+    }
+}
@@ -0,0 +1,162 @@
+use std::{ collections::{ HashMap, HashSet }, ops::{ Add, AddAssign }, sync::Arc };
+
+/// Addresses of `Arc` allocations which have been counted already
+pub type SharedAllocations = HashSet<usize>;
+
+/// Bytes taken by a value. Sum of all three is the whole memory it keeps alive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Footprint {
+    /// The value itself (`size_of_val`)
+    pub inline: usize,
+    /// Allocations owned only by the value (vector buffers, boxed nodes, hash map tables)
+    pub heap: usize,
+    /// Allocations behind `Arc`s, each one counted once no matter how many times it is referenced
+    pub shared: usize,
+}
+
+impl Footprint {
+    pub fn get_total( &self ) -> usize {
+        self.inline + self.heap + self.shared
+    }
+}
+
+impl Add for Footprint {
+    type Output = Self;
+
+    fn add( self, other:Self ) -> Self {
+        Self { inline:self.inline + other.inline, heap:self.heap + other.heap, shared:self.shared + other.shared }
+    }
+}
+
+impl AddAssign for Footprint {
+    fn add_assign( &mut self, other:Self ) {
+        *self = *self + other;
+    }
+}
+
+pub trait MemoryFootprint {
+    /// Adds allocations reachable from the value to `heap` and `shared`. The value itself is already counted by the caller
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations );
+
+    fn get_footprint( &self ) -> Footprint {
+        let mut footprint = Footprint { inline:size_of_val( self ), ..Default::default() };
+        self.add_heap_footprint( &mut footprint, &mut SharedAllocations::new() );
+        footprint
+    }
+}
+
+/// Counts the `Arc` allocation (with its counters) and everything behind it as shared, unless it has been seen
+pub fn add_shared_footprint<T:MemoryFootprint>( value:&Arc<T>, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+    if !seen.insert( Arc::as_ptr( value ) as usize ) {
+        return
+    }
+
+    let mut inner = Footprint::default();
+    T::add_heap_footprint( value, &mut inner, seen );
+
+    footprint.shared += 2 * size_of::<usize>() + size_of::<T>() + inner.heap + inner.shared;
+}
+
+/// Table of the std (hashbrown) hash map: buckets with entries and one control byte each, plus one group of control bytes
+pub fn get_hash_map_heap<K, V>( map:&HashMap<K, V> ) -> usize {
+    const GROUP_WIDTH:usize = 16;
+
+    let buckets = match map.capacity() {
+        0 => return 0,
+        capacity if capacity < 4 => 4,
+        capacity if capacity < 8 => 8,
+        capacity => (capacity * 8 / 7).next_power_of_two(),
+    };
+
+    buckets * size_of::<(K, V)>() + buckets + GROUP_WIDTH
+}
+
+pub fn get_vec_heap<T>( vec:&Vec<T> ) -> usize {
+    vec.capacity() * size_of::<T>()
+}
+
+impl MemoryFootprint for String {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, _seen:&mut SharedAllocations ) {
+        footprint.heap += self.capacity();
+    }
+}
+
+impl<T:MemoryFootprint> MemoryFootprint for Arc<T> {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        add_shared_footprint( self, footprint, seen );
+    }
+}
+
+/// Plain values owning no allocations, like the leaves of light or density octrees
+macro_rules! impl_plain_footprint {
+    ( $( $type:ty ),* ) => {
+        $( impl MemoryFootprint for $type {
+            fn add_heap_footprint( &self, _footprint:&mut Footprint, _seen:&mut SharedAllocations ) {}
+        } )*
+    };
+}
+
+impl_plain_footprint!( u8, u16, u32, f32 );
+
+/// Hash maps with `Arc` values, like the ones of `VoxelDataset`
+pub fn add_shared_map_footprint<T:MemoryFootprint>( map:&HashMap<String, Arc<T>>, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+    footprint.heap += get_hash_map_heap( map );
+
+    for (key, value) in map {
+        key.add_heap_footprint( footprint, seen );
+        add_shared_footprint( value, footprint, seen );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Leaf;
+
+    impl MemoryFootprint for Leaf {
+        fn add_heap_footprint( &self, _footprint:&mut Footprint, _seen:&mut SharedAllocations ) {}
+    }
+
+    struct Holder {
+        values: Vec<Arc<Leaf>>,
+    }
+
+    impl MemoryFootprint for Holder {
+        fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+            footprint.heap += get_vec_heap( &self.values );
+
+            for value in &self.values {
+                add_shared_footprint( value, footprint, seen );
+            }
+        }
+    }
+
+    #[test]
+    fn test_shared_values_are_counted_once() {
+        let leaf = Arc::new( Leaf );
+        let mut values = Vec::with_capacity( 10 );
+        values.extend( [ leaf.clone(), leaf.clone(), leaf, Arc::new( Leaf ) ] );
+
+        let footprint = Holder { values }.get_footprint();
+
+        assert_eq!( footprint.inline, size_of::<Holder>() );
+        assert_eq!( footprint.heap, 10 * size_of::<Arc<Leaf>>() );
+        assert_eq!( footprint.shared, 2 * 2 * size_of::<usize>() );
+    }
+
+    #[test]
+    fn test_hash_map_table_grows_with_capacity() {
+        let mut map = HashMap::<u32, u64>::new();
+        assert_eq!( get_hash_map_heap( &map ), 0 );
+
+        map.insert( 1, 1 );
+        let small = get_hash_map_heap( &map );
+
+        map.extend( (0..1000).map( |i| (i, i as u64) ) );
+        let large = get_hash_map_heap( &map );
+
+        assert!( small > 0 );
+        assert!( large >= 1000 * size_of::<(u32, u64)>() );
+    }
+}
@@ -1,8 +1,15 @@
 pub mod chunk_region_iterator;
 pub mod world_generator;
 pub mod world_chunk_worker;
+pub mod chunks_map;
+pub mod chunk_storage;
 pub mod world_chunk;
+pub mod world_edit_journal;
+pub mod world_light;
+pub mod voxel_metadata;
 pub mod voxel_vertices;
+pub mod serialization;
 pub mod world_holder;
+pub mod memory_footprint;
 pub mod world_renderer;
 pub mod world;
\ No newline at end of file
@@ -0,0 +1,85 @@
+use anyhow::{ anyhow, Result };
+
+pub struct ByteReader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+#[allow(dead_code)]
+impl<'a> ByteReader<'a> {
+    pub fn new( bytes:&'a [u8] ) -> Self {
+        Self { bytes, cursor:0 }
+    }
+
+    pub fn read_bytes( &mut self, count:usize ) -> Result<&'a [u8]> {
+        let end = self.cursor + count;
+        let bytes = self.bytes.get( self.cursor..end ).ok_or_else( || anyhow!( "Unexpected end of data (cursor={}, needed={count})", self.cursor ) )?;
+
+        self.cursor = end;
+        Ok( bytes )
+    }
+
+    pub fn read_u8( &mut self ) -> Result<u8> {
+        Ok( self.read_bytes( 1 )?[ 0 ] )
+    }
+
+    pub fn read_u16( &mut self ) -> Result<u16> {
+        Ok( u16::from_le_bytes( self.read_bytes( 2 )?.try_into()? ) )
+    }
+
+    pub fn read_u32( &mut self ) -> Result<u32> {
+        Ok( u32::from_le_bytes( self.read_bytes( 4 )?.try_into()? ) )
+    }
+
+    pub fn read_string( &mut self ) -> Result<String> {
+        let len = self.read_u16()? as usize;
+        Ok( String::from_utf8( self.read_bytes( len )?.to_vec() )? )
+    }
+}
+
+pub fn write_u16( out:&mut Vec<u8>, value:u16 ) {
+    out.extend_from_slice( &value.to_le_bytes() );
+}
+
+pub fn write_u32( out:&mut Vec<u8>, value:u32 ) {
+    out.extend_from_slice( &value.to_le_bytes() );
+}
+
+/// Lengths which don't fit into the field are refused, cutting them would corrupt everything written after
+pub fn write_length_u16( out:&mut Vec<u8>, length:usize ) -> Result<()> {
+    let length = u16::try_from( length ).map_err( |_| anyhow!( "Length {length} doesn't fit into 16 bits" ) )?;
+
+    write_u16( out, length );
+    Ok( () )
+}
+
+pub fn write_length_u32( out:&mut Vec<u8>, length:usize ) -> Result<()> {
+    let length = u32::try_from( length ).map_err( |_| anyhow!( "Length {length} doesn't fit into 32 bits" ) )?;
+
+    write_u32( out, length );
+    Ok( () )
+}
+
+pub fn write_string( out:&mut Vec<u8>, value:&str ) -> Result<()> {
+    write_length_u16( out, value.len() )?;
+    out.extend_from_slice( value.as_bytes() );
+
+    Ok( () )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_too_long_strings_are_refused() {
+        let mut out = vec![];
+
+        assert!( write_string( &mut out, &"ą".repeat( u16::MAX as usize / 2 ) ).is_ok() );
+        assert_eq!( ByteReader::new( &out ).read_string().unwrap().len(), u16::MAX as usize - 1 );
+
+        out.clear();
+        assert!( write_string( &mut out, &"ą".repeat( u16::MAX as usize / 2 + 1 ) ).is_err() );
+        assert!( write_length_u32( &mut out, u32::MAX as usize + 1 ).is_err() );
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::world::{
+    memory_footprint::{ get_hash_map_heap, get_vec_heap, Footprint, MemoryFootprint, SharedAllocations },
+    serialization::{ write_length_u16, write_length_u32, write_string, write_u16, write_u32, ByteReader }
+};
+
+pub type LocalPosition = (u32, u32, u32);
+
+const FLAG_ORIENTATION: u8 = 1;
+const FLAG_GROWTH_STAGE: u8 = 1 << 1;
+const FLAG_INVENTORY: u8 = 1 << 2;
+
+/// Data of a single voxel instance. Voxels themselves are shared between positions, so it lives beside them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VoxelMetadata {
+    /// Direction in `VoxelSide` convention (1 - 6)
+    pub orientation: Option<u8>,
+    pub growth_stage: Option<u8>,
+    /// (item name, count)
+    pub inventory: Vec<(String, u32)>,
+}
+
+impl VoxelMetadata {
+    pub fn is_empty( &self ) -> bool {
+        self.orientation.is_none() && self.growth_stage.is_none() && self.inventory.is_empty()
+    }
+}
+
+/// Sparse per-position metadata of a chunk, keyed by local coordinates
+#[derive(Default)]
+pub struct VoxelMetadataMap {
+    data: HashMap<LocalPosition, VoxelMetadata>,
+}
+
+#[allow(dead_code)]
+impl VoxelMetadataMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get( &self, position:LocalPosition ) -> Option<&VoxelMetadata> {
+        self.data.get( &position )
+    }
+
+    pub fn set( &mut self, position:LocalPosition, metadata:Option<VoxelMetadata> ) {
+        match metadata {
+            Some( metadata ) if !metadata.is_empty() => { self.data.insert( position, metadata ); }
+            _ => { self.data.remove( &position ); }
+        }
+    }
+
+    pub fn remove( &mut self, position:LocalPosition ) -> Option<VoxelMetadata> {
+        self.data.remove( &position )
+    }
+
+    pub fn remove_range( &mut self, from:LocalPosition, to:LocalPosition ) {
+        if self.data.is_empty() { return }
+
+        let (min, max) = (
+            (from.0.min( to.0 ), from.1.min( to.1 ), from.2.min( to.2 )),
+            (from.0.max( to.0 ), from.1.max( to.1 ), from.2.max( to.2 )),
+        );
+
+        self.data.retain( |position, _| !(
+            position.0 >= min.0 && position.0 <= max.0 &&
+            position.1 >= min.1 && position.1 <= max.1 &&
+            position.2 >= min.2 && position.2 <= max.2
+        ) );
+    }
+
+    pub fn len( &self ) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn iter( &self ) -> impl Iterator<Item = (&LocalPosition, &VoxelMetadata)> {
+        self.data.iter()
+    }
+
+    pub fn write( &self, out:&mut Vec<u8> ) -> Result<()> {
+        write_length_u32( out, self.data.len() )?;
+
+        for (position, metadata) in &self.data {
+            write_u16( out, position.0 as u16 );
+            write_u16( out, position.1 as u16 );
+            write_u16( out, position.2 as u16 );
+
+            let flags = metadata.orientation.map_or( 0, |_| FLAG_ORIENTATION )
+                | metadata.growth_stage.map_or( 0, |_| FLAG_GROWTH_STAGE )
+                | if metadata.inventory.is_empty() { 0 } else { FLAG_INVENTORY };
+
+            out.push( flags );
+
+            if let Some( orientation ) = metadata.orientation {
+                out.push( orientation );
+            }
+
+            if let Some( growth_stage ) = metadata.growth_stage {
+                out.push( growth_stage );
+            }
+
+            if !metadata.inventory.is_empty() {
+                write_length_u16( out, metadata.inventory.len() )?;
+
+                for (item, count) in &metadata.inventory {
+                    write_string( out, item )?;
+                    write_u32( out, *count );
+                }
+            }
+        }
+
+        Ok( () )
+    }
+
+    pub fn read( reader:&mut ByteReader ) -> Result<Self> {
+        let count = reader.read_u32()? as usize;
+        let mut data = HashMap::with_capacity( count );
+
+        for _ in 0..count {
+            let position = (reader.read_u16()? as u32, reader.read_u16()? as u32, reader.read_u16()? as u32);
+            let flags = reader.read_u8()?;
+            let mut metadata = VoxelMetadata::default();
+
+            if flags & FLAG_ORIENTATION != 0 {
+                metadata.orientation = Some( reader.read_u8()? );
+            }
+
+            if flags & FLAG_GROWTH_STAGE != 0 {
+                metadata.growth_stage = Some( reader.read_u8()? );
+            }
+
+            if flags & FLAG_INVENTORY != 0 {
+                let items_count = reader.read_u16()?;
+
+                for _ in 0..items_count {
+                    metadata.inventory.push( (reader.read_string()?, reader.read_u32()?) );
+                }
+            }
+
+            data.insert( position, metadata );
+        }
+
+        Ok( Self { data } )
+    }
+}
+
+impl MemoryFootprint for VoxelMetadataMap {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        footprint.heap += get_hash_map_heap( &self.data );
+
+        for metadata in self.data.values() {
+            footprint.heap += get_vec_heap( &metadata.inventory );
+
+            for (item, _) in &metadata.inventory {
+                item.add_heap_footprint( footprint, seen );
+            }
+        }
+    }
+}
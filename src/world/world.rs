@@ -1,15 +1,16 @@
 use std::{
     cell::RefCell,
-    collections::{HashMap, VecDeque},
-    sync::{ self, mpsc, Arc, Condvar, Mutex, RwLock }, time::Instant,
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{ self, mpsc, Arc, Condvar, Mutex, RwLock }, thread::JoinHandle, time::Instant,
 };
 
-use crate::{app::camera::{Camera, Frustum, FrustumCheck}, flags::{CPUS_COUNT, FLAG_PROFILING_WORLD_GENERATION, FLAG_PROFILING_WORLD_GENERATION_QUEUE, FLAG_PROFILING_WORLD_RENDERING}, world::{
-    world_chunk::{ WorldChunk, WorldChunkState }, world_chunk_worker::{ start_chunk_worker, ChunkCmd, ChunkRes, ChunksDataset, GroupId }, world_generator::WorldGenerative, world_holder::{ VoxelDataset, VoxelSide }
+use crate::{app::camera::{Camera, Frustum, FrustumCheck}, chunks_generators::{ heightmap_cache::get_heightmap_cache_capacity, utilities::create_voxel }, flags::{CPUS_COUNT, FLAG_PROFILING_WORLD_GENERATION, FLAG_PROFILING_WORLD_GENERATION_QUEUE, FLAG_PROFILING_WORLD_RENDERING}, world::{
+    chunk_storage::Backend, voxel_metadata::VoxelMetadata, world_chunk::{ WorldChunk, WorldChunkState }, chunks_map::ChunksLockWait, world_chunk_worker::{ start_chunk_worker, ChunkCmd, ChunkRes, ChunksDataset, GroupId }, world_edit_journal::{ ChunkDiff, WorldEdit, WorldEditJournal }, world_generator::{ GenerationStage, WorldGenerative }, world_holder::{ Color, Material, Voxel, VoxelDataset, VoxelSide }, world_light::{ update_light, MAX_LIGHT_LEVEL }
 }};
 
 pub type ChunkLoaderId = u16;
 pub type GridPosition = (i64, i64, i64);
+pub type VoxelPosition = (i64, i64, i64);
 pub type Position = (f32, f32, f32);
 
 pub static CHUNK_SIZE:usize = 64; // should be <= 64, because it is bit capacity of u64
@@ -17,8 +18,16 @@ pub static CHUNK_SIZE_X2:usize = CHUNK_SIZE * CHUNK_SIZE;
 pub static CHUNK_SIZE_X3:usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
 
 const CHUNK_SIZE_F32:f32 = CHUNK_SIZE as f32;
+const EDIT_JOURNAL_MAX_STORED_BYTES:usize = 64 * 1024 * 1024;
 
 
+#[derive(Clone, Default)]
+pub struct WorldRenderables {
+    pub opaque: Vec<VoxelSide>,
+    /// Sorted from the farthest to the nearest face
+    pub transparent: Vec<VoxelSide>,
+}
+
 pub struct ChunkLoader {
     id: ChunkLoaderId,
     render_distance: u8,
@@ -42,40 +51,75 @@ pub struct World {
     pub max_radius: Option<u8>,
     chunk_loaders: HashMap<ChunkLoaderId, sync::Weak<RefCell<ChunkLoader>>>,
     dataset: VoxelDataset,
+    palette: Vec<Arc<Voxel>>,
+    journal: WorldEditJournal,
     // chunks_tx: mpsc::Sender<ChunkCmd>,
     chunks_rx: mpsc::Receiver<ChunkRes>,
     worker_tasks: Arc<(Mutex<VecDeque<ChunkCmd>>,Condvar)>,
+    workers: Vec<JoinHandle<()>>,
     blocking_tasks_queue: VecDeque<BlockingTask>,
     tasks_groups: HashMap<GroupId,(Option<ChunkLoaderId>, u32, Instant)>,
-    pub debug_meshes: Vec<VoxelSide>,
+    /// Center, cube size and finished stage of the generation groups, their chunks are meshed after the last stage
+    generation_groups: HashMap<GroupId,(GridPosition, u32, GenerationStage)>,
+    pub debug_meshes: WorldRenderables,
 }
 
 impl World {
     pub fn new( default_generator:Box<dyn WorldGenerative>, max_radius:Option<u8> ) -> Self {
+        Self::with_workers_count( default_generator, max_radius, CPUS_COUNT - 1 )
+    }
+
+    /// The main thread takes one of `CPUS_COUNT` cores, so `new` starts one worker less
+    pub fn with_workers_count( default_generator:Box<dyn WorldGenerative>, max_radius:Option<u8>, workers_count:u8 ) -> Self {
+        Self::with_storage( default_generator, max_radius, workers_count, Backend::Octree )
+    }
+
+    /// Chunk voxels are kept in the `storage` structure
+    pub fn with_storage( default_generator:Box<dyn WorldGenerative>, max_radius:Option<u8>, workers_count:u8, storage:Backend ) -> Self {
         debug_assert!( CHUNK_SIZE <= 64, "CHUNK_SIZE should be <= 64, because it is bit capacity of u64" );
 
         // let (cmd_tx, cmd_rx) = mpsc::channel();
         let (res_tx, res_rx) = mpsc::channel();
-        let chunks_dataset = Arc::new( ChunksDataset::new( default_generator ) );
+        let chunks_dataset = Arc::new( ChunksDataset::new( default_generator, storage ) );
         let worker_tasks = Arc::new( (Mutex::new( VecDeque::<ChunkCmd>::new() ), Condvar::new()) );
-
-        for i in 0..CPUS_COUNT-1 {
-            start_chunk_worker( i, &chunks_dataset, &worker_tasks, res_tx.clone() );
-        }
+        let workers = (0..workers_count)
+            .map( |i| start_chunk_worker( i, &chunks_dataset, &worker_tasks, res_tx.clone() ) )
+            .collect();
+
+        let mut dataset = VoxelDataset::new();
+        let palette = [
+            ("stone",  Color { red:120, green:120, blue:120 }, 0,               MAX_LIGHT_LEVEL),
+            ("dirt",   Color { red:100, green:60,  blue:40  }, 0,               MAX_LIGHT_LEVEL),
+            ("grass",  Color { red:30,  green:145, blue:20  }, 0,               MAX_LIGHT_LEVEL),
+            ("sand",   Color { red:220, green:205, blue:140 }, 0,               MAX_LIGHT_LEVEL),
+            ("log",    Color { red:175, green:40,  blue:20  }, 0,               MAX_LIGHT_LEVEL),
+            ("leaves", Color { red:20,  green:100, blue:20  }, 0,               MAX_LIGHT_LEVEL),
+            ("brick",  Color { red:170, green:60,  blue:50  }, 0,               MAX_LIGHT_LEVEL),
+            ("lamp",   Color { red:255, green:230, blue:150 }, MAX_LIGHT_LEVEL, MAX_LIGHT_LEVEL),
+            ("glass",  Color { red:200, green:230, blue:240 }, 0,               0),
+        ].map( |(name, color, emission, opacity)| create_voxel(
+            &mut dataset,
+            (String::from( name ), Material { _density:10, emission, opacity, ..Default::default() }),
+            (String::from( name ), color)
+        ) ).to_vec();
 
         Self {
             chunks_generation_group_size: 40,
             tasks_receiver_single_tick_size: 10,
             chunks_dataset,
             max_radius,
-            dataset: VoxelDataset::new(),
+            dataset,
+            palette,
+            journal: WorldEditJournal::new( EDIT_JOURNAL_MAX_STORED_BYTES ),
             chunk_loaders: HashMap::new(),
             // chunks_tx: cmd_tx,
             chunks_rx: res_rx,
             worker_tasks,
+            workers,
             blocking_tasks_queue: VecDeque::new(),
             tasks_groups: HashMap::new(),
-            debug_meshes: vec![],
+            generation_groups: HashMap::new(),
+            debug_meshes: WorldRenderables::default(),
         }
     }
 
@@ -86,13 +130,14 @@ impl World {
 
         // println!( "create_chunk_loader | {:?}", position );
         self.chunk_loaders.insert( id, Arc::downgrade( &chunk_loader ) );
+        self.update_heightmap_cache_capacity();
 
         self.load_chunks( WorldChunk::get_chunk_position_from_world_position( position ), render_distance, Some( id ) );
 
         chunk_loader
     }
 
-    pub fn get_renderables( &mut self, camera:&Camera ) -> Vec<VoxelSide> {
+    pub fn get_renderables( &mut self, camera:&Camera ) -> WorldRenderables {
         // println!( "Getting renderables" );
 
         // let renderables = self.chunks_dataset.chunks.read().unwrap().iter().flat_map( |(_coords, chunk_lock)| {
@@ -112,7 +157,7 @@ impl World {
 
         // TODO make it working properly
 
-        let mut meshes = Vec::new();
+        let mut meshes = WorldRenderables::default();
         for loader in self.chunk_loaders.values() {
             // println!( "Throught chunk loaders" );
 
@@ -140,11 +185,20 @@ impl World {
             }
         }
 
+        // Transparent faces are blended, so they have to be drawn from the farthest one
+        let eye = (camera.position.x, camera.position.y, camera.position.z);
+        let distance = |side:&VoxelSide| {
+            let position = side.get_position();
+            (position.x - eye.0).powi( 2 ) + (position.y - eye.1).powi( 2 ) + (position.z - eye.2).powi( 2 )
+        };
+
+        meshes.transparent.sort_by( |a, b| distance( b ).total_cmp( &distance( a ) ) );
+
         self.debug_meshes = meshes.clone();
         meshes
     }
 
-    fn collect_visible_chunks( &self, result:&mut Vec<VoxelSide>, frustum:&Frustum, min:Position, max:Position, step:f32 ) {
+    fn collect_visible_chunks( &self, result:&mut WorldRenderables, frustum:&Frustum, min:Position, max:Position, step:f32 ) {
         let world_min = (min.0 * step, min.1 * step, min.2 * step);
         let world_max = (max.0 * step, max.1 * step, max.2 * step);
 
@@ -153,7 +207,7 @@ impl World {
                 // Nothing to see
             }
             FrustumCheck::Inside => {
-                let chunks = self.chunks_dataset.chunks.read().unwrap();
+                let chunks = &self.chunks_dataset.chunks;
                 let max = (max.0 as i64, max.1 as i64, max.2 as i64);
                 let mut x = min.0 as i64;
                 let y = min.1 as i64;
@@ -168,7 +222,8 @@ impl World {
                         while z < max.2 {
                             if let Some( chunk ) = chunks.get( &(x, y, z) ) {
                                 if let Ok( chunk ) = chunk.try_read() {
-                                    result.extend( chunk.renderables.clone() );
+                                    result.opaque.extend( chunk.renderables.clone() );
+                                    result.transparent.extend( chunk.transparent_renderables.clone() );
                                 }
                             }
 
@@ -189,9 +244,10 @@ impl World {
                 if size_x == 1 && size_y == 1 && size_z == 1 {
                     let grid_min = (min.0 as i64, min.1 as i64, min.2 as i64);
 
-                    if let Some( chunk ) = self.chunks_dataset.chunks.read().unwrap().get( &grid_min ) {
+                    if let Some( chunk ) = self.chunks_dataset.chunks.get( &grid_min ) {
                         if let Ok( chunk ) = chunk.try_read() {
-                            result.extend( chunk.renderables.clone() );
+                            result.opaque.extend( chunk.renderables.clone() );
+                            result.transparent.extend( chunk.transparent_renderables.clone() );
                         }
                     }
                 } else {
@@ -299,7 +355,7 @@ impl World {
                     // println!( "main:  - chunks_to_remove={chunks_to_remove:?}" );
                     // println!( "main:  - chunks_to_calculable={chunks_to_calculable:?}" );
 
-                    let chunks = self.chunks_dataset.chunks.read().unwrap();
+                    let chunks = &self.chunks_dataset.chunks;
                     // println!( "main: {:?}", chunks.iter()
                     //     .filter_map( |(k, c)| if matches!( c.read().unwrap().state, WorldChunkState::Meshed ) {
                     //         Some( k )
@@ -313,6 +369,7 @@ impl World {
                     for pos in chunks_to_calculable {
                         if let Some( chunk ) = chunks.get( &pos ) {
                             let Ok( mut chunk ) = chunk.write() else {continue };
+                            if matches!( chunk.state, WorldChunkState::Generating( _ ) ) { continue }
                             chunk.state = WorldChunkState::Calculable;
                         }
                     }
@@ -322,7 +379,6 @@ impl World {
                             chunk.state = WorldChunkState::Stashing;
                         }
                     }
-                    drop( chunks );
 
                     let Some( chunk_loader ) = self.chunk_loaders.get( &loader_id ) else { break };
                     let Some( chunk_loader ) = chunk_loader.upgrade() else { break };
@@ -347,7 +403,19 @@ impl World {
                     }
 
                     if group_tasks.1 == 0 {
-                        let Some( loader_id ) = group_tasks.0 else { break };
+                        let (group_loader_id, _, generation_start) = *group_tasks;
+                        self.tasks_groups.remove( &group_id );
+
+                        if let Some( (center, cube_size, stage) ) = self.generation_groups.remove( &group_id ) {
+                            let last_stage = self.chunks_dataset.default_generator.get_last_stage();
+
+                            if let Some( next_stage ) = stage.get_next().filter( |next_stage| *next_stage <= last_stage ) {
+                                self.queue_generation_stage( center, cube_size, next_stage, group_loader_id, generation_start );
+                                continue
+                            }
+                        }
+
+                        let Some( loader_id ) = group_loader_id else { break };
                         let Some( chunk_loader ) = self.chunk_loaders.get( &loader_id ) else { break };
                         let Some( chunk_loader ) = chunk_loader.upgrade() else { break };
                         let chunk_loader = chunk_loader.borrow();
@@ -381,7 +449,11 @@ impl World {
                         // println!( "Remesh queued" );
 
                         if FLAG_PROFILING_WORLD_GENERATION {
-                            println!( "Chunks generation time: {:?}", group_tasks.2.elapsed() );
+                            println!( "Chunks generation time: {:?}", generation_start.elapsed() );
+
+                            if let Some( stats ) = self.chunks_dataset.default_generator.get_heightmap_cache_stats() {
+                                println!( "Heightmap cache hit rate: {:.1}% ({} hits, {} misses)", stats.get_hit_rate() * 100.0, stats.hits, stats.misses );
+                            }
                         }
 
                         self.tasks_groups.insert( meshing_id.clone(), (Some( loader_id ), 1, Instant::now()) );
@@ -394,11 +466,19 @@ impl World {
                     let group_tasks = self.tasks_groups.get_mut( &group_id ).unwrap();
                     group_tasks.1 -= 1;
 
-                    if FLAG_PROFILING_WORLD_RENDERING && group_tasks.1 == 0 {
-                        println!( "Chunks meshing time: {:?}", group_tasks.2.elapsed() );
+                    if group_tasks.1 == 0 {
+                        if FLAG_PROFILING_WORLD_RENDERING {
+                            println!( "Chunks meshing time: {:?}", group_tasks.2.elapsed() );
+                        }
+
+                        self.tasks_groups.remove( &group_id );
                     }
                 }
 
+                ChunkRes::VoxelsAccessed( group_id ) => {
+                    self.tasks_groups.remove( &group_id );
+                }
+
                 // ChunkRes::NewChunks( chunks, position, render_distance ) => {
                 //     // println!( "main: NewChunks" );
 
@@ -411,20 +491,24 @@ impl World {
         }
 
         if self.blocking_tasks_queue.len() > 0 {
-            let Ok( mut chunks ) = self.chunks_dataset.chunks.try_write() else { return };
+            let chunks = &self.chunks_dataset.chunks;
 
             for _ in 0..self.tasks_receiver_single_tick_size {
                 let Some( task ) = self.blocking_tasks_queue.pop_front() else { break };
 
                 match task {
                     BlockingTask::ChunksToRemove( chunks_to_remove ) => {
-                        for pos in chunks_to_remove {
-                            chunks.remove( &pos );
-                        }
+                        let removed = chunks_to_remove.into_iter()
+                            .filter( |pos| chunks.remove( pos ).is_some() )
+                            .collect::<HashSet<_>>();
+
+                        self.journal.forget_chunks( &removed );
                     }
 
                     BlockingTask::ChunksEnsured( new_chunks, id, position, index_from, index_to ) => {
-                        chunks.extend( new_chunks );
+                        for (chunk_pos, chunk) in new_chunks {
+                            chunks.insert( chunk_pos, chunk );
+                        }
 
                         self.worker_tasks.0.lock().unwrap().push_back( ChunkCmd::GenerateChunks( id, position, index_from, index_to ) );
                         self.worker_tasks.1.notify_one();
@@ -434,8 +518,349 @@ impl World {
         }
     }
 
+    /// There are no queued tasks and no results to receive, so the workers are done
+    pub fn is_idle( &self ) -> bool {
+        self.blocking_tasks_queue.is_empty()
+            && self.worker_tasks.0.lock().unwrap().is_empty()
+            && self.tasks_groups.values().all( |(_, tasks_count, _)| *tasks_count == 0 )
+    }
+
+    /// Drops the queued tasks and joins the workers once they finish the current ones
+    pub fn shutdown( &mut self ) {
+        if self.workers.is_empty() { return }
+
+        let mut tasks = self.worker_tasks.0.lock().unwrap();
+        tasks.clear();
+        tasks.extend( self.workers.iter().map( |_| ChunkCmd::Stop ) );
+        drop( tasks );
+        self.worker_tasks.1.notify_all();
+
+        for worker in self.workers.drain( .. ) {
+            let _ = worker.join();
+        }
+    }
+
+    /// Reads the voxels on one of the workers
+    #[allow(dead_code)]
+    pub fn queue_voxel_reads( &mut self, positions:Vec<VoxelPosition> ) {
+        self.queue_voxels_access( ChunkCmd::ReadVoxels( GroupId::new(), positions ) );
+    }
+
+    /// Sets the voxels on one of the workers. Chunks are marked dirty, but neither remeshed nor journaled
+    #[allow(dead_code)]
+    pub fn queue_voxel_writes( &mut self, voxels:Vec<(VoxelPosition, Option<Arc<Voxel>>)> ) {
+        self.queue_voxels_access( ChunkCmd::WriteVoxels( GroupId::new(), voxels ) );
+    }
+
+    fn queue_voxels_access( &mut self, task:ChunkCmd ) {
+        let (ChunkCmd::ReadVoxels( id, _ ) | ChunkCmd::WriteVoxels( id, _ )) = &task else { unreachable!() };
+
+        self.tasks_groups.insert( id.clone(), (None, 1, Instant::now()) );
+        self.worker_tasks.0.lock().unwrap().push_back( task );
+        self.worker_tasks.1.notify_one();
+    }
+
+    #[allow(dead_code)]
+    pub fn get_chunks_lock_wait( &self ) -> ChunksLockWait {
+        self.chunks_dataset.chunks.get_lock_wait()
+    }
+
+    #[allow(dead_code)]
+    pub fn set_chunks_lock_wait_collection( &self, is_collected:bool ) {
+        self.chunks_dataset.chunks.set_lock_wait_collection( is_collected )
+    }
+
+    #[allow(dead_code)]
+    pub fn reset_chunks_lock_wait( &self ) {
+        self.chunks_dataset.chunks.reset_lock_wait()
+    }
+
+    pub fn get_palette( &self ) -> &[Arc<Voxel>] {
+        &self.palette
+    }
+
+    pub fn get_voxel( &self, position:VoxelPosition ) -> Option<Arc<Voxel>> {
+        let (chunk_pos, local_pos) = Self::split_voxel_position( position );
+        let chunk = self.chunks_dataset.chunks.get( &chunk_pos )?;
+        let chunk = chunk.read().unwrap();
+
+        chunk.get_voxel( local_pos.0, local_pos.1, local_pos.2 )
+    }
+
+    #[allow(dead_code)]
+    pub fn get_voxel_metadata( &self, position:VoxelPosition ) -> Option<VoxelMetadata> {
+        let (chunk_pos, local_pos) = Self::split_voxel_position( position );
+        let chunk = self.chunks_dataset.chunks.get( &chunk_pos )?;
+        let chunk = chunk.read().unwrap();
+
+        chunk.get_metadata( local_pos ).cloned()
+    }
+
+    #[allow(dead_code)]
+    pub fn set_voxel_metadata( &mut self, position:VoxelPosition, metadata:Option<VoxelMetadata> ) -> bool {
+        let (chunk_pos, local_pos) = Self::split_voxel_position( position );
+        let chunks = &self.chunks_dataset.chunks;
+        let Some( chunk ) = chunks.get( &chunk_pos ) else { return false };
+        let mut chunk = chunk.write().unwrap();
+        let before = chunk.get_metadata( local_pos ).cloned();
+
+        if !chunk.set_metadata( local_pos, metadata ) {
+            return false
+        }
+
+        let mut diff = ChunkDiff::new( chunk_pos );
+        diff.push_metadata( local_pos, before, chunk.get_metadata( local_pos ).cloned() );
+        drop( chunk );
+
+        self.journal.record( vec![ diff ] );
+        true
+    }
+
+    pub fn set_voxel( &mut self, position:VoxelPosition, voxel:Option<Arc<Voxel>> ) -> bool {
+        let (chunk_pos, local_pos) = Self::split_voxel_position( position );
+        let chunks = &self.chunks_dataset.chunks;
+        let Some( chunk ) = chunks.get( &chunk_pos ) else { return false };
+        let mut chunk = chunk.write().unwrap();
+        let before = chunk.get_voxel( local_pos.0, local_pos.1, local_pos.2 );
+        let before_metadata = chunk.get_metadata( local_pos ).cloned();
+
+        if !chunk.set_voxel( local_pos.0, local_pos.1, local_pos.2, voxel.clone() ) {
+            return false
+        }
+
+        drop( chunk );
+
+        let mut diff = ChunkDiff::new( chunk_pos );
+        diff.push( local_pos, before, voxel.clone() );
+        diff.push_metadata( local_pos, before_metadata, None );
+
+        self.journal.record( vec![ diff ] );
+        self.journal.log_edit( WorldEdit::SetVoxel( position, voxel ) );
+        self.update_edited_region( &[ (position, position) ] );
+
+        true
+    }
+
+    /// Refused when its undo wouldn't fit into the edit journal
+    #[allow(dead_code)]
+    pub fn fill_voxels( &mut self, from:VoxelPosition, to:VoxelPosition, voxel:Option<Arc<Voxel>> ) -> bool {
+        let min = (from.0.min( to.0 ), from.1.min( to.1 ), from.2.min( to.2 ));
+        let max = (from.0.max( to.0 ), from.1.max( to.1 ), from.2.max( to.2 ));
+        let chunk_min = Self::split_voxel_position( min ).0;
+        let chunk_max = Self::split_voxel_position( max ).0;
+        let chunk_size = CHUNK_SIZE as i64;
+        let mut fills = vec![];
+
+        let chunks = &self.chunks_dataset.chunks;
+
+        for x in chunk_min.0..=chunk_max.0 {
+            for y in chunk_min.1..=chunk_max.1 {
+                for z in chunk_min.2..=chunk_max.2 {
+                    let Some( chunk ) = chunks.get( &(x, y, z) ) else { continue };
+                    let chunk = chunk.read().unwrap();
+                    if chunk.get_data().is_none() { continue }
+
+                    let origin = (x * chunk_size, y * chunk_size, z * chunk_size);
+                    let local_from = (
+                        (min.0.max( origin.0 ) - origin.0) as u32,
+                        (min.1.max( origin.1 ) - origin.1) as u32,
+                        (min.2.max( origin.2 ) - origin.2) as u32,
+                    );
+                    let local_to = (
+                        (max.0.min( origin.0 + chunk_size - 1 ) - origin.0) as u32,
+                        (max.1.min( origin.1 + chunk_size - 1 ) - origin.1) as u32,
+                        (max.2.min( origin.2 + chunk_size - 1 ) - origin.2) as u32,
+                    );
+                    let mut diff = ChunkDiff::new( (x, y, z) );
+
+                    for lx in local_from.0..=local_to.0 {
+                        for ly in local_from.1..=local_to.1 {
+                            for lz in local_from.2..=local_to.2 {
+                                diff.push( (lx, ly, lz), chunk.get_voxel( lx, ly, lz ), voxel.clone() );
+                                diff.push_metadata( (lx, ly, lz), chunk.get_metadata( (lx, ly, lz) ).cloned(), None );
+                            }
+                        }
+                    }
+
+                    if !diff.is_empty() {
+                        fills.push( (local_from, local_to, diff) );
+                    }
+                }
+            }
+        }
+
+        if !self.journal.can_record( fills.iter().map( |(_, _, diff)| diff.get_size_bytes() ).sum() ) {
+            return false
+        }
+
+        let mut diffs = vec![];
+
+        for (local_from, local_to, diff) in fills {
+            let Some( chunk ) = chunks.get( &diff.chunk ) else { continue };
+
+            if chunk.write().unwrap().fill_voxels( local_from, local_to, voxel.clone() ) {
+                diffs.push( diff );
+            }
+        }
+
+        if diffs.is_empty() {
+            return false
+        }
+
+        self.journal.record( diffs );
+        self.journal.log_edit( WorldEdit::FillVoxels( min, max, voxel ) );
+        self.update_edited_region( &[ (min, max) ] );
+
+        true
+    }
+
+    #[allow(dead_code)]
+    pub fn begin_edit_group( &mut self ) {
+        self.journal.begin_group();
+    }
+
+    #[allow(dead_code)]
+    pub fn end_edit_group( &mut self ) {
+        self.journal.end_group();
+    }
+
+    /// Logs the voxel writes, undo and redo included, until `take_edit_log`
+    #[allow(dead_code)]
+    pub fn start_edit_log( &mut self ) {
+        self.journal.start_log();
+    }
+
+    #[allow(dead_code)]
+    pub fn take_edit_log( &mut self ) -> Vec<WorldEdit> {
+        self.journal.take_log()
+    }
+
+    pub fn undo( &mut self ) -> bool {
+        let Some( diffs ) = self.journal.take_undo() else { return false };
+
+        self.apply_diffs( diffs.iter().rev(), true );
+        true
+    }
+
+    pub fn redo( &mut self ) -> bool {
+        let Some( diffs ) = self.journal.take_redo() else { return false };
+
+        self.apply_diffs( diffs.iter(), false );
+        true
+    }
+
+    fn apply_diffs<'a>( &mut self, diffs:impl Iterator<Item=&'a ChunkDiff>, undo:bool ) {
+        let mut edited = vec![];
+        let chunks = &self.chunks_dataset.chunks;
+        let chunk_size = CHUNK_SIZE as i64;
+
+        for diff in diffs {
+            let (Some( chunk ), Some( (from, to) )) = (chunks.get( &diff.chunk ), diff.get_bounds()) else { continue };
+            let origin = (diff.chunk.0 * chunk_size, diff.chunk.1 * chunk_size, diff.chunk.2 * chunk_size);
+
+            let mut chunk = chunk.write().unwrap();
+            chunk.set_voxel_runs( diff.get_runs( undo ) );
+
+            for run in diff.get_runs( undo ) {
+                run.for_each_row( |from, to| self.journal.log_edit( WorldEdit::FillVoxels(
+                    (origin.0 + from.0 as i64, origin.1 + from.1 as i64, origin.2 + from.2 as i64),
+                    (origin.0 + to.0 as i64, origin.1 + to.1 as i64, origin.2 + to.2 as i64),
+                    run.voxel.clone()
+                ) ) );
+            }
+
+            for (position, metadata) in diff.get_metadata( undo ) {
+                chunk.set_metadata( position, metadata );
+            }
+
+            drop( chunk );
+            edited.push( (
+                (origin.0 + from.0 as i64, origin.1 + from.1 as i64, origin.2 + from.2 as i64),
+                (origin.0 + to.0 as i64, origin.1 + to.1 as i64, origin.2 + to.2 as i64),
+            ) );
+        }
+
+        self.update_edited_region( &edited );
+    }
+
+    /// Updates the light around the edited boxes and remeshes the chunks which see the edits or the changed light
+    fn update_edited_region( &mut self, edited:&[(VoxelPosition, VoxelPosition)] ) {
+        let mut chunks_to_remesh = update_light( &self.chunks_dataset.chunks, edited );
+
+        for &(min, max) in edited {
+            Self::collect_chunks_around( min, max, &mut chunks_to_remesh );
+        }
+
+        self.remesh_chunks( chunks_to_remesh );
+    }
+
+    fn collect_chunks_around( min:VoxelPosition, max:VoxelPosition, result:&mut HashSet<GridPosition> ) {
+        // Faces of the voxels next to the edited ones can be covered or uncovered
+        let chunk_min = Self::split_voxel_position( (min.0 - 1, min.1 - 1, min.2 - 1) ).0;
+        let chunk_max = Self::split_voxel_position( (max.0 + 1, max.1 + 1, max.2 + 1) ).0;
+
+        for x in chunk_min.0..=chunk_max.0 {
+            for y in chunk_min.1..=chunk_max.1 {
+                for z in chunk_min.2..=chunk_max.2 {
+                    result.insert( (x, y, z) );
+                }
+            }
+        }
+    }
+
+    fn remesh_chunks( &mut self, chunks_positions:HashSet<GridPosition> ) {
+        let mut positions = vec![];
+        let chunks = &self.chunks_dataset.chunks;
+
+        for chunk_pos in chunks_positions {
+            let Some( chunk ) = chunks.get( &chunk_pos ) else { continue };
+            let mut chunk = chunk.write().unwrap();
+
+            chunk.mark_dirty();
+
+            if matches!( chunk.state, WorldChunkState::Dirty ) {
+                positions.push( chunk_pos );
+            }
+        }
+
+
+        let meshing_id = GroupId::new();
+
+        self.tasks_groups.insert( meshing_id.clone(), (None, 1, Instant::now()) );
+        self.worker_tasks.0.lock().unwrap().push_back( ChunkCmd::RemeshChunksList( meshing_id, positions ) );
+        self.worker_tasks.1.notify_one();
+    }
+
+    pub fn split_voxel_position( position:VoxelPosition ) -> (GridPosition, (u32, u32, u32)) {
+        let chunk_size = CHUNK_SIZE as i64;
+
+        (
+            (
+                position.0.div_euclid( chunk_size ),
+                position.1.div_euclid( chunk_size ),
+                position.2.div_euclid( chunk_size ),
+            ),
+            (
+                position.0.rem_euclid( chunk_size ) as u32,
+                position.1.rem_euclid( chunk_size ) as u32,
+                position.2.rem_euclid( chunk_size ) as u32,
+            ),
+        )
+    }
+
+    /// Heightmap cache of the generator holds the columns of all loaders, so moving between them doesn't thrash it
+    fn update_heightmap_cache_capacity( &self ) {
+        let generation_margin = self.chunks_dataset.get_generation_margin();
+        let columns = self.chunk_loaders.values()
+            .filter_map( |loader| loader.upgrade() )
+            .map( |loader| get_heightmap_cache_capacity( loader.borrow().render_distance, generation_margin ) )
+            .sum();
+
+        self.chunks_dataset.default_generator.set_heightmap_cache_capacity( columns );
+    }
+
     fn load_chunks( &mut self, center_chunk_position:GridPosition, render_distance:u8, loader_id:Option<ChunkLoaderId> ) {
-        let diameter = (render_distance + 1) as u32 * 2 + 1;
+        let diameter = (render_distance as u32 + 1 + self.chunks_dataset.get_generation_margin()) * 2 + 1;
         let cube_size = diameter * diameter * diameter;
         let generation_id = GroupId::new();
         let mut tasks = vec![];
@@ -450,8 +875,51 @@ impl World {
             if i >= cube_size { break }
         }
 
+        self.generation_groups.insert( generation_id.clone(), (center_chunk_position, cube_size, GenerationStage::Terrain) );
         self.tasks_groups.insert( generation_id, (loader_id, i / group_size, Instant::now()) );
         self.worker_tasks.0.lock().unwrap().extend( tasks );
         self.worker_tasks.1.notify_all();
     }
+
+    /// Every stage is queued for the whole loaded cube after the previous one has finished.
+    /// Chunks without all neighbours at the previous stage stay behind in stages reading neighbours, so outer rings end on earlier stages
+    fn queue_generation_stage( &mut self, center_chunk_position:GridPosition, cube_size:u32, stage:GenerationStage, loader_id:Option<ChunkLoaderId>, generation_start:Instant ) {
+        let generation_id = GroupId::new();
+        let mut tasks = vec![];
+        let mut i = 0;
+        let group_size = self.chunks_generation_group_size;
+
+        loop {
+            let count = if cube_size - i >= group_size { group_size } else { cube_size - i };
+            tasks.push( ChunkCmd::AdvanceChunks( generation_id.clone(), center_chunk_position, stage, i, i + count ) );
+
+            i += group_size;
+            if i >= cube_size { break }
+        }
+
+        self.generation_groups.insert( generation_id.clone(), (center_chunk_position, cube_size, stage) );
+        self.tasks_groups.insert( generation_id, (loader_id, i / group_size, generation_start) );
+        self.worker_tasks.0.lock().unwrap().extend( tasks );
+        self.worker_tasks.1.notify_all();
+    }
+}
+
+impl Drop for World {
+    fn drop( &mut self ) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voxel_positions_are_split_at_chunk_borders() {
+        let last = CHUNK_SIZE as i64 - 1;
+
+        assert_eq!( World::split_voxel_position( (0, last, last + 1) ), ((0, 0, 1), (0, last as u32, 0)) );
+        assert_eq!( World::split_voxel_position( (-1, -last - 1, -last - 2) ), ((-1, -1, -2), (last as u32, 0, last as u32)) );
+        assert_eq!( World::split_voxel_position( (-last - 1, -2 * last - 2, 3) ), ((-1, -2, 0), (0, 0, 3)) );
+    }
 }
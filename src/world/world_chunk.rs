@@ -1,16 +1,30 @@
-use std::sync::RwLockReadGuard;
+use std::{ collections::HashMap, sync::{ Arc, RwLockReadGuard } };
+
+use anyhow::{ bail, Result };
 
 use crate::{
+    chunks_generators::utilities::create_voxel,
     structure_tests::octree::Octree,
     world::{
-        world::{ GridPosition, Position, CHUNK_SIZE, CHUNK_SIZE_X2, CHUNK_SIZE_X3 },
-        world_holder::{ Voxel, VoxelSide, WorldHolding }
+        serialization::{ write_length_u32, write_u32, ByteReader },
+        voxel_metadata::{ LocalPosition, VoxelMetadata, VoxelMetadataMap },
+        world::{ GridPosition, Position, CHUNK_SIZE, CHUNK_SIZE_X2 },
+        world_edit_journal::VoxelRun,
+        world_generator::GenerationStage,
+        memory_footprint::{ get_vec_heap, Footprint, MemoryFootprint, SharedAllocations },
+        world_holder::{ ChunkHolding, Color, Material, Voxel, VoxelDataset, VoxelLeaf, VoxelSide, WorldHolding },
+        world_light::ChunkLight
     }
 };
 
+#[allow(dead_code)]
+const CHUNK_FORMAT_VERSION: u8 = 1;
+
 #[derive(Debug)]
 pub enum WorldChunkState {
     Empty,
+    /// Has data, the given stage has been finished but it isn't the last one
+    Generating( GenerationStage ),
     Dirty,
     Meshed,
     Calculable,
@@ -19,15 +33,33 @@ pub enum WorldChunkState {
 }
 
 struct WorldChunkData {
-    data: Octree<Voxel>,
-    solids_mask: ChunkBitmask,
+    data: Box<dyn ChunkHolding>,
+    /// Opaque voxels only, they hide faces of their neighbours
+    opaque_mask: ChunkBitmask,
+    transparents_mask: ChunkBitmask,
+    /// Non solid voxels (like water), their faces are hidden only by the same kind of transparent voxels
+    non_solids_mask: ChunkBitmask,
+    light: Option<ChunkLight>,
+    metadata: VoxelMetadataMap,
+}
+
+impl WorldChunkData {
+    fn rebuild_masks( &mut self ) {
+        self.opaque_mask = self.data.to_bitmask();
+        self.transparents_mask = self.data.to_transparents_bitmask();
+        self.non_solids_mask = self.data.to_non_solids_bitmask();
+    }
 }
 
 #[allow(dead_code)]
 pub struct WorldChunk {
     structure: Option<WorldChunkData>,
+    /// Data after the stages followed by a stage reading neighbours. It is kept while the chunk is loaded,
+    /// so neighbours running that stage later, eg. loaded after a move, read the same data as the earlier ones
+    stage_snapshots: Vec<(GenerationStage, Box<dyn ChunkHolding>)>,
     pub state: WorldChunkState,
     pub renderables: Vec<VoxelSide>,
+    pub transparent_renderables: Vec<VoxelSide>,
 }
 
 impl WorldChunk {
@@ -36,7 +68,9 @@ impl WorldChunk {
         Self {
             state: WorldChunkState::Empty,
             renderables: vec![],
-            structure: None
+            transparent_renderables: vec![],
+            structure: None,
+            stage_snapshots: vec![],
         }
     }
 
@@ -46,38 +80,299 @@ impl WorldChunk {
         chunk
     }
 
-    pub fn set_data( &mut self, data:Octree<Voxel> ) {
-        self.structure = Some( WorldChunkData { solids_mask:data.to_bitmask(), data } );
+    pub fn set_data( &mut self, data:Box<dyn ChunkHolding> ) {
+        self.structure = Some( WorldChunkData {
+            opaque_mask: data.to_bitmask(),
+            transparents_mask: data.to_transparents_bitmask(),
+            non_solids_mask: data.to_non_solids_bitmask(),
+            data,
+            light: None,
+            metadata: VoxelMetadataMap::new(),
+        } );
         self.state = WorldChunkState::Dirty;
     }
 
-    pub fn remesh( &mut self, offset:GridPosition, neighbours:Vec<RwLockReadGuard<'_, WorldChunk>> ) -> bool {
-        let Some( ref structure ) = self.structure else { return false };
+    /// Chunk becomes `Dirty` after the last stage of its generator, `Generating` before
+    pub fn set_stage_data( &mut self, data:Box<dyn ChunkHolding>, stage:GenerationStage, last_stage:GenerationStage ) {
+        self.set_data( data );
+
+        if stage < last_stage {
+            self.state = WorldChunkState::Generating( stage );
+        }
+    }
+
+    /// Like `set_stage_data`, but the data of the stage before `stage` is kept for the neighbours which still have to run
+    /// `stage`, so they never see the output of `stage` from this chunk
+    pub fn set_stage_data_keeping_previous( &mut self, data:Box<dyn ChunkHolding>, stage:GenerationStage, last_stage:GenerationStage ) {
+        if let (Some( previous_stage ), Some( structure )) = (stage.get_previous(), self.structure.take()) {
+            self.stage_snapshots.push( (previous_stage, structure.data) );
+        }
+
+        self.set_stage_data( data, stage, last_stage );
+    }
+
+    /// Data of the chunk right after finishing `stage`, either the current one or its snapshot
+    pub fn get_stage_data( &self, stage:GenerationStage ) -> Option<&dyn ChunkHolding> {
+        if matches!( self.state, WorldChunkState::Generating( finished_stage ) if finished_stage == stage ) {
+            return self.get_data()
+        }
+
+        self.stage_snapshots.iter()
+            .find( |(snapshot_stage, _)| *snapshot_stage == stage )
+            .map( |(_, data)| data.as_ref() )
+    }
+
+    pub fn get_data( &self ) -> Option<&dyn ChunkHolding> {
+        self.structure.as_ref().map( |structure| structure.data.as_ref() )
+    }
+
+    /// Disabled chunks never get data, so they don't hold back their neighbours
+    pub fn has_finished_stage( &self, stage:GenerationStage ) -> bool {
+        match self.state {
+            WorldChunkState::Empty => false,
+            WorldChunkState::Generating( finished_stage ) => finished_stage >= stage,
+            _ => true,
+        }
+    }
+
+    /// All generation stages are finished, so the chunk can be a meshing neighbour
+    pub fn is_generated( &self ) -> bool {
+        !matches!( self.state, WorldChunkState::Empty | WorldChunkState::Generating( _ ) )
+    }
+
+    pub fn get_voxel( &self, x:u32, y:u32, z:u32 ) -> Option<Arc<Voxel>> {
+        self.structure.as_ref()?.data.get_voxel( x, y, z )
+    }
+
+    pub fn get_opaque_mask( &self ) -> Option<&ChunkBitmask> {
+        self.structure.as_ref().map( |structure| &structure.opaque_mask )
+    }
+
+    pub fn get_light( &self ) -> Option<&ChunkLight> {
+        self.structure.as_ref()?.light.as_ref()
+    }
+
+    pub fn get_light_mut( &mut self ) -> Option<&mut ChunkLight> {
+        self.structure.as_mut()?.light.as_mut()
+    }
+
+    /// For edits done without `world_light::update_light`, the light is calculated again by the next remesh
+    pub fn clear_light( &mut self ) {
+        if let Some( ref mut structure ) = self.structure {
+            structure.light = None;
+        }
+    }
+
+    pub fn set_voxel( &mut self, x:u32, y:u32, z:u32, voxel:Option<Arc<Voxel>> ) -> bool {
+        let Some( ref mut structure ) = self.structure else { return false };
+
+        let is_transparent = voxel.as_ref().is_some_and( |voxel| voxel.get_material().is_transparent() );
+
+        let is_non_solid = voxel.as_ref().is_some_and( |voxel| !voxel.get_material().is_solid );
+
+        structure.opaque_mask.set( x as usize, y as usize, z as usize, voxel.is_some() && !is_transparent );
+        structure.transparents_mask.set( x as usize, y as usize, z as usize, is_transparent );
+        structure.non_solids_mask.set( x as usize, y as usize, z as usize, is_non_solid );
+        structure.data.set_voxel( x, y, z, voxel );
+        structure.metadata.remove( (x, y, z) );
+        self.mark_dirty();
+
+        true
+    }
+
+    #[allow(dead_code)]
+    pub fn fill_voxels( &mut self, from:(u32, u32, u32), to:(u32, u32, u32), voxel:Option<Arc<Voxel>> ) -> bool {
+        let Some( ref mut structure ) = self.structure else { return false };
+
+        structure.data.fill_voxels( from, to, voxel );
+        structure.metadata.remove_range( from, to );
+        structure.rebuild_masks();
+        self.mark_dirty();
+
+        true
+    }
+
+    /// Applies runs of a `ChunkDiff`, masks are rebuilt once for all of them
+    pub fn set_voxel_runs( &mut self, runs:&[VoxelRun] ) -> bool {
+        let Some( ref mut structure ) = self.structure else { return false };
+
+        for run in runs {
+            run.for_each_row( |from, to| {
+                structure.data.fill_voxels( from, to, run.voxel.clone() );
+                structure.metadata.remove_range( from, to );
+            } );
+        }
+
+        structure.rebuild_masks();
+        self.mark_dirty();
+
+        true
+    }
+
+    #[allow(dead_code)]
+    pub fn get_metadata( &self, position:LocalPosition ) -> Option<&VoxelMetadata> {
+        self.structure.as_ref()?.metadata.get( position )
+    }
+
+    /// Metadata is dropped together with the voxel when the voxel at its position changes
+    #[allow(dead_code)]
+    pub fn set_metadata( &mut self, position:LocalPosition, metadata:Option<VoxelMetadata> ) -> bool {
+        let Some( ref mut structure ) = self.structure else { return false };
+        if structure.data.get_voxel( position.0, position.1, position.2 ).is_none() { return false }
+
+        structure.metadata.set( position, metadata );
+        true
+    }
+
+    /// Serialises voxels (with palette of their properties) and metadata. Light and meshes are recalculated after loading
+    #[allow(dead_code)]
+    pub fn serialize( &self ) -> Result<Vec<u8>> {
+        let mut out = vec![ CHUNK_FORMAT_VERSION ];
+
+        let Some( ref structure ) = self.structure else {
+            out.push( 0 );
+            return Ok( out )
+        };
+
+        out.push( 1 );
+
+        let mut palette:Vec<Arc<Voxel>> = vec![];
+        let mut palette_ids = HashMap::new();
+        let mut nodes = vec![];
+        let mut octree = Octree::from_max_size( CHUNK_SIZE as u32 );
+
+        for (from, size, voxel) in structure.data.get_all_leaves() {
+            octree.fill( from, (from.0 + size - 1, from.1 + size - 1, from.2 + size - 1), Some( voxel ) );
+        }
+
+        octree.write_nodes( &mut nodes, &mut |voxel| {
+            *palette_ids.entry( Arc::as_ptr( voxel ) ).or_insert_with( || {
+                palette.push( Arc::clone( voxel ) );
+                palette.len() as u32 - 1
+            } )
+        } );
+
+        write_length_u32( &mut out, palette.len() )?;
+
+        for voxel in &palette {
+            let material = voxel.get_material();
+            let color = &voxel._common_data.color;
+
+            write_u32( &mut out, material._density );
+            out.extend_from_slice( &[ material.emission, material.opacity, material.is_solid as u8, color.red, color.green, color.blue ] );
+        }
+
+        out.extend( nodes );
+        structure.metadata.write( &mut out )?;
+
+        Ok( out )
+    }
+
+    /// Voxels are taken from `dataset` or added to it, so they share `Arc`s with the voxels already in the world
+    #[allow(dead_code)]
+    pub fn deserialize( bytes:&[u8], dataset:&mut VoxelDataset ) -> Result<Self> {
+        let mut reader = ByteReader::new( bytes );
+        let version = reader.read_u8()?;
+
+        if version != CHUNK_FORMAT_VERSION {
+            bail!( "Unsupported chunk format version {version}" )
+        }
+
+        let mut chunk = Self::new();
+
+        if reader.read_u8()? == 0 {
+            return Ok( chunk )
+        }
+
+        let palette_size = reader.read_u32()?;
+        let mut palette = Vec::with_capacity( palette_size as usize );
+        let mut voxel_lookup = dataset.get_voxel_lookup();
+
+        for _ in 0..palette_size {
+            let material = Material {
+                _density: reader.read_u32()?,
+                emission: reader.read_u8()?,
+                opacity: reader.read_u8()?,
+                is_solid: reader.read_u8()? != 0,
+            };
+
+            let color = Color { red:reader.read_u8()?, green:reader.read_u8()?, blue:reader.read_u8()? };
+
+            if let Some( voxel ) = voxel_lookup.get( &(material, color) ) {
+                palette.push( Arc::clone( voxel ) );
+                continue
+            }
+
+            let material_key = format!( "d{}e{}o{}s{}", material._density, material.emission, material.opacity, material.is_solid as u8 );
+            let color_key = format!( "{}-{}-{}", color.red, color.green, color.blue );
+            let voxel = create_voxel( dataset, (material_key, material), (color_key, color) );
+
+            voxel_lookup.insert( (material, color), Arc::clone( &voxel ) );
+            palette.push( voxel );
+        }
+
+        let data = Octree::read_nodes( &mut reader, &|id| palette.get( id as usize ).cloned() )?;
+        let metadata = VoxelMetadataMap::read( &mut reader )?;
+
+        chunk.set_data( Box::new( data ) );
+
+        if let Some( ref mut structure ) = chunk.structure {
+            structure.metadata = metadata;
+        }
+
+        Ok( chunk )
+    }
+
+    pub fn mark_dirty( &mut self ) {
+        if self.structure.is_some() && matches!( self.state, WorldChunkState::Meshed | WorldChunkState::Dirty | WorldChunkState::Calculable ) {
+            self.state = WorldChunkState::Dirty;
+        }
+    }
 
+    pub fn remesh( &mut self, offset:GridPosition, neighbours:Vec<RwLockReadGuard<'_, WorldChunk>> ) -> bool {
         if matches!( self.state, WorldChunkState::Meshed | WorldChunkState::Disabled ) {
             return false
         }
 
-        // self.renderables = self.data.get_visible_with_flood( (0, self.data.get_size() as u32 - 1, 0) )
-        //     .into_iter()
-        //     .filter_map( |mut s| {
-        //         // if s.get_position().x != 0.0 { return None }
-        //         s.move_by( (offset.0 as f32, offset.1 as f32, offset.2 as f32) );
-        //         Some( s )
-        //     } )
-        //     .collect::<Vec<VoxelSide>>();
-        // return;
+        let Some( ref mut structure ) = self.structure else { return false };
+
+        // Light is calculated only for the first mesh, edits update it with `world_light::update_light`
+        let light = structure.light.take().unwrap_or_else( || ChunkLight::calculate( structure.data.as_ref(), &structure.opaque_mask, &neighbours ) );
 
         // println!( "Remeshing chunk {:?}", offset );
 
+        let (renderables, transparent_renderables) = Self::collect_renderables(
+            structure.data.as_ref(),
+            (&structure.opaque_mask, &structure.transparents_mask, &structure.non_solids_mask),
+            &light,
+            offset,
+            &neighbours
+        );
+
+        structure.light = Some( light );
+        self.renderables = renderables;
+        self.transparent_renderables = transparent_renderables;
+        self.state = WorldChunkState::Meshed;
+
+        true
+    }
+
+    /// Visible (opaque, transparent) faces of the chunk. Masks are (opaque, transparents, non solids), voxels are read only for the visible faces
+    pub fn collect_renderables(
+        data: &dyn WorldHolding,
+        masks: (&ChunkBitmask, &ChunkBitmask, &ChunkBitmask),
+        light: &ChunkLight,
+        offset: GridPosition,
+        neighbours: &[RwLockReadGuard<'_, WorldChunk>]
+    ) -> (Vec<VoxelSide>, Vec<VoxelSide>) {
         let world_offset = (
             offset.0 * CHUNK_SIZE as i64,
             offset.1 * CHUNK_SIZE as i64,
             offset.2 * CHUNK_SIZE as i64,
         );
 
-        let mut renderables = vec![];
-        let mut col_face_masks = vec![ 0; CHUNK_SIZE_X3 * 2 ];
+        let mut col_face_masks = vec![ 0; CHUNK_SIZE_X2 * 6 ];
+        let mut transparent_col_face_masks = vec![ 0; CHUNK_SIZE_X2 * 6 ];
         let neighbour_shift = CHUNK_SIZE - 1;
         let axies_neighbours = [
             (&neighbours[ 13 ], &neighbours[ 12 ]), // (right, left)
@@ -86,34 +381,65 @@ impl WorldChunk {
         ];
 
         for axis in 0..3 {
+            let neighbour_a = Self::get_meshing_neighbour( axies_neighbours[ axis ].0, offset, axis, -1 );
+            let neighbour_b = Self::get_meshing_neighbour( axies_neighbours[ axis ].1, offset, axis,  1 );
+
             for i in 0..CHUNK_SIZE_X2 {
                 let index = CHUNK_SIZE_X2 * axis + i;
-                let column = structure.solids_mask.data[ index ];
-
-                let neighbour_a_shift = if let Some( ref neighbour_a_shift ) = axies_neighbours[ axis ].0.structure {
-                    (neighbour_a_shift.solids_mask.data[ index ] & 1) << neighbour_shift
-                } else {
-                    match axies_neighbours[ axis ].0.state {
-                        WorldChunkState::Disabled => 0,
-                        _ => Self::panic_meshing_missing_neighbour( axies_neighbours[ axis ].0, offset, axis, -1 )
-                    }
-                };
-
-                let neighbour_b_shift = if let Some( ref neighbour_b_shift ) = axies_neighbours[ axis ].1.structure {
-                    (neighbour_b_shift.solids_mask.data[ index ] >> neighbour_shift) & 1
-                } else {
-                    match axies_neighbours[ axis ].1.state {
-                        WorldChunkState::Disabled => 0,
-                        _ => Self::panic_meshing_missing_neighbour( axies_neighbours[ axis ].1, offset, axis,  1 )
-                    }
-                };
-
-                col_face_masks[ CHUNK_SIZE_X2 * (axis * 2    ) + i ] = column & !(column << 1 | neighbour_b_shift);
-                col_face_masks[ CHUNK_SIZE_X2 * (axis * 2 + 1) + i ] = column & !(column >> 1 | neighbour_a_shift);
+                let (opaque, solid, non_solid) = Self::get_column_kinds( (masks.0, masks.1, masks.2), index );
+
+                // Kinds of the neighbour voxels touching the column
+                let neighbour_a = neighbour_a.map_or( (0, 0, 0), |neighbour| {
+                    let (opaque, solid, non_solid) = Self::get_column_kinds( (&neighbour.opaque_mask, &neighbour.transparents_mask, &neighbour.non_solids_mask), index );
+                    ((opaque & 1) << neighbour_shift, (solid & 1) << neighbour_shift, (non_solid & 1) << neighbour_shift)
+                } );
+
+                let neighbour_b = neighbour_b.map_or( (0, 0, 0), |neighbour| {
+                    let (opaque, solid, non_solid) = Self::get_column_kinds( (&neighbour.opaque_mask, &neighbour.transparents_mask, &neighbour.non_solids_mask), index );
+                    ((opaque >> neighbour_shift) & 1, (solid >> neighbour_shift) & 1, (non_solid >> neighbour_shift) & 1)
+                } );
+
+                // Opaque faces are hidden only by opaque voxels. Transparent ones also by transparent voxels of the same kind,
+                // so water stays visible through glass and the other way round
+                let faces = [
+                    (opaque, opaque, (neighbour_a.0, neighbour_b.0)),
+                    (solid, opaque | solid, (neighbour_a.0 | neighbour_a.1, neighbour_b.0 | neighbour_b.1)),
+                    (non_solid, opaque | non_solid, (neighbour_a.0 | neighbour_a.2, neighbour_b.0 | neighbour_b.2)),
+                ].map( |(column, covering, neighbour_covering)| (
+                    column & !(covering << 1 | neighbour_covering.1),
+                    column & !(covering >> 1 | neighbour_covering.0),
+                ) );
+
+                col_face_masks[ CHUNK_SIZE_X2 * (axis * 2    ) + i ] = faces[ 0 ].0;
+                col_face_masks[ CHUNK_SIZE_X2 * (axis * 2 + 1) + i ] = faces[ 0 ].1;
+
+                transparent_col_face_masks[ CHUNK_SIZE_X2 * (axis * 2    ) + i ] = faces[ 1 ].0 | faces[ 2 ].0;
+                transparent_col_face_masks[ CHUNK_SIZE_X2 * (axis * 2 + 1) + i ] = faces[ 1 ].1 | faces[ 2 ].1;
             }
         }
 
-        // let mut collected = HashSet::new();
+        (
+            Self::collect_faces( &col_face_masks, data, light, neighbours, world_offset ),
+            Self::collect_faces( &transparent_col_face_masks, data, light, neighbours, world_offset ),
+        )
+    }
+
+    /// (opaque, transparent solid, transparent non solid) voxels of a mask column
+    fn get_column_kinds( masks:(&ChunkBitmask, &ChunkBitmask, &ChunkBitmask), index:usize ) -> (u64, u64, u64) {
+        let transparent = masks.1.data[ index ];
+        let non_solid = masks.2.data[ index ];
+
+        (masks.0.data[ index ], transparent & !non_solid, transparent & non_solid)
+    }
+
+    fn collect_faces(
+        col_face_masks: &[u64],
+        data: &dyn WorldHolding,
+        light: &ChunkLight,
+        neighbours: &[RwLockReadGuard<'_, WorldChunk>],
+        world_offset: (i64, i64, i64)
+    ) -> Vec<VoxelSide> {
+        let mut renderables = vec![];
 
         for axis_turn in 0..6 {
             for x in 0..CHUNK_SIZE {
@@ -130,21 +456,17 @@ impl WorldChunk {
                             _     => (x as u32, y as u32, z), // x,y=z 5,6 Z
                         };
 
-                        // if collected.contains( &voxel_pos ) {
-                        //     num &= num - 1;
-                        //     continue
-                        // }
+                        if let Some( voxel ) = data.get_voxel( voxel_pos.0, voxel_pos.1, voxel_pos.2 ) {
+                            let direction = axis_turn as u8 + 1;
+                            let light_level = light.get_face_level( neighbours, voxel_pos, direction );
 
-                        // collected.insert( voxel_pos );
-
-                        if let Some( voxel ) = structure.data.get( voxel_pos.0, voxel_pos.1, voxel_pos.2 ) {
                             renderables.push( VoxelSide::from_voxel_rc(
                                 world_offset.0 + voxel_pos.0 as i64,
                                 world_offset.1 + voxel_pos.1 as i64,
                                 world_offset.2 + voxel_pos.2 as i64,
-                                axis_turn as u8 + 1,
+                                direction,
                                 &voxel
-                            ) );
+                            ).with_light( light_level ) );
                         }
 
                         num &= num - 1;
@@ -153,10 +475,17 @@ impl WorldChunk {
             }
         }
 
-        self.renderables = renderables;
-        self.state = WorldChunkState::Meshed;
+        renderables
+    }
 
-        true
+    fn get_meshing_neighbour<'a>( neighbour:&'a RwLockReadGuard<'_, WorldChunk>, chunk_pos:GridPosition, axis:usize, addition:i64 ) -> Option<&'a WorldChunkData> {
+        match neighbour.structure {
+            Some( ref structure ) => Some( structure ),
+            None => match neighbour.state {
+                WorldChunkState::Disabled => None,
+                _ => Self::panic_meshing_missing_neighbour( neighbour, chunk_pos, axis, addition )
+            }
+        }
     }
 
     fn panic_meshing_missing_neighbour( neighbour:&RwLockReadGuard<'_, WorldChunk>, chunk_pos:GridPosition, axis:usize, addition:i64 ) -> ! {
@@ -173,7 +502,7 @@ impl WorldChunk {
     #[allow(unused)]
     pub fn print_bitmask_layer( &self, layer:usize ) {
         let Some( ref structure ) = self.structure else { return };
-        let size = structure.data.get_size() as usize;
+        let size = CHUNK_SIZE;
         let mut num = 0;
 
         print!( "   " );
@@ -193,14 +522,14 @@ impl WorldChunk {
             print!( "\n{num} |" );
 
             for x in 0..size {
-                let bit = (structure.solids_mask.data[ z + (layer * CHUNK_SIZE) + CHUNK_SIZE_X2     ] >> x) & 1;
+                let bit = (structure.opaque_mask.data[ z + (layer * CHUNK_SIZE) + CHUNK_SIZE_X2     ] >> x) & 1;
                 print!("{}", if bit == 0 { " " } else { "#" } );
             }
 
             print!( "|" );
 
             for x in 0..size {
-                let bit = (structure.solids_mask.data[ x + (layer * CHUNK_SIZE) + CHUNK_SIZE_X2 * 2 ] >> z) & 1;
+                let bit = (structure.opaque_mask.data[ x + (layer * CHUNK_SIZE) + CHUNK_SIZE_X2 * 2 ] >> z) & 1;
                 print!("{}", if bit == 0 { " " } else { "#" } );
             }
 
@@ -221,14 +550,168 @@ impl WorldChunk {
     }
 }
 
+/// Voxels are shared with other chunks, so for many chunks pass one `seen` set to `add_heap_footprint` of all of them
+impl MemoryFootprint for WorldChunk {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        footprint.heap += get_vec_heap( &self.renderables ) + get_vec_heap( &self.transparent_renderables );
+
+        let Some( structure ) = &self.structure else { return };
+
+        structure.data.add_heap_footprint( footprint, seen );
+        structure.opaque_mask.add_heap_footprint( footprint, seen );
+        structure.transparents_mask.add_heap_footprint( footprint, seen );
+        structure.non_solids_mask.add_heap_footprint( footprint, seen );
+        structure.metadata.add_heap_footprint( footprint, seen );
+
+        if let Some( light ) = &structure.light {
+            light.add_heap_footprint( footprint, seen );
+        }
+    }
+}
+
 pub struct ChunkBitmask {
     pub data: Vec<u64>,
 }
 
+impl MemoryFootprint for ChunkBitmask {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, _seen:&mut SharedAllocations ) {
+        footprint.heap += get_vec_heap( &self.data );
+    }
+}
+
 impl ChunkBitmask {
     pub fn new( size:usize ) -> Self {
         Self {
             data: vec![ 0; size ]
         }
     }
+
+    /// Mask of a chunk with the voxels of leaves passing `is_set`
+    pub fn from_leaves( leaves:Vec<VoxelLeaf>, is_set:impl Fn( &Voxel ) -> bool ) -> Self {
+        let mut mask = Self::new( CHUNK_SIZE_X2 * 3 );
+
+        for ((x, y, z), size, voxel) in leaves {
+            if !is_set( &voxel ) { continue }
+
+            for x in x..x + size {
+                for y in y..y + size {
+                    for z in z..z + size {
+                        mask.set( x as usize, y as usize, z as usize, true );
+                    }
+                }
+            }
+        }
+
+        mask
+    }
+
+    pub fn set( &mut self, x:usize, y:usize, z:usize, is_set:bool ) {
+        let indices = [
+            (y + (z * CHUNK_SIZE),                     x), // y,z = x axis
+            (x + (z * CHUNK_SIZE) + CHUNK_SIZE_X2,     y), // x,z = y axis
+            (x + (y * CHUNK_SIZE) + CHUNK_SIZE_X2 * 2, z), // x,y = z axis
+        ];
+
+        for (index, bit) in indices {
+            if is_set {
+                self.data[ index ] |= 1 << bit;
+            } else {
+                self.data[ index ] &= !(1 << bit);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock;
+
+    use super::*;
+
+    /// (opaque, transparent) faces of voxels placed in a row along the x axis
+    fn count_faces( row:&[Material] ) -> (usize, usize) {
+        let mut dataset = VoxelDataset::new();
+        let mut octree = Octree::from_max_size( CHUNK_SIZE as u32 );
+
+        for (x, material) in row.iter().enumerate() {
+            let name = format!( "d{}o{}s{}", material._density, material.opacity, material.is_solid );
+            let voxel = create_voxel( &mut dataset, (name.clone(), *material), (name, Color { red:1, green:2, blue:3 }) );
+
+            octree.set_voxel( 1 + x as u32, 1, 1, Some( voxel ) );
+        }
+
+        let disabled_chunks = (0..26).map( |_| RwLock::new( WorldChunk::new_disabled() ) ).collect::<Vec<_>>();
+        let neighbours = disabled_chunks.iter().map( |neighbour| neighbour.read().unwrap() ).collect::<Vec<_>>();
+        let masks = (octree.to_bitmask(), octree.to_transparents_bitmask(), octree.to_non_solids_bitmask());
+        let light = ChunkLight::calculate( &octree, &masks.0, &neighbours );
+        let (opaque, transparent) = WorldChunk::collect_renderables( &octree, (&masks.0, &masks.1, &masks.2), &light, (0, 0, 0), &neighbours );
+
+        (opaque.len(), transparent.len())
+    }
+
+    #[test]
+    fn test_faces_between_solid_and_transparent_voxels() {
+        let stone = Material::default();
+        let glass = Material { _density:1, opacity:0, ..Default::default() };
+        let water = Material { _density:2, opacity:2, is_solid:false, ..Default::default() };
+
+        assert_eq!( count_faces( &[ stone, stone ] ), (10, 0) );
+        assert_eq!( count_faces( &[ stone, water ] ), (6, 5) );
+        assert_eq!( count_faces( &[ stone, glass ] ), (6, 5) );
+        assert_eq!( count_faces( &[ water, water ] ), (0, 10) );
+        assert_eq!( count_faces( &[ glass, glass ] ), (0, 10) );
+        assert_eq!( count_faces( &[ glass, water ] ), (0, 12) );
+    }
+
+    #[test]
+    fn test_serialization_keeps_voxels_shared_and_metadata() {
+        let mut dataset = VoxelDataset::new();
+        let stone = create_voxel( &mut dataset, (String::from( "stone" ), Material::default()), (String::from( "stone" ), Color { red:1, green:2, blue:3 }) );
+        let mut octree = Octree::from_max_size( CHUNK_SIZE as u32 );
+        octree.set_voxel( 1, 2, 3, Some( Arc::clone( &stone ) ) );
+        octree.set_voxel( 40, 50, 60, Some( stone ) );
+
+        let mut chunk = WorldChunk::new();
+        chunk.set_data( Box::new( octree ) );
+
+        let metadata = VoxelMetadata { orientation:Some( 4 ), growth_stage:None, inventory:vec![ (String::from( "coal" ), 12) ] };
+        assert!( chunk.set_metadata( (1, 2, 3), Some( metadata.clone() ) ) );
+        assert!( !chunk.set_metadata( (0, 0, 0), Some( metadata.clone() ) ) );
+
+        let mut loaded_dataset = VoxelDataset::new();
+        let loaded = WorldChunk::deserialize( &chunk.serialize().unwrap(), &mut loaded_dataset ).unwrap();
+        let a = loaded.get_voxel( 1, 2, 3 ).unwrap();
+        let b = loaded.get_voxel( 40, 50, 60 ).unwrap();
+
+        assert!( Arc::ptr_eq( &a, &b ) );
+        assert!( loaded.get_voxel( 0, 0, 0 ).is_none() );
+        assert_eq!( a._common_data.color.green, 2 );
+        assert_eq!( loaded.get_metadata( (1, 2, 3) ), Some( &metadata ) );
+        assert_eq!( loaded.get_metadata( (40, 50, 60) ), None );
+
+        let loaded_again = WorldChunk::deserialize( &loaded.serialize().unwrap(), &mut loaded_dataset ).unwrap();
+        assert!( Arc::ptr_eq( &loaded_again.get_voxel( 1, 2, 3 ).unwrap(), &a ) );
+
+        let loaded_into_world = WorldChunk::deserialize( &chunk.serialize().unwrap(), &mut dataset ).unwrap();
+        assert!( Arc::ptr_eq( &loaded_into_world.get_voxel( 40, 50, 60 ).unwrap(), &chunk.get_voxel( 40, 50, 60 ).unwrap() ) );
+        assert_eq!( dataset.voxels.len(), 1 );
+    }
+
+    #[test]
+    fn test_chunk_is_generated_only_after_last_stage() {
+        let mut chunk = WorldChunk::new();
+        assert!( !chunk.has_finished_stage( GenerationStage::Terrain ) );
+
+        chunk.set_stage_data( Box::new( Octree::<Voxel>::from_max_size( CHUNK_SIZE as u32 ) ), GenerationStage::Terrain, GenerationStage::Features );
+        assert!( chunk.has_finished_stage( GenerationStage::Terrain ) );
+        assert!( !chunk.has_finished_stage( GenerationStage::Carving ) );
+        assert!( !chunk.is_generated() );
+
+        chunk.set_stage_data( Box::new( Octree::<Voxel>::from_max_size( CHUNK_SIZE as u32 ) ), GenerationStage::Hydrology, GenerationStage::Hydrology );
+        assert!( chunk.has_finished_stage( GenerationStage::Decoration ) );
+        assert!( chunk.is_generated() );
+        assert!( matches!( chunk.state, WorldChunkState::Dirty ) );
+
+        assert!( WorldChunk::new_disabled().has_finished_stage( GenerationStage::Decoration ) );
+    }
 }
@@ -1,8 +1,8 @@
 use std::{
-    collections::{HashMap, VecDeque}, sync::{ atomic::AtomicU64, mpsc, Arc, Condvar, Mutex, RwLock }, thread, vec
+    collections::VecDeque, sync::{ atomic::AtomicU64, mpsc, Arc, Condvar, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard }, thread::{ self, JoinHandle }, vec
 };
 
-use crate::world::{chunk_region_iterator::ChunkRegionIterator, world::{ ChunkLoaderId, GridPosition, CHUNK_SIZE as CHUNK_SIZE_USIZE }, world_chunk::{WorldChunk, WorldChunkState}, world_generator::WorldGenerative, world_holder::VoxelDataset};
+use crate::world::{ chunk_region_iterator::ChunkRegionIterator, chunk_storage::Backend, chunks_map::{ ChunkLock, ChunksMap }, world::{ ChunkLoaderId, GridPosition, VoxelPosition, World, CHUNK_SIZE as CHUNK_SIZE_USIZE }, world_chunk::{WorldChunk, WorldChunkState}, world_generator::{ GenerationStage, StageNeighbours, WorldGenerative }, world_holder::{ ClippedHolder, Voxel, VoxelDataset, WorldHolding }, world_light::spread_light_from_chunk };
 
 const CHUNK_SIZE:i64 = CHUNK_SIZE_USIZE as i64;
 
@@ -17,29 +17,49 @@ impl GroupId {
     }
 }
 
-type WorldChunkCoord = i64;
-
 pub struct ChunksDataset {
-    pub chunks: RwLock<HashMap<(WorldChunkCoord, WorldChunkCoord, WorldChunkCoord), RwLock<WorldChunk>>>,
+    pub chunks: ChunksMap,
     pub default_generator: Box<dyn WorldGenerative>,
+    /// Structure holding the voxels of every chunk
+    pub storage: Backend,
 }
 
 impl ChunksDataset {
-    pub fn new( default_generator:Box<dyn WorldGenerative> ) -> Self {
+    pub fn new( default_generator:Box<dyn WorldGenerative>, storage:Backend ) -> Self {
         Self {
-            chunks: RwLock::new( HashMap::new() ),
+            chunks: ChunksMap::new(),
             default_generator,
+            storage,
         }
     }
+
+    /// Extra rings of chunks loaded around the simulated ones. Every generation stage reading neighbours
+    /// needs one more ring, because a chunk advances only when all its neighbours finished the previous stage
+    pub fn get_generation_margin( &self ) -> u32 {
+        let generator = &self.default_generator;
+
+        GenerationStage::ALL.into_iter()
+            .filter( |&stage| stage <= generator.get_last_stage() && generator.reads_neighbours( stage ) )
+            .count() as u32
+    }
 }
 
 #[allow(dead_code)]
 pub enum ChunkCmd {
     EnsureChunks( GroupId, GridPosition, Option<u8>, u32, u32 ),
     GenerateChunks( GroupId, GridPosition, u32, u32 ),
+    /// Runs the stage on chunks which finished the previous one, answered by `ChunksGenerated`
+    AdvanceChunks( GroupId, GridPosition, GenerationStage, u32, u32 ),
     MultithreadedRemeshChunks( GroupId, GridPosition, u32, u32 ),
     RemeshChunks( GroupId, GridPosition, u8 ),
+    RemeshChunksList( GroupId, Vec<GridPosition> ),
     UpdateChunkLoaderChunks( ChunkLoaderId, u8, GridPosition, GridPosition ),
+    /// Reads voxels of generated chunks, answered by `VoxelsAccessed`
+    ReadVoxels( GroupId, Vec<VoxelPosition> ),
+    /// Sets voxels of generated chunks and marks them dirty without remeshing, answered by `VoxelsAccessed`
+    WriteVoxels( GroupId, Vec<(VoxelPosition, Option<Arc<Voxel>>)> ),
+    /// Ends the worker loop, one per worker
+    Stop,
 }
 
 #[allow(dead_code)]
@@ -48,9 +68,10 @@ pub enum ChunkRes {
     ChunksStateUpdate( ChunkLoaderId, Vec<GridPosition>, Vec<GridPosition> ),
     ChunksGenerated( GroupId ),
     ChunksMeshed( GroupId ),
+    VoxelsAccessed( GroupId ),
 }
 
-pub fn start_chunk_worker( worker_id:u8, chunks_dataset:&Arc<ChunksDataset>, tasks_lock:&Arc<(Mutex<VecDeque<ChunkCmd>>,Condvar)>, tx:mpsc::Sender<ChunkRes> ) {
+pub fn start_chunk_worker( worker_id:u8, chunks_dataset:&Arc<ChunksDataset>, tasks_lock:&Arc<(Mutex<VecDeque<ChunkCmd>>,Condvar)>, tx:mpsc::Sender<ChunkRes> ) -> JoinHandle<()> {
     let name = format!( "chunk-worker-{worker_id}" );
 
     thread::Builder::new()
@@ -97,23 +118,40 @@ pub fn start_chunk_worker( worker_id:u8, chunks_dataset:&Arc<ChunksDataset>, tas
                             generate_chunks( &chunks_dataset, position, index_from, index_to );
                             let _ = tx.send( ChunkRes::ChunksGenerated( id ) );
                         }
+                        ChunkCmd::AdvanceChunks( id, position, stage, index_from, index_to ) => {
+                            advance_chunks( &chunks_dataset, position, stage, index_from, index_to );
+                            let _ = tx.send( ChunkRes::ChunksGenerated( id ) );
+                        }
                         ChunkCmd::RemeshChunks( id, position, render_distance ) => {
                             remesh_chunks( &chunks_dataset, position, render_distance );
                             let _ = tx.send( ChunkRes::ChunksMeshed( id ) );
                         }
+                        ChunkCmd::RemeshChunksList( id, positions ) => {
+                            remesh_chunks_list( &chunks_dataset, positions );
+                            let _ = tx.send( ChunkRes::ChunksMeshed( id ) );
+                        }
                         ChunkCmd::MultithreadedRemeshChunks( id, position, index_from, count ) => {
                             let index_to = index_from + count;
                             multithreaded_remesh_chunks( &chunks_dataset, position, index_from, index_to );
                             let _ = tx.send( ChunkRes::ChunksMeshed( id ) );
                         }
                         ChunkCmd::UpdateChunkLoaderChunks( loader_id, render_distance, new_pos, shift ) => {
-                            update_chunk_loader_chunks( &tx, loader_id, render_distance, new_pos, shift );
+                            update_chunk_loader_chunks( &chunks_dataset, &tx, loader_id, render_distance, new_pos, shift );
+                        }
+                        ChunkCmd::ReadVoxels( id, positions ) => {
+                            read_voxels( &chunks_dataset, &positions );
+                            let _ = tx.send( ChunkRes::VoxelsAccessed( id ) );
+                        }
+                        ChunkCmd::WriteVoxels( id, voxels ) => {
+                            write_voxels( &chunks_dataset, voxels );
+                            let _ = tx.send( ChunkRes::VoxelsAccessed( id ) );
                         }
+                        ChunkCmd::Stop => break,
                     }
                 }
             }
         } )
-        .expect( "Failed to spawn thread" );
+        .expect( "Failed to spawn thread" )
 }
 
 fn generate_chunks( chunks_dataset:&Arc<ChunksDataset>, position:GridPosition, index_from:u32, index_to:u32 ) {
@@ -125,7 +163,7 @@ fn generate_chunks( chunks_dataset:&Arc<ChunksDataset>, position:GridPosition, i
 
 
     // Collecting chunks to generate
-    let chunks = chunks_dataset.chunks.read().unwrap();
+    let chunks = &chunks_dataset.chunks;
     loop {
         let Some( relative_pos ) = cube_layer_iter.next() else { break };
         let chunk_pos = (
@@ -141,29 +179,122 @@ fn generate_chunks( chunks_dataset:&Arc<ChunksDataset>, position:GridPosition, i
             }
         }
     }
-    drop( chunks );
 
     // Generating the chunks
     // println!( "Generating the chunks" );
     for pos in chunks_pos_to_generate {
         // println!( "Generating a chunk {pos:?}" );
-        let chunk_data = chunks_dataset.default_generator.generate_chunk( &mut dataset, pos, CHUNK_SIZE as u8 );
-        let chunks = chunks_dataset.chunks.read().unwrap();
+        let mut chunk_data = chunks_dataset.storage.create_chunk();
+        chunks_dataset.default_generator.generate_chunk_into( &mut dataset, &mut ClippedHolder::new( chunk_data.as_mut(), CHUNK_SIZE as u32 ), pos, CHUNK_SIZE as u8 );
+        let chunks = &chunks_dataset.chunks;
         let Some( chunk ) = chunks.get( &pos ) else { continue };
         let mut chunk = chunk.write().unwrap();
 
         if matches!( chunk.state, WorldChunkState::Empty ) {
-            chunk.set_data( chunk_data );
+            chunk.set_stage_data( chunk_data, GenerationStage::Terrain, chunks_dataset.default_generator.get_last_stage() );
+        }
+    }
+}
+
+fn advance_chunks( chunks_dataset:&Arc<ChunksDataset>, position:GridPosition, stage:GenerationStage, index_from:u32, index_to:u32 ) {
+    let Some( previous_stage ) = stage.get_previous() else { return };
+    let generator = &chunks_dataset.default_generator;
+    let reads_neighbours = generator.reads_neighbours( stage );
+    let mut dataset = VoxelDataset::new();
+    let chunks = &chunks_dataset.chunks;
+
+    for relative_pos in ChunkRegionIterator::with_range( index_from..index_to ) {
+        let chunk_pos = (
+            position.0 + relative_pos.0 as i64,
+            position.1 + relative_pos.1 as i64,
+            position.2 + relative_pos.2 as i64
+        );
+
+        let Some( chunk_lock ) = chunks.get( &chunk_pos ) else { continue };
+        let chunk = chunk_lock.read().unwrap();
+
+        if !matches!( chunk.state, WorldChunkState::Generating( finished_stage ) if finished_stage == previous_stage ) { continue }
+        let Some( mut data ) = chunk.get_data().map( |data| data.clone_boxed() ) else { continue };
+        drop( chunk );
+
+        // Only read locks are held during the stage, the chunk itself is locked for writing after releasing them.
+        // The offsets go in the order of chunk positions, like every other place locking more than one chunk
+        let neighbour_chunks = get_neighbour_offsets()
+            .filter( |_| reads_neighbours )
+            .filter_map( |offset| Some( (offset, chunks.get( &(chunk_pos.0 + offset.0 as i64, chunk_pos.1 + offset.1 as i64, chunk_pos.2 + offset.2 as i64) )?) ) )
+            .collect::<Vec<_>>();
+        let neighbour_locks = neighbour_chunks.iter()
+            .map( |(offset, neighbour)| (*offset, neighbour.read().unwrap()) )
+            .collect::<Vec<_>>();
+
+        // Neighbours are read as they were after the previous stage, even the ones which have already run this one
+        let is_ready = |neighbour:&WorldChunk| neighbour.has_finished_stage( previous_stage )
+            && (neighbour.get_data().is_none() || neighbour.get_stage_data( previous_stage ).is_some());
+
+        if reads_neighbours && (neighbour_locks.len() != 26 || neighbour_locks.iter().any( |(_, neighbour)| !is_ready( neighbour ) )) { continue }
+
+        let neighbours = StageNeighbours::new(
+            neighbour_locks.iter().filter_map( |(offset, neighbour)| Some( (*offset, neighbour.get_stage_data( previous_stage )? as &dyn WorldHolding) ) ).collect(),
+            CHUNK_SIZE as u32
+        );
+
+        generator.generate_stage( stage, &mut dataset, &mut ClippedHolder::new( data.as_mut(), CHUNK_SIZE as u32 ), chunk_pos, &neighbours );
+        drop( neighbours );
+        drop( neighbour_locks );
+
+        let mut chunk = chunk_lock.write().unwrap();
+
+        if !matches!( chunk.state, WorldChunkState::Generating( finished_stage ) if finished_stage == previous_stage ) { continue }
+
+        if reads_neighbours {
+            chunk.set_stage_data_keeping_previous( data, stage, generator.get_last_stage() );
+        } else {
+            chunk.set_stage_data( data, stage, generator.get_last_stage() );
+        }
+    }
+}
+
+fn read_voxels( chunks_dataset:&Arc<ChunksDataset>, positions:&[VoxelPosition] ) -> usize {
+    let chunks = &chunks_dataset.chunks;
+
+    positions.iter()
+        .filter( |position| {
+            let (chunk_pos, local_pos) = World::split_voxel_position( **position );
+            let Some( chunk ) = chunks.get( &chunk_pos ) else { return false };
+            let voxel = chunk.read().unwrap().get_voxel( local_pos.0, local_pos.1, local_pos.2 );
+
+            voxel.is_some()
+        } )
+        .count()
+}
+
+fn write_voxels( chunks_dataset:&Arc<ChunksDataset>, voxels:Vec<(VoxelPosition, Option<Arc<Voxel>>)> ) {
+    let chunks = &chunks_dataset.chunks;
+
+    for (position, voxel) in voxels {
+        let (chunk_pos, local_pos) = World::split_voxel_position( position );
+        let Some( chunk ) = chunks.get( &chunk_pos ) else { continue };
+        let mut chunk = chunk.write().unwrap();
+
+        if chunk.is_generated() {
+            chunk.set_voxel( local_pos.0, local_pos.1, local_pos.2, voxel );
+            chunk.clear_light();
         }
     }
 }
 
-fn update_chunk_loader_chunks( tx:&mpsc::Sender<ChunkRes>, loader_id:ChunkLoaderId, render_distance:u8, position:GridPosition, shift:GridPosition ) {
+fn get_neighbour_offsets() -> impl Iterator<Item = (i8, i8, i8)> {
+    (-1..=1).flat_map( |dx| (-1..=1).flat_map( move |dy| (-1..=1).map( move |dz| (dx, dy, dz) ) ) )
+        .filter( |offset| *offset != (0, 0, 0) )
+}
+
+fn update_chunk_loader_chunks( chunks_dataset:&Arc<ChunksDataset>, tx:&mpsc::Sender<ChunkRes>, loader_id:ChunkLoaderId, render_distance:u8, position:GridPosition, shift:GridPosition ) {
     let render_distance = render_distance as i64;
+    let simulation_distance = render_distance + 1 + chunks_dataset.get_generation_margin() as i64;
 
-    let render_distance_addition_surrounding_x = shift.0.signum() * (render_distance + 1);
-    let render_distance_addition_surrounding_y = shift.1.signum() * (render_distance + 1);
-    let render_distance_addition_surrounding_z = shift.2.signum() * (render_distance + 1);
+    let render_distance_addition_surrounding_x = shift.0.signum() * simulation_distance;
+    let render_distance_addition_surrounding_y = shift.1.signum() * simulation_distance;
+    let render_distance_addition_surrounding_z = shift.2.signum() * simulation_distance;
 
     let render_distance_addition_rendering_x = shift.0.signum() * render_distance;
     let render_distance_addition_rendering_y = shift.1.signum() * render_distance;
@@ -181,9 +312,9 @@ fn update_chunk_loader_chunks( tx:&mpsc::Sender<ChunkRes>, loader_id:ChunkLoader
 
     // println!( "position={position:?}, shift={shift:?}" );
 
-    axis_processor( &mut update_coords, from_s_x, from_r_x, shift.0, position.1, position.2, render_distance, &|a, b, c| (a, b, c) );
-    axis_processor( &mut update_coords, from_s_y, from_r_y, shift.1, position.0, position.2, render_distance, &|a, b, c| (b, a, c) );
-    axis_processor( &mut update_coords, from_s_z, from_r_z, shift.2, position.0, position.1, render_distance, &|a, b, c| (b, c, a) );
+    axis_processor( &mut update_coords, from_s_x, from_r_x, shift.0, position.1, position.2, (simulation_distance, render_distance), &|a, b, c| (a, b, c) );
+    axis_processor( &mut update_coords, from_s_y, from_r_y, shift.1, position.0, position.2, (simulation_distance, render_distance), &|a, b, c| (b, a, c) );
+    axis_processor( &mut update_coords, from_s_z, from_r_z, shift.2, position.0, position.1, (simulation_distance, render_distance), &|a, b, c| (b, c, a) );
 
     let _ = tx.send( ChunkRes::ChunksStateUpdate( loader_id, update_coords.0, update_coords.1 ) );
 }
@@ -195,7 +326,7 @@ fn axis_processor(
     shift:i64,
     second_dim:i64,
     third_dim:i64,
-    render_distance:i64,
+    (simulation_distance, render_distance):(i64, i64),
     get_coords:&dyn Fn(i64, i64, i64) -> (i64, i64, i64)
 ) {
     let ranges = if shift < 0 {
@@ -206,8 +337,8 @@ fn axis_processor(
 
     // Logic
     for a in ranges.0 {
-        for b in (second_dim - render_distance - 1)..=(second_dim + render_distance + 1) {
-            for c in (third_dim - render_distance - 1)..=(third_dim + render_distance + 1) {
+        for b in (second_dim - simulation_distance)..=(second_dim + simulation_distance) {
+            for c in (third_dim - simulation_distance)..=(third_dim + simulation_distance) {
                 update_coords.0.push( get_coords( a, b, c ) );
             }
         }
@@ -227,7 +358,7 @@ fn get_nonexistant_chunks( chunks_dataset:&Arc<ChunksDataset>, position:GridPosi
     // println!( "get_nonexistant_chunks | {index_from}..{index_to}" );
 
     let mut new_chunks = vec![];
-    let chunks = chunks_dataset.chunks.read().unwrap();
+    let chunks = &chunks_dataset.chunks;
     let mut cube_layer_iter = ChunkRegionIterator::with_range( index_from..index_to );
 
     loop {
@@ -268,9 +399,9 @@ fn multithreaded_remesh_chunks( chunks_dataset:&Arc<ChunksDataset>, center_chunk
     // println!( "remesh_chunks" );
 
     let mut cube_layer_iter = ChunkRegionIterator::with_range( index_from..index_to );
-    let chunks = chunks_dataset.chunks.read().unwrap();
+    let chunks = &chunks_dataset.chunks;
 
-    'chunks: loop {
+    loop {
         let Some( relative_pos ) = cube_layer_iter.next() else { break };
         let chunk_pos = (
             center_chunk_position.0 + relative_pos.0 as i64,
@@ -280,32 +411,13 @@ fn multithreaded_remesh_chunks( chunks_dataset:&Arc<ChunksDataset>, center_chunk
 
         // println!( "index_from={index_from} {: >2?} | side={}, {:?}", cube_layer_iter.iterations, cube_layer_iter.side, relative_pos );
 
-        let Some( chunk ) = chunks.get( &chunk_pos ) else {
+        if !chunks.contains_key( &chunk_pos ) {
             println!( "Chunk not exists ({chunk_pos:?})" );
             continue
-        };
-
-        let mut chunk = chunk.write().unwrap();
-        if !matches!( chunk.state, WorldChunkState::Dirty ) { continue }
-
-        let mut neighbours = vec![];
-
-        for dy in -1..=1 {
-            for dz in -1..=1 {
-                for dx in -1..=1 {
-                    if dx != 0 || dy != 0 || dz != 0 {
-                        let Some( chunk ) = chunks.get( &(chunk_pos.0 + dx, chunk_pos.1 + dy, chunk_pos.2 + dz) ) else { continue 'chunks };
-                        let chunk = chunk.read().unwrap();
-                        if matches!( chunk.state, WorldChunkState::Empty ) { continue 'chunks }
-
-                        neighbours.push( chunk );
-                    }
-                }
-            }
         }
 
         // println!( "Remesihng {chunk_pos:?}" );
-        chunk.remesh( chunk_pos, neighbours );
+        remesh_chunk( chunks, chunk_pos );
     }
 
     // println!( "{:?}", chunks.values().map( |c| format!( "{:?}", c.read().unwrap().state ) ).collect::<Vec<_>>() );
@@ -318,34 +430,290 @@ fn remesh_chunks( chunks_dataset:&Arc<ChunksDataset>, center_chunk_position:Grid
 
     // Chunks meshing
     let render_distance = render_distance as i64;
-    let chunks = chunks_dataset.chunks.read().unwrap();
-    for y in -render_distance..=render_distance {
+    let chunks = &chunks_dataset.chunks;
+
+    // From top to bottom, so the sunlight falls through already lit chunks
+    for y in (-render_distance..=render_distance).rev() {
         for x in -render_distance..=render_distance {
-            'chunks: for z in -render_distance..=render_distance {
+            for z in -render_distance..=render_distance {
                 let chunk_pos = (center_chunk_position.0 + x, center_chunk_position.1 + y, center_chunk_position.2 + z);
-                let Some( chunk ) = chunks.get( &chunk_pos ) else { continue };
-                let mut chunk = chunk.write().unwrap();
-                // if matches!( chunk.state, WorldChunkState::Meshed | WorldChunkState::Stashing ) { continue }
-                if !matches!( chunk.state, WorldChunkState::Dirty ) { continue }
-
-                let mut neighbours = vec![];
-
-                for dy in -1..=1 {
-                    for dz in -1..=1 {
-                        for dx in -1..=1 {
-                            if dx != 0 || dy != 0 || dz != 0 {
-                                let Some( chunk ) = chunks.get( &(chunk_pos.0 + dx, chunk_pos.1 + dy, chunk_pos.2 + dz) ) else { continue 'chunks };
-                                let chunk = chunk.read().unwrap();
-                                if matches!( chunk.state, WorldChunkState::Empty ) { continue 'chunks }
-
-                                neighbours.push( chunk );
-                            }
+                remesh_chunk( chunks, chunk_pos );
+            }
+        }
+    }
+}
+
+fn remesh_chunks_list( chunks_dataset:&Arc<ChunksDataset>, mut positions:Vec<GridPosition> ) {
+    let chunks = &chunks_dataset.chunks;
+
+    positions.sort_by_key( |position| -position.1 );
+
+    for chunk_pos in positions {
+        remesh_chunk( chunks, chunk_pos );
+    }
+}
+
+fn remesh_chunk( chunks:&ChunksMap, chunk_pos:GridPosition ) {
+    let Some( chunk_lock ) = chunks.get( &chunk_pos ) else { return };
+    // if matches!( chunk.state, WorldChunkState::Meshed | WorldChunkState::Stashing ) { continue }
+    if !matches!( chunk_lock.read().unwrap().state, WorldChunkState::Dirty ) { return }
+
+    let Some( neighbour_locks ) = get_neighbour_chunks( chunks, chunk_pos ) else { return };
+    let (mut chunk, neighbours) = lock_for_remesh( chunk_pos, &chunk_lock, &neighbour_locks );
+    if !matches!( chunk.state, WorldChunkState::Dirty ) { return }
+    if neighbours.iter().any( |chunk| !chunk.is_generated() ) { return }
+
+    let is_unlit = chunk.get_light().is_none();
+    chunk.remesh( chunk_pos, neighbours );
+    drop( chunk );
+
+    // The lit neighbours didn't see the light of this chunk yet, they are remeshed with it
+    if is_unlit {
+        for position in spread_light_from_chunk( chunks, chunk_pos ) {
+            let Some( changed_chunk ) = chunks.get( &position ) else { continue };
+            changed_chunk.write().unwrap().mark_dirty();
+
+            remesh_chunk( chunks, position );
+        }
+    }
+}
+
+/// All 26 neighbours in the order expected by `WorldChunk::remesh`, `None` when any of them isn't loaded
+fn get_neighbour_chunks( chunks:&ChunksMap, chunk_pos:GridPosition ) -> Option<Vec<(GridPosition, ChunkLock)>> {
+    let mut neighbours = Vec::with_capacity( 26 );
+
+    for dy in -1..=1 {
+        for dz in -1..=1 {
+            for dx in -1..=1 {
+                if dx != 0 || dy != 0 || dz != 0 {
+                    let position = (chunk_pos.0 + dx, chunk_pos.1 + dy, chunk_pos.2 + dz);
+                    neighbours.push( (position, chunks.get( &position )?) );
+                }
+            }
+        }
+    }
+
+    Some( neighbours )
+}
+
+/// Locks the chunk for writing and its neighbours for reading, returned in the order of `neighbour_locks`.
+/// Every thread locking more than one chunk takes the locks in the order of chunk positions, so no two wait for each other
+fn lock_for_remesh<'a>( chunk_pos:GridPosition, chunk_lock:&'a ChunkLock, neighbour_locks:&'a [(GridPosition, ChunkLock)] ) -> (RwLockWriteGuard<'a, WorldChunk>, Vec<RwLockReadGuard<'a, WorldChunk>>) {
+    let mut order = (0..neighbour_locks.len()).collect::<Vec<_>>();
+    order.sort_by_key( |&index| neighbour_locks[ index ].0 );
+
+    let mut chunk = None;
+    let mut neighbours = neighbour_locks.iter().map( |_| None ).collect::<Vec<_>>();
+
+    for index in order {
+        let (position, neighbour_lock) = &neighbour_locks[ index ];
+
+        if chunk.is_none() && chunk_pos < *position {
+            chunk = Some( chunk_lock.write().unwrap() );
+        }
+
+        neighbours[ index ] = Some( neighbour_lock.read().unwrap() );
+    }
+
+    let chunk = chunk.unwrap_or_else( || chunk_lock.write().unwrap() );
+
+    (chunk, neighbours.into_iter().map( |neighbour| neighbour.unwrap() ).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chunks_generators::{
+            caves::{ CaveCarver, GeneratorWithCaves },
+            features::{ FeaturePlacer, GeneratorWithFeatures, StructureLibrary },
+            test_1_empty::GeneratorOfTest1Empty,
+            utilities::{ create_voxel, create_water_voxel }
+        },
+        noise::noise_graph::{ Constant, NoiseNodeExt },
+        world::world_holder::Material
+    };
+
+    use super::*;
+
+    /// Marks every stage it runs with a voxel at `(stage index, 0, 0)`, only `Features` reads neighbours.
+    /// `Features` also marks `(0, 1, 0)` when it sees the `Features` marker of a neighbour
+    struct StagedGenerator;
+
+    impl WorldGenerative for StagedGenerator {
+        fn generate_chunk_into( &self, _dataset:&mut VoxelDataset, _world_holder:&mut dyn WorldHolding, _origin:(i64, i64, i64), _size:u8 ) {}
+
+        fn get_last_stage( &self ) -> GenerationStage {
+            GenerationStage::Features
+        }
+
+        fn reads_neighbours( &self, stage:GenerationStage ) -> bool {
+            stage == GenerationStage::Features
+        }
+
+        fn generate_stage( &self, stage:GenerationStage, dataset:&mut VoxelDataset, chunk:&mut dyn WorldHolding, _origin:(i64, i64, i64), neighbours:&StageNeighbours ) {
+            let marker = create_voxel( dataset, (String::from( "marker" ), Material::default()), (String::from( "marker" ), (1, 2, 3).into()) );
+            let features_index = GenerationStage::Features.get_index() as u32;
+            let sees_features = get_neighbour_offsets().any( |offset| {
+                neighbours.get( offset ).is_some_and( |neighbour| neighbour.get_voxel( features_index, 0, 0 ).is_some() )
+            } );
+
+            if stage == GenerationStage::Features && sees_features {
+                chunk.set_voxel( 0, 1, 0, Some( Arc::clone( &marker ) ) );
+            }
+
+            chunk.set_voxel( stage.get_index() as u32, 0, 0, Some( marker ) );
+        }
+    }
+
+    /// Stone up to y = 19 and a checkerboard of 8x8 stone and water squares at y = 20, so only some anchors have ground
+    struct PondsGenerator;
+
+    impl WorldGenerative for PondsGenerator {
+        fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
+            let size = size as u32;
+            let stone = create_voxel( dataset, (String::from( "stone" ), Material::default()), (String::from( "stone" ), (110, 110, 110).into()) );
+            let water = create_water_voxel( dataset );
+
+            match origin.1 {
+                ..0 => world_holder.fill_voxels( (0, 0, 0), (size - 1, size - 1, size - 1), Some( stone ) ),
+                0 => {
+                    world_holder.fill_voxels( (0, 0, 0), (size - 1, 19, size - 1), Some( Arc::clone( &stone ) ) );
+
+                    for x in (0..size).step_by( 8 ) {
+                        for z in (0..size).step_by( 8 ) {
+                            let square = (origin.0 * size as i64 + x as i64) / 8 + (origin.2 * size as i64 + z as i64) / 8;
+                            let voxel = if square.rem_euclid( 2 ) == 0 { &stone } else { &water };
+
+                            world_holder.fill_voxels( (x, 20, z), (x + 7, 20, z + 7), Some( Arc::clone( voxel ) ) );
                         }
                     }
                 }
-
-                chunk.remesh( chunk_pos, neighbours );
+                _ => {}
             }
         }
     }
+
+    /// Chunks of the cube with radius 2 around the origin, generated up to `Terrain`
+    fn create_generated_dataset() -> Arc<ChunksDataset> {
+        let chunks_dataset = Arc::new( ChunksDataset::new( Box::new( StagedGenerator ), Backend::Octree ) );
+
+        for (position, chunk) in get_nonexistant_chunks( &chunks_dataset, (0, 0, 0), None, 0, 125 ) {
+            chunks_dataset.chunks.insert( position, chunk );
+        }
+
+        generate_chunks( &chunks_dataset, (0, 0, 0), 0, 125 );
+        chunks_dataset
+    }
+
+    #[test]
+    fn test_only_stages_reading_neighbours_add_a_ring() {
+        assert_eq!( ChunksDataset::new( Box::new( StagedGenerator ), Backend::Octree ).get_generation_margin(), 1 );
+
+        let caves = GeneratorWithCaves::new( Box::new( GeneratorOfTest1Empty::new( 0 ) ), CaveCarver::new( 0 ) );
+        assert_eq!( ChunksDataset::new( Box::new( caves ), Backend::Octree ).get_generation_margin(), 0 );
+    }
+
+    #[test]
+    fn test_stages_advance_in_order_and_leave_the_outer_ring_behind() {
+        let chunks_dataset = create_generated_dataset();
+        let get_chunk = |position| chunks_dataset.chunks.get( &position ).unwrap();
+
+        // Stages can't be skipped
+        advance_chunks( &chunks_dataset, (0, 0, 0), GenerationStage::Hydrology, 0, 125 );
+        assert!( matches!( get_chunk( (0, 0, 0) ).read().unwrap().state, WorldChunkState::Generating( GenerationStage::Terrain ) ) );
+
+        for stage in [ GenerationStage::Carving, GenerationStage::Hydrology, GenerationStage::Features ] {
+            advance_chunks( &chunks_dataset, (0, 0, 0), stage, 0, 125 );
+        }
+
+        for position in [ (0, 0, 0), (1, -1, 1), (-1, 0, 0) ] {
+            let chunk = get_chunk( position );
+            let chunk = chunk.read().unwrap();
+
+            assert!( chunk.is_generated(), "{position:?}" );
+            assert!( (1..=3).all( |x| chunk.get_voxel( x, 0, 0 ).is_some() ), "{position:?}" );
+        }
+
+        for position in [ (2, 0, 0), (-2, 2, -2), (1, -2, 0) ] {
+            let chunk = get_chunk( position );
+            let chunk = chunk.read().unwrap();
+
+            assert!( matches!( chunk.state, WorldChunkState::Generating( GenerationStage::Hydrology ) ), "{position:?}" );
+            assert!( chunk.get_voxel( 2, 0, 0 ).is_some() && chunk.get_voxel( 3, 0, 0 ).is_none(), "{position:?}" );
+        }
+    }
+
+    #[test]
+    fn test_stage_reads_neighbours_as_they_were_after_the_previous_stage() {
+        let advance_in_order = |first:GridPosition, second:GridPosition| {
+            let chunks_dataset = create_generated_dataset();
+
+            for stage in [ GenerationStage::Carving, GenerationStage::Hydrology ] {
+                advance_chunks( &chunks_dataset, (0, 0, 0), stage, 0, 125 );
+            }
+
+            // Single chunk around each position
+            advance_chunks( &chunks_dataset, first, GenerationStage::Features, 0, 1 );
+            advance_chunks( &chunks_dataset, second, GenerationStage::Features, 0, 1 );
+
+            [ first, second ].map( |position| {
+                let chunk = chunks_dataset.chunks.get( &position ).unwrap();
+                let chunk = chunk.read().unwrap();
+
+                assert!( chunk.has_finished_stage( GenerationStage::Features ), "{position:?}" );
+                chunk.get_data().unwrap().get_all_voxels().len()
+            } )
+        };
+
+        let [ first, second ] = advance_in_order( (0, 0, 0), (1, 0, 0) );
+        let [ second_first, first_second ] = advance_in_order( (1, 0, 0), (0, 0, 0) );
+
+        assert_eq!( (first, second), (first_second, second_first) );
+        assert_eq!( first, 3, "Features of a neighbour can't be seen" );
+    }
+
+    #[test]
+    fn test_features_do_not_depend_on_generation_order() {
+        let generate_in_order = |first:GridPosition, second:GridPosition| {
+            let mut library = StructureLibrary::new_default();
+            library.rules.iter_mut().for_each( |rule| { rule.chance = 1.0; rule.attempts *= 4; rule.min_height = None; } );
+
+            let placer = FeaturePlacer::new( 7, Box::new( Constant( 20.0 ).boxed() ), library ).unwrap();
+            let generator = GeneratorWithFeatures::new( Box::new( PondsGenerator ), placer );
+            let chunks_dataset = Arc::new( ChunksDataset::new( Box::new( generator ), Backend::Octree ) );
+
+            for x in -1..=2 {
+                for y in -1..=1 {
+                    for z in -1..=1 {
+                        chunks_dataset.chunks.insert( (x, y, z), RwLock::new( WorldChunk::new() ) );
+                    }
+                }
+            }
+
+            generate_chunks( &chunks_dataset, (0, 0, 0), 0, 125 );
+
+            for stage in [ GenerationStage::Carving, GenerationStage::Hydrology ] {
+                advance_chunks( &chunks_dataset, (0, 0, 0), stage, 0, 125 );
+            }
+
+            advance_chunks( &chunks_dataset, first, GenerationStage::Features, 0, 1 );
+            advance_chunks( &chunks_dataset, second, GenerationStage::Features, 0, 1 );
+
+            [ (0, 0, 0), (1, 0, 0) ].map( |position| {
+                let chunk = chunks_dataset.chunks.get( &position ).unwrap();
+                let chunk = chunk.read().unwrap();
+
+                assert!( chunk.is_generated(), "{position:?}" );
+                chunk.get_data().unwrap().get_all_voxels().into_iter()
+                    .map( |(x, y, z, voxel)| (x, y, z, voxel._common_data.color.red) )
+                    .collect::<Vec<_>>()
+            } )
+        };
+
+        let left_first = generate_in_order( (0, 0, 0), (1, 0, 0) );
+        let right_first = generate_in_order( (1, 0, 0), (0, 0, 0) );
+
+        assert!( left_first[ 0 ].iter().any( |&(_, y, _, _)| y > 20 ), "Some structures have to be placed" );
+        assert_eq!( left_first, right_first );
+    }
 }
@@ -0,0 +1,463 @@
+use std::{ collections::{ HashSet, VecDeque }, mem::size_of, sync::Arc };
+
+use crate::world::{
+    voxel_metadata::{ LocalPosition, VoxelMetadata },
+    world::{ GridPosition, VoxelPosition, CHUNK_SIZE, CHUNK_SIZE_X2 },
+    world_holder::Voxel
+};
+
+/// Voxel write done through `World`, logged to be replayed into other structures with `WriteTrace::from_edits`
+#[derive(Clone, Debug)]
+pub enum WorldEdit {
+    SetVoxel( VoxelPosition, Option<Arc<Voxel>> ),
+    FillVoxels( VoxelPosition, VoxelPosition, Option<Arc<Voxel>> ),
+}
+
+/// Voxels of `length` consecutive local indexes starting from `start`. Indexes go along z, then y, then x
+#[derive(Clone, Debug)]
+pub struct VoxelRun {
+    pub start: u32,
+    pub length: u32,
+    pub voxel: Option<Arc<Voxel>>,
+}
+
+impl VoxelRun {
+    pub fn get_index( position:LocalPosition ) -> u32 {
+        position.0 * CHUNK_SIZE_X2 as u32 + position.1 * CHUNK_SIZE as u32 + position.2
+    }
+
+    pub fn get_position( index:u32 ) -> LocalPosition {
+        let chunk_size = CHUNK_SIZE as u32;
+        (index / CHUNK_SIZE_X2 as u32, index / chunk_size % chunk_size, index % chunk_size)
+    }
+
+    /// Splits the run into rows along z, which can be filled at once
+    pub fn for_each_row( &self, mut callback:impl FnMut( LocalPosition, LocalPosition ) ) {
+        let chunk_size = CHUNK_SIZE as u32;
+        let end = self.start + self.length;
+        let mut index = self.start;
+
+        while index < end {
+            let row_end = end.min( (index / chunk_size + 1) * chunk_size );
+
+            callback( Self::get_position( index ), Self::get_position( row_end - 1 ) );
+            index = row_end;
+        }
+    }
+}
+
+/// Metadata of a voxel before and after an edit
+pub type MetadataChange = (LocalPosition, Option<VoxelMetadata>, Option<VoxelMetadata>);
+
+/// Voxels and metadata of a single chunk changed by an edit, before and after it. Unchanged voxels aren't stored
+#[derive(Clone, Debug)]
+pub struct ChunkDiff {
+    pub chunk: GridPosition,
+    pub before: Vec<VoxelRun>,
+    pub after: Vec<VoxelRun>,
+    pub metadata: Vec<MetadataChange>,
+}
+
+impl ChunkDiff {
+    pub fn new( chunk:GridPosition ) -> Self {
+        Self { chunk, before:vec![], after:vec![], metadata:vec![] }
+    }
+
+    /// Positions have to be pushed in the order of their indexes to be merged into runs
+    pub fn push( &mut self, position:LocalPosition, before:Option<Arc<Voxel>>, after:Option<Arc<Voxel>> ) {
+        if is_same_voxel( &before, &after ) { return }
+
+        let index = VoxelRun::get_index( position );
+
+        push_to_runs( &mut self.before, index, before );
+        push_to_runs( &mut self.after, index, after );
+    }
+
+    pub fn push_metadata( &mut self, position:LocalPosition, before:Option<VoxelMetadata>, after:Option<VoxelMetadata> ) {
+        if before != after {
+            self.metadata.push( (position, before, after) );
+        }
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.before.is_empty() && self.metadata.is_empty()
+    }
+
+    /// Voxels are shared with the chunks, so only the runs and the metadata are counted
+    pub fn get_size_bytes( &self ) -> usize {
+        let metadata_heap = self.metadata.iter()
+            .flat_map( |(_, before, after)| before.iter().chain( after ) )
+            .flat_map( |metadata| &metadata.inventory )
+            .map( |(item, _)| size_of::<(String, u32)>() + item.len() )
+            .sum::<usize>();
+
+        size_of::<Self>()
+            + (self.before.len() + self.after.len()) * size_of::<VoxelRun>()
+            + self.metadata.len() * size_of::<MetadataChange>()
+            + metadata_heap
+    }
+
+    /// Local bounds of the changed voxels and metadata
+    pub fn get_bounds( &self ) -> Option<(LocalPosition, LocalPosition)> {
+        let mut bounds:Option<(LocalPosition, LocalPosition)> = None;
+        let mut include = |from:LocalPosition, to:LocalPosition| {
+            let (min, max) = bounds.unwrap_or( (from, to) );
+            bounds = Some( (
+                (min.0.min( from.0 ), min.1.min( from.1 ), min.2.min( from.2 )),
+                (max.0.max( to.0 ), max.1.max( to.1 ), max.2.max( to.2 )),
+            ) );
+        };
+
+        for run in &self.before {
+            run.for_each_row( &mut include );
+        }
+
+        for (position, _, _) in &self.metadata {
+            include( *position, *position );
+        }
+
+        bounds
+    }
+
+    pub fn get_runs( &self, undo:bool ) -> &[VoxelRun] {
+        if undo { &self.before } else { &self.after }
+    }
+
+    /// Metadata to set after the runs, which drop the metadata of the voxels they replace
+    pub fn get_metadata( &self, undo:bool ) -> impl Iterator<Item = (LocalPosition, Option<VoxelMetadata>)> + '_ {
+        self.metadata.iter().map( move |(position, before, after)| (*position, if undo { before } else { after }.clone()) )
+    }
+}
+
+fn is_same_voxel( a:&Option<Arc<Voxel>>, b:&Option<Arc<Voxel>> ) -> bool {
+    match (a, b) {
+        (Some( a ), Some( b )) => Arc::ptr_eq( a, b ),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn push_to_runs( runs:&mut Vec<VoxelRun>, index:u32, voxel:Option<Arc<Voxel>> ) {
+    if let Some( last ) = runs.last_mut() {
+        if last.start + last.length == index && is_same_voxel( &last.voxel, &voxel ) {
+            last.length += 1;
+            return
+        }
+    }
+
+    runs.push( VoxelRun { start:index, length:1, voxel } );
+}
+
+#[derive(Default)]
+struct EditGroup {
+    diffs: Vec<ChunkDiff>,
+    size_bytes: usize,
+}
+
+impl EditGroup {
+    fn extend( &mut self, diffs:Vec<ChunkDiff>, size_bytes:usize ) {
+        self.diffs.extend( diffs );
+        self.size_bytes += size_bytes;
+    }
+
+    fn forget_chunks( &mut self, chunks:&HashSet<GridPosition> ) {
+        self.diffs.retain( |diff| !chunks.contains( &diff.chunk ) );
+        self.size_bytes = self.diffs.iter().map( ChunkDiff::get_size_bytes ).sum();
+    }
+}
+
+/// Undo and redo stacks of edit groups, capped by the bytes of their diffs. The oldest groups are dropped first.
+/// The log of edits is kept only between `start_log` and `take_log`, it isn't capped
+pub struct WorldEditJournal {
+    undo_stack: VecDeque<EditGroup>,
+    redo_stack: Vec<EditGroup>,
+    open_group: Option<EditGroup>,
+    stored_bytes: usize,
+    pub max_stored_bytes: usize,
+    log: Option<Vec<WorldEdit>>,
+}
+
+#[allow(dead_code)]
+impl WorldEditJournal {
+    pub fn new( max_stored_bytes:usize ) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: vec![],
+            open_group: None,
+            stored_bytes: 0,
+            max_stored_bytes,
+            log: None,
+        }
+    }
+
+    pub fn begin_group( &mut self ) {
+        if self.open_group.is_none() {
+            self.open_group = Some( EditGroup::default() );
+        }
+    }
+
+    pub fn end_group( &mut self ) {
+        if let Some( group ) = self.open_group.take() {
+            self.push_group( group );
+        }
+    }
+
+    /// Edits bigger than the whole journal couldn't be undone, so they shouldn't be made
+    pub fn can_record( &self, size_bytes:usize ) -> bool {
+        size_bytes <= self.max_stored_bytes
+    }
+
+    /// Returns false when the diffs don't fit into the journal. An open group which would outgrow it is split
+    pub fn record( &mut self, diffs:Vec<ChunkDiff> ) -> bool {
+        let diffs = diffs.into_iter().filter( |diff| !diff.is_empty() ).collect::<Vec<_>>();
+        let size_bytes = diffs.iter().map( ChunkDiff::get_size_bytes ).sum();
+
+        if !self.can_record( size_bytes ) { return false }
+        if diffs.is_empty() { return true }
+
+        self.redo_stack.clear();
+
+        match self.open_group.take() {
+            Some( mut group ) => {
+                if group.size_bytes + size_bytes > self.max_stored_bytes {
+                    self.push_group( group );
+                    group = EditGroup::default();
+                }
+
+                group.extend( diffs, size_bytes );
+                self.open_group = Some( group );
+            }
+
+            None => {
+                let mut group = EditGroup::default();
+                group.extend( diffs, size_bytes );
+                self.push_group( group );
+            }
+        }
+
+        true
+    }
+
+    pub fn take_undo( &mut self ) -> Option<Vec<ChunkDiff>> {
+        self.end_group();
+
+        let group = self.undo_stack.pop_back()?;
+        self.stored_bytes -= group.size_bytes;
+
+        let diffs = group.diffs.clone();
+        self.redo_stack.push( group );
+
+        Some( diffs )
+    }
+
+    pub fn take_redo( &mut self ) -> Option<Vec<ChunkDiff>> {
+        self.end_group();
+
+        let group = self.redo_stack.pop()?;
+        let diffs = group.diffs.clone();
+        self.push_group( group );
+
+        Some( diffs )
+    }
+
+    pub fn can_undo( &self ) -> bool {
+        !self.undo_stack.is_empty() || self.open_group.as_ref().is_some_and( |g| !g.diffs.is_empty() )
+    }
+
+    pub fn can_redo( &self ) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn get_stored_bytes( &self ) -> usize {
+        self.stored_bytes
+    }
+
+    /// Unloaded chunks are generated anew when they come back, their diffs would overwrite the new voxels
+    pub fn forget_chunks( &mut self, chunks:&HashSet<GridPosition> ) {
+        let groups = self.undo_stack.iter_mut().chain( &mut self.redo_stack ).chain( &mut self.open_group );
+
+        for group in groups {
+            group.forget_chunks( chunks );
+        }
+
+        self.undo_stack.retain( |group| !group.diffs.is_empty() );
+        self.redo_stack.retain( |group| !group.diffs.is_empty() );
+        self.stored_bytes = self.undo_stack.iter().map( |group| group.size_bytes ).sum();
+    }
+
+    pub fn start_log( &mut self ) {
+        if self.log.is_none() {
+            self.log = Some( vec![] );
+        }
+    }
+
+    /// Edits logged since `start_log`, logging stops
+    pub fn take_log( &mut self ) -> Vec<WorldEdit> {
+        self.log.take().unwrap_or_default()
+    }
+
+    pub fn log_edit( &mut self, edit:WorldEdit ) {
+        if let Some( log ) = &mut self.log {
+            log.push( edit );
+        }
+    }
+
+    pub fn clear( &mut self ) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.open_group = None;
+        self.stored_bytes = 0;
+    }
+
+    fn push_group( &mut self, group:EditGroup ) {
+        if group.diffs.is_empty() { return }
+
+        self.stored_bytes += group.size_bytes;
+        self.undo_stack.push_back( group );
+
+        while self.stored_bytes > self.max_stored_bytes {
+            let Some( oldest ) = self.undo_stack.pop_front() else { break };
+            self.stored_bytes -= oldest.size_bytes;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chunks_generators::utilities::create_voxel,
+        structure_tests::octree::Octree,
+        world::{ world_chunk::WorldChunk, world_holder::{ Color, Material, VoxelDataset } }
+    };
+
+    use super::*;
+
+    fn create_stone() -> Arc<Voxel> {
+        create_voxel( &mut VoxelDataset::new(), (String::from( "stone" ), Material::default()), (String::from( "stone" ), Color { red:1, green:2, blue:3 }) )
+    }
+
+    fn get_fill_diff( from:LocalPosition, to:LocalPosition, voxel:&Option<Arc<Voxel>> ) -> ChunkDiff {
+        let mut diff = ChunkDiff::new( (0, 0, 0) );
+
+        for x in from.0..=to.0 {
+            for y in from.1..=to.1 {
+                for z in from.2..=to.2 {
+                    diff.push( (x, y, z), None, voxel.clone() );
+                }
+            }
+        }
+
+        diff
+    }
+
+    #[test]
+    fn test_diff_keeps_only_changed_voxels_in_runs() {
+        let stone = Some( create_stone() );
+        let mut diff = get_fill_diff( (2, 3, 0), (2, 4, CHUNK_SIZE as u32 - 1), &stone );
+        diff.push( (5, 0, 0), stone.clone(), stone.clone() );
+
+        assert_eq!( diff.before.len(), 1 );
+        assert_eq!( diff.after[ 0 ].length, 2 * CHUNK_SIZE as u32 );
+        assert_eq!( diff.get_bounds(), Some( ((2, 3, 0), (2, 4, CHUNK_SIZE as u32 - 1)) ) );
+    }
+
+    #[test]
+    fn test_undo_and_redo_restore_the_chunk() {
+        let stone = Some( create_stone() );
+        let mut chunk = WorldChunk::new();
+        chunk.set_data( Box::new( Octree::<Voxel>::from_max_size( CHUNK_SIZE as u32 ) ) );
+
+        let mut journal = WorldEditJournal::new( 1 << 20 );
+        let diff = get_fill_diff( (1, 1, 1), (3, 3, 3), &stone );
+        chunk.fill_voxels( (1, 1, 1), (3, 3, 3), stone.clone() );
+        assert!( journal.record( vec![ diff ] ) );
+
+        let undone = journal.take_undo().unwrap();
+        chunk.set_voxel_runs( undone[ 0 ].get_runs( true ) );
+        assert!( chunk.get_voxel( 2, 2, 2 ).is_none() );
+        assert!( !journal.can_undo() );
+
+        let redone = journal.take_redo().unwrap();
+        chunk.set_voxel_runs( redone[ 0 ].get_runs( false ) );
+        assert!( chunk.get_voxel( 3, 3, 3 ).is_some() );
+        assert!( chunk.get_voxel( 4, 3, 3 ).is_none() );
+        assert!( journal.can_undo() );
+        assert!( !journal.can_redo() );
+    }
+
+    #[test]
+    fn test_undo_restores_the_metadata_of_replaced_voxels() {
+        let stone = Some( create_stone() );
+        let metadata = VoxelMetadata { orientation:Some( 3 ), ..Default::default() };
+        let mut chunk = WorldChunk::new();
+        chunk.set_data( Box::new( Octree::<Voxel>::from_max_size( CHUNK_SIZE as u32 ) ) );
+        chunk.set_voxel( 2, 2, 2, stone.clone() );
+        chunk.set_metadata( (2, 2, 2), Some( metadata.clone() ) );
+
+        let mut diff = ChunkDiff::new( (0, 0, 0) );
+        diff.push( (2, 2, 2), stone.clone(), None );
+        diff.push_metadata( (2, 2, 2), chunk.get_metadata( (2, 2, 2) ).cloned(), None );
+        diff.push_metadata( (5, 5, 5), None, None );
+        chunk.set_voxel( 2, 2, 2, None );
+
+        assert_eq!( diff.metadata.len(), 1 );
+        assert_eq!( diff.get_bounds(), Some( ((2, 2, 2), (2, 2, 2)) ) );
+
+        chunk.set_voxel_runs( diff.get_runs( true ) );
+        for (position, metadata) in diff.get_metadata( true ) {
+            chunk.set_metadata( position, metadata );
+        }
+
+        assert_eq!( chunk.get_metadata( (2, 2, 2) ), Some( &metadata ) );
+    }
+
+    #[test]
+    fn test_oldest_groups_are_evicted_and_oversized_edits_refused() {
+        let stone = Some( create_stone() );
+        let single_size = get_fill_diff( (0, 0, 0), (0, 0, 0), &stone ).get_size_bytes();
+        let mut journal = WorldEditJournal::new( single_size * 2 );
+
+        for x in 0..3 {
+            assert!( journal.record( vec![ get_fill_diff( (x, 0, 0), (x, 0, 0), &stone ) ] ) );
+        }
+
+        assert_eq!( journal.get_stored_bytes(), single_size * 2 );
+        assert!( journal.take_undo().is_some() );
+        assert!( journal.take_undo().is_some() );
+        assert!( journal.take_undo().is_none() );
+
+        let scattered = (0..10).map( |z| (0, 0, z * 2) ).fold( ChunkDiff::new( (0, 0, 0) ), |mut diff, position| {
+            diff.push( position, None, stone.clone() );
+            diff
+        } );
+
+        assert!( !journal.record( vec![ scattered ] ) );
+        assert!( !journal.can_undo() );
+
+        journal.begin_group();
+        journal.record( vec![ get_fill_diff( (0, 0, 0), (0, 0, 0), &stone ) ] );
+        journal.record( vec![ get_fill_diff( (1, 0, 0), (1, 0, 0), &stone ) ] );
+        journal.record( vec![ get_fill_diff( (2, 0, 0), (2, 0, 0), &stone ) ] );
+        journal.end_group();
+
+        assert_eq!( journal.take_undo().map( |diffs| diffs.len() ), Some( 1 ) );
+    }
+
+    #[test]
+    fn test_forgotten_chunks_are_neither_undone_nor_redone() {
+        let stone = Some( create_stone() );
+        let mut journal = WorldEditJournal::new( 1 << 20 );
+        let mut other_diff = get_fill_diff( (0, 0, 0), (0, 0, 0), &stone );
+        other_diff.chunk = (1, 0, 0);
+
+        journal.record( vec![ get_fill_diff( (0, 0, 0), (0, 0, 0), &stone ), other_diff ] );
+        journal.record( vec![ get_fill_diff( (1, 0, 0), (1, 0, 0), &stone ) ] );
+        journal.take_undo();
+        journal.forget_chunks( &HashSet::from( [ (0, 0, 0) ] ) );
+
+        assert!( !journal.can_redo() );
+        assert_eq!( journal.get_stored_bytes(), get_fill_diff( (0, 0, 0), (0, 0, 0), &stone ).get_size_bytes() );
+        assert_eq!( journal.take_undo().map( |diffs| diffs.iter().map( |diff| diff.chunk ).collect::<Vec<_>>() ), Some( vec![ (1, 0, 0) ] ) );
+        assert!( !journal.can_undo() );
+    }
+}
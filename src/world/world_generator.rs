@@ -1,8 +1,162 @@
+use std::sync::Arc;
+
 use crate::{
+    chunks_generators::heightmap_cache::HeightmapCacheStats,
     structure_tests::octree::Octree,
-    world::world_holder::{ Voxel, VoxelDataset }
+    world::world_holder::{ Voxel, VoxelDataset, WorldHolding }
 };
 
+/// Chunk generation stages, in order. A chunk advances to a stage only when all its neighbours have finished the previous one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GenerationStage {
+    /// `WorldGenerative::generate_chunk`
+    Terrain,
+    Carving,
+    Hydrology,
+    Features,
+    Decoration,
+}
+
+impl GenerationStage {
+    pub const ALL: [GenerationStage; 5] = [
+        GenerationStage::Terrain, GenerationStage::Carving, GenerationStage::Hydrology, GenerationStage::Features, GenerationStage::Decoration
+    ];
+
+    pub fn get_index( self ) -> u8 {
+        self as u8
+    }
+
+    pub fn get_next( self ) -> Option<Self> {
+        Self::ALL.get( self.get_index() as usize + 1 ).copied()
+    }
+
+    pub fn get_previous( self ) -> Option<Self> {
+        Self::ALL.get( (self.get_index() as usize).checked_sub( 1 )? ).copied()
+    }
+}
+
+/// Offset (-1 - 1 on every axis) of a neighbour and its data
+pub type StageNeighbour<'a> = ((i8, i8, i8), &'a dyn WorldHolding);
+
+/// Data of the chunks around the one processed by a generation stage, all of them have finished at least the previous stage
+pub struct StageNeighbours<'a> {
+    chunks: Vec<StageNeighbour<'a>>,
+    size: u32,
+}
+
+#[allow(dead_code)]
+impl<'a> StageNeighbours<'a> {
+    pub fn new( chunks:Vec<StageNeighbour<'a>>, size:u32 ) -> Self {
+        Self { chunks, size }
+    }
+
+    /// For generation without neighbours, eg. single chunks in tests
+    pub fn empty( size:u32 ) -> Self {
+        Self { chunks:vec![], size }
+    }
+
+    /// Size of the processed chunk and its neighbours
+    pub fn get_size( &self ) -> u32 {
+        self.size
+    }
+
+    pub fn get( &self, offset:(i8, i8, i8) ) -> Option<&'a dyn WorldHolding> {
+        self.chunks.iter().find( |(chunk_offset, _)| *chunk_offset == offset ).map( |(_, chunk)| *chunk )
+    }
+
+    /// Voxel at position local to the processed chunk, positions up to one chunk outside of it are read from neighbours
+    pub fn get_voxel( &self, x:i64, y:i64, z:i64 ) -> Option<Arc<Voxel>> {
+        self.get_neighbour_voxel( x, y, z )?
+    }
+
+    /// Like `get_voxel`, but tells the missing neighbours (`None`) apart from the empty voxels
+    pub fn get_neighbour_voxel( &self, x:i64, y:i64, z:i64 ) -> Option<Option<Arc<Voxel>>> {
+        let size = self.size as i64;
+        let offset = (x.div_euclid( size ) as i8, y.div_euclid( size ) as i8, z.div_euclid( size ) as i8);
+
+        Some( self.get( offset )?.get_voxel( x.rem_euclid( size ) as u32, y.rem_euclid( size ) as u32, z.rem_euclid( size ) as u32 ) )
+    }
+}
+
 pub trait WorldGenerative: Send + Sync {
-    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel>;
+    /// Writes the terrain of the chunk into an empty `world_holder` of `size` voxels along every axis
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 );
+
+    #[allow(dead_code)]
+    fn generate_chunk( &self, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
+        let mut chunk = Octree::from_max_size( size as u32 );
+        self.generate_chunk_into( dataset, &mut chunk, origin, size );
+        chunk
+    }
+
+    /// Last stage the generator uses
+    fn get_last_stage( &self ) -> GenerationStage {
+        GenerationStage::Terrain
+    }
+
+    /// Stages reading `StageNeighbours` wait for all neighbours to finish the previous stage, so the world loads
+    /// an extra ring of chunks for each of them. The other stages get no neighbours and run as soon as the chunk is ready
+    fn reads_neighbours( &self, _stage:GenerationStage ) -> bool {
+        false
+    }
+
+    /// Runs one of the stages after `Terrain` on a chunk
+    fn generate_stage( &self, _stage:GenerationStage, _dataset:&mut VoxelDataset, _chunk:&mut dyn WorldHolding, _origin:(i64, i64, i64), _neighbours:&StageNeighbours ) {}
+
+    /// Hits of the column heightmaps cache, for generators which keep one
+    fn get_heightmap_cache_stats( &self ) -> Option<HeightmapCacheStats> {
+        None
+    }
+
+    /// Lets generators keeping column heightmaps hold all `columns` the world loaders keep loaded
+    fn set_heightmap_cache_capacity( &self, _columns:usize ) {}
+}
+
+/// Lets one generator be shared, eg. biomes building the terrain and giving heights to the features placed on it
+impl<T: WorldGenerative + ?Sized> WorldGenerative for Arc<T> {
+    fn generate_chunk_into( &self, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
+        (**self).generate_chunk_into( dataset, world_holder, origin, size )
+    }
+
+    fn get_last_stage( &self ) -> GenerationStage {
+        (**self).get_last_stage()
+    }
+
+    fn reads_neighbours( &self, stage:GenerationStage ) -> bool {
+        (**self).reads_neighbours( stage )
+    }
+
+    fn generate_stage( &self, stage:GenerationStage, dataset:&mut VoxelDataset, chunk:&mut dyn WorldHolding, origin:(i64, i64, i64), neighbours:&StageNeighbours ) {
+        (**self).generate_stage( stage, dataset, chunk, origin, neighbours )
+    }
+
+    fn get_heightmap_cache_stats( &self ) -> Option<HeightmapCacheStats> {
+        (**self).get_heightmap_cache_stats()
+    }
+
+    fn set_heightmap_cache_capacity( &self, columns:usize ) {
+        (**self).set_heightmap_cache_capacity( columns )
+    }
+}
+
+/// Runs all stages of the generator on a single chunk, with no access to neighbours
+#[allow(dead_code)]
+pub fn generate_chunk_stages( generator:&dyn WorldGenerative, dataset:&mut VoxelDataset, origin:(i64, i64, i64), size:u8 ) -> Octree<Voxel> {
+    let mut chunk = Octree::from_max_size( size as u32 );
+    generate_chunk_stages_into( generator, dataset, &mut chunk, origin, size );
+    chunk
+}
+
+/// `generate_chunk_stages` writing into any structure
+#[allow(dead_code)]
+pub fn generate_chunk_stages_into( generator:&dyn WorldGenerative, dataset:&mut VoxelDataset, world_holder:&mut dyn WorldHolding, origin:(i64, i64, i64), size:u8 ) {
+    let neighbours = StageNeighbours::empty( size as u32 );
+    let mut stage = GenerationStage::Terrain;
+
+    generator.generate_chunk_into( dataset, world_holder, origin, size );
+
+    while let Some( next_stage ) = stage.get_next().filter( |next_stage| *next_stage <= generator.get_last_stage() ) {
+        generator.generate_stage( next_stage, dataset, world_holder, origin, &neighbours );
+        stage = next_stage;
+    }
 }
@@ -1,13 +1,23 @@
 use std::{
-    collections::HashMap, rc::Rc,
+    collections::{ HashMap, HashSet, VecDeque },
     sync::{ Arc }
 };
 
 use cgmath::Vector3;
 
-use crate::{ rendering::vertex::Vec3, world::world_chunk::ChunkBitmask };
+use crate::{
+    rendering::vertex::Vec3,
+    world::{
+        memory_footprint::{ add_shared_footprint, add_shared_map_footprint, Footprint, MemoryFootprint, SharedAllocations },
+        world_chunk::ChunkBitmask,
+        world_light::{ get_light_brightness, MAX_LIGHT_LEVEL }
+    }
+};
 
 pub type Coordinate = u32;
+pub type Coordinates = (Coordinate, Coordinate, Coordinate);
+/// Cube of the same voxel as (origin, size, voxel)
+pub type VoxelLeaf = ((Coordinate, Coordinate, Coordinate), Coordinate, Arc<Voxel>);
 
 pub struct VoxelDataset {
     pub materials: HashMap<String, Arc<Material>>,
@@ -33,47 +43,54 @@ impl VoxelDataset {
         self.voxels.extend( dataset.voxels );
     }
 
-    pub fn get_size( &self ) {
-        println!( "TestDataset sizes (in bytes by default)" );
-
-        println!(
-            " - rc size = {};  hashmap of colors size = {}",
-            size_of::<Rc<Voxel>>(),
-            size_of::<HashMap<String, Rc<Color>>>(),
-        );
+    /// Voxels of the dataset by their material and colour, whatever keys they were added under
+    pub fn get_voxel_lookup( &self ) -> HashMap<(Material, Color), Arc<Voxel>> {
+        self.voxels.values()
+            .map( |voxel| ((*voxel.get_material(), *voxel._common_data.color), Arc::clone( voxel )) )
+            .collect()
+    }
 
-        println!(
-            " - colors = {};  color size = {}",
-            self.colors.len(),
-            size_of::<Color>(),
-        );
+    pub fn get_size( &self ) {
+        let footprint = self.get_footprint();
 
+        println!( "VoxelDataset sizes (in bytes by default)" );
         println!(
-            " - materials = {};  material size = {}",
-            self.materials.len(),
-            size_of::<Material>(),
+            " - materials = {};  colors = {};  common_data = {};  voxels = {}",
+            self.materials.len(), self.colors.len(), self.common_voxel_dataset.len(), self.voxels.len(),
         );
+        println!( " - {:?}, total = {}", footprint, footprint.get_total() );
+    }
+}
 
-        println!(
-            " - common_data = {};  common_data size = {}",
-            self.common_voxel_dataset.len(),
-            size_of::<CommonVoxelData>(),
-        );
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Material {
+    pub _density: u32,
+    /// Block light level emitted by the voxel (0 - 15)
+    pub emission: u8,
+    /// Light levels absorbed by the voxel (0 - 15). Anything below 15 is transparent and does not hide faces behind it
+    pub opacity: u8,
+    /// Non solid voxels (like water) can't be targeted and are replaced when placing voxels
+    pub is_solid: bool,
+}
 
-        println!(
-            " - voxels = {};   voxel size = {}",
-            self.voxels.len(),
-            size_of::<Voxel>(),
-        );
+impl Material {
+    pub fn is_transparent( &self ) -> bool {
+        self.opacity < MAX_LIGHT_LEVEL
     }
 }
 
-#[derive(Debug)]
-pub struct Material {
-    pub _density: u32
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            _density: 0,
+            emission: 0,
+            opacity: MAX_LIGHT_LEVEL,
+            is_solid: true,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[allow(dead_code)]
 pub struct Color {
     pub red: u8,
@@ -100,10 +117,45 @@ pub struct CommonVoxelData {
 
 #[derive(Debug)]
 pub struct Voxel {
-    pub _individual_data: Vec<String>,
     pub _common_data: Arc<CommonVoxelData>,
 }
 
+impl MemoryFootprint for VoxelDataset {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        add_shared_map_footprint( &self.materials, footprint, seen );
+        add_shared_map_footprint( &self.colors, footprint, seen );
+        add_shared_map_footprint( &self.common_voxel_dataset, footprint, seen );
+        add_shared_map_footprint( &self.voxels, footprint, seen );
+    }
+}
+
+impl MemoryFootprint for Material {
+    fn add_heap_footprint( &self, _footprint:&mut Footprint, _seen:&mut SharedAllocations ) {}
+}
+
+impl MemoryFootprint for Color {
+    fn add_heap_footprint( &self, _footprint:&mut Footprint, _seen:&mut SharedAllocations ) {}
+}
+
+impl MemoryFootprint for CommonVoxelData {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        add_shared_footprint( &self.material, footprint, seen );
+        add_shared_footprint( &self.color, footprint, seen );
+    }
+}
+
+impl MemoryFootprint for Voxel {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        add_shared_footprint( &self._common_data, footprint, seen );
+    }
+}
+
+impl Voxel {
+    pub fn get_material( &self ) -> &Material {
+        &self._common_data.material
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct VoxelSide {
@@ -126,9 +178,24 @@ impl VoxelSide {
         }
     }
 
+    pub fn with_light( mut self, light_level:u8 ) -> Self {
+        let brightness = get_light_brightness( light_level );
+
+        self.color = Color {
+            red: (self.color.red as f32 * brightness) as u8,
+            green: (self.color.green as f32 * brightness) as u8,
+            blue: (self.color.blue as f32 * brightness) as u8,
+        };
+
+        self
+    }
+
     pub fn get_color( &self ) -> Color {
         self.color.clone()
     }
+    pub fn get_direction( &self ) -> u8 {
+        self.direction
+    }
     pub fn get_position( &self ) -> Vec3 {
         self.pos.clone()
     }
@@ -140,16 +207,45 @@ impl VoxelSide {
 }
 
 #[allow(dead_code)]
-pub trait WorldHolding {
+pub trait WorldHolding: MemoryFootprint {
     fn get_voxel( &self, x:Coordinate, y:Coordinate, z:Coordinate ) -> Option<Arc<Voxel>>;
     fn get_all_voxels( &self ) -> Vec<(u32, u32, u32, Arc<Voxel>)>;
     fn get_all_visible_voxels_from( &self, from:(Coordinate, Coordinate, Coordinate) ) -> Vec<VoxelSide>;
 
+    /// Structures without cubes of the same voxel return every voxel as a cube of size 1
+    fn get_all_leaves( &self ) -> Vec<VoxelLeaf> {
+        self.get_all_voxels().into_iter().map( |(x, y, z, voxel)| ((x, y, z), 1, voxel) ).collect()
+    }
+
     fn set_voxel( &mut self, x:Coordinate, y:Coordinate, z:Coordinate, voxel:Option<Arc<Voxel>> );
     fn fill_voxels( &mut self, from:(Coordinate, Coordinate, Coordinate), to:(Coordinate, Coordinate, Coordinate), voxel:Option<Arc<Voxel>> );
 
-    fn to_bitmask( &self ) -> ChunkBitmask;
-    fn get_size( &self );
+    /// Opaque voxels of a world chunk
+    fn to_bitmask( &self ) -> ChunkBitmask {
+        ChunkBitmask::from_leaves( self.get_all_leaves(), |voxel| !voxel.get_material().is_transparent() )
+    }
+
+    /// Transparent voxels of a world chunk
+    fn to_transparents_bitmask( &self ) -> ChunkBitmask {
+        ChunkBitmask::from_leaves( self.get_all_leaves(), |voxel| voxel.get_material().is_transparent() )
+    }
+
+    /// Voxels of non solid materials (like water) of a world chunk
+    fn to_non_solids_bitmask( &self ) -> ChunkBitmask {
+        ChunkBitmask::from_leaves( self.get_all_leaves(), |voxel| !voxel.get_material().is_solid )
+    }
+
+    /// Prints the memory footprint of the structure and returns its total size in bytes
+    fn get_size( &self ) -> usize {
+        let footprint = self.get_footprint();
+
+        println!( " - inline = {}", footprint.inline );
+        println!( " - heap = {}", self.get_bytes_with_prefixes( footprint.heap ) );
+        println!( " - shared = {}", self.get_bytes_with_prefixes( footprint.shared ) );
+        println!( " - full size = {}", self.get_bytes_with_prefixes( footprint.get_total() ) );
+
+        footprint.get_total()
+    }
     fn get_bytes_with_prefixes( &self, bytes:usize ) -> String {
         match bytes {
             size if size / 1024 / 1024 / 1024 > 0 => format!( "{size} B = {} KiB = {} MiB = {} GiB", size / 1024, size / 1024 / 1024, size / 1024 / 1024 / 1024 ),
@@ -160,9 +256,142 @@ pub trait WorldHolding {
     }
 }
 
+/// Structure of world chunk voxels, shared between the chunk workers
+pub trait ChunkHolding: WorldHolding + Send + Sync {
+    fn clone_boxed( &self ) -> Box<dyn ChunkHolding>;
+}
+
+impl<T:WorldHolding + Clone + Send + Sync + 'static> ChunkHolding for T {
+    fn clone_boxed( &self ) -> Box<dyn ChunkHolding> {
+        Box::new( self.clone() )
+    }
+}
+
+/// Drops the writes outside of a chunk, like the octree does, so structures without bounds keep only the chunk voxels
+pub struct ClippedHolder<'a> {
+    world_holder: &'a mut dyn WorldHolding,
+    size: Coordinate,
+}
+
+impl<'a> ClippedHolder<'a> {
+    pub fn new( world_holder:&'a mut dyn WorldHolding, size:Coordinate ) -> Self {
+        Self { world_holder, size }
+    }
+}
+
+impl MemoryFootprint for ClippedHolder<'_> {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, seen:&mut SharedAllocations ) {
+        self.world_holder.add_heap_footprint( footprint, seen );
+    }
+}
+
+impl WorldHolding for ClippedHolder<'_> {
+    fn get_voxel( &self, x:Coordinate, y:Coordinate, z:Coordinate ) -> Option<Arc<Voxel>> {
+        self.world_holder.get_voxel( x, y, z )
+    }
+
+    fn get_all_voxels( &self ) -> Vec<(u32, u32, u32, Arc<Voxel>)> {
+        self.world_holder.get_all_voxels()
+    }
+
+    fn get_all_visible_voxels_from( &self, from:(Coordinate, Coordinate, Coordinate) ) -> Vec<VoxelSide> {
+        self.world_holder.get_all_visible_voxels_from( from )
+    }
+
+    fn get_all_leaves( &self ) -> Vec<VoxelLeaf> {
+        self.world_holder.get_all_leaves()
+    }
+
+    fn set_voxel( &mut self, x:Coordinate, y:Coordinate, z:Coordinate, voxel:Option<Arc<Voxel>> ) {
+        if x < self.size && y < self.size && z < self.size {
+            self.world_holder.set_voxel( x, y, z, voxel );
+        }
+    }
+
+    fn fill_voxels( &mut self, from:(Coordinate, Coordinate, Coordinate), to:(Coordinate, Coordinate, Coordinate), voxel:Option<Arc<Voxel>> ) {
+        if let Some( (from, to) ) = get_clipped_range( from, to, self.size ) {
+            self.world_holder.fill_voxels( from, to, voxel );
+        }
+    }
+
+    fn to_bitmask( &self ) -> ChunkBitmask {
+        self.world_holder.to_bitmask()
+    }
+
+    fn to_transparents_bitmask( &self ) -> ChunkBitmask {
+        self.world_holder.to_transparents_bitmask()
+    }
+
+    fn to_non_solids_bitmask( &self ) -> ChunkBitmask {
+        self.world_holder.to_non_solids_bitmask()
+    }
+}
+
+/// Part of the fill from `from` to `to` inside of a chunk, `None` when the fill misses it
+pub fn get_clipped_range( from:Coordinates, to:Coordinates, chunk_size:Coordinate ) -> Option<(Coordinates, Coordinates)> {
+    let max = chunk_size - 1;
+    let (from, to) = (
+        (from.0.min( to.0 ), from.1.min( to.1 ), from.2.min( to.2 )),
+        (from.0.max( to.0 ).min( max ), from.1.max( to.1 ).min( max ), from.2.max( to.2 ).min( max )),
+    );
+
+    (from.0 <= to.0 && from.1 <= to.1 && from.2 <= to.2).then_some( (from, to) )
+}
+
+/// Sides of voxels facing the empty space reachable from `from`, found by flooding single empty voxels inside of the area of `size`.
+/// Sides are turned in directions 1 - 6 (-x, +x, -y, +y, -z, +z), nothing is visible when `from` isn't empty
+pub fn get_visible_sides_with_flood( world_holder:&(impl WorldHolding + ?Sized), size:(Coordinate, Coordinate, Coordinate), from:(Coordinate, Coordinate, Coordinate) ) -> Vec<VoxelSide> {
+    const STEPS: [(i64, i64, i64); 6] = [ (-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1) ];
+
+    let is_inside = |position:(Coordinate, Coordinate, Coordinate)| position.0 < size.0 && position.1 < size.1 && position.2 < size.2;
+
+    if !is_inside( from ) || world_holder.get_voxel( from.0, from.1, from.2 ).is_some() {
+        return vec![]
+    }
+
+    let mut visited = HashSet::from([ from ]);
+    let mut positions = VecDeque::from([ from ]);
+    let mut sides = vec![];
+
+    while let Some( position ) = positions.pop_front() {
+        for (step_index, step) in STEPS.iter().enumerate() {
+            let neighbour = (position.0 as i64 + step.0, position.1 as i64 + step.1, position.2 as i64 + step.2);
+
+            if neighbour.0 < 0 || neighbour.1 < 0 || neighbour.2 < 0 {
+                continue
+            }
+
+            let neighbour = (neighbour.0 as Coordinate, neighbour.1 as Coordinate, neighbour.2 as Coordinate);
+
+            if !is_inside( neighbour ) {
+                continue
+            }
+
+            match world_holder.get_voxel( neighbour.0, neighbour.1, neighbour.2 ) {
+                // Side of the neighbour is turned back, to the flooded voxel
+                Some( voxel ) => sides.push( VoxelSide::from_voxel_rc( neighbour.0 as i64, neighbour.1 as i64, neighbour.2 as i64, (step_index ^ 1) as u8 + 1, &voxel ) ),
+                None => if visited.insert( neighbour ) {
+                    positions.push_back( neighbour );
+                },
+            }
+        }
+    }
+
+    sides
+}
+
+/// Area spanned by the voxels and the flood start, for structures without fixed bounds
+pub fn get_flood_size( positions:impl Iterator<Item=(Coordinate, Coordinate, Coordinate)>, from:(Coordinate, Coordinate, Coordinate) ) -> (Coordinate, Coordinate, Coordinate) {
+    positions.fold( (from.0 + 1, from.1 + 1, from.2 + 1), |size, position| (
+        size.0.max( position.0 + 1 ),
+        size.1.max( position.1 + 1 ),
+        size.2.max( position.2 + 1 ),
+    ) )
+}
+
 #[allow(dead_code)]
 pub fn fill_with( from:(u32, u32, u32), to:(u32, u32, u32), world_holder:&mut dyn WorldHolding, setup:(&str, Color) ) -> VoxelDataset {
-    let materials = HashMap::from([ (setup.0.to_string(), Arc::new( Material { _density:100 } )) ]);
+    let materials = HashMap::from([ (setup.0.to_string(), Arc::new( Material { _density:100, ..Default::default() } )) ]);
     let colors = HashMap::from([ (setup.0.to_string(), Arc::new( setup.1 )) ]);
 
     let common_voxel_dataset = HashMap::from([ (setup.0.to_string(), Arc::new( CommonVoxelData {
@@ -172,7 +401,6 @@ pub fn fill_with( from:(u32, u32, u32), to:(u32, u32, u32), world_holder:&mut dy
 
     let voxels = HashMap::from([ (setup.0.to_string(), Arc::new( Voxel {
         _common_data: common_voxel_dataset.get( setup.0 ).unwrap().clone(),
-        _individual_data: vec![],
     }) ) ]);
 
     let voxel = voxels.get( setup.0 ).unwrap();
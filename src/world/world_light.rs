@@ -0,0 +1,710 @@
+use std::{
+    collections::{ BTreeMap, HashMap, HashSet, VecDeque },
+    sync::{ RwLockReadGuard, RwLockWriteGuard }
+};
+
+use crate::world::{
+    chunks_map::{ ChunkLock, ChunksMap },
+    memory_footprint::{ get_vec_heap, Footprint, MemoryFootprint, SharedAllocations },
+    world::{ GridPosition, VoxelPosition, World, CHUNK_SIZE, CHUNK_SIZE_X2, CHUNK_SIZE_X3 },
+    world_chunk::{ ChunkBitmask, WorldChunk },
+    world_holder::WorldHolding
+};
+
+pub const MAX_LIGHT_LEVEL: u8 = 15;
+const MIN_BRIGHTNESS: f32 = 0.08;
+const BRIGHTNESS_FALLOFF: f32 = 0.8;
+
+/// (neighbour index in remesh neighbours list, axis, our border layer, neighbour border layer)
+const LIGHT_NEIGHBOURS: [(usize, usize, usize, usize); 6] = [
+    (13, 0, CHUNK_SIZE - 1, 0), // right
+    (12, 0, 0, CHUNK_SIZE - 1), // left
+    (21, 1, CHUNK_SIZE - 1, 0), // top
+    ( 4, 1, 0, CHUNK_SIZE - 1), // bottom
+    (15, 2, CHUNK_SIZE - 1, 0), // front
+    (10, 2, 0, CHUNK_SIZE - 1), // back
+];
+
+const TOP_NEIGHBOUR: usize = 2;
+
+/// Steps to the six neighbouring cells, in the order used by `ChunkLight::flood`
+const LIGHT_DIRECTIONS: [(i64, i64, i64); 6] = [ (-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1) ];
+const DOWN_DIRECTION: usize = 2;
+
+/// Light volume of a single chunk. Every cell keeps sunlight in the high nibble and block light in the low one.
+pub struct ChunkLight {
+    data: Vec<u8>,
+}
+
+impl ChunkLight {
+    pub fn get_sunlight( &self, x:usize, y:usize, z:usize ) -> u8 {
+        self.data[ Self::get_index( x, y, z ) ] >> 4
+    }
+
+    pub fn get_block_light( &self, x:usize, y:usize, z:usize ) -> u8 {
+        self.data[ Self::get_index( x, y, z ) ] & 0x0F
+    }
+
+    pub fn set_sunlight( &mut self, x:usize, y:usize, z:usize, level:u8 ) {
+        let cell = &mut self.data[ Self::get_index( x, y, z ) ];
+        *cell = (level << 4) | (*cell & 0x0F);
+    }
+
+    pub fn set_block_light( &mut self, x:usize, y:usize, z:usize, level:u8 ) {
+        let cell = &mut self.data[ Self::get_index( x, y, z ) ];
+        *cell = (*cell & 0xF0) | level;
+    }
+
+    pub fn get_level( &self, x:usize, y:usize, z:usize ) -> u8 {
+        let cell = self.data[ Self::get_index( x, y, z ) ];
+        (cell >> 4).max( cell & 0x0F )
+    }
+
+    /// Calculates light of the chunk with BFS flood-fill.
+    /// Sunlight comes from the top neighbour (or from the sky if the neighbour is not lit yet),
+    /// both sunlight and block light leak through all six borders from lit neighbours.
+    /// Opaque voxels block the light, transparent ones dim it by their opacity.
+    pub fn calculate( data:&dyn WorldHolding, opaque_mask:&ChunkBitmask, neighbours:&[RwLockReadGuard<'_, WorldChunk>] ) -> Self {
+        let mut sunlight = vec![ 0; CHUNK_SIZE_X3 ];
+        let mut block_light = vec![ 0; CHUNK_SIZE_X3 ];
+        let mut sun_queue = VecDeque::new();
+        let mut block_queue = VecDeque::new();
+        let mut opacities = vec![ 0; CHUNK_SIZE_X3 ];
+        let leaves = data.get_all_leaves();
+
+        for ((x, y, z), size, voxel) in &leaves {
+            let material = voxel.get_material();
+            if !material.is_transparent() || material.opacity == 0 { continue }
+
+            Self::for_each_cell( (*x, *y, *z), *size, |index| opacities[ index ] = material.opacity );
+        }
+
+        for (side, (neighbour_index, axis, layer, neighbour_layer)) in LIGHT_NEIGHBOURS.into_iter().enumerate() {
+            let neighbour = &neighbours[ neighbour_index ];
+            let neighbour_light = neighbour.get_light();
+            let neighbour_mask = neighbour.get_opaque_mask();
+
+            for a in 0..CHUNK_SIZE {
+                for b in 0..CHUNK_SIZE {
+                    let (x, y, z) = Self::get_border_cell( axis, layer, a, b );
+                    if Self::is_solid( opaque_mask, x, y, z ) { continue }
+
+                    let (nx, ny, nz) = Self::get_border_cell( axis, neighbour_layer, a, b );
+                    let index = Self::get_index( x, y, z );
+
+                    let (neighbour_sunlight, neighbour_block_light) = match (neighbour_light, neighbour_mask) {
+                        (Some( light ), _) => (light.get_sunlight( nx, ny, nz ), light.get_block_light( nx, ny, nz )),
+
+                        // Not lit yet neighbour above lets the sun in through its empty columns
+                        (None, Some( mask )) if side == TOP_NEIGHBOUR => {
+                            let column = mask.data[ nx + (nz * CHUNK_SIZE) + CHUNK_SIZE_X2 ];
+                            (if column == 0 { MAX_LIGHT_LEVEL } else { 0 }, 0)
+                        }
+
+                        (None, None) if side == TOP_NEIGHBOUR => (MAX_LIGHT_LEVEL, 0),
+                        _ => (0, 0),
+                    };
+
+                    let sun = if side == TOP_NEIGHBOUR && neighbour_sunlight == MAX_LIGHT_LEVEL {
+                        MAX_LIGHT_LEVEL - opacities[ index ]
+                    } else {
+                        neighbour_sunlight.saturating_sub( 1 + opacities[ index ] )
+                    };
+
+                    if sun > sunlight[ index ] {
+                        sunlight[ index ] = sun;
+                        sun_queue.push_back( index );
+                    }
+
+                    let block = neighbour_block_light.saturating_sub( 1 + opacities[ index ] );
+                    if block > block_light[ index ] {
+                        block_light[ index ] = block;
+                        block_queue.push_back( index );
+                    }
+                }
+            }
+        }
+
+        for ((x, y, z), size, voxel) in leaves {
+            let emission = voxel.get_material().emission.min( MAX_LIGHT_LEVEL );
+            if emission == 0 { continue }
+
+            Self::for_each_cell( (x, y, z), size, |index| {
+                if emission > block_light[ index ] {
+                    block_light[ index ] = emission;
+                    block_queue.push_back( index );
+                }
+            } );
+        }
+
+        Self::flood( &mut sunlight, sun_queue, opaque_mask, &opacities, true );
+        Self::flood( &mut block_light, block_queue, opaque_mask, &opacities, false );
+
+        Self {
+            data: sunlight.into_iter().zip( block_light ).map( |(sun, block)| (sun << 4) | block ).collect()
+        }
+    }
+
+    /// Light level of the cell in front of the voxel face. Direction follows `VoxelSide` directions (1 - 6).
+    pub fn get_face_level( &self, neighbours:&[RwLockReadGuard<'_, WorldChunk>], position:(u32, u32, u32), direction:u8 ) -> u8 {
+        let axis = (direction as usize - 1) / 2;
+        let is_positive = direction.is_multiple_of( 2 );
+        let mut cell = [ position.0 as i64, position.1 as i64, position.2 as i64 ];
+
+        cell[ axis ] += if is_positive { 1 } else { -1 };
+
+        if (0..CHUNK_SIZE as i64).contains( &cell[ axis ] ) {
+            return self.get_level( cell[ 0 ] as usize, cell[ 1 ] as usize, cell[ 2 ] as usize )
+        }
+
+        cell[ axis ] = cell[ axis ].rem_euclid( CHUNK_SIZE as i64 );
+
+        let neighbour_index = LIGHT_NEIGHBOURS[ axis * 2 + if is_positive { 0 } else { 1 } ].0;
+
+        match neighbours[ neighbour_index ].get_light() {
+            Some( light ) => light.get_level( cell[ 0 ] as usize, cell[ 1 ] as usize, cell[ 2 ] as usize ),
+            None => MAX_LIGHT_LEVEL,
+        }
+    }
+
+    fn flood( light:&mut [u8], mut queue:VecDeque<usize>, opaque_mask:&ChunkBitmask, opacities:&[u8], is_sunlight:bool ) {
+        while let Some( index ) = queue.pop_front() {
+            let level = light[ index ];
+            if level <= 1 { continue }
+
+            let (x, y, z) = Self::get_position( index );
+            let neighbours = [
+                (x.wrapping_sub( 1 ), y, z),
+                (x + 1,               y, z),
+                (x, y.wrapping_sub( 1 ), z),
+                (x, y + 1,               z),
+                (x, y, z.wrapping_sub( 1 )),
+                (x, y, z + 1              ),
+            ];
+
+            for (direction, (nx, ny, nz)) in neighbours.into_iter().enumerate() {
+                if nx >= CHUNK_SIZE || ny >= CHUNK_SIZE || nz >= CHUNK_SIZE { continue }
+                if Self::is_solid( opaque_mask, nx, ny, nz ) { continue }
+
+                // Full sunlight goes straight down without fading, transparent voxels dim it only by their opacity
+                let next_index = Self::get_index( nx, ny, nz );
+                let opacity = opacities[ next_index ];
+                let next_level = if is_sunlight && direction == 2 && (level == MAX_LIGHT_LEVEL || opacity > 0) {
+                    level.saturating_sub( opacity )
+                } else {
+                    level.saturating_sub( 1 + opacity )
+                };
+
+                if next_level > light[ next_index ] {
+                    light[ next_index ] = next_level;
+                    queue.push_back( next_index );
+                }
+            }
+        }
+    }
+
+    fn for_each_cell( offset:(u32, u32, u32), size:u32, mut processor:impl FnMut( usize ) ) {
+        for x in offset.0..offset.0 + size {
+            for y in offset.1..offset.1 + size {
+                for z in offset.2..offset.2 + size {
+                    processor( Self::get_index( x as usize, y as usize, z as usize ) );
+                }
+            }
+        }
+    }
+
+    fn is_solid( opaque_mask:&ChunkBitmask, x:usize, y:usize, z:usize ) -> bool {
+        (opaque_mask.data[ y + (z * CHUNK_SIZE) ] >> x) & 1 == 1
+    }
+
+    fn get_border_cell( axis:usize, layer:usize, a:usize, b:usize ) -> (usize, usize, usize) {
+        match axis {
+            0 => (layer, a, b),
+            1 => (a, layer, b),
+            _ => (a, b, layer),
+        }
+    }
+
+    fn get_index( x:usize, y:usize, z:usize ) -> usize {
+        x + (y * CHUNK_SIZE) + (z * CHUNK_SIZE_X2)
+    }
+
+    fn get_position( index:usize ) -> (usize, usize, usize) {
+        (index % CHUNK_SIZE, (index / CHUNK_SIZE) % CHUNK_SIZE, index / CHUNK_SIZE_X2)
+    }
+}
+
+impl MemoryFootprint for ChunkLight {
+    fn add_heap_footprint( &self, footprint:&mut Footprint, _seen:&mut SharedAllocations ) {
+        footprint.heap += get_vec_heap( &self.data );
+    }
+}
+
+/// Write locked chunks around an edit. Light is updated only in the lit ones, the rest stop it like the world border
+struct LightVolume<'a> {
+    chunks: HashMap<GridPosition, RwLockWriteGuard<'a, WorldChunk>>,
+    changed: HashSet<GridPosition>,
+}
+
+impl<'a> LightVolume<'a> {
+    /// `locks` are sorted by position, every thread locking more than one chunk takes the locks in that order so none waits for another
+    fn lock( locks:&'a [(GridPosition, ChunkLock)] ) -> Self {
+        let chunks = locks.iter()
+            .map( |(position, lock)| (*position, lock.write().unwrap()) )
+            .collect();
+
+        Self { chunks, changed:HashSet::new() }
+    }
+
+    /// Removes the light of the edited cells and of every cell lit through them, then floods the hole again
+    /// from the brighter cells around it, the voxels emitting light and the open sky above the edited cells
+    fn update( &mut self, edited:&[(VoxelPosition, VoxelPosition)], is_sunlight:bool ) {
+        let mut removal = VecDeque::new();
+        let mut addition = VecDeque::new();
+
+        for &(min, max) in edited {
+            for x in min.0..=max.0 {
+                for y in min.1..=max.1 {
+                    for z in min.2..=max.2 {
+                        let position = (x, y, z);
+                        let Some( level ) = self.get_light( position, is_sunlight ) else { continue };
+
+                        self.set_light( position, is_sunlight, 0 );
+                        removal.push_back( (position, level) );
+
+                        let source = if is_sunlight { self.get_sky_light( position ) } else { self.get_emission( position ) };
+                        self.add_source( position, source, is_sunlight, &mut addition );
+                    }
+                }
+            }
+        }
+
+        while let Some( (position, level) ) = removal.pop_front() {
+            for (direction, step) in LIGHT_DIRECTIONS.into_iter().enumerate() {
+                let next = (position.0 + step.0, position.1 + step.1, position.2 + step.2);
+                let Some( next_level ) = self.get_light( next, is_sunlight ) else { continue };
+                if next_level == 0 { continue }
+
+                // Darker cells and full sunlight below were lit by the removed cell, brighter ones light the hole again
+                let is_sunlit_below = is_sunlight && direction == DOWN_DIRECTION && level == MAX_LIGHT_LEVEL;
+
+                if next_level < level || is_sunlit_below {
+                    self.set_light( next, is_sunlight, 0 );
+                    removal.push_back( (next, next_level) );
+
+                    if !is_sunlight {
+                        let emission = self.get_emission( next );
+                        self.add_source( next, emission, false, &mut addition );
+                    }
+                } else {
+                    addition.push_back( next );
+                }
+            }
+        }
+
+        self.flood( addition, is_sunlight );
+    }
+
+    /// Spreads the light of the queued cells to every darker cell it can reach
+    fn flood( &mut self, mut addition:VecDeque<VoxelPosition>, is_sunlight:bool ) {
+        while let Some( position ) = addition.pop_front() {
+            let Some( level ) = self.get_light( position, is_sunlight ) else { continue };
+            if level <= 1 { continue }
+
+            for (direction, step) in LIGHT_DIRECTIONS.into_iter().enumerate() {
+                let next = (position.0 + step.0, position.1 + step.1, position.2 + step.2);
+                let (Some( next_level ), Some( opacity )) = (self.get_light( next, is_sunlight ), self.get_opacity( next )) else { continue };
+
+                let lit_level = if is_sunlight && direction == DOWN_DIRECTION && (level == MAX_LIGHT_LEVEL || opacity > 0) {
+                    level.saturating_sub( opacity )
+                } else {
+                    level.saturating_sub( 1 + opacity )
+                };
+
+                if lit_level > next_level {
+                    self.set_light( next, is_sunlight, lit_level );
+                    addition.push_back( next );
+                }
+            }
+        }
+    }
+
+    fn add_source( &mut self, position:VoxelPosition, level:u8, is_sunlight:bool, addition:&mut VecDeque<VoxelPosition> ) {
+        if level == 0 || self.get_light( position, is_sunlight ).is_none_or( |current| current >= level ) { return }
+
+        self.set_light( position, is_sunlight, level );
+        addition.push_back( position );
+    }
+
+    fn get_cell( &self, position:VoxelPosition ) -> Option<(&WorldChunk, (usize, usize, usize))> {
+        let (chunk_position, local) = World::split_voxel_position( position );
+        let chunk = self.chunks.get( &chunk_position )?;
+
+        Some( (chunk, (local.0 as usize, local.1 as usize, local.2 as usize)) )
+    }
+
+    /// `None` outside of the lit chunks
+    fn get_light( &self, position:VoxelPosition, is_sunlight:bool ) -> Option<u8> {
+        let (chunk, (x, y, z)) = self.get_cell( position )?;
+        let light = chunk.get_light()?;
+
+        Some( if is_sunlight { light.get_sunlight( x, y, z ) } else { light.get_block_light( x, y, z ) } )
+    }
+
+    /// Marks also the neighbouring chunks touching the cell, their faces are lit by it
+    fn set_light( &mut self, position:VoxelPosition, is_sunlight:bool, level:u8 ) {
+        let (chunk_position, local) = World::split_voxel_position( position );
+        let (x, y, z) = (local.0 as usize, local.1 as usize, local.2 as usize);
+        let Some( light ) = self.chunks.get_mut( &chunk_position ).and_then( |chunk| chunk.get_light_mut() ) else { return };
+
+        if is_sunlight {
+            light.set_sunlight( x, y, z, level );
+        } else {
+            light.set_block_light( x, y, z, level );
+        }
+
+        self.changed.insert( chunk_position );
+
+        for (axis, coordinate) in [ x, y, z ].into_iter().enumerate() {
+            let mut neighbour = [ chunk_position.0, chunk_position.1, chunk_position.2 ];
+
+            if coordinate == 0 {
+                neighbour[ axis ] -= 1;
+            } else if coordinate == CHUNK_SIZE - 1 {
+                neighbour[ axis ] += 1;
+            } else {
+                continue
+            }
+
+            self.changed.insert( (neighbour[ 0 ], neighbour[ 1 ], neighbour[ 2 ]) );
+        }
+    }
+
+    /// Light absorbed by the cell, `None` for opaque cells and cells outside of the lit chunks
+    fn get_opacity( &self, position:VoxelPosition ) -> Option<u8> {
+        let (chunk, (x, y, z)) = self.get_cell( position )?;
+        chunk.get_light()?;
+
+        if ChunkLight::is_solid( chunk.get_opaque_mask()?, x, y, z ) { return None }
+
+        Some( chunk.get_voxel( x as u32, y as u32, z as u32 ).map_or( 0, |voxel| voxel.get_material().opacity ) )
+    }
+
+    fn get_emission( &self, position:VoxelPosition ) -> u8 {
+        let Some( (chunk, (x, y, z)) ) = self.get_cell( position ) else { return 0 };
+
+        chunk.get_voxel( x as u32, y as u32, z as u32 ).map_or( 0, |voxel| voxel.get_material().emission.min( MAX_LIGHT_LEVEL ) )
+    }
+
+    /// Sunlight falling into the cell from above when there is no lit chunk above it, like `ChunkLight::calculate` does
+    fn get_sky_light( &self, position:VoxelPosition ) -> u8 {
+        let above = (position.0, position.1 + 1, position.2);
+        if self.get_light( above, true ).is_some() { return 0 }
+
+        if let Some( (chunk, (x, y, z)) ) = self.get_cell( above ) {
+            if chunk.get_opaque_mask().is_some_and( |mask| ChunkLight::is_solid( mask, x, y, z ) ) { return 0 }
+        }
+
+        self.get_opacity( position ).map_or( 0, |opacity| MAX_LIGHT_LEVEL - opacity )
+    }
+}
+
+/// Updates the light of the lit chunks around the edited boxes of voxels and returns the chunks whose faces
+/// see the changed light. Only the light reachable from the edits is removed and flooded again, across chunk borders
+pub fn update_light( chunks:&ChunksMap, edited:&[(VoxelPosition, VoxelPosition)] ) -> HashSet<GridPosition> {
+    let locks = get_light_region( chunks, edited ).into_iter().collect::<Vec<_>>();
+    let mut volume = LightVolume::lock( &locks );
+
+    volume.update( edited, true );
+    volume.update( edited, false );
+
+    volume.changed
+}
+
+/// Spreads the light of a newly lit chunk into its lit neighbours and theirs into it, across the six shared faces.
+/// Returns the chunks whose faces see the changed light
+pub fn spread_light_from_chunk( chunks:&ChunksMap, chunk_position:GridPosition ) -> HashSet<GridPosition> {
+    let size = CHUNK_SIZE as i64;
+    let min = [ chunk_position.0 * size, chunk_position.1 * size, chunk_position.2 * size ];
+    let max = min.map( |coordinate| coordinate + size - 1 );
+
+    let locks = get_light_region( chunks, &[ ((min[ 0 ], min[ 1 ], min[ 2 ]), (max[ 0 ], max[ 1 ], max[ 2 ])) ] ).into_iter().collect::<Vec<_>>();
+    let mut volume = LightVolume::lock( &locks );
+
+    // Border layers on both sides of every face
+    let mut seams = Vec::with_capacity( 6 * 2 * CHUNK_SIZE_X2 );
+
+    for axis in 0..3 {
+        let (axis_a, axis_b) = ((axis + 1) % 3, (axis + 2) % 3);
+
+        for layer in [ min[ axis ] - 1, min[ axis ], max[ axis ], max[ axis ] + 1 ] {
+            for a in min[ axis_a ]..=max[ axis_a ] {
+                for b in min[ axis_b ]..=max[ axis_b ] {
+                    let mut position = [ 0; 3 ];
+                    position[ axis ] = layer;
+                    position[ axis_a ] = a;
+                    position[ axis_b ] = b;
+
+                    seams.push( (position[ 0 ], position[ 1 ], position[ 2 ]) );
+                }
+            }
+        }
+    }
+
+    volume.flood( seams.iter().copied().collect(), true );
+    volume.flood( seams.into_iter().collect(), false );
+
+    volume.changed
+}
+
+/// Chunks the light of the edits can reach. Sunlight blocked by an edit goes dark down to the bottom of the loaded column
+fn get_light_region( chunks:&ChunksMap, edited:&[(VoxelPosition, VoxelPosition)] ) -> BTreeMap<GridPosition, ChunkLock> {
+    let margin = MAX_LIGHT_LEVEL as i64;
+    let mut region = BTreeMap::new();
+
+    for &(min, max) in edited {
+        let chunk_min = World::split_voxel_position( (min.0 - margin, min.1 - margin, min.2 - margin) ).0;
+        let chunk_max = World::split_voxel_position( (max.0 + margin, max.1 + margin, max.2 + margin) ).0;
+
+        for x in chunk_min.0..=chunk_max.0 {
+            for z in chunk_min.2..=chunk_max.2 {
+                let mut y = chunk_max.1;
+
+                while y >= chunk_min.1 || region.contains_key( &(x, y + 1, z) ) {
+                    match chunks.get( &(x, y, z) ) {
+                        Some( chunk ) => { region.insert( (x, y, z), chunk ); }
+                        None if y < chunk_min.1 => break,
+                        None => {}
+                    }
+
+                    y -= 1;
+                }
+            }
+        }
+    }
+
+    region
+}
+
+/// Maps light level into colour multiplier. Every missing level dims the colour a bit more.
+pub fn get_light_brightness( level:u8 ) -> f32 {
+    let falloff = BRIGHTNESS_FALLOFF.powi( (MAX_LIGHT_LEVEL - level.min( MAX_LIGHT_LEVEL )) as i32 );
+    MIN_BRIGHTNESS + (1.0 - MIN_BRIGHTNESS) * falloff
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{ Arc, RwLock };
+
+    use super::*;
+    use crate::{
+        structure_tests::octree::Octree,
+        world::{
+            world_holder::{ Color, CommonVoxelData, Material, Voxel },
+            world_chunk::WorldChunk
+        }
+    };
+
+    fn create_voxel( emission:u8 ) -> Arc<Voxel> {
+        create_voxel_of( Material { emission, ..Default::default() } )
+    }
+
+    fn create_voxel_of( material:Material ) -> Arc<Voxel> {
+        Arc::new( Voxel {
+            _common_data: Arc::new( CommonVoxelData {
+                material: Arc::new( material ),
+                color: Arc::new( Color { red:100, green:100, blue:100 } ),
+            } ),
+        } )
+    }
+
+    fn calculate( octree:&Octree<Voxel> ) -> ChunkLight {
+        let neighbours = (0..26).map( |_| RwLock::new( WorldChunk::new_disabled() ) ).collect::<Vec<_>>();
+        let guards = neighbours.iter().map( |n| n.read().unwrap() ).collect::<Vec<_>>();
+
+        ChunkLight::calculate( octree, &octree.to_bitmask(), &guards )
+    }
+
+    #[test]
+    fn test_sunlight_falls_down_without_fading() {
+        let mut octree = Octree::from_max_size( CHUNK_SIZE as u32 );
+        octree.fill_voxels( (0, 0, 0), (63, 9, 63), Some( create_voxel( 0 ) ) );
+
+        let light = calculate( &octree );
+
+        assert_eq!( light.get_sunlight( 5, 10, 5 ), MAX_LIGHT_LEVEL );
+        assert_eq!( light.get_sunlight( 5, 9, 5 ), 0 );
+    }
+
+    #[test]
+    fn test_block_light_under_roof() {
+        let mut octree = Octree::from_max_size( CHUNK_SIZE as u32 );
+        octree.fill_voxels( (0, 40, 0), (63, 40, 63), Some( create_voxel( 0 ) ) );
+        octree.set_voxel( 10, 10, 10, Some( create_voxel( MAX_LIGHT_LEVEL ) ) );
+
+        let light = calculate( &octree );
+
+        assert_eq!( light.get_sunlight( 10, 20, 10 ), 0 );
+        assert_eq!( light.get_block_light( 11, 10, 10 ), MAX_LIGHT_LEVEL - 1 );
+        assert_eq!( light.get_block_light( 13, 10, 10 ), MAX_LIGHT_LEVEL - 3 );
+        assert_eq!( light.get_level( 10, 30, 10 ), 0 );
+    }
+
+    /// Chunks lit separately, as if their neighbours weren't loaded yet
+    fn create_lit_chunks( octrees:Vec<(GridPosition, Octree<Voxel>)> ) -> ChunksMap {
+        let chunks = ChunksMap::new();
+        let neighbours = (0..26).map( |_| RwLock::new( WorldChunk::new_disabled() ) ).collect::<Vec<_>>();
+
+        for (position, octree) in octrees {
+            let mut chunk = WorldChunk::new();
+            chunk.set_data( Box::new( octree ) );
+            chunk.remesh( position, neighbours.iter().map( |n| n.read().unwrap() ).collect() );
+            chunks.insert( position, RwLock::new( chunk ) );
+        }
+
+        chunks
+    }
+
+    fn set_voxel( chunks:&ChunksMap, position:VoxelPosition, voxel:Option<Arc<Voxel>> ) -> HashSet<GridPosition> {
+        let (chunk_position, local) = World::split_voxel_position( position );
+        chunks.get( &chunk_position ).unwrap().write().unwrap().set_voxel( local.0, local.1, local.2, voxel );
+
+        update_light( chunks, &[ (position, position) ] )
+    }
+
+    /// Lights the chunks one by one in the given order, each with the already lit ones around it
+    fn light_in_order( octrees:Vec<(GridPosition, Octree<Voxel>)>, order:&[GridPosition] ) -> ChunksMap {
+        let chunks = ChunksMap::new();
+
+        for (position, octree) in octrees {
+            let mut chunk = WorldChunk::new();
+            chunk.set_data( Box::new( octree ) );
+            chunks.insert( position, RwLock::new( chunk ) );
+        }
+
+        for &position in order {
+            let neighbours = (-1..=1).flat_map( |dy| (-1..=1).flat_map( move |dz| (-1..=1).map( move |dx| (dx, dy, dz) ) ) )
+                .filter( |offset| *offset != (0, 0, 0) )
+                .map( |(dx, dy, dz)| chunks.get( &(position.0 + dx, position.1 + dy, position.2 + dz) )
+                    .unwrap_or_else( || Arc::new( RwLock::new( WorldChunk::new_disabled() ) ) ) )
+                .collect::<Vec<_>>();
+
+            let chunk = chunks.get( &position ).unwrap();
+            chunk.write().unwrap().remesh( position, neighbours.iter().map( |n| n.read().unwrap() ).collect() );
+
+            spread_light_from_chunk( &chunks, position );
+        }
+
+        chunks
+    }
+
+    #[test]
+    fn test_light_crosses_chunk_borders_in_both_lighting_orders() {
+        let octrees = || {
+            let mut left_octree = create_roofed_octree();
+            left_octree.set_voxel( 63, 20, 20, Some( create_voxel( MAX_LIGHT_LEVEL ) ) );
+
+            let mut right_octree = create_roofed_octree();
+            right_octree.set_voxel( 0, 30, 30, Some( create_voxel( MAX_LIGHT_LEVEL ) ) );
+            right_octree.fill_voxels( (0, 40, 20), (2, 40, 22), None );
+
+            vec![ ((0, 0, 0), left_octree), ((1, 0, 0), right_octree) ]
+        };
+
+        let left_first = light_in_order( octrees(), &[ (0, 0, 0), (1, 0, 0) ] );
+        let right_first = light_in_order( octrees(), &[ (1, 0, 0), (0, 0, 0) ] );
+
+        for position in [ (0, 0, 0), (1, 0, 0) ] {
+            let left_first = left_first.get( &position ).unwrap();
+            let right_first = right_first.get( &position ).unwrap();
+
+            assert!( left_first.read().unwrap().get_light().unwrap().data == right_first.read().unwrap().get_light().unwrap().data, "light of {position:?} depends on the order" );
+        }
+
+        let left_chunk = left_first.get( &(0, 0, 0) ).unwrap();
+        let left_light = left_chunk.read().unwrap();
+        let right_chunk = left_first.get( &(1, 0, 0) ).unwrap();
+        let right_light = right_chunk.read().unwrap();
+
+        assert_eq!( right_light.get_light().unwrap().get_block_light( 0, 20, 20 ), MAX_LIGHT_LEVEL - 1 );
+        assert_eq!( left_light.get_light().unwrap().get_block_light( 63, 30, 30 ), MAX_LIGHT_LEVEL - 1 );
+        assert_eq!( left_light.get_light().unwrap().get_sunlight( 63, 39, 21 ), MAX_LIGHT_LEVEL - 1 );
+    }
+
+    fn create_roofed_octree() -> Octree<Voxel> {
+        let mut octree = Octree::from_max_size( CHUNK_SIZE as u32 );
+        octree.fill_voxels( (0, 40, 0), (63, 40, 63), Some( create_voxel( 0 ) ) );
+        octree
+    }
+
+    #[test]
+    fn test_updated_light_matches_recalculated_light() {
+        let mut octree = create_roofed_octree();
+        octree.set_voxel( 10, 10, 10, Some( create_voxel( MAX_LIGHT_LEVEL ) ) );
+        octree.fill_voxels( (12, 0, 8), (12, 20, 12), Some( create_voxel( 0 ) ) );
+
+        let chunks = create_lit_chunks( vec![ ((0, 0, 0), octree) ] );
+        let edits = [
+            ((30, 40, 30), None),
+            ((12, 10, 10), None),
+            ((20, 10, 20), Some( create_voxel( MAX_LIGHT_LEVEL - 3 ) )),
+            ((10, 10, 10), None),
+            ((30, 40, 30), Some( create_voxel( 0 ) )),
+        ];
+
+        for (position, voxel) in edits {
+            set_voxel( &chunks, position, voxel );
+
+            let chunk = chunks.get( &(0, 0, 0) ).unwrap();
+            let chunk = chunk.read().unwrap();
+            let neighbours = (0..26).map( |_| RwLock::new( WorldChunk::new_disabled() ) ).collect::<Vec<_>>();
+            let guards = neighbours.iter().map( |n| n.read().unwrap() ).collect::<Vec<_>>();
+            let recalculated = ChunkLight::calculate( chunk.get_data().unwrap() as &dyn WorldHolding, chunk.get_opaque_mask().unwrap(), &guards );
+
+            assert!( chunk.get_light().unwrap().data == recalculated.data, "light differs after editing {position:?}" );
+        }
+    }
+
+    #[test]
+    fn test_block_light_crosses_chunk_borders() {
+        let chunks = create_lit_chunks( vec![ ((0, 0, 0), create_roofed_octree()), ((1, 0, 0), create_roofed_octree()) ] );
+
+        let changed = set_voxel( &chunks, (63, 20, 20), Some( create_voxel( MAX_LIGHT_LEVEL ) ) );
+        let right_chunk = chunks.get( &(1, 0, 0) ).unwrap();
+
+        assert!( changed.contains( &(0, 0, 0) ) && changed.contains( &(1, 0, 0) ) );
+        assert!( !changed.contains( &(2, 0, 0) ) && !changed.contains( &(0, 1, 0) ) );
+        assert_eq!( right_chunk.read().unwrap().get_light().unwrap().get_block_light( 0, 20, 20 ), MAX_LIGHT_LEVEL - 1 );
+        assert_eq!( right_chunk.read().unwrap().get_light().unwrap().get_block_light( 13, 20, 20 ), 1 );
+
+        set_voxel( &chunks, (63, 20, 20), None );
+        assert_eq!( right_chunk.read().unwrap().get_light().unwrap().get_block_light( 0, 20, 20 ), 0 );
+    }
+
+    #[test]
+    fn test_covered_sunlight_goes_dark_in_the_chunk_below() {
+        let empty_octree = || Octree::from_max_size( CHUNK_SIZE as u32 );
+        let chunks = create_lit_chunks( vec![ ((0, 0, 0), empty_octree()), ((0, -1, 0), empty_octree()) ] );
+
+        let changed = set_voxel( &chunks, (5, 10, 5), Some( create_voxel( 0 ) ) );
+        let bottom_chunk = chunks.get( &(0, -1, 0) ).unwrap();
+        let bottom_chunk = bottom_chunk.read().unwrap();
+
+        assert!( changed.contains( &(0, -1, 0) ) );
+        assert_eq!( bottom_chunk.get_light().unwrap().get_sunlight( 5, 0, 5 ), MAX_LIGHT_LEVEL - 1 );
+        assert_eq!( bottom_chunk.get_light().unwrap().get_sunlight( 6, 0, 5 ), MAX_LIGHT_LEVEL );
+    }
+
+    #[test]
+    fn test_sunlight_fades_in_transparent_voxels() {
+        let mut octree = Octree::from_max_size( CHUNK_SIZE as u32 );
+        octree.fill_voxels( (0, 0, 0), (63, 9, 63), Some( create_voxel( 0 ) ) );
+        octree.fill_voxels( (0, 10, 0), (63, 19, 63), Some( create_voxel_of( Material { opacity:1, is_solid:false, ..Default::default() } ) ) );
+
+        let light = calculate( &octree );
+
+        assert_eq!( light.get_sunlight( 5, 20, 5 ), MAX_LIGHT_LEVEL );
+        assert_eq!( light.get_sunlight( 5, 19, 5 ), MAX_LIGHT_LEVEL - 1 );
+        assert_eq!( light.get_sunlight( 5, 10, 5 ), MAX_LIGHT_LEVEL - 10 );
+    }
+}
@@ -12,6 +12,7 @@ use super::{
 pub struct WorldRenderer {
     pub model: ModelStrip<VoxelVertex>,
     // pub model: Model<VoxelVertex>,
+    opacity: f32,
 }
 
 impl WorldRenderer {
@@ -21,9 +22,15 @@ impl WorldRenderer {
                 ModelStrip::<VoxelVertex>::new( renderer, VOXEL_SIDE_VERTICES.to_vec() ).unwrap()
                 // Model::<VoxelVertex>::new( renderer, VOXEL_SIDE_VERTICES.to_vec(), VOXEL_SIDE_INDICES.to_vec() ).unwrap()
             },
+            opacity: 1.0,
         }
     }
 
+    pub fn with_opacity( mut self, opacity:f32 ) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
     pub fn update_instances_buffer( &mut self, renderer:&Renderer, renderables:Vec<VoxelSide> ) {
         unsafe{ self.model.update_instances_buffer( renderer, renderables ).unwrap() };
     }
@@ -33,6 +40,10 @@ impl Renderable for WorldRenderer {
     unsafe fn render( &self, device:&Device, command_buffer:vk::CommandBuffer ) {
         self.model.render( device, command_buffer );
     }
+
+    fn get_opacity( &self ) -> f32 {
+        self.opacity
+    }
 }
 
 